@@ -1,5 +1,4 @@
 use std::any::Any;
-use std::io::ErrorKind;
 use std::thread;
 
 use bytebuf_rs::bytebuf::ByteBuf;
@@ -7,6 +6,8 @@ use crossbeam::sync::WaitGroup;
 
 use retty::channel::channel_handler_ctx::{ChannelInboundHandlerCtx, ChannelOutboundHandlerCtx};
 use retty::channel::codec::first_integer_length_field_decoder::FirstIntegerLengthFieldDecoder;
+use retty::channel::codec::length_field_prepender::LengthFieldPrepender;
+use retty::channel::event::IdleStateEvent;
 use retty::channel::handler::{ChannelInboundHandler, ChannelOutboundHandler};
 use retty::channel::handler_pipe::{ChannelInboundHandlerPipe, ChannelOutboundHandlerPipe};
 use retty::core::bootstrap::Bootstrap;
@@ -155,20 +156,29 @@ impl ChannelInboundHandler for InboundExceptionHandler {
         &mut self,
         channel_handler_ctx: &mut ChannelInboundHandlerCtx,
         error: RettyErrorKind,
+    ) {
+        println!(
+            "channel_id:{} 异常: {:?} {}",
+            channel_handler_ctx.channel().id(),
+            error.kind,
+            error.message
+        );
+    }
+
+    fn user_event_triggered(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        event: Box<dyn Any + Send>,
     ) {
         let ch = channel_handler_ctx.channel();
 
-        // 处理 ReadIdleTimeout
+        // 处理读空闲
 
-        if error.kind == ErrorKind::TimedOut {
+        if let Some(IdleStateEvent::ReaderIdle) = event.downcast_ref::<IdleStateEvent>() {
             println!(
-                "channel_id:{} 在 {}",
+                "channel_id:{} 在 {} ms 没有读到数据！",
                 ch.id(),
-                format!(
-                    "{} ms 没有读到数据！ , error_message:{}",
-                    ch.read_idle_timeout_ms(),
-                    error.message
-                )
+                ch.read_idle_timeout_ms()
             );
             ch.close()
         }
@@ -197,7 +207,7 @@ impl ChannelOutboundHandler for Encoder {
         println!("回执消息，编码器 ：====>Encoder Handler:{}", msg);
         let mut buf = ByteBuf::new_with_capacity(0);
         let re = format!("回执消息，编码器 ：====>Encoder Handler:{}", msg);
-        buf.write_u32_be((1 + re.as_bytes().len()) as u32).unwrap();
+        // 长度字段由 LengthFieldPrepender 加上
         buf.write_string_with_u8_be_len(re).unwrap();
         channel_handler_ctx.fire_channel_write(&mut buf);
     }
@@ -234,6 +244,8 @@ fn main() {
         .initialize_outbound_handler_pipeline(|| {
             let mut handler_pipe = ChannelOutboundHandlerPipe::new();
             let encoder_handler = Box::new(Encoder::new());
+            // 出站消息先经过后加入的 handler: Encoder -> LengthFieldPrepender
+            handler_pipe.add_last(Box::new(LengthFieldPrepender::new(4)));
             handler_pipe.add_last(encoder_handler);
             handler_pipe
         })
@@ -0,0 +1 @@
+pub mod pooled_allocator;
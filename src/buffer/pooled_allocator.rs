@@ -0,0 +1,330 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use bytebuf_rs::bytebuf::ByteBuf;
+
+// 最小的档位 64B, 之后每档翻倍, 最大 64KB, 更大的申请不走池
+pub const MIN_POOLED_SIZE: usize = 64;
+pub const MAX_POOLED_SIZE: usize = 64 * 1024;
+pub const DEFAULT_MAX_CACHED_PER_SIZE_CLASS: usize = 256;
+
+///
+/// 池化的内存分配器, 每个 EventLoop 一个, handler 通过 ctx.alloc() 拿到
+/// 按 2 的幂分档缓存 Vec<u8>, PooledBuf drop 时放回对应的档位, 超过 MAX_POOLED_SIZE 的直接分配不缓存
+/// 目前用在解码器的累积缓冲区和出站缓冲区上; 读 socket 时 eventloop 复用同一个读缓冲区,
+/// 但交给 pipeline 的 ByteBuf 自己持有一份 Vec, 每次读还是会复制一次
+///
+#[derive(Clone)]
+pub struct PooledByteBufAllocator {
+    inner: Arc<PoolInner>,
+}
+
+struct PoolInner {
+    size_classes: Vec<SizeClass>,
+    max_cached_per_size_class: usize,
+    pooled_allocations: AtomicUsize,
+    unpooled_allocations: AtomicUsize,
+    pool_hits: AtomicUsize,
+    released: AtomicUsize,
+    discarded: AtomicUsize,
+}
+
+struct SizeClass {
+    size: usize,
+    free: Mutex<Vec<Vec<u8>>>,
+}
+
+///
+/// 分配器的统计信息
+///
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct PooledByteBufAllocatorStats {
+    /// 从池里分配的次数
+    pub pooled_allocations: usize,
+    /// 超过 MAX_POOLED_SIZE 直接分配的次数
+    pub unpooled_allocations: usize,
+    /// 池里分配时复用到缓存的次数
+    pub pool_hits: usize,
+    /// drop 时放回池里的次数
+    pub released: usize,
+    /// drop 时对应档位已经满了或者容量超过 MAX_POOLED_SIZE 而丢弃的次数
+    pub discarded: usize,
+    /// 目前缓存的 buffer 个数
+    pub cached_buffers: usize,
+    /// 目前缓存的字节数
+    pub cached_bytes: usize,
+}
+
+impl Default for PooledByteBufAllocator {
+    fn default() -> Self {
+        PooledByteBufAllocator::new(DEFAULT_MAX_CACHED_PER_SIZE_CLASS)
+    }
+}
+
+impl PooledByteBufAllocator {
+    pub fn new(max_cached_per_size_class: usize) -> PooledByteBufAllocator {
+        let mut size_classes = vec![];
+        let mut size = MIN_POOLED_SIZE;
+        while size <= MAX_POOLED_SIZE {
+            size_classes.push(SizeClass {
+                size,
+                free: Mutex::new(vec![]),
+            });
+            size <<= 1;
+        }
+        PooledByteBufAllocator {
+            inner: Arc::new(PoolInner {
+                size_classes,
+                max_cached_per_size_class,
+                pooled_allocations: AtomicUsize::new(0),
+                unpooled_allocations: AtomicUsize::new(0),
+                pool_hits: AtomicUsize::new(0),
+                released: AtomicUsize::new(0),
+                discarded: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    ///
+    /// 分配一个空的、容量至少为 capacity 的 buffer
+    ///
+    pub fn allocate(&self, capacity: usize) -> PooledBuf {
+        let index = match self.size_class_index(capacity) {
+            Some(index) => index,
+            None => {
+                self.inner
+                    .unpooled_allocations
+                    .fetch_add(1, Ordering::Relaxed);
+                return PooledBuf {
+                    buf: Vec::with_capacity(capacity),
+                    pool: None,
+                };
+            }
+        };
+        self.inner
+            .pooled_allocations
+            .fetch_add(1, Ordering::Relaxed);
+        let size_class = &self.inner.size_classes[index];
+        let cached = size_class.free.lock().unwrap().pop();
+        let buf = match cached {
+            Some(buf) => {
+                self.inner.pool_hits.fetch_add(1, Ordering::Relaxed);
+                buf
+            }
+            None => Vec::with_capacity(size_class.size),
+        };
+        PooledBuf {
+            buf,
+            pool: Some(self.inner.clone()),
+        }
+    }
+
+    ///
+    /// 分配一个 buffer 并复制 bytes
+    ///
+    pub fn allocate_from(&self, bytes: &[u8]) -> PooledBuf {
+        let mut buf = self.allocate(bytes.len());
+        buf.extend_from_slice(bytes);
+        buf
+    }
+
+    pub fn stats(&self) -> PooledByteBufAllocatorStats {
+        let (cached_buffers, cached_bytes) =
+            self.inner
+                .size_classes
+                .iter()
+                .fold((0, 0), |(buffers, bytes), size_class| {
+                    let free = size_class.free.lock().unwrap();
+                    (
+                        buffers + free.len(),
+                        bytes + free.iter().map(|b| b.capacity()).sum::<usize>(),
+                    )
+                });
+        PooledByteBufAllocatorStats {
+            pooled_allocations: self.inner.pooled_allocations.load(Ordering::Relaxed),
+            unpooled_allocations: self.inner.unpooled_allocations.load(Ordering::Relaxed),
+            pool_hits: self.inner.pool_hits.load(Ordering::Relaxed),
+            released: self.inner.released.load(Ordering::Relaxed),
+            discarded: self.inner.discarded.load(Ordering::Relaxed),
+            cached_buffers,
+            cached_bytes,
+        }
+    }
+
+    fn size_class_index(&self, capacity: usize) -> Option<usize> {
+        self.inner
+            .size_classes
+            .iter()
+            .position(|size_class| size_class.size >= capacity)
+    }
+}
+
+impl PoolInner {
+    fn release(&self, mut buf: Vec<u8>) {
+        // 使用过程中扩容到超过 MAX_POOLED_SIZE 的 buffer 不缓存, 避免一个大帧一直占着内存
+        if buf.capacity() > MAX_POOLED_SIZE {
+            self.discarded.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        // 放回容量能装下的最大档位, 使用过程中扩容的 buffer 也能复用
+        let size_class = self
+            .size_classes
+            .iter()
+            .rev()
+            .find(|size_class| size_class.size <= buf.capacity());
+        if let Some(size_class) = size_class {
+            let mut free = size_class.free.lock().unwrap();
+            if free.len() < self.max_cached_per_size_class {
+                buf.clear();
+                free.push(buf);
+                self.released.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        }
+        self.discarded.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+///
+/// 从 PooledByteBufAllocator 分配的 buffer, 用法和 Vec<u8> 一样, drop 时自动放回池里
+///
+pub struct PooledBuf {
+    buf: Vec<u8>,
+    pool: Option<Arc<PoolInner>>,
+}
+
+impl PooledBuf {
+    pub fn is_pooled(&self) -> bool {
+        self.pool.is_some()
+    }
+
+    ///
+    /// 复制成 ByteBuf 交给 pipeline
+    ///
+    pub fn to_bytebuf(&self) -> ByteBuf {
+        ByteBuf::new_from(&self.buf[..])
+    }
+
+    ///
+    /// 取出内部的 Vec, 之后不再放回池里
+    ///
+    pub fn into_vec(mut self) -> Vec<u8> {
+        self.pool = None;
+        std::mem::take(&mut self.buf)
+    }
+}
+
+impl Deref for PooledBuf {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.buf
+    }
+}
+
+impl DerefMut for PooledBuf {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.buf
+    }
+}
+
+impl Drop for PooledBuf {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.release(std::mem::take(&mut self.buf));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn released_buffer_is_reused_by_the_same_size_class() {
+        let alloc = PooledByteBufAllocator::default();
+        let mut buf = alloc.allocate(100);
+        assert!(buf.is_pooled());
+        assert_eq!(buf.capacity(), 128);
+        buf.extend_from_slice(b"data");
+        let ptr = buf.as_ptr();
+        drop(buf);
+        assert_eq!(alloc.stats().cached_buffers, 1);
+        assert_eq!(alloc.stats().cached_bytes, 128);
+
+        let buf = alloc.allocate(70);
+        assert_eq!(buf.as_ptr(), ptr);
+        // 放回池里时清空
+        assert!(buf.is_empty());
+        assert_eq!(
+            alloc.stats(),
+            PooledByteBufAllocatorStats {
+                pooled_allocations: 2,
+                pool_hits: 1,
+                released: 1,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn grown_buffer_goes_back_to_the_largest_class_it_fits() {
+        let alloc = PooledByteBufAllocator::default();
+        let mut buf = alloc.allocate(MIN_POOLED_SIZE);
+        buf.extend_from_slice(&[0; 300]);
+        let capacity = buf.capacity();
+        drop(buf);
+
+        let buf = alloc.allocate(256);
+        assert!(buf.capacity() >= 256);
+        assert_eq!(buf.capacity(), capacity);
+        assert_eq!(alloc.stats().pool_hits, 1);
+    }
+
+    #[test]
+    fn buffers_larger_than_max_pooled_size_are_not_cached() {
+        let alloc = PooledByteBufAllocator::default();
+        let buf = alloc.allocate(MAX_POOLED_SIZE + 1);
+        assert!(!buf.is_pooled());
+        drop(buf);
+
+        // 使用过程中扩容超过 MAX_POOLED_SIZE 的也丢弃
+        let mut buf = alloc.allocate(MAX_POOLED_SIZE);
+        buf.extend_from_slice(&vec![0; MAX_POOLED_SIZE + 1]);
+        drop(buf);
+
+        let stats = alloc.stats();
+        assert_eq!(stats.unpooled_allocations, 1);
+        assert_eq!(stats.pooled_allocations, 1);
+        assert_eq!(stats.discarded, 1);
+        assert_eq!(stats.released, 0);
+        assert_eq!(stats.cached_buffers, 0);
+    }
+
+    #[test]
+    fn each_size_class_caches_at_most_max_cached_per_size_class() {
+        let alloc = PooledByteBufAllocator::new(2);
+        let bufs = (0..3).map(|_| alloc.allocate(64)).collect::<Vec<_>>();
+        let other = alloc.allocate(1024);
+        drop(bufs);
+        drop(other);
+
+        let stats = alloc.stats();
+        assert_eq!(stats.released, 3);
+        assert_eq!(stats.discarded, 1);
+        assert_eq!(stats.cached_buffers, 3);
+        assert_eq!(stats.cached_bytes, 64 * 2 + 1024);
+    }
+
+    #[test]
+    fn into_vec_takes_the_buffer_out_of_the_pool() {
+        let alloc = PooledByteBufAllocator::default();
+        let vec = alloc.allocate_from(b"abc").into_vec();
+        assert_eq!(vec, b"abc");
+        let stats = alloc.stats();
+        assert_eq!(stats.released, 0);
+        assert_eq!(stats.discarded, 0);
+        assert_eq!(stats.cached_buffers, 0);
+    }
+}
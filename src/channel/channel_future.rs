@@ -0,0 +1,245 @@
+use std::io::ErrorKind;
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::time::Duration;
+
+use crate::errors::RettyErrorKind;
+use crate::transport::channel::{close_channel, Channel};
+
+type ChannelFutureListener = Box<dyn FnOnce(&ChannelFuture) + Send>;
+
+struct ChannelFutureState {
+    result: Option<Result<(), RettyErrorKind>>,
+    listeners: Vec<ChannelFutureListener>,
+}
+
+///
+/// write / close / connect 的异步结果
+/// 操作完成(数据全部写进 socket、连接建立、channel 关闭)或者失败时完成, 失败时带上 RettyErrorKind
+/// listener 在完成 future 的线程上调用, 一般就是 eventloop 线程
+///
+#[derive(Clone)]
+pub struct ChannelFuture {
+    channel: Weak<Mutex<Channel>>,
+    inner: Arc<(Mutex<ChannelFutureState>, Condvar)>,
+}
+
+impl ChannelFuture {
+    pub(crate) fn new(channel: Weak<Mutex<Channel>>) -> ChannelFuture {
+        ChannelFuture {
+            channel,
+            inner: Arc::new((
+                Mutex::new(ChannelFutureState {
+                    result: None,
+                    listeners: vec![],
+                }),
+                Condvar::new(),
+            )),
+        }
+    }
+
+    pub(crate) fn failed(channel: Weak<Mutex<Channel>>, error: RettyErrorKind) -> ChannelFuture {
+        let future = ChannelFuture::new(channel);
+        future.complete(Err(error));
+        future
+    }
+
+    ///
+    /// 只有第一次调用生效, 唤醒 wait 并按添加顺序调用 listener
+    ///
+    pub(crate) fn complete(&self, result: Result<(), RettyErrorKind>) {
+        let listeners = {
+            let (state, cvar) = &*self.inner;
+            let mut state = state.lock().unwrap();
+            if state.result.is_some() {
+                return;
+            }
+            state.result = Some(result);
+            cvar.notify_all();
+            std::mem::take(&mut state.listeners)
+        };
+        for listener in listeners {
+            listener(self);
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        let state = self.inner.0.lock().unwrap();
+        state.result.is_some()
+    }
+
+    pub fn is_success(&self) -> bool {
+        let state = self.inner.0.lock().unwrap();
+        matches!(state.result, Some(Ok(())))
+    }
+
+    ///
+    /// 失败的原因, 未完成或者成功时返回 None
+    ///
+    pub fn cause(&self) -> Option<RettyErrorKind> {
+        let state = self.inner.0.lock().unwrap();
+        match &state.result {
+            Some(Err(e)) => Some(e.clone()),
+            _ => None,
+        }
+    }
+
+    ///
+    /// 阻塞等待完成, 不要在 eventloop 线程(handler 里)调用, 否则会一直等下去
+    ///
+    pub fn wait(&self) -> Result<(), RettyErrorKind> {
+        let (state, cvar) = &*self.inner;
+        let mut state = state.lock().unwrap();
+        while state.result.is_none() {
+            state = cvar.wait(state).unwrap();
+        }
+        state.result.clone().unwrap()
+    }
+
+    ///
+    /// 最多等待 timeout_ms, 超时返回 TimedOut
+    ///
+    pub fn wait_timeout_ms(&self, timeout_ms: u64) -> Result<(), RettyErrorKind> {
+        let (state, cvar) = &*self.inner;
+        let state = state.lock().unwrap();
+        let (state, _) = cvar
+            .wait_timeout_while(state, Duration::from_millis(timeout_ms), |s| {
+                s.result.is_none()
+            })
+            .unwrap();
+        match &state.result {
+            Some(result) => result.clone(),
+            None => Err(RettyErrorKind::new(
+                ErrorKind::TimedOut,
+                "ChannelFuture wait timeout".to_string(),
+            )),
+        }
+    }
+
+    ///
+    /// 添加完成回调, 已经完成的 future 会在当前线程立即调用
+    ///
+    pub fn add_listener<F>(&self, listener: F) -> &Self
+    where
+        F: FnOnce(&ChannelFuture) + Send + 'static,
+    {
+        {
+            let mut state = self.inner.0.lock().unwrap();
+            if state.result.is_none() {
+                state.listeners.push(Box::new(listener));
+                return self;
+            }
+        }
+        listener(self);
+        self
+    }
+
+    ///
+    /// 完成之后关闭 channel, 不论成功还是失败, 例如写完响应之后关闭连接
+    ///
+    pub fn add_close_listener(&self) -> &Self {
+        self.add_listener(|future| {
+            future.close_channel();
+        })
+    }
+
+    ///
+    /// 失败时关闭 channel
+    ///
+    pub fn add_close_on_failure_listener(&self) -> &Self {
+        self.add_listener(|future| {
+            if !future.is_success() {
+                future.close_channel();
+            }
+        })
+    }
+
+    fn close_channel(&self) {
+        if let Some(channel) = self.channel.upgrade() {
+            close_channel(&channel);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytebuf_rs::bytebuf::ByteBuf;
+
+    use super::*;
+    use crate::channel::embedded_channel::EmbeddedChannel;
+    use crate::channel::handler_pipe::ChannelHandlerPipe;
+
+    fn error() -> RettyErrorKind {
+        RettyErrorKind::new(ErrorKind::BrokenPipe, "broken".to_string())
+    }
+
+    fn embedded_channel() -> EmbeddedChannel<ByteBuf> {
+        EmbeddedChannel::new(ChannelHandlerPipe::new())
+    }
+
+    #[test]
+    fn listeners_run_in_order_on_completion_and_immediately_after_it() {
+        let future = ChannelFuture::new(Weak::new());
+        let calls = Arc::new(Mutex::new(vec![]));
+        for i in 0..2 {
+            let calls = calls.clone();
+            future.add_listener(move |f| calls.lock().unwrap().push((i, f.is_success())));
+        }
+        assert!(calls.lock().unwrap().is_empty());
+
+        future.complete(Ok(()));
+        // 只有第一次 complete 生效
+        future.complete(Err(error()));
+        assert_eq!(*calls.lock().unwrap(), vec![(0, true), (1, true)]);
+
+        let late = calls.clone();
+        future.add_listener(move |f| late.lock().unwrap().push((2, f.is_success())));
+        assert_eq!(calls.lock().unwrap().len(), 3);
+        assert_eq!(future.wait(), Ok(()));
+    }
+
+    #[test]
+    fn failed_future_is_already_done() {
+        let future = ChannelFuture::failed(Weak::new(), error());
+        assert!(future.is_done());
+        assert!(!future.is_success());
+        assert_eq!(future.cause(), Some(error()));
+        assert_eq!(future.wait(), Err(error()));
+        assert_eq!(future.wait_timeout_ms(0), Err(error()));
+    }
+
+    #[test]
+    fn wait_timeout_on_a_pending_future() {
+        let future = ChannelFuture::new(Weak::new());
+        assert_eq!(
+            future.wait_timeout_ms(10).unwrap_err().kind,
+            ErrorKind::TimedOut
+        );
+        assert!(future.cause().is_none());
+    }
+
+    #[test]
+    fn close_listener_closes_the_channel_after_success() {
+        let channel = embedded_channel();
+        let future = channel.write_outbound(&mut ByteBuf::new_from(b"bye"));
+        assert!(future.is_success());
+        assert!(channel.is_active());
+
+        future.add_close_listener();
+        assert!(!channel.is_active());
+    }
+
+    #[test]
+    fn close_on_failure_listener_only_closes_after_failure() {
+        let channel = embedded_channel();
+        channel
+            .write_outbound(&mut ByteBuf::new_from(b"ok"))
+            .add_close_on_failure_listener();
+        assert!(channel.is_active());
+
+        // 没有 handler 能把 String 编码成字节, 写失败
+        let future = channel.write_outbound(&mut "not bytes".to_string());
+        assert!(!future.is_success());
+        future.add_close_on_failure_listener();
+        assert!(!channel.is_active());
+    }
+}
@@ -1,13 +1,29 @@
 use std::any::Any;
+use std::io::ErrorKind;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
+use crate::buffer::pooled_allocator::PooledByteBufAllocator;
+use crate::channel::channel_future::ChannelFuture;
 use crate::channel::channel_handler_ctx_pipe::{
     ChannelInboundHandlerCtxPipe, ChannelOutboundHandlerCtxPipe,
 };
 use crate::channel::handler::{ChannelInboundHandler, ChannelOutboundHandler};
 use crate::core::eventloop::EventLoop;
 use crate::errors::RettyErrorKind;
-use crate::transport::channel::{Channel, InboundChannelCtx, OutboundChannelCtx};
+use crate::transport::channel::{close_channel, Channel, InboundChannelCtx, OutboundChannelCtx};
+
+pub(crate) type InboundHandlerRef = Arc<Mutex<Box<dyn ChannelInboundHandler + Send + Sync>>>;
+pub(crate) type OutboundHandlerRef = Arc<Mutex<Box<dyn ChannelOutboundHandler + Send + Sync>>>;
+
+///
+/// 指向下一个 ctx 和它的 handler, 单独加锁
+/// 运行时修改 pipeline 只需要改这里, 不用锁住正在处理事件的 ctx
+///
+pub(crate) type InboundLink =
+    Arc<Mutex<Option<(Arc<Mutex<ChannelInboundHandlerCtx>>, InboundHandlerRef)>>>;
+pub(crate) type OutboundLink =
+    Arc<Mutex<Option<(Arc<Mutex<ChannelOutboundHandlerCtx>>, OutboundHandlerRef)>>>;
 
 /**
 一个handlerctx 对应一个handler
@@ -18,13 +34,15 @@ pub struct ChannelInboundHandlerCtx {
     pub(crate) eventloop: Arc<EventLoop>,
     pub(crate) channel_ctx: InboundChannelCtx,
     pub(crate) channel_handler_ctx_pipe: Option<ChannelInboundHandlerCtxPipe>,
-    pub(crate) handler: Arc<Mutex<Box<dyn ChannelInboundHandler + Send + Sync>>>,
+    pub(crate) handler: InboundHandlerRef,
+
+    pub(crate) next: InboundLink,
 
-    pub(crate) head_ctx: Option<Arc<Mutex<ChannelInboundHandlerCtx>>>,
-    pub(crate) next_ctx: Option<Arc<Mutex<ChannelInboundHandlerCtx>>>,
+    // 已经从 pipeline 移除, 由 pipeline 设置, 不用锁住 ctx
+    pub(crate) removed: Arc<AtomicBool>,
 
-    pub(crate) head_handler: Option<Arc<Mutex<Box<dyn ChannelInboundHandler + Send + Sync>>>>,
-    pub(crate) next_handler: Option<Arc<Mutex<Box<dyn ChannelInboundHandler + Send + Sync>>>>,
+    // 不为 None 时 fire_channel_exception 先把异常存在这里, 见 with_deferred_exceptions
+    pub(crate) deferred_exceptions: Option<Vec<RettyErrorKind>>,
 
     ///
     /// 持有ChannelOutboundHandlerCtxPipe,用于写数据
@@ -46,10 +64,9 @@ impl ChannelInboundHandlerCtx {
             channel_ctx: InboundChannelCtx::new(channel),
             channel_handler_ctx_pipe: None,
             handler,
-            next_ctx: None,
-            next_handler: None,
-            head_ctx: None,
-            head_handler: None,
+            next: Arc::new(Mutex::new(None)),
+            removed: Arc::new(AtomicBool::new(false)),
+            deferred_exceptions: None,
             outbound_context_pipe,
         }
     }
@@ -58,47 +75,107 @@ impl ChannelInboundHandlerCtx {
         self.id.clone()
     }
 
+    ///
+    /// 这个 handler 已经从 pipeline 移除或者被替换, 比如解码器在回调里被移除之后应该停止解码
+    ///
+    pub fn is_removed(&self) -> bool {
+        self.removed.load(Ordering::SeqCst)
+    }
+
+    ///
+    /// 这个连接的入站 pipeline, 可以在 handler 的回调里增删 handler
+    ///
+    pub fn pipeline(&self) -> ChannelInboundHandlerCtxPipe {
+        self.channel_handler_ctx_pipe.clone().unwrap()
+    }
+
+    ///
+    /// 这个连接的出站 pipeline
+    ///
+    pub fn outbound_pipeline(&self) -> Option<ChannelOutboundHandlerCtxPipe> {
+        self.outbound_context_pipe
+            .as_ref()
+            .map(|pipe| pipe.lock().unwrap().clone())
+    }
+
+    ///
+    /// f 里触发的异常等 f 返回之后再往下传
+    /// 编解码器持有两个方向共用的锁时用, 下游在 channel_exception 里写数据不会再去拿同一把锁
+    ///
+    pub(crate) fn with_deferred_exceptions<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+        let outer = self.deferred_exceptions.replace(vec![]);
+        let ret = f(self);
+        let deferred = std::mem::replace(&mut self.deferred_exceptions, outer).unwrap_or_default();
+        for error in deferred {
+            self.fire_channel_exception(error);
+        }
+        ret
+    }
+
+    fn next(&self) -> Option<(Arc<Mutex<ChannelInboundHandlerCtx>>, InboundHandlerRef)> {
+        self.next.lock().unwrap().clone()
+    }
+
     pub fn fire_channel_active(&mut self) {
-        if self.next_ctx.is_some() {
-            let next_ctx = self.next_ctx.as_ref().unwrap();
-            let next_ctx_clone = next_ctx.clone();
-            let next_handler_arc = self.next_handler.as_ref().unwrap();
+        if let Some((next_ctx, next_handler_arc)) = self.next() {
             let mut next_handler = next_handler_arc.lock().unwrap();
-            let mut next_ctx_clone_ref = next_ctx_clone.lock().unwrap();
-            next_handler.channel_active(&mut *next_ctx_clone_ref)
+            let mut next_ctx_ref = next_ctx.lock().unwrap();
+            next_handler.channel_active(&mut *next_ctx_ref)
         }
     }
 
     pub fn fire_channel_inactive(&mut self) {
-        if self.next_ctx.is_some() {
-            let next_ctx = self.next_ctx.as_ref().unwrap();
-            let next_ctx_clone = next_ctx.clone();
-            let next_handler_arc = self.next_handler.as_ref().unwrap();
+        if let Some((next_ctx, next_handler_arc)) = self.next() {
             let mut next_handler = next_handler_arc.lock().unwrap();
-            let mut next_ctx_clone_ref = next_ctx_clone.lock().unwrap();
-            next_handler.channel_inactive(&mut *next_ctx_clone_ref)
+            let mut next_ctx_ref = next_ctx.lock().unwrap();
+            next_handler.channel_inactive(&mut *next_ctx_ref)
         }
     }
 
     pub fn fire_channel_read(&mut self, message: &mut dyn Any) {
-        if self.next_ctx.is_some() {
-            let next_ctx = self.next_ctx.as_ref().unwrap();
-            let next_ctx_clone = next_ctx.clone();
-            let next_handler_arc = self.next_handler.as_ref().unwrap();
+        if let Some((next_ctx, next_handler_arc)) = self.next() {
             let mut next_handler = next_handler_arc.lock().unwrap();
-            let mut next_ctx_clone_ref = next_ctx_clone.lock().unwrap();
-            next_handler.channel_read(&mut *next_ctx_clone_ref, message)
+            let mut next_ctx_ref = next_ctx.lock().unwrap();
+            next_handler.channel_read(&mut *next_ctx_ref, message)
         }
     }
 
     pub fn fire_channel_exception(&mut self, error: RettyErrorKind) {
-        if self.next_ctx.is_some() {
-            let next_ctx = self.next_ctx.as_ref().unwrap();
-            let next_ctx_clone = next_ctx.clone();
-            let next_handler_arc = self.next_handler.as_ref().unwrap();
+        if let Some(deferred) = self.deferred_exceptions.as_mut() {
+            deferred.push(error);
+            return;
+        }
+        if let Some((next_ctx, next_handler_arc)) = self.next() {
+            let mut next_handler = next_handler_arc.lock().unwrap();
+            let mut next_ctx_ref = next_ctx.lock().unwrap();
+            next_handler.channel_exception(&mut *next_ctx_ref, error)
+        }
+    }
+
+    pub fn fire_channel_writability_changed(&mut self) {
+        if let Some((next_ctx, next_handler_arc)) = self.next() {
+            let mut next_handler = next_handler_arc.lock().unwrap();
+            let mut next_ctx_ref = next_ctx.lock().unwrap();
+            next_handler.channel_writability_changed(&mut next_ctx_ref)
+        }
+    }
+
+    pub fn fire_channel_read_complete(&mut self) {
+        if let Some((next_ctx, next_handler_arc)) = self.next() {
+            let mut next_handler = next_handler_arc.lock().unwrap();
+            let mut next_ctx_ref = next_ctx.lock().unwrap();
+            next_handler.channel_read_complete(&mut next_ctx_ref)
+        }
+    }
+
+    ///
+    /// 把用户事件传给下一个handler, 在 user_event_triggered 里处理
+    ///
+    pub fn fire_user_event(&mut self, event: Box<dyn Any + Send>) {
+        if let Some((next_ctx, next_handler_arc)) = self.next() {
             let mut next_handler = next_handler_arc.lock().unwrap();
-            let mut next_ctx_clone_ref = next_ctx_clone.lock().unwrap();
-            next_handler.channel_exception(&mut *next_ctx_clone_ref, error)
+            let mut next_ctx_ref = next_ctx.lock().unwrap();
+            next_handler.user_event_triggered(&mut next_ctx_ref, event)
         }
     }
 
@@ -110,27 +187,65 @@ impl ChannelInboundHandlerCtx {
         next_handler.channel_active(&mut *ctx_ref_clone_ref);
     }
 
-    pub fn write_and_flush(&mut self, message: &mut dyn Any) {
-        if self.outbound_context_pipe.is_some() {
-            let pipe_arc = self.outbound_context_pipe.as_ref().unwrap();
+    ///
+    /// 经过出站 pipeline 写数据, 返回的 future 在数据全部写进 socket 之后完成
+    ///
+    pub fn write_and_flush(&mut self, message: &mut dyn Any) -> ChannelFuture {
+        if let Some(pipe_arc) = self.outbound_context_pipe.as_ref() {
             let pipe = pipe_arc.lock().unwrap();
-            pipe.head_channel_write(message);
+            pipe.write_and_flush(message)
         } else {
-            println!("self.outbound_context_pipe is None");
+            self.outbound_context_pipe_missing()
         }
     }
 
+    ///
+    /// 只把数据放进出站缓冲区, 调用 flush 之后才会写进 socket
+    ///
+    pub fn write(&mut self, message: &mut dyn Any) -> ChannelFuture {
+        if let Some(pipe_arc) = self.outbound_context_pipe.as_ref() {
+            let pipe = pipe_arc.lock().unwrap();
+            pipe.write(message)
+        } else {
+            self.outbound_context_pipe_missing()
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if let Some(pipe_arc) = self.outbound_context_pipe.as_ref() {
+            let pipe = pipe_arc.lock().unwrap();
+            pipe.flush();
+        }
+    }
+
+    fn outbound_context_pipe_missing(&self) -> ChannelFuture {
+        ChannelFuture::failed(
+            Arc::downgrade(&self.channel_ctx.channel),
+            RettyErrorKind::new(
+                ErrorKind::NotConnected,
+                "outbound_context_pipe is None".to_string(),
+            ),
+        )
+    }
+
     pub fn channel(&mut self) -> &mut InboundChannelCtx {
         &mut self.channel_ctx
     }
 
-    pub fn close(&mut self) {
-        self.channel_ctx.close()
+    pub fn close(&mut self) -> ChannelFuture {
+        close_channel(&self.channel_ctx.channel)
     }
 
     pub fn event_loop(&mut self) -> Arc<EventLoop> {
         self.eventloop.clone()
     }
+
+    ///
+    /// 当前 eventloop 的池化内存分配器
+    ///
+    pub fn alloc(&self) -> PooledByteBufAllocator {
+        self.eventloop.allocator()
+    }
 }
 
 ///
@@ -141,15 +256,9 @@ pub struct ChannelOutboundHandlerCtx {
     pub(crate) eventloop: Arc<EventLoop>,
     pub(crate) channel_ctx: OutboundChannelCtx,
     pub(crate) channel_handler_ctx_pipe: Option<ChannelOutboundHandlerCtxPipe>,
-    pub(crate) handler: Arc<Mutex<Box<dyn ChannelOutboundHandler + Send + Sync>>>,
+    pub(crate) handler: OutboundHandlerRef,
 
-    ///
-    /// 出站处理器 head 就是 tail
-    ///
-    pub(crate) head_ctx: Option<Arc<Mutex<ChannelOutboundHandlerCtx>>>,
-    pub(crate) next_ctx: Option<Arc<Mutex<ChannelOutboundHandlerCtx>>>,
-    pub(crate) head_handler: Option<Arc<Mutex<Box<dyn ChannelOutboundHandler + Send + Sync>>>>,
-    pub(crate) next_handler: Option<Arc<Mutex<Box<dyn ChannelOutboundHandler + Send + Sync>>>>,
+    pub(crate) next: OutboundLink,
 }
 
 impl ChannelOutboundHandlerCtx {
@@ -165,24 +274,40 @@ impl ChannelOutboundHandlerCtx {
             channel_ctx: OutboundChannelCtx::new(channel),
             channel_handler_ctx_pipe: None,
             handler,
-            next_ctx: None,
-            next_handler: None,
-            head_ctx: None,
-            head_handler: None,
+            next: Arc::new(Mutex::new(None)),
         }
     }
 
+    ///
+    /// 这个连接的出站 pipeline, 可以在 handler 的回调里增删 handler
+    ///
+    pub fn pipeline(&self) -> ChannelOutboundHandlerCtxPipe {
+        self.channel_handler_ctx_pipe.clone().unwrap()
+    }
+
+    fn next(&self) -> Option<(Arc<Mutex<ChannelOutboundHandlerCtx>>, OutboundHandlerRef)> {
+        self.next.lock().unwrap().clone()
+    }
+
     ///
     /// 从当前的ctx往下写
     ///
     pub fn fire_channel_write(&mut self, message: &mut dyn Any) {
-        if self.next_ctx.is_some() {
-            let next_ctx = self.next_ctx.as_ref().unwrap();
-            let next_ctx_clone = next_ctx.clone();
-            let next_handler_arc = self.next_handler.as_ref().unwrap();
+        if let Some((next_ctx, next_handler_arc)) = self.next() {
             let mut next_handler = next_handler_arc.lock().unwrap();
-            let mut next_ctx_clone_ref = next_ctx_clone.lock().unwrap();
-            next_handler.channel_write(&mut *next_ctx_clone_ref, message)
+            let mut next_ctx_ref = next_ctx.lock().unwrap();
+            next_handler.channel_write(&mut *next_ctx_ref, message)
+        }
+    }
+
+    ///
+    /// 从当前的ctx往下 flush
+    ///
+    pub fn fire_channel_flush(&mut self) {
+        if let Some((next_ctx, next_handler_arc)) = self.next() {
+            let mut next_handler = next_handler_arc.lock().unwrap();
+            let mut next_ctx_ref = next_ctx.lock().unwrap();
+            next_handler.channel_flush(&mut next_ctx_ref)
         }
     }
 
@@ -197,4 +322,8 @@ impl ChannelOutboundHandlerCtx {
     pub fn event_loop(&mut self) -> Arc<EventLoop> {
         self.eventloop.clone()
     }
+
+    pub fn alloc(&self) -> PooledByteBufAllocator {
+        self.eventloop.allocator()
+    }
 }
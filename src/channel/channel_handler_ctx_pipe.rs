@@ -1,114 +1,1189 @@
-use std::any::Any;
+use std::any::{Any, TypeId};
+use std::io::ErrorKind;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
-use crate::channel::channel_handler_ctx::{ChannelInboundHandlerCtx, ChannelOutboundHandlerCtx};
+use crate::channel::channel_future::ChannelFuture;
+use crate::channel::channel_handler_ctx::{
+    ChannelInboundHandlerCtx, ChannelOutboundHandlerCtx, InboundHandlerRef, InboundLink,
+    OutboundHandlerRef, OutboundLink,
+};
 use crate::channel::handler::{ChannelInboundHandler, ChannelOutboundHandler};
+use crate::core::eventloop::EventLoop;
 use crate::errors::RettyErrorKind;
+use crate::transport::channel::{notify_promises, Channel};
 
+struct InboundEntry {
+    id: String,
+    // handler 的具体类型, 按类型查找时不用锁住 handler
+    type_id: TypeId,
+    ctx: Arc<Mutex<ChannelInboundHandlerCtx>>,
+    handler: InboundHandlerRef,
+    // 和 ctx.next 是同一个
+    next: InboundLink,
+    // 和 ctx.removed 是同一个
+    removed: Arc<AtomicBool>,
+}
+
+type RemovedInbound = Vec<(Arc<Mutex<ChannelInboundHandlerCtx>>, InboundHandlerRef)>;
+
+///
+/// 入站 pipeline, 第一个是 HEAD
+///
+/// 所有 clone 共享同一条 pipeline, 运行时可以用 add_before / add_after / remove / replace 按 handler 的 id 修改,
+/// 包括在 handler 自己的回调里, 修改之后的事件马上按新的顺序传递
+/// 加入的 handler 调用 handler_added, 移除的 handler 调用 handler_removed,
+/// 移除时正在处理事件的 handler(比如在回调里移除自己) 在事件处理完之后调用
+///
 #[derive(Clone)]
 pub struct ChannelInboundHandlerCtxPipe {
-    pub(crate) channel_handler_ctx_pipe: Vec<Arc<Mutex<ChannelInboundHandlerCtx>>>,
-    pub(crate) channel_handler_pipe: Vec<Arc<Mutex<Box<dyn ChannelInboundHandler + Send + Sync>>>>,
+    entries: Arc<Mutex<Vec<InboundEntry>>>,
+    // 还没有调用 handler_removed 的 handler
+    removed: Arc<Mutex<RemovedInbound>>,
+    eventloop: Arc<EventLoop>,
+    channel: Arc<Mutex<Channel>>,
+    outbound_context_pipe: Arc<Mutex<ChannelOutboundHandlerCtxPipe>>,
 }
 
 impl ChannelInboundHandlerCtxPipe {
-    pub(crate) fn new() -> ChannelInboundHandlerCtxPipe {
+    pub(crate) fn new(
+        eventloop: Arc<EventLoop>,
+        channel: Arc<Mutex<Channel>>,
+        outbound_context_pipe: Arc<Mutex<ChannelOutboundHandlerCtxPipe>>,
+    ) -> ChannelInboundHandlerCtxPipe {
         ChannelInboundHandlerCtxPipe {
-            channel_handler_ctx_pipe: vec![],
-            channel_handler_pipe: vec![],
+            entries: Arc::new(Mutex::new(vec![])),
+            removed: Arc::new(Mutex::new(vec![])),
+            eventloop,
+            channel,
+            outbound_context_pipe,
         }
     }
 
     pub fn header_handler_ctx(&self) -> Arc<Mutex<ChannelInboundHandlerCtx>> {
-        self.channel_handler_ctx_pipe.get(0).unwrap().clone()
+        self.entries.lock().unwrap().get(0).unwrap().ctx.clone()
     }
     pub fn header_handler(&self) -> Arc<Mutex<Box<dyn ChannelInboundHandler + Send + Sync>>> {
-        self.channel_handler_pipe.get(0).unwrap().clone()
+        self.entries.lock().unwrap().get(0).unwrap().handler.clone()
+    }
+
+    ///
+    /// 按顺序返回所有 handler 的 id, 第一个是 HEAD
+    ///
+    pub fn names(&self) -> Vec<String> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|e| e.id.clone())
+            .collect()
+    }
+
+    ///
+    /// 按 id 查找 handler
+    ///
+    pub fn get(
+        &self,
+        name: &str,
+    ) -> Option<Arc<Mutex<Box<dyn ChannelInboundHandler + Send + Sync>>>> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|e| e.id == name)
+            .map(|e| e.handler.clone())
+    }
+
+    ///
+    /// 按 id 查找 handler 的 ctx, 可以从这个 handler 的位置开始往下传事件
+    ///
+    pub fn context(&self, name: &str) -> Option<Arc<Mutex<ChannelInboundHandlerCtx>>> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|e| e.id == name)
+            .map(|e| e.ctx.clone())
+    }
+
+    ///
+    /// 找到第一个类型是 T 的 handler, 加锁之后交给 f
+    /// 比如认证之后调大后面的解码器的最大帧长度:
+    /// pipeline.get_typed(|h: &mut ByteToMessageHandler<LineBasedFrameDecoder>| h.decoder_mut().set_max_length(8192))
+    /// 没有这个类型的 handler 时返回 NotFound,
+    /// 这个 handler 正在处理事件时(比如调用者自己和它前面的 handler) 已经被锁住, 返回 WouldBlock
+    ///
+    pub fn get_typed<T, R, F>(&self, f: F) -> Result<R, RettyErrorKind>
+    where
+        T: ChannelInboundHandler + 'static,
+        F: FnOnce(&mut T) -> R,
+    {
+        let handler = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|e| e.type_id == TypeId::of::<T>())
+            .map(|e| e.handler.clone())
+            .ok_or_else(typed_not_found_error::<T>)?;
+        let mut handler = handler.try_lock().map_err(|_| typed_busy_error::<T>())?;
+        (**handler)
+            .as_any_mut()
+            .downcast_mut::<T>()
+            .map(f)
+            .ok_or_else(typed_not_found_error::<T>)
     }
 
     pub(crate) fn head_channel_read(&self, msg: &mut dyn Any) {
-        let ctx_head = self.header_handler_ctx();
-        let head_handler_clone = self.header_handler().clone();
-        let mut head_handler = head_handler_clone.lock().unwrap();
-        let mut ctx_head_ref = ctx_head.lock().unwrap();
-        head_handler.channel_read(&mut ctx_head_ref, msg);
+        {
+            let ctx_head = self.header_handler_ctx();
+            let head_handler_clone = self.header_handler();
+            let mut head_handler = head_handler_clone.lock().unwrap();
+            let mut ctx_head_ref = ctx_head.lock().unwrap();
+            head_handler.channel_read(&mut ctx_head_ref, msg);
+        }
+        self.fire_handler_removed();
     }
 
     pub(crate) fn head_channel_active(&self) {
-        let pipe = self.clone();
-        let ctx_head = pipe.header_handler_ctx();
-        let head_handler_clone = pipe.header_handler().clone();
-        let mut head_handler = head_handler_clone.lock().unwrap();
-        let mut ctx_head_ref = ctx_head.lock().unwrap();
-        head_handler.channel_active(&mut *ctx_head_ref);
+        {
+            let ctx_head = self.header_handler_ctx();
+            let head_handler_clone = self.header_handler();
+            let mut head_handler = head_handler_clone.lock().unwrap();
+            let mut ctx_head_ref = ctx_head.lock().unwrap();
+            head_handler.channel_active(&mut *ctx_head_ref);
+        }
+        self.fire_handler_removed();
     }
 
     pub(crate) fn head_channel_exception(&self, error: RettyErrorKind) {
-        let pipe = self.clone();
-        let ctx_head = pipe.header_handler_ctx();
-        let head_handler_clone = pipe.header_handler().clone();
-        let mut head_handler = head_handler_clone.lock().unwrap();
-        let mut ctx_head_ref = ctx_head.lock().unwrap();
-        head_handler.channel_exception(&mut *ctx_head_ref, error);
+        {
+            let ctx_head = self.header_handler_ctx();
+            let head_handler_clone = self.header_handler();
+            let mut head_handler = head_handler_clone.lock().unwrap();
+            let mut ctx_head_ref = ctx_head.lock().unwrap();
+            head_handler.channel_exception(&mut *ctx_head_ref, error);
+        }
+        self.fire_handler_removed();
     }
 
     pub(crate) fn head_channel_inactive(&self) {
-        let pipe = self.clone();
-        let ctx_head = pipe.header_handler_ctx();
-        let head_handler_clone = pipe.header_handler().clone();
-        let mut head_handler = head_handler_clone.lock().unwrap();
-        let mut ctx_head_ref = ctx_head.lock().unwrap();
-        head_handler.channel_inactive(&mut *ctx_head_ref);
+        {
+            let ctx_head = self.header_handler_ctx();
+            let head_handler_clone = self.header_handler();
+            let mut head_handler = head_handler_clone.lock().unwrap();
+            let mut ctx_head_ref = ctx_head.lock().unwrap();
+            head_handler.channel_inactive(&mut *ctx_head_ref);
+        }
+        self.fire_handler_removed();
     }
 
-    pub(crate) fn add_last(
-        &mut self,
-        ctx: Arc<Mutex<ChannelInboundHandlerCtx>>,
-        handler: Arc<Mutex<Box<dyn ChannelInboundHandler + Send + Sync>>>,
+    pub(crate) fn head_channel_writability_changed(&self) {
+        {
+            let ctx_head = self.header_handler_ctx();
+            let head_handler_clone = self.header_handler();
+            let mut head_handler = head_handler_clone.lock().unwrap();
+            let mut ctx_head_ref = ctx_head.lock().unwrap();
+            head_handler.channel_writability_changed(&mut ctx_head_ref);
+        }
+        self.fire_handler_removed();
+    }
+
+    pub(crate) fn head_channel_read_complete(&self) {
+        {
+            let ctx_head = self.header_handler_ctx();
+            let head_handler_clone = self.header_handler();
+            let mut head_handler = head_handler_clone.lock().unwrap();
+            let mut ctx_head_ref = ctx_head.lock().unwrap();
+            head_handler.channel_read_complete(&mut ctx_head_ref);
+        }
+        self.fire_handler_removed();
+    }
+
+    pub(crate) fn head_user_event_triggered(&self, event: Box<dyn Any + Send>) {
+        {
+            let ctx_head = self.header_handler_ctx();
+            let head_handler_clone = self.header_handler();
+            let mut head_handler = head_handler_clone.lock().unwrap();
+            let mut ctx_head_ref = ctx_head.lock().unwrap();
+            head_handler.user_event_triggered(&mut ctx_head_ref, event);
+        }
+        self.fire_handler_removed();
+    }
+
+    ///
+    /// 通过出站 pipeline flush, eventloop 在一批数据读完之后执行被推迟的 flush
+    ///
+    pub(crate) fn flush_outbound(&self) {
+        self.outbound_context_pipe.lock().unwrap().flush();
+    }
+
+    pub(crate) fn add_last(&self, handler: Box<dyn ChannelInboundHandler + Send + Sync>) {
+        // 创建 pipeline 时 id 可以重复
+        self.insert(handler, false, |entries| Ok(entries.len()))
+            .unwrap();
+    }
+
+    ///
+    /// 在 base_id 的 handler 前面加入 handler, 不能加在 HEAD 前面
+    ///
+    pub fn add_before(
+        &self,
+        base_id: &str,
+        handler: Box<dyn ChannelInboundHandler + Send + Sync>,
+    ) -> Result<(), RettyErrorKind> {
+        self.insert(handler, true, |entries| match position(entries, base_id)? {
+            0 => Err(head_error()),
+            index => Ok(index),
+        })
+    }
+
+    ///
+    /// 在 base_id 的 handler 后面加入 handler
+    ///
+    pub fn add_after(
+        &self,
+        base_id: &str,
+        handler: Box<dyn ChannelInboundHandler + Send + Sync>,
+    ) -> Result<(), RettyErrorKind> {
+        self.insert(handler, true, |entries| Ok(position(entries, base_id)? + 1))
+    }
+
+    ///
+    /// 移除 id 的 handler, 之后它的 ctx 往下传的事件仍然交给原来的下一个handler
+    ///
+    pub fn remove(&self, id: &str) -> Result<(), RettyErrorKind> {
+        {
+            let mut entries = self.entries.lock().unwrap();
+            let index = match position(&entries, id)? {
+                0 => return Err(head_error()),
+                index => index,
+            };
+            let entry = entries.remove(index);
+            let next = entry.next.lock().unwrap().clone();
+            *entries[index - 1].next.lock().unwrap() = next;
+            entry.removed.store(true, Ordering::SeqCst);
+            self.removed
+                .lock()
+                .unwrap()
+                .push((entry.ctx, entry.handler));
+        }
+        self.fire_handler_removed();
+        Ok(())
+    }
+
+    ///
+    /// 用 handler 替换 old_id 的 handler, 被替换的 handler 之后往下传的事件交给新的 handler,
+    /// 比如协议升级时替换掉自己, 再把已经读到的数据往下传
+    ///
+    pub fn replace(
+        &self,
+        old_id: &str,
+        handler: Box<dyn ChannelInboundHandler + Send + Sync>,
+    ) -> Result<(), RettyErrorKind> {
+        let id = handler.id();
+        let (ctx, handler, type_id) = self.new_ctx(handler);
+        {
+            let mut entries = self.entries.lock().unwrap();
+            let index = match position(&entries, old_id)? {
+                0 => return Err(head_error()),
+                index => index,
+            };
+            if id != old_id && entries.iter().any(|e| e.id == id) {
+                return Err(duplicate_error(&id));
+            }
+            let old = entries.remove(index);
+            let (next, removed) = {
+                let ctx = ctx.lock().unwrap();
+                (ctx.next.clone(), ctx.removed.clone())
+            };
+            *next.lock().unwrap() = old.next.lock().unwrap().clone();
+            *entries[index - 1].next.lock().unwrap() = Some((ctx.clone(), handler.clone()));
+            *old.next.lock().unwrap() = Some((ctx.clone(), handler.clone()));
+            old.removed.store(true, Ordering::SeqCst);
+            entries.insert(
+                index,
+                InboundEntry {
+                    id,
+                    type_id,
+                    ctx: ctx.clone(),
+                    handler: handler.clone(),
+                    next,
+                    removed,
+                },
+            );
+            self.removed.lock().unwrap().push((old.ctx, old.handler));
+        }
+        handler
+            .lock()
+            .unwrap()
+            .handler_added(&mut ctx.lock().unwrap());
+        self.fire_handler_removed();
+        Ok(())
+    }
+
+    fn new_ctx(
+        &self,
+        handler: Box<dyn ChannelInboundHandler + Send + Sync>,
+    ) -> (
+        Arc<Mutex<ChannelInboundHandlerCtx>>,
+        InboundHandlerRef,
+        TypeId,
     ) {
-        self.channel_handler_pipe.push(handler);
-        self.channel_handler_ctx_pipe.push(ctx);
+        let id = handler.id();
+        let type_id = (*handler).as_any().type_id();
+        let handler = Arc::new(Mutex::new(handler));
+        let mut ctx = ChannelInboundHandlerCtx::new(
+            id,
+            self.eventloop.clone(),
+            self.channel.clone(),
+            handler.clone(),
+            Some(self.outbound_context_pipe.clone()),
+        );
+        ctx.channel_handler_ctx_pipe = Some(self.clone());
+        (Arc::new(Mutex::new(ctx)), handler, type_id)
+    }
+
+    fn insert<F>(
+        &self,
+        handler: Box<dyn ChannelInboundHandler + Send + Sync>,
+        unique: bool,
+        index: F,
+    ) -> Result<(), RettyErrorKind>
+    where
+        F: FnOnce(&[InboundEntry]) -> Result<usize, RettyErrorKind>,
+    {
+        let id = handler.id();
+        let (ctx, handler, type_id) = self.new_ctx(handler);
+        {
+            let mut entries = self.entries.lock().unwrap();
+            let index = index(&entries)?;
+            if unique && entries.iter().any(|e| e.id == id) {
+                return Err(duplicate_error(&id));
+            }
+            let (next, removed) = {
+                let ctx = ctx.lock().unwrap();
+                (ctx.next.clone(), ctx.removed.clone())
+            };
+            if index > 0 {
+                let mut prev = entries[index - 1].next.lock().unwrap();
+                *next.lock().unwrap() = prev.take();
+                *prev = Some((ctx.clone(), handler.clone()));
+            }
+            entries.insert(
+                index,
+                InboundEntry {
+                    id,
+                    type_id,
+                    ctx: ctx.clone(),
+                    handler: handler.clone(),
+                    next,
+                    removed,
+                },
+            );
+        }
+        handler
+            .lock()
+            .unwrap()
+            .handler_added(&mut ctx.lock().unwrap());
+        Ok(())
+    }
+
+    ///
+    /// 调用已经移除的 handler 的 handler_removed, 还在处理事件的留到下一次
+    ///
+    fn fire_handler_removed(&self) {
+        let removed = std::mem::take(&mut *self.removed.lock().unwrap());
+        let mut busy = vec![];
+        for (ctx, handler) in removed {
+            let called = match (handler.try_lock(), ctx.try_lock()) {
+                (Ok(mut handler), Ok(mut ctx)) => {
+                    handler.handler_removed(&mut ctx);
+                    true
+                }
+                _ => false,
+            };
+            if !called {
+                busy.push((ctx, handler));
+            }
+        }
+        self.removed.lock().unwrap().append(&mut busy);
     }
 }
 
+struct OutboundEntry {
+    id: String,
+    // handler 的具体类型, 按类型查找时不用锁住 handler
+    type_id: TypeId,
+    ctx: Arc<Mutex<ChannelOutboundHandlerCtx>>,
+    handler: OutboundHandlerRef,
+    next: OutboundLink,
+}
+
+type RemovedOutbound = Vec<(Arc<Mutex<ChannelOutboundHandlerCtx>>, OutboundHandlerRef)>;
+
+///
+/// 出站 pipeline, 最后一个是 TAIL, 和入站 pipeline 一样可以在运行时修改
+///
 #[derive(Clone)]
 pub struct ChannelOutboundHandlerCtxPipe {
-    pub(crate) channel_handler_ctx_pipe: Vec<Arc<Mutex<ChannelOutboundHandlerCtx>>>,
-    pub(crate) channel_handler_pipe: Vec<Arc<Mutex<Box<dyn ChannelOutboundHandler + Send + Sync>>>>,
+    entries: Arc<Mutex<Vec<OutboundEntry>>>,
+    removed: Arc<Mutex<RemovedOutbound>>,
+    eventloop: Arc<EventLoop>,
+    channel: Arc<Mutex<Channel>>,
 }
 
 impl ChannelOutboundHandlerCtxPipe {
-    pub(crate) fn new() -> ChannelOutboundHandlerCtxPipe {
+    pub(crate) fn new(
+        eventloop: Arc<EventLoop>,
+        channel: Arc<Mutex<Channel>>,
+    ) -> ChannelOutboundHandlerCtxPipe {
         ChannelOutboundHandlerCtxPipe {
-            channel_handler_ctx_pipe: vec![],
-            channel_handler_pipe: vec![],
+            entries: Arc::new(Mutex::new(vec![])),
+            removed: Arc::new(Mutex::new(vec![])),
+            eventloop,
+            channel,
         }
     }
 
     pub(crate) fn header_handler_ctx(&self) -> Arc<Mutex<ChannelOutboundHandlerCtx>> {
-        self.channel_handler_ctx_pipe.get(0).unwrap().clone()
+        self.entries.lock().unwrap().get(0).unwrap().ctx.clone()
     }
     pub(crate) fn header_handler(
         &self,
     ) -> Arc<Mutex<Box<dyn ChannelOutboundHandler + Send + Sync>>> {
-        self.channel_handler_pipe.get(0).unwrap().clone()
+        self.entries.lock().unwrap().get(0).unwrap().handler.clone()
+    }
+
+    ///
+    /// 按顺序返回所有 handler 的 id, 最后一个是 TAIL
+    ///
+    pub fn names(&self) -> Vec<String> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|e| e.id.clone())
+            .collect()
+    }
+
+    ///
+    /// 按 id 查找 handler
+    ///
+    pub fn get(
+        &self,
+        name: &str,
+    ) -> Option<Arc<Mutex<Box<dyn ChannelOutboundHandler + Send + Sync>>>> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|e| e.id == name)
+            .map(|e| e.handler.clone())
+    }
+
+    ///
+    /// 按 id 查找 handler 的 ctx, 可以从这个 handler 的位置开始往下传事件
+    ///
+    pub fn context(&self, name: &str) -> Option<Arc<Mutex<ChannelOutboundHandlerCtx>>> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|e| e.id == name)
+            .map(|e| e.ctx.clone())
+    }
+
+    ///
+    /// 找到第一个类型是 T 的 handler, 加锁之后交给 f
+    /// 比如认证之后调大后面的解码器的最大帧长度:
+    /// pipeline.get_typed(|h: &mut ByteToMessageHandler<LineBasedFrameDecoder>| h.decoder_mut().set_max_length(8192))
+    /// 没有这个类型的 handler 时返回 NotFound,
+    /// 这个 handler 正在处理事件时(比如调用者自己和它前面的 handler) 已经被锁住, 返回 WouldBlock
+    ///
+    pub fn get_typed<T, R, F>(&self, f: F) -> Result<R, RettyErrorKind>
+    where
+        T: ChannelOutboundHandler + 'static,
+        F: FnOnce(&mut T) -> R,
+    {
+        let handler = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|e| e.type_id == TypeId::of::<T>())
+            .map(|e| e.handler.clone())
+            .ok_or_else(typed_not_found_error::<T>)?;
+        let mut handler = handler.try_lock().map_err(|_| typed_busy_error::<T>())?;
+        (**handler)
+            .as_any_mut()
+            .downcast_mut::<T>()
+            .map(f)
+            .ok_or_else(typed_not_found_error::<T>)
     }
 
     pub(crate) fn head_channel_write(&self, msg: &mut dyn Any) {
-        let pipe = self.clone();
-        let ctx_head = pipe.header_handler_ctx();
-        let head_handler_clone = pipe.header_handler().clone();
+        let ctx_head = self.header_handler_ctx();
+        let head_handler_clone = self.header_handler();
         let mut head_handler = head_handler_clone.lock().unwrap();
         let mut ctx_head_ref = ctx_head.lock().unwrap();
         head_handler.channel_write(&mut *ctx_head_ref, msg);
     }
 
-    pub(crate) fn add_last(
-        &mut self,
-        ctx: Arc<Mutex<ChannelOutboundHandlerCtx>>,
-        handler: Arc<Mutex<Box<dyn ChannelOutboundHandler + Send + Sync>>>,
+    pub(crate) fn head_channel_flush(&self) {
+        let ctx_head = self.header_handler_ctx();
+        let head_handler_clone = self.header_handler();
+        let mut head_handler = head_handler_clone.lock().unwrap();
+        let mut ctx_head_ref = ctx_head.lock().unwrap();
+        head_handler.channel_flush(&mut ctx_head_ref);
+    }
+
+    ///
+    /// 写完整条出站 pipeline 之后把 future 交给 channel, 出站缓冲区写到这里为止的数据之后完成
+    ///
+    pub(crate) fn write(&self, msg: &mut dyn Any) -> ChannelFuture {
+        let channel = self.channel.clone();
+        let promise = ChannelFuture::new(Arc::downgrade(&channel));
+        let written_messages = channel.lock().unwrap().begin_write();
+        self.head_channel_write(msg);
+        channel
+            .lock()
+            .unwrap()
+            .finish_write(written_messages, promise.clone());
+        notify_promises(&channel);
+        self.fire_handler_removed();
+        promise
+    }
+
+    pub(crate) fn flush(&self) {
+        self.head_channel_flush();
+        notify_promises(&self.channel);
+        self.fire_handler_removed();
+    }
+
+    pub(crate) fn write_and_flush(&self, msg: &mut dyn Any) -> ChannelFuture {
+        let promise = self.write(msg);
+        self.flush();
+        promise
+    }
+
+    pub(crate) fn add_last(&self, handler: Box<dyn ChannelOutboundHandler + Send + Sync>) {
+        self.insert(handler, false, |entries| Ok(entries.len()))
+            .unwrap();
+    }
+
+    ///
+    /// 在 base_id 的 handler 前面(离 socket 更远) 加入 handler
+    ///
+    pub fn add_before(
+        &self,
+        base_id: &str,
+        handler: Box<dyn ChannelOutboundHandler + Send + Sync>,
+    ) -> Result<(), RettyErrorKind> {
+        self.insert(handler, true, |entries| position(entries, base_id))
+    }
+
+    ///
+    /// 在 base_id 的 handler 后面(离 socket 更近) 加入 handler, 不能加在 TAIL 后面
+    ///
+    pub fn add_after(
+        &self,
+        base_id: &str,
+        handler: Box<dyn ChannelOutboundHandler + Send + Sync>,
+    ) -> Result<(), RettyErrorKind> {
+        self.insert(handler, true, |entries| {
+            let index = position(entries, base_id)?;
+            if index + 1 == entries.len() {
+                return Err(tail_error());
+            }
+            Ok(index + 1)
+        })
+    }
+
+    ///
+    /// 移除 id 的 handler, 之后它的 ctx 往下写的消息仍然交给原来的下一个handler
+    ///
+    pub fn remove(&self, id: &str) -> Result<(), RettyErrorKind> {
+        {
+            let mut entries = self.entries.lock().unwrap();
+            let index = position(&entries, id)?;
+            if index + 1 == entries.len() {
+                return Err(tail_error());
+            }
+            let entry = entries.remove(index);
+            if index > 0 {
+                let next = entry.next.lock().unwrap().clone();
+                *entries[index - 1].next.lock().unwrap() = next;
+            }
+            self.removed
+                .lock()
+                .unwrap()
+                .push((entry.ctx, entry.handler));
+        }
+        self.fire_handler_removed();
+        Ok(())
+    }
+
+    ///
+    /// 用 handler 替换 old_id 的 handler, 被替换的 handler 之后往下写的消息交给新的 handler
+    ///
+    pub fn replace(
+        &self,
+        old_id: &str,
+        handler: Box<dyn ChannelOutboundHandler + Send + Sync>,
+    ) -> Result<(), RettyErrorKind> {
+        let id = handler.id();
+        let (ctx, handler, type_id) = self.new_ctx(handler);
+        {
+            let mut entries = self.entries.lock().unwrap();
+            let index = position(&entries, old_id)?;
+            if index + 1 == entries.len() {
+                return Err(tail_error());
+            }
+            if id != old_id && entries.iter().any(|e| e.id == id) {
+                return Err(duplicate_error(&id));
+            }
+            let old = entries.remove(index);
+            let next = ctx.lock().unwrap().next.clone();
+            *next.lock().unwrap() = old.next.lock().unwrap().clone();
+            if index > 0 {
+                *entries[index - 1].next.lock().unwrap() = Some((ctx.clone(), handler.clone()));
+            }
+            *old.next.lock().unwrap() = Some((ctx.clone(), handler.clone()));
+            entries.insert(
+                index,
+                OutboundEntry {
+                    id,
+                    type_id,
+                    ctx: ctx.clone(),
+                    handler: handler.clone(),
+                    next,
+                },
+            );
+            self.removed.lock().unwrap().push((old.ctx, old.handler));
+        }
+        handler
+            .lock()
+            .unwrap()
+            .handler_added(&mut ctx.lock().unwrap());
+        self.fire_handler_removed();
+        Ok(())
+    }
+
+    fn new_ctx(
+        &self,
+        handler: Box<dyn ChannelOutboundHandler + Send + Sync>,
+    ) -> (
+        Arc<Mutex<ChannelOutboundHandlerCtx>>,
+        OutboundHandlerRef,
+        TypeId,
     ) {
-        self.channel_handler_pipe.push(handler);
-        self.channel_handler_ctx_pipe.push(ctx);
+        let id = handler.id();
+        let type_id = (*handler).as_any().type_id();
+        let handler = Arc::new(Mutex::new(handler));
+        let mut ctx = ChannelOutboundHandlerCtx::new(
+            id,
+            self.eventloop.clone(),
+            self.channel.clone(),
+            handler.clone(),
+        );
+        ctx.channel_handler_ctx_pipe = Some(self.clone());
+        (Arc::new(Mutex::new(ctx)), handler, type_id)
+    }
+
+    fn insert<F>(
+        &self,
+        handler: Box<dyn ChannelOutboundHandler + Send + Sync>,
+        unique: bool,
+        index: F,
+    ) -> Result<(), RettyErrorKind>
+    where
+        F: FnOnce(&[OutboundEntry]) -> Result<usize, RettyErrorKind>,
+    {
+        let id = handler.id();
+        let (ctx, handler, type_id) = self.new_ctx(handler);
+        {
+            let mut entries = self.entries.lock().unwrap();
+            let index = index(&entries)?;
+            if unique && entries.iter().any(|e| e.id == id) {
+                return Err(duplicate_error(&id));
+            }
+            let next = ctx.lock().unwrap().next.clone();
+            if index > 0 {
+                let mut prev = entries[index - 1].next.lock().unwrap();
+                *next.lock().unwrap() = prev.take();
+                *prev = Some((ctx.clone(), handler.clone()));
+            } else if let Some(first) = entries.first() {
+                // 成为新的出站 pipeline 的头
+                *next.lock().unwrap() = Some((first.ctx.clone(), first.handler.clone()));
+            }
+            entries.insert(
+                index,
+                OutboundEntry {
+                    id,
+                    type_id,
+                    ctx: ctx.clone(),
+                    handler: handler.clone(),
+                    next,
+                },
+            );
+        }
+        handler
+            .lock()
+            .unwrap()
+            .handler_added(&mut ctx.lock().unwrap());
+        Ok(())
+    }
+
+    fn fire_handler_removed(&self) {
+        let removed = std::mem::take(&mut *self.removed.lock().unwrap());
+        let mut busy = vec![];
+        for (ctx, handler) in removed {
+            let called = match (handler.try_lock(), ctx.try_lock()) {
+                (Ok(mut handler), Ok(mut ctx)) => {
+                    handler.handler_removed(&mut ctx);
+                    true
+                }
+                _ => false,
+            };
+            if !called {
+                busy.push((ctx, handler));
+            }
+        }
+        self.removed.lock().unwrap().append(&mut busy);
+    }
+}
+
+trait Entry {
+    fn id(&self) -> &str;
+}
+
+impl Entry for InboundEntry {
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+impl Entry for OutboundEntry {
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+fn position<E: Entry>(entries: &[E], id: &str) -> Result<usize, RettyErrorKind> {
+    entries
+        .iter()
+        .position(|e| e.id() == id)
+        .ok_or_else(|| RettyErrorKind::new(ErrorKind::NotFound, format!("no such handler: {}", id)))
+}
+
+fn typed_not_found_error<T>() -> RettyErrorKind {
+    RettyErrorKind::new(
+        ErrorKind::NotFound,
+        format!("no handler of type {}", std::any::type_name::<T>()),
+    )
+}
+
+fn typed_busy_error<T>() -> RettyErrorKind {
+    RettyErrorKind::new(
+        ErrorKind::WouldBlock,
+        format!(
+            "handler of type {} is handling an event",
+            std::any::type_name::<T>()
+        ),
+    )
+}
+
+fn duplicate_error(id: &str) -> RettyErrorKind {
+    RettyErrorKind::new(
+        ErrorKind::AlreadyExists,
+        format!("duplicate handler id: {}", id),
+    )
+}
+
+fn head_error() -> RettyErrorKind {
+    RettyErrorKind::new(
+        ErrorKind::InvalidInput,
+        "can not add before, remove or replace HEAD".to_string(),
+    )
+}
+
+fn tail_error() -> RettyErrorKind {
+    RettyErrorKind::new(
+        ErrorKind::InvalidInput,
+        "can not add after, remove or replace TAIL".to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytebuf_rs::bytebuf::ByteBuf;
+
+    use crate::channel::codec::byte_to_message_decoder::ByteToMessageHandler;
+    use crate::channel::codec::first_integer_length_field_decoder::FirstIntegerLengthFieldDecoder;
+    use crate::channel::codec::line_based_frame_decoder::LineBasedFrameDecoder;
+    use crate::channel::embedded_channel::EmbeddedChannel;
+    use crate::channel::handler_pipe::ChannelHandlerPipe;
+
+    struct Probe {
+        kinds: Arc<Mutex<Vec<Result<usize, ErrorKind>>>>,
+    }
+
+    impl ChannelInboundHandler for Probe {
+        fn id(&self) -> String {
+            "Probe".to_string()
+        }
+
+        fn channel_active(&mut self, _channel_handler_ctx: &mut ChannelInboundHandlerCtx) {}
+
+        fn channel_inactive(&mut self, _channel_handler_ctx: &mut ChannelInboundHandlerCtx) {}
+
+        fn channel_read(
+            &mut self,
+            channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+            message: &mut dyn Any,
+        ) {
+            let pipeline = channel_handler_ctx.pipeline();
+            let mut kinds = self.kinds.lock().unwrap();
+            kinds.push(
+                pipeline
+                    .get_typed(|h: &mut ByteToMessageHandler<LineBasedFrameDecoder>| {
+                        h.decoder_mut().set_max_length(3);
+                        3
+                    })
+                    .map_err(|e| e.kind),
+            );
+            kinds.push(pipeline.get_typed(|_: &mut Probe| 0).map_err(|e| e.kind));
+            kinds.push(
+                pipeline
+                    .get_typed(|_: &mut FirstIntegerLengthFieldDecoder| 0)
+                    .map_err(|e| e.kind),
+            );
+            channel_handler_ctx.fire_channel_read(message);
+        }
+
+        fn channel_exception(
+            &mut self,
+            _channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+            _error: RettyErrorKind,
+        ) {
+        }
+    }
+
+    #[test]
+    fn get_typed_separates_busy_from_missing() {
+        let kinds = Arc::new(Mutex::new(vec![]));
+        let mut handler_pipe = ChannelHandlerPipe::new();
+        handler_pipe.add_last_inbound(Box::new(Probe {
+            kinds: kinds.clone(),
+        }));
+        handler_pipe.add_last_inbound(Box::new(ByteToMessageHandler::new(
+            LineBasedFrameDecoder::new(1024),
+        )));
+        let channel = EmbeddedChannel::<ByteBuf>::new(handler_pipe);
+
+        channel.write_inbound(b"abcdef\n");
+
+        assert_eq!(
+            *kinds.lock().unwrap(),
+            vec![Ok(3), Err(ErrorKind::WouldBlock), Err(ErrorKind::NotFound)]
+        );
+        assert!(channel.read_inbound().is_none());
+        assert_eq!(channel.take_exceptions().len(), 1);
+
+        let found = channel
+            .pipeline()
+            .get_typed(|_: &mut Probe| ())
+            .map_err(|e| e.kind);
+        assert_eq!(found, Ok(()));
+    }
+
+    type Log = Arc<Mutex<Vec<String>>>;
+
+    enum OnRead {
+        Forward,
+        RemoveSelf,
+        ReplaceSelf(&'static str),
+    }
+
+    struct Recorder {
+        id: &'static str,
+        log: Log,
+        on_read: OnRead,
+    }
+
+    fn recorder(id: &'static str, log: &Log, on_read: OnRead) -> Box<Recorder> {
+        Box::new(Recorder {
+            id,
+            log: log.clone(),
+            on_read,
+        })
+    }
+
+    impl Recorder {
+        fn record(&self, what: &str) {
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("{} {}", what, self.id));
+        }
+    }
+
+    impl ChannelInboundHandler for Recorder {
+        fn id(&self) -> String {
+            self.id.to_string()
+        }
+
+        fn channel_active(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+            channel_handler_ctx.fire_channel_active();
+        }
+
+        fn channel_inactive(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+            channel_handler_ctx.fire_channel_inactive();
+        }
+
+        fn channel_read(
+            &mut self,
+            channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+            message: &mut dyn Any,
+        ) {
+            self.record("read");
+            let pipeline = channel_handler_ctx.pipeline();
+            match std::mem::replace(&mut self.on_read, OnRead::Forward) {
+                OnRead::Forward => {}
+                OnRead::RemoveSelf => pipeline.remove(self.id).unwrap(),
+                OnRead::ReplaceSelf(id) => pipeline
+                    .replace(self.id, recorder(id, &self.log, OnRead::Forward))
+                    .unwrap(),
+            }
+            if channel_handler_ctx.is_removed() {
+                self.record("forward removed");
+            }
+            channel_handler_ctx.fire_channel_read(message);
+        }
+
+        fn channel_exception(
+            &mut self,
+            channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+            error: RettyErrorKind,
+        ) {
+            channel_handler_ctx.fire_channel_exception(error);
+        }
+
+        fn handler_added(&mut self, _channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+            self.record("added");
+        }
+
+        fn handler_removed(&mut self, _channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+            self.record("removed");
+        }
+    }
+
+    impl ChannelOutboundHandler for Recorder {
+        fn id(&self) -> String {
+            self.id.to_string()
+        }
+
+        fn channel_write(
+            &mut self,
+            channel_handler_ctx: &mut ChannelOutboundHandlerCtx,
+            message: &mut dyn Any,
+        ) {
+            self.record("write");
+            channel_handler_ctx.fire_channel_write(message);
+        }
+
+        fn handler_added(&mut self, _channel_handler_ctx: &mut ChannelOutboundHandlerCtx) {
+            self.record("added");
+        }
+
+        fn handler_removed(&mut self, _channel_handler_ctx: &mut ChannelOutboundHandlerCtx) {
+            self.record("removed");
+        }
+    }
+
+    fn recorder_channel(log: &Log, first: OnRead) -> EmbeddedChannel<ByteBuf> {
+        let mut handler_pipe = ChannelHandlerPipe::new();
+        handler_pipe.add_last_inbound(recorder("a", log, first));
+        handler_pipe.add_last_inbound(recorder("b", log, OnRead::Forward));
+        EmbeddedChannel::new(handler_pipe)
+    }
+
+    fn take(log: &Log) -> Vec<String> {
+        std::mem::take(&mut *log.lock().unwrap())
+    }
+
+    #[test]
+    fn add_before_and_after_insert_by_id() {
+        let log = Log::default();
+        let channel = recorder_channel(&log, OnRead::Forward);
+        assert_eq!(take(&log), vec!["added a", "added b"]);
+
+        let pipeline = channel.pipeline();
+        pipeline
+            .add_before("a", recorder("x", &log, OnRead::Forward))
+            .unwrap();
+        pipeline
+            .add_after("a", recorder("y", &log, OnRead::Forward))
+            .unwrap();
+        pipeline
+            .add_after(
+                "EmbeddedChannelCollector",
+                recorder("z", &log, OnRead::Forward),
+            )
+            .unwrap();
+
+        assert_eq!(
+            pipeline.names(),
+            vec!["HEAD", "x", "a", "y", "b", "EmbeddedChannelCollector", "z"]
+        );
+        assert_eq!(take(&log), vec!["added x", "added y", "added z"]);
+        channel.write_inbound(b"hi");
+        assert_eq!(take(&log), vec!["read x", "read a", "read y", "read b"]);
+        assert_eq!(channel.read_inbound().unwrap().available_bytes(), b"hi");
+    }
+
+    #[test]
+    fn handler_removing_itself_is_notified_after_the_event() {
+        let log = Log::default();
+        let channel = recorder_channel(&log, OnRead::RemoveSelf);
+        take(&log);
+
+        channel.write_inbound(b"hi");
+
+        // 移除之后 a 往下传的数据仍然到达 b, handler_removed 在这次读返回之后才调用
+        assert_eq!(
+            take(&log),
+            vec!["read a", "forward removed a", "read b", "removed a"]
+        );
+        assert_eq!(channel.read_all_inbound().len(), 1);
+        assert_eq!(
+            channel.pipeline().names(),
+            vec!["HEAD", "b", "EmbeddedChannelCollector"]
+        );
+
+        channel.write_inbound(b"hi");
+        assert_eq!(take(&log), vec!["read b"]);
+        assert_eq!(channel.read_all_inbound().len(), 1);
+    }
+
+    #[test]
+    fn remove_from_outside_a_callback_notifies_immediately() {
+        let log = Log::default();
+        let channel = recorder_channel(&log, OnRead::Forward);
+        take(&log);
+
+        channel.pipeline().remove("b").unwrap();
+
+        assert_eq!(take(&log), vec!["removed b"]);
+        channel.write_inbound(b"hi");
+        assert_eq!(take(&log), vec!["read a"]);
+        assert_eq!(channel.read_all_inbound().len(), 1);
+    }
+
+    #[test]
+    fn replace_routes_the_old_ctx_to_the_new_handler() {
+        let log = Log::default();
+        let channel = recorder_channel(&log, OnRead::ReplaceSelf("c"));
+        take(&log);
+
+        channel.write_inbound(b"hi");
+
+        // a 替换成 c 之后, a 的 ctx 往下传的数据先经过 c
+        assert_eq!(
+            take(&log),
+            vec![
+                "read a",
+                "added c",
+                "forward removed a",
+                "read c",
+                "read b",
+                "removed a"
+            ]
+        );
+        assert_eq!(
+            channel.pipeline().names(),
+            vec!["HEAD", "c", "b", "EmbeddedChannelCollector"]
+        );
+        channel.write_inbound(b"hi");
+        assert_eq!(take(&log), vec!["read c", "read b"]);
+        assert_eq!(channel.read_all_inbound().len(), 2);
+    }
+
+    #[test]
+    fn rejects_duplicate_missing_and_head_ids() {
+        let log = Log::default();
+        let channel = recorder_channel(&log, OnRead::Forward);
+        let pipeline = channel.pipeline();
+        let kind = |ret: Result<(), RettyErrorKind>| ret.unwrap_err().kind;
+
+        assert_eq!(
+            kind(pipeline.add_after("a", recorder("b", &log, OnRead::Forward))),
+            ErrorKind::AlreadyExists
+        );
+        assert_eq!(
+            kind(pipeline.replace("a", recorder("b", &log, OnRead::Forward))),
+            ErrorKind::AlreadyExists
+        );
+        assert_eq!(
+            kind(pipeline.add_before("missing", recorder("x", &log, OnRead::Forward))),
+            ErrorKind::NotFound
+        );
+        assert_eq!(kind(pipeline.remove("missing")), ErrorKind::NotFound);
+        assert_eq!(
+            kind(pipeline.add_before("HEAD", recorder("x", &log, OnRead::Forward))),
+            ErrorKind::InvalidInput
+        );
+        assert_eq!(kind(pipeline.remove("HEAD")), ErrorKind::InvalidInput);
+        assert_eq!(
+            kind(pipeline.replace("HEAD", recorder("x", &log, OnRead::Forward))),
+            ErrorKind::InvalidInput
+        );
+        // 替换成同一个 id 是允许的
+        pipeline
+            .replace("a", recorder("a", &log, OnRead::Forward))
+            .unwrap();
+
+        assert_eq!(
+            pipeline.names(),
+            vec!["HEAD", "a", "b", "EmbeddedChannelCollector"]
+        );
+    }
+
+    #[test]
+    fn outbound_pipeline_inserts_and_rejects_tail() {
+        let log = Log::default();
+        let mut channel = recorder_channel(&log, OnRead::Forward);
+        let pipeline = channel.outbound_pipeline();
+        pipeline
+            .add_before("TAIL", recorder("y", &log, OnRead::Forward))
+            .unwrap();
+        pipeline
+            .add_before("y", recorder("x", &log, OnRead::Forward))
+            .unwrap();
+        take(&log);
+
+        assert_eq!(pipeline.names(), vec!["x", "y", "TAIL"]);
+        assert_eq!(
+            pipeline
+                .add_after("TAIL", recorder("z", &log, OnRead::Forward))
+                .unwrap_err()
+                .kind,
+            ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            pipeline.remove("TAIL").unwrap_err().kind,
+            ErrorKind::InvalidInput
+        );
+
+        channel.write_outbound(&mut ByteBuf::new_from(b"hi"));
+        assert_eq!(take(&log), vec!["write x", "write y"]);
+        assert_eq!(channel.read_outbound(), b"hi");
+
+        pipeline.remove("x").unwrap();
+        assert_eq!(take(&log), vec!["removed x"]);
+        channel.write_outbound(&mut ByteBuf::new_from(b"hi"));
+        assert_eq!(take(&log), vec!["write y"]);
     }
 }
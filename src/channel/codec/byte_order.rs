@@ -0,0 +1,57 @@
+use std::io::ErrorKind;
+
+use crate::errors::RettyErrorKind;
+
+///
+/// 长度字段等多字节整数的字节序
+///
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum ByteOrder {
+    #[default]
+    BigEndian,
+    LittleEndian,
+}
+
+impl ByteOrder {
+    ///
+    /// 按字节序读出 bytes 表示的无符号整数, bytes 长度为 1 ~ 8
+    ///
+    pub fn read_uint(&self, bytes: &[u8]) -> u64 {
+        let fold = |v: u64, b: &u8| (v << 8) | u64::from(*b);
+        match self {
+            ByteOrder::BigEndian => bytes.iter().fold(0, fold),
+            ByteOrder::LittleEndian => bytes.iter().rev().fold(0, fold),
+        }
+    }
+
+    ///
+    /// 按字节序把 value 写成 width 个字节, width 为 1 / 2 / 3 / 4 / 8, 放不下时返回错误
+    ///
+    pub fn write_uint(&self, value: u64, width: usize) -> Result<Vec<u8>, RettyErrorKind> {
+        check_length_field_length(width)?;
+        if width < 8 && value >= 1u64 << (width * 8) {
+            return Err(RettyErrorKind::new(
+                ErrorKind::InvalidInput,
+                format!("length does not fit into a {}-byte field: {}", width, value),
+            ));
+        }
+        let bytes = value.to_be_bytes()[8 - width..].to_vec();
+        Ok(match self {
+            ByteOrder::BigEndian => bytes,
+            ByteOrder::LittleEndian => bytes.into_iter().rev().collect(),
+        })
+    }
+}
+
+pub(crate) fn check_length_field_length(width: usize) -> Result<(), RettyErrorKind> {
+    match width {
+        1 | 2 | 3 | 4 | 8 => Ok(()),
+        _ => Err(RettyErrorKind::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "length_field_length must be either 1, 2, 3, 4, or 8: {}",
+                width
+            ),
+        )),
+    }
+}
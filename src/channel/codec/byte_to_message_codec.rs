@@ -0,0 +1,202 @@
+use std::sync::{Arc, Mutex};
+
+use crate::channel::codec::byte_to_message_decoder::{
+    ByteToMessageDecoder, ByteToMessageHandler, DEFAULT_MAX_CUMULATION_BYTES,
+};
+use crate::channel::codec::message_to_byte_encoder::{MessageToByteEncoder, MessageToByteHandler};
+use crate::channel::handler::{ChannelCodec, ChannelInboundHandler, ChannelOutboundHandler};
+
+///
+/// 字节和消息之间的编解码器, 同一个实例处理入站和出站, 可以在两个方向之间共享状态(比如请求和响应的对应关系)
+/// 用 ChannelInboundHandlerPipe::add_last_codec 注册
+/// decode 里不要往 channel 写数据, 编解码共用一把锁; decode 里触发的异常在锁释放之后才往下传
+///
+pub struct ByteToMessageCodec<C> {
+    codec: Arc<Mutex<C>>,
+    max_cumulation_bytes: usize,
+}
+
+impl<C> ByteToMessageCodec<C>
+where
+    C: ByteToMessageDecoder + MessageToByteEncoder + Send + 'static,
+{
+    pub fn new(codec: C) -> Self {
+        ByteToMessageCodec::new_with_max_cumulation_bytes(codec, DEFAULT_MAX_CUMULATION_BYTES)
+    }
+
+    pub fn new_with_max_cumulation_bytes(codec: C, max_cumulation_bytes: usize) -> Self {
+        ByteToMessageCodec {
+            codec: Arc::new(Mutex::new(codec)),
+            max_cumulation_bytes,
+        }
+    }
+}
+
+impl<C> ChannelCodec for ByteToMessageCodec<C>
+where
+    C: ByteToMessageDecoder + MessageToByteEncoder + Send + 'static,
+{
+    fn into_handlers(
+        self,
+    ) -> (
+        Box<dyn ChannelInboundHandler + Send + Sync>,
+        Box<dyn ChannelOutboundHandler + Send + Sync>,
+    ) {
+        (
+            Box::new(ByteToMessageHandler::new_with_max_cumulation_bytes(
+                self.codec.clone(),
+                self.max_cumulation_bytes,
+            )),
+            Box::new(MessageToByteHandler::new(self.codec)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::Any;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use bytebuf_rs::bytebuf::ByteBuf;
+
+    use super::*;
+    use crate::channel::channel_handler_ctx::{
+        ChannelInboundHandlerCtx, ChannelOutboundHandlerCtx,
+    };
+    use crate::channel::codec::line_based_frame_decoder::LineBasedFrameDecoder;
+    use crate::channel::embedded_channel::EmbeddedChannel;
+    use crate::channel::handler_pipe::ChannelHandlerPipe;
+    use crate::errors::{CodecErrorKind, RettyErrorKind};
+
+    // 按行解码成 String, 编码时带上已经解码的行数, 验证两个方向共享状态
+    struct LineCodec {
+        lines: LineBasedFrameDecoder,
+        decoded: usize,
+    }
+
+    impl ByteToMessageDecoder for LineCodec {
+        type Message = String;
+
+        fn id(&self) -> String {
+            "LineCodec".to_string()
+        }
+
+        fn decode(
+            &mut self,
+            channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+            input: &[u8],
+            out: &mut Vec<String>,
+        ) -> Result<usize, RettyErrorKind> {
+            let mut frames = vec![];
+            let consumed = self.lines.decode(channel_handler_ctx, input, &mut frames)?;
+            for frame in frames {
+                self.decoded += 1;
+                out.push(String::from_utf8_lossy(frame.available_bytes()).to_string());
+            }
+            Ok(consumed)
+        }
+    }
+
+    impl MessageToByteEncoder for LineCodec {
+        type Message = String;
+
+        fn id(&self) -> String {
+            "LineCodec".to_string()
+        }
+
+        fn encode(
+            &mut self,
+            _channel_handler_ctx: &mut ChannelOutboundHandlerCtx,
+            message: &mut String,
+            out: &mut Vec<u8>,
+        ) -> Result<(), RettyErrorKind> {
+            out.extend_from_slice(format!("{} ({})\n", message, self.decoded).as_bytes());
+            Ok(())
+        }
+    }
+
+    // 收到的行原样写回, 收到异常时写回 "error"
+    struct Echo;
+
+    impl ChannelInboundHandler for Echo {
+        fn id(&self) -> String {
+            "Echo".to_string()
+        }
+
+        fn channel_active(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+            channel_handler_ctx.fire_channel_active();
+        }
+
+        fn channel_inactive(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+            channel_handler_ctx.fire_channel_inactive();
+        }
+
+        fn channel_read(
+            &mut self,
+            channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+            message: &mut dyn Any,
+        ) {
+            if let Some(line) = message.downcast_ref::<String>() {
+                channel_handler_ctx.write_and_flush(&mut line.clone());
+            }
+            channel_handler_ctx.fire_channel_read(message);
+        }
+
+        fn channel_exception(
+            &mut self,
+            channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+            error: RettyErrorKind,
+        ) {
+            channel_handler_ctx.write_and_flush(&mut "error".to_string());
+            channel_handler_ctx.fire_channel_exception(error);
+        }
+    }
+
+    fn echo_channel(max_length: usize) -> EmbeddedChannel<String> {
+        let mut handler_pipe = ChannelHandlerPipe::new();
+        handler_pipe.add_last_codec(ByteToMessageCodec::new(LineCodec {
+            lines: LineBasedFrameDecoder::new(max_length),
+            decoded: 0,
+        }));
+        handler_pipe.add_last_inbound(Box::new(Echo));
+        EmbeddedChannel::new(handler_pipe)
+    }
+
+    #[test]
+    fn decoder_and_encoder_share_state() {
+        let mut channel = echo_channel(1024);
+        channel.write_inbound(b"a\nb");
+        channel.write_inbound(b"\n");
+
+        assert_eq!(channel.read_all_inbound(), vec!["a", "b"]);
+        assert_eq!(channel.read_outbound(), b"a (1)\nb (2)\n");
+        // 编码器不认识的消息原样写出
+        channel.write_outbound(&mut ByteBuf::new_from(b"raw"));
+        assert_eq!(channel.read_outbound(), b"raw");
+    }
+
+    #[test]
+    fn writing_in_channel_exception_fired_by_decode_does_not_deadlock() {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut channel = echo_channel(3);
+            channel.write_inbound(b"too long\nok\n");
+            let errors = channel.take_exceptions();
+            tx.send((
+                channel.read_all_inbound(),
+                channel.read_outbound(),
+                errors.len() == 1 && errors[0].is_codec_error(CodecErrorKind::TooLongFrame),
+            ))
+            .unwrap();
+        });
+
+        let (inbound, outbound, too_long) = rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("codec deadlocked");
+        assert_eq!(inbound, vec!["ok"]);
+        assert_eq!(outbound, b"error (0)\nok (1)\n");
+        assert!(too_long);
+    }
+}
@@ -0,0 +1,448 @@
+use std::any::Any;
+use std::io::ErrorKind;
+use std::sync::{Arc, Mutex};
+
+use bytebuf_rs::bytebuf::ByteBuf;
+
+use crate::buffer::pooled_allocator::PooledBuf;
+use crate::channel::channel_handler_ctx::ChannelInboundHandlerCtx;
+use crate::channel::handler::ChannelInboundHandler;
+use crate::errors::{CodecErrorKind, RettyErrorKind};
+
+///
+/// 不限制累积的字节数
+///
+pub const DEFAULT_MAX_CUMULATION_BYTES: usize = usize::MAX;
+
+///
+/// 累积式解码器, 配合 ByteToMessageHandler 使用
+///
+/// input 是目前累积的所有还没有消费的数据, decode 每次解出一个消息放进 out, 返回消费的字节数
+/// 数据不够一个消息时返回 Ok(0), 等下一次读到数据再解
+/// 可以跳过的错误(比如丢弃一个超长的帧) 自己 fire_channel_exception 然后返回跳过的字节数,
+/// 返回 Err 表示数据流已经无法继续解析, 累积的数据会被全部丢弃
+///
+pub trait ByteToMessageDecoder {
+    type Message: Any;
+
+    fn id(&self) -> String;
+
+    fn decode(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        input: &[u8],
+        out: &mut Vec<Self::Message>,
+    ) -> Result<usize, RettyErrorKind>;
+
+    ///
+    /// 连接断开时在 decode 之后调用一次, input 可能为空, 默认和 decode 相同
+    ///
+    fn decode_last(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        input: &[u8],
+        out: &mut Vec<Self::Message>,
+    ) -> Result<usize, RettyErrorKind> {
+        self.decode(channel_handler_ctx, input, out)
+    }
+}
+
+///
+/// codec 的入站和出站部分共用一个解码器
+/// decode 里触发的异常等锁释放之后再往下传, 下游在 channel_exception 里写数据时编码器要拿同一把锁
+///
+impl<D: ByteToMessageDecoder> ByteToMessageDecoder for Arc<Mutex<D>> {
+    type Message = D::Message;
+
+    fn id(&self) -> String {
+        self.lock().unwrap().id()
+    }
+
+    fn decode(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        input: &[u8],
+        out: &mut Vec<Self::Message>,
+    ) -> Result<usize, RettyErrorKind> {
+        channel_handler_ctx
+            .with_deferred_exceptions(|ctx| self.lock().unwrap().decode(ctx, input, out))
+    }
+
+    fn decode_last(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        input: &[u8],
+        out: &mut Vec<Self::Message>,
+    ) -> Result<usize, RettyErrorKind> {
+        channel_handler_ctx
+            .with_deferred_exceptions(|ctx| self.lock().unwrap().decode_last(ctx, input, out))
+    }
+}
+
+///
+/// 把 ByteToMessageDecoder 包装成入站 handler
+/// 负责累积半包, 反复调用 decode 直到没有进展, 把解出的消息依次传给下一个handler
+/// 消费掉的数据在每次读完之后压缩掉, 累积超过 max_cumulation_bytes 时丢弃并触发 TooLongFrame
+/// 不是 ByteBuf 的消息原样往下传
+/// 从 pipeline 移除之后马上停止解码, 还没有解码的数据传给原来的下一个handler, 比如协议升级之后交给新协议的解码器
+///
+pub struct ByteToMessageHandler<D: ByteToMessageDecoder> {
+    decoder: D,
+    max_cumulation_bytes: usize,
+    // 还没有解码的数据, 从 eventloop 的内存池分配
+    cumulation: Option<PooledBuf>,
+}
+
+impl<D: ByteToMessageDecoder> ByteToMessageHandler<D> {
+    pub fn new(decoder: D) -> Self {
+        ByteToMessageHandler::new_with_max_cumulation_bytes(decoder, DEFAULT_MAX_CUMULATION_BYTES)
+    }
+
+    pub fn new_with_max_cumulation_bytes(decoder: D, max_cumulation_bytes: usize) -> Self {
+        ByteToMessageHandler {
+            decoder,
+            max_cumulation_bytes: max_cumulation_bytes.max(1),
+            cumulation: None,
+        }
+    }
+
+    pub fn decoder(&self) -> &D {
+        &self.decoder
+    }
+
+    pub fn decoder_mut(&mut self) -> &mut D {
+        &mut self.decoder
+    }
+
+    ///
+    /// 累积但还没有解码的字节数
+    ///
+    pub fn cumulation_bytes(&self) -> usize {
+        self.cumulation.as_ref().map_or(0, |c| c.len())
+    }
+
+    ///
+    /// 取出累积但还没有解码的数据, 比如协议升级之后交给后面的handler
+    ///
+    pub fn take_cumulation(&mut self) -> Option<Vec<u8>> {
+        self.cumulation.take().map(|c| c[..].to_vec())
+    }
+
+    ///
+    /// 反复 decode 直到没有进展, 返回消费的字节数, 出错时返回 None
+    ///
+    fn call_decode(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        input: &[u8],
+    ) -> Option<usize> {
+        let mut reader_index = 0;
+        loop {
+            let mut out = vec![];
+            let consumed =
+                match self
+                    .decoder
+                    .decode(channel_handler_ctx, &input[reader_index..], &mut out)
+                {
+                    Ok(consumed) => consumed.min(input.len() - reader_index),
+                    Err(e) => {
+                        channel_handler_ctx.fire_channel_exception(e);
+                        return None;
+                    }
+                };
+            reader_index += consumed;
+            let decoded = !out.is_empty();
+            for mut message in out {
+                channel_handler_ctx.fire_channel_read(&mut message);
+            }
+            // 后面的 handler 在处理消息时把这个解码器移除了, 剩下的数据不再由它解码
+            if channel_handler_ctx.is_removed() {
+                return Some(reader_index);
+            }
+            if consumed == 0 {
+                if decoded {
+                    // 不消费数据却一直解出消息会死循环
+                    let e = RettyErrorKind::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "{}.decode() did not read anything but decoded a message",
+                            self.decoder.id()
+                        ),
+                    );
+                    channel_handler_ctx.fire_channel_exception(e);
+                    return None;
+                }
+                // 没有消费也没有解出消息, 等更多的数据
+                return Some(reader_index);
+            }
+        }
+    }
+
+    fn decode_last(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        let cumulation = self.cumulation.take();
+        let input = cumulation.as_ref().map_or(&[][..], |c| &c[..]);
+        let reader_index = match self.call_decode(channel_handler_ctx, input) {
+            Some(reader_index) => reader_index,
+            None => return,
+        };
+        let mut out = vec![];
+        match self
+            .decoder
+            .decode_last(channel_handler_ctx, &input[reader_index..], &mut out)
+        {
+            Ok(_) => {
+                for mut message in out {
+                    channel_handler_ctx.fire_channel_read(&mut message);
+                }
+            }
+            Err(e) => channel_handler_ctx.fire_channel_exception(e),
+        }
+    }
+}
+
+impl<D: ByteToMessageDecoder + 'static> ChannelInboundHandler for ByteToMessageHandler<D> {
+    fn id(&self) -> String {
+        self.decoder.id()
+    }
+
+    fn channel_active(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        channel_handler_ctx.fire_channel_active();
+    }
+
+    fn channel_inactive(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        self.decode_last(channel_handler_ctx);
+        channel_handler_ctx.fire_channel_inactive();
+    }
+
+    fn channel_read(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        message: &mut dyn Any,
+    ) {
+        let buf = match message.downcast_ref::<ByteBuf>() {
+            Some(buf) => buf,
+            None => {
+                channel_handler_ctx.fire_channel_read(message);
+                return;
+            }
+        };
+        let mut cumulation = match self.cumulation.take() {
+            Some(cumulation) => cumulation,
+            None => channel_handler_ctx.alloc().allocate(buf.readable_bytes()),
+        };
+        cumulation.extend_from_slice(buf.available_bytes());
+
+        let reader_index = match self.call_decode(channel_handler_ctx, &cumulation[..]) {
+            Some(reader_index) => reader_index,
+            // 数据流已经无法解析, 丢弃累积的数据
+            None => return,
+        };
+        if reader_index == cumulation.len() {
+            // 全部消费完, buffer 放回内存池
+            return;
+        }
+        if cumulation.len() - reader_index > self.max_cumulation_bytes {
+            let e = RettyErrorKind::codec(
+                CodecErrorKind::TooLongFrame,
+                format!(
+                    "Cumulation exceeds {}: {} - discarded",
+                    self.max_cumulation_bytes,
+                    cumulation.len() - reader_index
+                ),
+            );
+            channel_handler_ctx.fire_channel_exception(e);
+            return;
+        }
+        // 保留半包
+        cumulation.drain(..reader_index);
+        self.cumulation = Some(cumulation);
+    }
+
+    fn channel_exception(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        error: RettyErrorKind,
+    ) {
+        channel_handler_ctx.fire_channel_exception(error);
+    }
+
+    fn handler_removed(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        if let Some(remaining) = self.take_cumulation() {
+            channel_handler_ctx.fire_channel_read(&mut ByteBuf::new_from(&remaining));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::embedded_channel::EmbeddedChannel;
+    use crate::channel::handler_pipe::ChannelHandlerPipe;
+
+    // 每 length 个字节解出一个消息, 连接断开时剩下的不足 length 的字节也作为一个消息
+    struct FixedLengthDecoder {
+        length: usize,
+    }
+
+    impl ByteToMessageDecoder for FixedLengthDecoder {
+        type Message = ByteBuf;
+
+        fn id(&self) -> String {
+            "FixedLengthDecoder".to_string()
+        }
+
+        fn decode(
+            &mut self,
+            _channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+            input: &[u8],
+            out: &mut Vec<ByteBuf>,
+        ) -> Result<usize, RettyErrorKind> {
+            if input.len() < self.length {
+                return Ok(0);
+            }
+            out.push(ByteBuf::new_from(&input[..self.length]));
+            Ok(self.length)
+        }
+
+        fn decode_last(
+            &mut self,
+            _channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+            input: &[u8],
+            out: &mut Vec<ByteBuf>,
+        ) -> Result<usize, RettyErrorKind> {
+            if !input.is_empty() {
+                out.push(ByteBuf::new_from(input));
+            }
+            Ok(input.len())
+        }
+    }
+
+    // 收到第一个消息时把解码器从 pipeline 移除
+    struct RemoveDecoder;
+
+    impl ChannelInboundHandler for RemoveDecoder {
+        fn id(&self) -> String {
+            "RemoveDecoder".to_string()
+        }
+
+        fn channel_active(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+            channel_handler_ctx.fire_channel_active();
+        }
+
+        fn channel_inactive(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+            channel_handler_ctx.fire_channel_inactive();
+        }
+
+        fn channel_read(
+            &mut self,
+            channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+            message: &mut dyn Any,
+        ) {
+            let pipeline = channel_handler_ctx.pipeline();
+            if pipeline.names().contains(&"FixedLengthDecoder".to_string()) {
+                pipeline.remove("FixedLengthDecoder").unwrap();
+            }
+            channel_handler_ctx.fire_channel_read(message);
+        }
+
+        fn channel_exception(
+            &mut self,
+            channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+            error: RettyErrorKind,
+        ) {
+            channel_handler_ctx.fire_channel_exception(error);
+        }
+    }
+
+    fn decoder_channel(length: usize, max_cumulation_bytes: usize) -> EmbeddedChannel<ByteBuf> {
+        let mut handler_pipe = ChannelHandlerPipe::new();
+        handler_pipe.add_last_inbound(Box::new(
+            ByteToMessageHandler::new_with_max_cumulation_bytes(
+                FixedLengthDecoder { length },
+                max_cumulation_bytes,
+            ),
+        ));
+        EmbeddedChannel::new(handler_pipe)
+    }
+
+    fn read_all(channel: &EmbeddedChannel<ByteBuf>) -> Vec<Vec<u8>> {
+        channel
+            .read_all_inbound()
+            .iter()
+            .map(|buf| buf.available_bytes().to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn cumulates_across_reads() {
+        let channel = decoder_channel(3, DEFAULT_MAX_CUMULATION_BYTES);
+        channel.write_inbound(b"ab");
+        assert!(read_all(&channel).is_empty());
+
+        channel.write_inbound(b"cdefg");
+        assert_eq!(read_all(&channel), vec![b"abc".to_vec(), b"def".to_vec()]);
+
+        channel.write_inbound(b"hi");
+        assert_eq!(read_all(&channel), vec![b"ghi".to_vec()]);
+        assert!(channel.take_exceptions().is_empty());
+    }
+
+    #[test]
+    fn stops_decoding_once_removed_and_hands_on_the_rest() {
+        let mut handler_pipe = ChannelHandlerPipe::new();
+        handler_pipe.add_last_inbound(Box::new(ByteToMessageHandler::new(FixedLengthDecoder {
+            length: 3,
+        })));
+        handler_pipe.add_last_inbound(Box::new(RemoveDecoder));
+        let channel: EmbeddedChannel<ByteBuf> = EmbeddedChannel::new(handler_pipe);
+
+        channel.write_inbound(b"abcdefgh");
+        // 移除之后剩下的数据不再按 3 个字节拆开, 在 handler_removed 里原样往下传
+        assert_eq!(read_all(&channel), vec![b"abc".to_vec(), b"defgh".to_vec()]);
+        assert_eq!(
+            channel.pipeline().names(),
+            vec!["HEAD", "RemoveDecoder", "EmbeddedChannelCollector"]
+        );
+
+        channel.write_inbound(b"ij");
+        assert_eq!(read_all(&channel), vec![b"ij".to_vec()]);
+    }
+
+    #[test]
+    fn too_much_cumulation_is_discarded() {
+        let channel = decoder_channel(4, 2);
+        channel.write_inbound(b"abc");
+        assert!(read_all(&channel).is_empty());
+        let errors = channel.take_exceptions();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].is_codec_error(CodecErrorKind::TooLongFrame));
+
+        // 丢弃之后从新数据开始解码
+        channel.write_inbound(b"defg");
+        assert_eq!(read_all(&channel), vec![b"defg".to_vec()]);
+        assert!(channel.take_exceptions().is_empty());
+    }
+
+    #[test]
+    fn decodes_the_rest_when_inactive() {
+        let channel = decoder_channel(3, DEFAULT_MAX_CUMULATION_BYTES);
+        channel.write_inbound(b"abcde");
+        assert_eq!(read_all(&channel), vec![b"abc".to_vec()]);
+
+        channel.finish();
+        assert_eq!(read_all(&channel), vec![b"de".to_vec()]);
+    }
+
+    #[test]
+    fn passes_other_messages_through() {
+        let mut handler_pipe = ChannelHandlerPipe::new();
+        handler_pipe.add_last_inbound(Box::new(ByteToMessageHandler::new(FixedLengthDecoder {
+            length: 3,
+        })));
+        let channel: EmbeddedChannel<String> = EmbeddedChannel::new(handler_pipe);
+        channel
+            .pipeline()
+            .head_channel_read(&mut "not bytes".to_string());
+        assert_eq!(channel.read_all_inbound(), vec!["not bytes"]);
+    }
+}
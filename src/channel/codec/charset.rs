@@ -0,0 +1,218 @@
+use crate::errors::{CodecErrorKind, RettyErrorKind};
+
+const UTF16_BE_BOM: [u8; 2] = [0xFE, 0xFF];
+const UTF16_LE_BOM: [u8; 2] = [0xFF, 0xFE];
+
+///
+/// StringDecoder / StringEncoder 支持的字符集
+///
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum Charset {
+    #[default]
+    Utf8,
+    ///
+    /// ISO-8859-1, 每个字节对应 U+0000 ~ U+00FF
+    ///
+    Latin1,
+    Utf16Be,
+    Utf16Le,
+    ///
+    /// 解码时根据 BOM 判断字节序(没有 BOM 时按大端), 编码时写大端和 BOM
+    ///
+    Utf16,
+}
+
+impl Charset {
+    ///
+    /// strict 为 false 时非法的字节替换成 U+FFFD, 否则返回 MalformedInput 错误
+    ///
+    pub fn decode(&self, bytes: &[u8], strict: bool) -> Result<String, RettyErrorKind> {
+        match self {
+            Charset::Utf8 => {
+                if strict {
+                    String::from_utf8(bytes.to_vec())
+                        .map_err(|e| malformed_input(self, e.to_string()))
+                } else {
+                    Ok(String::from_utf8_lossy(bytes).into_owned())
+                }
+            }
+            Charset::Latin1 => Ok(bytes.iter().map(|b| char::from(*b)).collect()),
+            Charset::Utf16Be => decode_utf16(self, bytes, u16::from_be_bytes, strict),
+            Charset::Utf16Le => decode_utf16(self, bytes, u16::from_le_bytes, strict),
+            Charset::Utf16 => {
+                if bytes.starts_with(&UTF16_LE_BOM) {
+                    decode_utf16(self, &bytes[2..], u16::from_le_bytes, strict)
+                } else if bytes.starts_with(&UTF16_BE_BOM) {
+                    decode_utf16(self, &bytes[2..], u16::from_be_bytes, strict)
+                } else {
+                    decode_utf16(self, bytes, u16::from_be_bytes, strict)
+                }
+            }
+        }
+    }
+
+    ///
+    /// strict 为 false 时字符集不能表示的字符替换成 '?', 否则返回 MalformedInput 错误
+    ///
+    pub fn encode(&self, s: &str, strict: bool) -> Result<Vec<u8>, RettyErrorKind> {
+        match self {
+            Charset::Utf8 => Ok(s.as_bytes().to_vec()),
+            Charset::Latin1 => {
+                let mut bytes = Vec::with_capacity(s.len());
+                for c in s.chars() {
+                    match c as u32 {
+                        code if code <= 0xFF => bytes.push(code as u8),
+                        _ if !strict => bytes.push(b'?'),
+                        _ => {
+                            return Err(malformed_input(
+                                self,
+                                format!("unmappable character {:?}", c),
+                            ))
+                        }
+                    }
+                }
+                Ok(bytes)
+            }
+            Charset::Utf16Be => Ok(s.encode_utf16().flat_map(u16::to_be_bytes).collect()),
+            Charset::Utf16Le => Ok(s.encode_utf16().flat_map(u16::to_le_bytes).collect()),
+            Charset::Utf16 => {
+                let mut bytes = UTF16_BE_BOM.to_vec();
+                bytes.extend(s.encode_utf16().flat_map(u16::to_be_bytes));
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+fn decode_utf16(
+    charset: &Charset,
+    bytes: &[u8],
+    from_bytes: fn([u8; 2]) -> u16,
+    strict: bool,
+) -> Result<String, RettyErrorKind> {
+    let chunks = bytes.chunks_exact(2);
+    // 多出来的半个字符
+    let odd = !chunks.remainder().is_empty();
+    if strict && odd {
+        return Err(malformed_input(
+            charset,
+            format!("odd number of bytes: {}", bytes.len()),
+        ));
+    }
+    let units = chunks
+        .map(|c| from_bytes([c[0], c[1]]))
+        .collect::<Vec<u16>>();
+    if strict {
+        String::from_utf16(&units).map_err(|e| malformed_input(charset, e.to_string()))
+    } else {
+        let mut s = String::from_utf16_lossy(&units);
+        if odd {
+            s.push(char::REPLACEMENT_CHARACTER);
+        }
+        Ok(s)
+    }
+}
+
+fn malformed_input(charset: &Charset, message: String) -> RettyErrorKind {
+    RettyErrorKind::codec(
+        CodecErrorKind::MalformedInput,
+        format!("{:?}: {}", charset, message),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_malformed(result: Result<impl std::fmt::Debug, RettyErrorKind>) -> bool {
+        result.is_err_and(|e| e.is_codec_error(CodecErrorKind::MalformedInput))
+    }
+
+    #[test]
+    fn utf16_detects_byte_order_from_bom() {
+        assert_eq!(
+            Charset::Utf16.decode(&[0xFF, 0xFE, b'h', 0, b'i', 0], true),
+            Ok("hi".to_string())
+        );
+        assert_eq!(
+            Charset::Utf16.decode(&[0xFE, 0xFF, 0, b'h', 0, b'i'], true),
+            Ok("hi".to_string())
+        );
+        // 没有 BOM 时按大端
+        assert_eq!(
+            Charset::Utf16.decode(&[0, b'h', 0, b'i'], true),
+            Ok("hi".to_string())
+        );
+        assert_eq!(
+            Charset::Utf16.encode("hi", true),
+            Ok(vec![0xFE, 0xFF, 0, b'h', 0, b'i'])
+        );
+    }
+
+    #[test]
+    fn utf16_round_trips() {
+        let s = "a\u{e9}\u{20ac}\u{1f600}";
+        for charset in [Charset::Utf16Be, Charset::Utf16Le, Charset::Utf16] {
+            let bytes = charset.encode(s, true).unwrap();
+            assert_eq!(charset.decode(&bytes, true), Ok(s.to_string()));
+        }
+        assert_eq!(Charset::Utf16Be.encode("a", true), Ok(vec![0, b'a']));
+        assert_eq!(Charset::Utf16Le.encode("a", true), Ok(vec![b'a', 0]));
+        // 代理对
+        assert_eq!(
+            Charset::Utf16Be.encode("\u{1f600}", true),
+            Ok(vec![0xD8, 0x3D, 0xDE, 0x00])
+        );
+    }
+
+    #[test]
+    fn lone_surrogate_is_an_error_only_in_strict_mode() {
+        let bytes = [0, b'a', 0xD8, 0x3D, 0, b'b'];
+        assert!(is_malformed(Charset::Utf16Be.decode(&bytes, true)));
+        assert_eq!(
+            Charset::Utf16Be.decode(&bytes, false),
+            Ok("a\u{fffd}b".to_string())
+        );
+
+        // 多出来的半个字符
+        assert!(is_malformed(
+            Charset::Utf16Le.decode(&[b'a', 0, b'b'], true)
+        ));
+        assert_eq!(
+            Charset::Utf16Le.decode(&[b'a', 0, b'b'], false),
+            Ok("a\u{fffd}".to_string())
+        );
+    }
+
+    #[test]
+    fn latin1_maps_bytes_to_the_first_256_code_points() {
+        let all = (0..=255u8).collect::<Vec<u8>>();
+        let s = Charset::Latin1.decode(&all, true).unwrap();
+        assert_eq!(s.chars().count(), 256);
+        assert_eq!(Charset::Latin1.encode(&s, true), Ok(all));
+    }
+
+    #[test]
+    fn latin1_rejects_or_replaces_characters_above_u00ff() {
+        assert!(is_malformed(
+            Charset::Latin1.encode("caf\u{e9} \u{20ac}", true)
+        ));
+        assert_eq!(
+            Charset::Latin1.encode("caf\u{e9} \u{20ac}", false),
+            Ok(b"caf\xe9 ?".to_vec())
+        );
+    }
+
+    #[test]
+    fn utf8_strict_and_lossy() {
+        assert_eq!(
+            Charset::Utf8.decode("h\u{e9}".as_bytes(), true),
+            Ok("h\u{e9}".to_string())
+        );
+        assert!(is_malformed(Charset::Utf8.decode(b"h\xc3", true)));
+        assert_eq!(
+            Charset::Utf8.decode(b"h\xc3", false),
+            Ok("h\u{fffd}".to_string())
+        );
+    }
+}
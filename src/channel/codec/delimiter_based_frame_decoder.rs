@@ -0,0 +1,316 @@
+use bytebuf_rs::bytebuf::ByteBuf;
+
+use crate::channel::channel_handler_ctx::ChannelInboundHandlerCtx;
+use crate::channel::codec::byte_to_message_decoder::ByteToMessageDecoder;
+use crate::channel::codec::line_based_frame_decoder::LineBasedFrameDecoder;
+use crate::errors::{CodecErrorKind, RettyErrorKind};
+
+///
+/// "\r\n" 和 "\n"
+///
+pub fn line_delimiter() -> Vec<Vec<u8>> {
+    vec![b"\r\n".to_vec(), b"\n".to_vec()]
+}
+
+///
+/// "\0"
+///
+pub fn nul_delimiter() -> Vec<Vec<u8>> {
+    vec![vec![0]]
+}
+
+///
+/// 按一个或多个分隔符拆帧的解码器, 有多个分隔符时选拆出的帧最短的那个
+///
+/// strip_delimiter 为 true 时传给下一个handler 的 ByteBuf 不包含分隔符
+/// 一帧超过 max_frame_length 时丢弃这一帧并触发 TooLongFrame 的 channel_exception,
+/// fail_fast 为 true 时超过就触发, 否则读到分隔符之后再触发
+///
+/// 用 ByteToMessageHandler 包装之后加入 pipeline
+///
+pub struct DelimiterBasedFrameDecoder {
+    delimiters: Vec<Vec<u8>>,
+    max_frame_length: usize,
+    strip_delimiter: bool,
+    fail_fast: bool,
+    discarding_too_long_frame: bool,
+    too_long_frame_length: usize,
+    // 分隔符是 line_delimiter 时交给 LineBasedFrameDecoder
+    line_based_decoder: Option<LineBasedFrameDecoder>,
+}
+
+impl DelimiterBasedFrameDecoder {
+    ///
+    /// 去掉分隔符, 读到分隔符之后才报告超长
+    ///
+    pub fn new(max_frame_length: usize, delimiters: Vec<Vec<u8>>) -> Self {
+        DelimiterBasedFrameDecoder::new_with_options(max_frame_length, true, false, delimiters)
+    }
+
+    ///
+    /// delimiters 为空或者包含空的分隔符时 panic
+    ///
+    pub fn new_with_options(
+        max_frame_length: usize,
+        strip_delimiter: bool,
+        fail_fast: bool,
+        delimiters: Vec<Vec<u8>>,
+    ) -> Self {
+        if delimiters.is_empty() {
+            panic!("delimiters is empty");
+        }
+        if delimiters.iter().any(|d| d.is_empty()) {
+            panic!("empty delimiter");
+        }
+        let line_based_decoder = if is_line_based(&delimiters) {
+            Some(LineBasedFrameDecoder::new_with_options(
+                max_frame_length,
+                strip_delimiter,
+                fail_fast,
+            ))
+        } else {
+            None
+        };
+        DelimiterBasedFrameDecoder {
+            delimiters,
+            max_frame_length,
+            strip_delimiter,
+            fail_fast,
+            discarding_too_long_frame: false,
+            too_long_frame_length: 0,
+            line_based_decoder,
+        }
+    }
+
+    ///
+    /// 修改一帧的最大长度, 从下一次 decode 开始生效
+    ///
+    pub fn set_max_frame_length(&mut self, max_frame_length: usize) {
+        self.max_frame_length = max_frame_length;
+        if let Some(line_based_decoder) = self.line_based_decoder.as_mut() {
+            line_based_decoder.set_max_length(max_frame_length);
+        }
+    }
+
+    fn fail(&self, channel_handler_ctx: &mut ChannelInboundHandlerCtx, frame_length: usize) {
+        let message = if frame_length > 0 {
+            format!(
+                "frame length exceeds {}: {} - discarded",
+                self.max_frame_length, frame_length
+            )
+        } else {
+            format!(
+                "frame length exceeds {} - discarding",
+                self.max_frame_length
+            )
+        };
+        channel_handler_ctx
+            .fire_channel_exception(RettyErrorKind::codec(CodecErrorKind::TooLongFrame, message));
+    }
+}
+
+fn is_line_based(delimiters: &[Vec<u8>]) -> bool {
+    delimiters.len() == 2
+        && delimiters.contains(&b"\r\n".to_vec())
+        && delimiters.contains(&b"\n".to_vec())
+}
+
+///
+/// delimiter 在 input 中第一次出现的位置
+///
+fn index_of(input: &[u8], delimiter: &[u8]) -> Option<usize> {
+    if input.len() < delimiter.len() {
+        return None;
+    }
+    input.windows(delimiter.len()).position(|w| w == delimiter)
+}
+
+impl ByteToMessageDecoder for DelimiterBasedFrameDecoder {
+    type Message = ByteBuf;
+
+    fn id(&self) -> String {
+        "DelimiterBasedFrameDecoder".to_string()
+    }
+
+    fn decode(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        input: &[u8],
+        out: &mut Vec<ByteBuf>,
+    ) -> Result<usize, RettyErrorKind> {
+        if let Some(line_based_decoder) = self.line_based_decoder.as_mut() {
+            return line_based_decoder.decode(channel_handler_ctx, input, out);
+        }
+
+        // 找拆出的帧最短的分隔符
+        let found = self
+            .delimiters
+            .iter()
+            .filter_map(|d| index_of(input, d).map(|i| (i, d.len())))
+            .min_by_key(|(i, _)| *i);
+
+        match found {
+            Some((frame_length, delim_length)) => {
+                if self.discarding_too_long_frame {
+                    // 丢弃到这个分隔符为止
+                    self.discarding_too_long_frame = false;
+                    let too_long_frame_length = self.too_long_frame_length + frame_length;
+                    self.too_long_frame_length = 0;
+                    if !self.fail_fast {
+                        self.fail(channel_handler_ctx, too_long_frame_length);
+                    }
+                    return Ok(frame_length + delim_length);
+                }
+                if frame_length > self.max_frame_length {
+                    self.fail(channel_handler_ctx, frame_length);
+                    return Ok(frame_length + delim_length);
+                }
+                let frame = if self.strip_delimiter {
+                    &input[..frame_length]
+                } else {
+                    &input[..frame_length + delim_length]
+                };
+                out.push(ByteBuf::new_from(frame));
+                Ok(frame_length + delim_length)
+            }
+            None => {
+                if !self.discarding_too_long_frame {
+                    if input.len() > self.max_frame_length {
+                        // 丢弃到下一个分隔符为止
+                        self.too_long_frame_length = input.len();
+                        self.discarding_too_long_frame = true;
+                        if self.fail_fast {
+                            self.fail(channel_handler_ctx, self.too_long_frame_length);
+                        }
+                        return Ok(input.len());
+                    }
+                    return Ok(0);
+                }
+                self.too_long_frame_length += input.len();
+                Ok(input.len())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::codec::byte_to_message_decoder::ByteToMessageHandler;
+    use crate::channel::embedded_channel::EmbeddedChannel;
+    use crate::channel::handler_pipe::ChannelHandlerPipe;
+
+    fn delimiter_channel(decoder: DelimiterBasedFrameDecoder) -> EmbeddedChannel<ByteBuf> {
+        let mut handler_pipe = ChannelHandlerPipe::new();
+        handler_pipe.add_last_inbound(Box::new(ByteToMessageHandler::new(decoder)));
+        EmbeddedChannel::new(handler_pipe)
+    }
+
+    fn read_frames(channel: &EmbeddedChannel<ByteBuf>) -> Vec<Vec<u8>> {
+        channel
+            .read_all_inbound()
+            .iter()
+            .map(|buf| buf.available_bytes().to_vec())
+            .collect()
+    }
+
+    fn too_long_errors(channel: &EmbeddedChannel<ByteBuf>) -> usize {
+        let errors = channel.take_exceptions();
+        assert!(errors
+            .iter()
+            .all(|e| e.is_codec_error(CodecErrorKind::TooLongFrame)));
+        errors.len()
+    }
+
+    #[test]
+    fn shortest_frame_wins_whatever_the_delimiter_order() {
+        for delimiters in [
+            vec![b"\0".to_vec(), b"XY".to_vec()],
+            vec![b"XY".to_vec(), b"\0".to_vec()],
+        ] {
+            let channel = delimiter_channel(DelimiterBasedFrameDecoder::new(1024, delimiters));
+            channel.write_inbound(b"abXYde\0fg\0hXY");
+            assert_eq!(
+                read_frames(&channel),
+                vec![
+                    b"ab".to_vec(),
+                    b"de".to_vec(),
+                    b"fg".to_vec(),
+                    b"h".to_vec()
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn strips_or_keeps_the_delimiter() {
+        let channel = delimiter_channel(DelimiterBasedFrameDecoder::new_with_options(
+            1024,
+            false,
+            false,
+            vec![b"\0".to_vec(), b"XY".to_vec()],
+        ));
+        channel.write_inbound(b"abXYde\0");
+        assert_eq!(
+            read_frames(&channel),
+            vec![b"abXY".to_vec(), b"de\0".to_vec()]
+        );
+    }
+
+    #[test]
+    fn delimiter_split_across_reads() {
+        let channel =
+            delimiter_channel(DelimiterBasedFrameDecoder::new(1024, vec![b"||".to_vec()]));
+        channel.write_inbound(b"ab|");
+        assert!(read_frames(&channel).is_empty());
+        channel.write_inbound(b"|cd||");
+        assert_eq!(read_frames(&channel), vec![b"ab".to_vec(), b"cd".to_vec()]);
+
+        // 行分隔符交给 LineBasedFrameDecoder
+        let channel = delimiter_channel(DelimiterBasedFrameDecoder::new(1024, line_delimiter()));
+        channel.write_inbound(b"ab\r");
+        channel.write_inbound(b"\ncd\n");
+        assert_eq!(read_frames(&channel), vec![b"ab".to_vec(), b"cd".to_vec()]);
+    }
+
+    #[test]
+    fn too_long_frame_is_reported_at_the_delimiter_without_fail_fast() {
+        let channel = delimiter_channel(DelimiterBasedFrameDecoder::new_with_options(
+            3,
+            true,
+            false,
+            nul_delimiter(),
+        ));
+        channel.write_inbound(b"abcdef");
+        assert_eq!(too_long_errors(&channel), 0);
+        channel.write_inbound(b"gh\0ok\0");
+        assert_eq!(too_long_errors(&channel), 1);
+        assert_eq!(read_frames(&channel), vec![b"ok".to_vec()]);
+
+        // 同一次读到分隔符的超长帧
+        channel.write_inbound(b"abcdef\0ok\0");
+        assert_eq!(too_long_errors(&channel), 1);
+        assert_eq!(read_frames(&channel), vec![b"ok".to_vec()]);
+    }
+
+    #[test]
+    fn too_long_frame_is_reported_immediately_with_fail_fast() {
+        let channel = delimiter_channel(DelimiterBasedFrameDecoder::new_with_options(
+            3,
+            true,
+            true,
+            nul_delimiter(),
+        ));
+        channel.write_inbound(b"abcdef");
+        assert_eq!(too_long_errors(&channel), 1);
+        channel.write_inbound(b"gh\0ok\0");
+        assert_eq!(too_long_errors(&channel), 0);
+        assert_eq!(read_frames(&channel), vec![b"ok".to_vec()]);
+    }
+
+    #[test]
+    #[should_panic(expected = "empty delimiter")]
+    fn rejects_empty_delimiter() {
+        DelimiterBasedFrameDecoder::new(1024, vec![b"\0".to_vec(), vec![]]);
+    }
+}
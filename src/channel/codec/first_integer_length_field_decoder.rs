@@ -1,17 +1,22 @@
 use std::any::Any;
-use std::io::ErrorKind;
-
-use bytebuf_rs::bytebuf::{slice_util, ByteBuf};
 
 use crate::channel::channel_handler_ctx::ChannelInboundHandlerCtx;
+use crate::channel::codec::byte_to_message_decoder::ByteToMessageHandler;
+use crate::channel::codec::length_field_based_frame_decoder::LengthFieldBasedFrameDecoder;
 use crate::channel::handler::ChannelInboundHandler;
 use crate::errors::RettyErrorKind;
 
+const LENGTH_FIELD_LENGTH: usize = 4;
+
 ///
 /// 第一个字段为长度字段的解码器
+/// 长度字段是大端 u32, 值为整帧的长度(包含自己的 4 个字节), 传给下一个handler 的 ByteBuf 包含长度字段
+///
+/// 每个 ByteBuf 正好是一帧, 一次读到多帧时逐帧往下传, 半包留到下一次读
+/// 以前的版本把累积的所有数据作为一个 ByteBuf 往下传, 由下一个handler 自己按长度字段读出每一帧
 ///
 pub struct FirstIntegerLengthFieldDecoder {
-    all_buf: Vec<u8>,
+    handler: ByteToMessageHandler<LengthFieldBasedFrameDecoder>,
 }
 
 impl Default for FirstIntegerLengthFieldDecoder {
@@ -23,7 +28,13 @@ impl Default for FirstIntegerLengthFieldDecoder {
 impl FirstIntegerLengthFieldDecoder {
     pub fn new() -> Self {
         FirstIntegerLengthFieldDecoder {
-            all_buf: Vec::<u8>::new(),
+            handler: ByteToMessageHandler::new(LengthFieldBasedFrameDecoder::new(
+                usize::MAX,
+                0,
+                LENGTH_FIELD_LENGTH,
+                -(LENGTH_FIELD_LENGTH as i64),
+                0,
+            )),
         }
     }
 }
@@ -34,11 +45,11 @@ impl ChannelInboundHandler for FirstIntegerLengthFieldDecoder {
     }
 
     fn channel_active(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
-        channel_handler_ctx.fire_channel_active();
+        self.handler.channel_active(channel_handler_ctx);
     }
 
     fn channel_inactive(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
-        channel_handler_ctx.fire_channel_inactive();
+        self.handler.channel_inactive(channel_handler_ctx);
     }
 
     fn channel_read(
@@ -46,36 +57,7 @@ impl ChannelInboundHandler for FirstIntegerLengthFieldDecoder {
         channel_handler_ctx: &mut ChannelInboundHandlerCtx,
         message: &mut dyn Any,
     ) {
-        let buf_option = message.downcast_ref::<ByteBuf>();
-        if let Some(buf) = buf_option {
-            let n = buf.len();
-            if !self.all_buf.is_empty() {
-                self.all_buf = slice_util::append::<u8>(&self.all_buf, &buf[..n]);
-            } else {
-                self.all_buf = buf[..n].to_owned();
-            }
-            let mut bytebuf = ByteBuf::new_from(&self.all_buf[..]);
-            //处理粘包
-            loop {
-                if bytebuf.readable_bytes() < std::mem::size_of::<u32>() {
-                    return;
-                }
-                bytebuf.mark_reader_index();
-                let pkt_len = bytebuf.read_u32_be();
-                bytebuf.reset_reader_index();
-                if (bytebuf.readable_bytes() as u32) < pkt_len {
-                    return;
-                }
-                channel_handler_ctx.fire_channel_read(&mut bytebuf);
-                if bytebuf.get_reader_index() == bytebuf.get_writer_index() {
-                    self.all_buf = Vec::<u8>::new();
-                    return;
-                }
-            }
-        } else {
-            let err = RettyErrorKind::new(ErrorKind::Other, String::from("decoding error"));
-            channel_handler_ctx.fire_channel_exception(err);
-        }
+        self.handler.channel_read(channel_handler_ctx, message);
     }
 
     fn channel_exception(
@@ -83,6 +65,49 @@ impl ChannelInboundHandler for FirstIntegerLengthFieldDecoder {
         channel_handler_ctx: &mut ChannelInboundHandlerCtx,
         error: RettyErrorKind,
     ) {
-        channel_handler_ctx.fire_channel_exception(error);
+        self.handler.channel_exception(channel_handler_ctx, error);
+    }
+
+    fn handler_removed(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        self.handler.handler_removed(channel_handler_ctx);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytebuf_rs::bytebuf::ByteBuf;
+
+    use super::*;
+    use crate::channel::embedded_channel::EmbeddedChannel;
+    use crate::channel::handler_pipe::ChannelHandlerPipe;
+
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let mut frame = ((payload.len() + LENGTH_FIELD_LENGTH) as u32)
+            .to_be_bytes()
+            .to_vec();
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn forwards_one_frame_at_a_time_including_the_length_field() {
+        let mut handler_pipe = ChannelHandlerPipe::new();
+        handler_pipe.add_last_inbound(Box::new(FirstIntegerLengthFieldDecoder::new()));
+        let channel: EmbeddedChannel<ByteBuf> = EmbeddedChannel::new(handler_pipe);
+
+        let mut bytes = frame(b"hello");
+        bytes.extend_from_slice(&frame(b""));
+        let third = frame(b"world");
+        bytes.extend_from_slice(&third[..6]);
+        channel.write_inbound(&bytes);
+        channel.write_inbound(&third[6..]);
+
+        let frames: Vec<Vec<u8>> = channel
+            .read_all_inbound()
+            .iter()
+            .map(|buf| buf.available_bytes().to_vec())
+            .collect();
+        assert_eq!(frames, vec![frame(b"hello"), frame(b""), third]);
+        assert!(channel.take_exceptions().is_empty());
     }
 }
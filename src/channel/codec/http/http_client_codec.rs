@@ -0,0 +1,453 @@
+use std::any::Any;
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::sync::{Arc, Mutex};
+
+use bytebuf_rs::bytebuf::ByteBuf;
+
+use crate::channel::channel_handler_ctx::{ChannelInboundHandlerCtx, ChannelOutboundHandlerCtx};
+use crate::channel::codec::byte_to_message_decoder::{ByteToMessageDecoder, ByteToMessageHandler};
+use crate::channel::codec::http::http_headers::names;
+use crate::channel::codec::http::http_message::{
+    FullHttpRequest, HttpContent, HttpMethod, HttpObject, HttpRequest, HttpUpgradeEvent,
+    LastHttpContent,
+};
+use crate::channel::codec::http::http_object_decoder::{
+    HttpObjectDecoder, DEFAULT_MAX_CHUNK_SIZE, DEFAULT_MAX_HEADER_SIZE,
+    DEFAULT_MAX_INITIAL_LINE_LENGTH,
+};
+use crate::channel::codec::http::http_object_encoder::HttpObjectEncoder;
+use crate::channel::handler::{ChannelCodec, ChannelInboundHandler, ChannelOutboundHandler};
+use crate::errors::RettyErrorKind;
+
+struct HttpClientCodecState {
+    decoder: HttpObjectDecoder,
+    encoder: HttpObjectEncoder,
+    // 收到的 101 的 Upgrade 头, 用 HttpUpgradeEvent 通知后面的 handler
+    upgrade_protocol: Option<String>,
+}
+
+///
+/// HTTP/1.1 客户端编解码器, 用 ChannelInboundHandlerPipe::add_last_codec 注册
+///
+/// 出站: 编码 HttpRequest / HttpContent / LastHttpContent / FullHttpRequest 和 HttpObject, 其他消息原样往下写
+/// 入站: 把字节解码成 HttpObject, 支持 Content-Length, chunked 和读到连接关闭为止的 body
+///
+/// 响应按请求发送的顺序对应, 同一个连接上可以连续发送多个请求(pipelining), HEAD 请求的响应没有 body
+/// 连接断开时还有请求没有收到响应, 触发 channel_exception
+/// 收到 101 Switching Protocols 之后停止解码并触发 HttpUpgradeEvent, 处理新协议的 handler 应该把 HttpClientCodec 从 pipeline 移除,
+/// 移除时已经读到但没有解码的数据传给原来的下一个handler, 见 WebSocketClientProtocolHandler
+///
+pub struct HttpClientCodec {
+    state: HttpClientCodecState,
+}
+
+impl Default for HttpClientCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HttpClientCodec {
+    pub fn new() -> HttpClientCodec {
+        HttpClientCodec::new_with_limits(
+            DEFAULT_MAX_INITIAL_LINE_LENGTH,
+            DEFAULT_MAX_HEADER_SIZE,
+            DEFAULT_MAX_CHUNK_SIZE,
+        )
+    }
+
+    pub fn new_with_limits(
+        max_initial_line_length: usize,
+        max_header_size: usize,
+        max_chunk_size: usize,
+    ) -> HttpClientCodec {
+        let mut decoder = HttpObjectDecoder::new_with_limits(
+            false,
+            max_initial_line_length,
+            max_header_size,
+            max_chunk_size,
+        );
+        decoder.head_requests = Some(VecDeque::new());
+        HttpClientCodec {
+            state: HttpClientCodecState {
+                decoder,
+                encoder: HttpObjectEncoder::new(),
+                upgrade_protocol: None,
+            },
+        }
+    }
+}
+
+impl ChannelCodec for HttpClientCodec {
+    fn into_handlers(
+        self,
+    ) -> (
+        Box<dyn ChannelInboundHandler + Send + Sync>,
+        Box<dyn ChannelOutboundHandler + Send + Sync>,
+    ) {
+        let state = Arc::new(Mutex::new(self.state));
+        (
+            Box::new(HttpClientCodecDecoder {
+                handler: ByteToMessageHandler::new(state.clone()),
+                state: state.clone(),
+            }),
+            Box::new(HttpClientCodecEncoder { state }),
+        )
+    }
+}
+
+impl ByteToMessageDecoder for HttpClientCodecState {
+    type Message = HttpObject;
+
+    fn id(&self) -> String {
+        "HttpClientCodec".to_string()
+    }
+
+    fn decode(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        input: &[u8],
+        out: &mut Vec<HttpObject>,
+    ) -> Result<usize, RettyErrorKind> {
+        let start = out.len();
+        let ret = self.decoder.decode(channel_handler_ctx, input, out);
+        for object in &out[start..] {
+            if let HttpObject::Response(response) = object {
+                if response.status.code == 101 {
+                    self.upgrade_protocol = Some(
+                        response
+                            .headers
+                            .get(names::UPGRADE)
+                            .unwrap_or_default()
+                            .to_string(),
+                    );
+                }
+            }
+        }
+        ret
+    }
+
+    fn decode_last(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        input: &[u8],
+        out: &mut Vec<HttpObject>,
+    ) -> Result<usize, RettyErrorKind> {
+        self.decoder.decode_last(channel_handler_ctx, input, out)
+    }
+}
+
+impl HttpClientCodecState {
+    ///
+    /// 还没有收到响应的请求个数
+    ///
+    fn missing_responses(&self) -> usize {
+        self.decoder.head_requests.as_ref().map_or(0, |r| r.len())
+    }
+
+    fn encode_request(
+        &mut self,
+        request: &HttpRequest,
+        out: &mut Vec<u8>,
+    ) -> Result<(), RettyErrorKind> {
+        self.encoder.encode_request_head(request, out)?;
+        // 按顺序记录, 解码响应时知道哪个响应没有 body
+        if let Some(head_requests) = self.decoder.head_requests.as_mut() {
+            head_requests.push_back(request.method == HttpMethod::Head);
+        }
+        Ok(())
+    }
+
+    fn encode_full_request(
+        &mut self,
+        request: &mut FullHttpRequest,
+        out: &mut Vec<u8>,
+    ) -> Result<(), RettyErrorKind> {
+        let headers = &mut request.headers;
+        let body_expected = matches!(
+            request.method,
+            HttpMethod::Post | HttpMethod::Put | HttpMethod::Patch
+        );
+        if (body_expected || !request.content.is_empty())
+            && !headers.is_transfer_encoding_chunked()
+            && !headers.contains(names::CONTENT_LENGTH)
+        {
+            headers.set_content_length(request.content.len() as u64);
+        }
+        let head = HttpRequest {
+            method: request.method.clone(),
+            uri: std::mem::take(&mut request.uri),
+            version: request.version,
+            headers: std::mem::take(&mut request.headers),
+        };
+        let ret = self.encode_request(&head, out);
+        request.uri = head.uri;
+        request.headers = head.headers;
+        ret?;
+        self.encoder
+            .encode_last_content(&request.content, &request.trailing_headers, out)
+    }
+
+    fn encode(
+        &mut self,
+        message: &mut dyn Any,
+        out: &mut Vec<u8>,
+    ) -> Option<Result<(), RettyErrorKind>> {
+        let ret = if let Some(object) = message.downcast_ref::<HttpObject>() {
+            match object {
+                HttpObject::Request(request) => self.encode_request(request, out),
+                HttpObject::Content(content) => self.encoder.encode_content(&content.content, out),
+                HttpObject::LastContent(last) => {
+                    self.encoder
+                        .encode_last_content(&last.content, &last.trailing_headers, out)
+                }
+                HttpObject::Response(_) => Err(RettyErrorKind::new(
+                    ErrorKind::InvalidInput,
+                    "HttpClientCodec can not encode a response".to_string(),
+                )),
+            }
+        } else if let Some(request) = message.downcast_mut::<FullHttpRequest>() {
+            self.encode_full_request(request, out)
+        } else if let Some(request) = message.downcast_ref::<HttpRequest>() {
+            self.encode_request(request, out)
+        } else if let Some(content) = message.downcast_ref::<HttpContent>() {
+            self.encoder.encode_content(&content.content, out)
+        } else if let Some(last) = message.downcast_ref::<LastHttpContent>() {
+            self.encoder
+                .encode_last_content(&last.content, &last.trailing_headers, out)
+        } else {
+            return None;
+        };
+        Some(ret)
+    }
+}
+
+struct HttpClientCodecDecoder {
+    handler: ByteToMessageHandler<Arc<Mutex<HttpClientCodecState>>>,
+    state: Arc<Mutex<HttpClientCodecState>>,
+}
+
+impl HttpClientCodecDecoder {
+    fn fire_upgrade_event(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        let protocol = self.state.lock().unwrap().upgrade_protocol.take();
+        if let Some(protocol) = protocol {
+            channel_handler_ctx.fire_user_event(Box::new(HttpUpgradeEvent { protocol }));
+        }
+    }
+}
+
+impl ChannelInboundHandler for HttpClientCodecDecoder {
+    fn id(&self) -> String {
+        "HttpClientCodec".to_string()
+    }
+
+    fn channel_active(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        self.handler.channel_active(channel_handler_ctx);
+    }
+
+    fn channel_inactive(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        self.handler.channel_inactive(channel_handler_ctx);
+        let missing = self.state.lock().unwrap().missing_responses();
+        if missing > 0 {
+            channel_handler_ctx.fire_channel_exception(RettyErrorKind::new(
+                ErrorKind::UnexpectedEof,
+                format!("channel gone inactive with {} missing response(s)", missing),
+            ));
+        }
+    }
+
+    fn channel_read(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        message: &mut dyn Any,
+    ) {
+        self.handler.channel_read(channel_handler_ctx, message);
+        self.fire_upgrade_event(channel_handler_ctx);
+    }
+
+    fn channel_exception(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        error: RettyErrorKind,
+    ) {
+        self.handler.channel_exception(channel_handler_ctx, error);
+    }
+
+    fn handler_removed(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        // 101 响应后面已经读到的数据属于新的协议
+        self.handler.handler_removed(channel_handler_ctx);
+    }
+}
+
+struct HttpClientCodecEncoder {
+    state: Arc<Mutex<HttpClientCodecState>>,
+}
+
+impl ChannelOutboundHandler for HttpClientCodecEncoder {
+    fn id(&self) -> String {
+        "HttpClientCodec".to_string()
+    }
+
+    fn channel_write(
+        &mut self,
+        channel_handler_ctx: &mut ChannelOutboundHandlerCtx,
+        message: &mut dyn Any,
+    ) {
+        let mut out = vec![];
+        let ret = self.state.lock().unwrap().encode(message, &mut out);
+        match ret {
+            Some(Ok(())) => channel_handler_ctx.fire_channel_write(&mut ByteBuf::new_from(&out)),
+            Some(Err(e)) => channel_handler_ctx.channel().fail_write(e),
+            None => channel_handler_ctx.fire_channel_write(message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::codec::http::http_message::HttpVersion;
+    use crate::channel::embedded_channel::EmbeddedChannel;
+    use crate::channel::handler_pipe::ChannelHandlerPipe;
+
+    fn client_channel() -> EmbeddedChannel<HttpObject> {
+        let mut handler_pipe = ChannelHandlerPipe::new();
+        handler_pipe.add_last_codec(HttpClientCodec::new());
+        EmbeddedChannel::new(handler_pipe)
+    }
+
+    fn send(channel: &EmbeddedChannel<HttpObject>, method: HttpMethod, uri: &str) {
+        let mut request = FullHttpRequest::new(HttpVersion::Http11, method, uri, vec![]);
+        channel.write_outbound(&mut request);
+    }
+
+    fn status(object: &HttpObject) -> u16 {
+        match object {
+            HttpObject::Response(response) => response.status.code,
+            other => panic!("expected a response, got {:?}", other),
+        }
+    }
+
+    fn last_content(content: &[u8]) -> HttpObject {
+        HttpObject::LastContent(LastHttpContent::new(content.to_vec()))
+    }
+
+    #[test]
+    fn encodes_requests() {
+        let mut channel = client_channel();
+        let mut request = FullHttpRequest::new(
+            HttpVersion::Http11,
+            HttpMethod::Post,
+            "/echo",
+            b"hi".to_vec(),
+        );
+        request.headers.set("Host", "localhost");
+        channel.write_outbound(&mut request);
+        assert_eq!(
+            channel.read_outbound(),
+            b"POST /echo HTTP/1.1\r\nHost: localhost\r\ncontent-length: 2\r\n\r\nhi".to_vec()
+        );
+    }
+
+    #[test]
+    fn head_response_has_no_body() {
+        let channel = client_channel();
+        send(&channel, HttpMethod::Head, "/");
+        send(&channel, HttpMethod::Get, "/");
+        channel.write_inbound(
+            b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n\
+              HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok",
+        );
+        let messages = channel.read_all_inbound();
+        assert_eq!(messages.len(), 4);
+        assert_eq!(status(&messages[0]), 200);
+        assert_eq!(
+            messages[1],
+            HttpObject::LastContent(LastHttpContent::empty())
+        );
+        assert_eq!(status(&messages[2]), 200);
+        assert_eq!(messages[3], last_content(b"ok"));
+        assert!(channel.take_exceptions().is_empty());
+    }
+
+    #[test]
+    fn no_content_and_not_modified_have_no_body() {
+        let channel = client_channel();
+        for _ in 0..3 {
+            send(&channel, HttpMethod::Get, "/");
+        }
+        channel.write_inbound(
+            b"HTTP/1.1 204 No Content\r\n\r\n\
+              HTTP/1.1 304 Not Modified\r\nContent-Length: 10\r\n\r\n\
+              HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\nabc",
+        );
+        let messages = channel.read_all_inbound();
+        assert_eq!(messages.len(), 6);
+        assert_eq!(status(&messages[0]), 204);
+        assert_eq!(
+            messages[1],
+            HttpObject::LastContent(LastHttpContent::empty())
+        );
+        assert_eq!(status(&messages[2]), 304);
+        assert_eq!(
+            messages[3],
+            HttpObject::LastContent(LastHttpContent::empty())
+        );
+        assert_eq!(status(&messages[4]), 200);
+        assert_eq!(messages[5], last_content(b"abc"));
+    }
+
+    #[test]
+    fn informational_response_before_final_response() {
+        let channel = client_channel();
+        send(&channel, HttpMethod::Head, "/");
+        channel.write_inbound(
+            b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n",
+        );
+        let messages = channel.read_all_inbound();
+        assert_eq!(status(&messages[0]), 100);
+        assert_eq!(status(&messages[2]), 200);
+        // 100 Continue 不占用 HEAD 请求的位置
+        assert_eq!(
+            messages[3],
+            HttpObject::LastContent(LastHttpContent::empty())
+        );
+    }
+
+    #[test]
+    fn close_delimited_body_ends_with_connection() {
+        let channel = client_channel();
+        send(&channel, HttpMethod::Get, "/");
+        channel.write_inbound(b"HTTP/1.0 200 OK\r\n\r\nhello ");
+        channel.write_inbound(b"world");
+        channel.finish();
+        let messages = channel.read_all_inbound();
+        assert_eq!(messages.len(), 4);
+        assert_eq!(
+            messages[1..],
+            [
+                HttpObject::Content(HttpContent::new(b"hello ".to_vec())),
+                HttpObject::Content(HttpContent::new(b"world".to_vec())),
+                HttpObject::LastContent(LastHttpContent::empty()),
+            ]
+        );
+        assert!(channel.take_exceptions().is_empty());
+    }
+
+    #[test]
+    fn missing_responses_on_close() {
+        let channel = client_channel();
+        send(&channel, HttpMethod::Get, "/a");
+        send(&channel, HttpMethod::Get, "/b");
+        channel.write_inbound(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+        channel.finish();
+        let errors = channel.take_exceptions();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, ErrorKind::UnexpectedEof);
+        assert_eq!(
+            errors[0].message,
+            "channel gone inactive with 1 missing response(s)"
+        );
+    }
+}
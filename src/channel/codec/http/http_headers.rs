@@ -0,0 +1,193 @@
+use std::fmt::{Display, Formatter};
+use std::io::ErrorKind;
+
+use crate::errors::{CodecErrorKind, RettyErrorKind};
+
+///
+/// 常用的 header 名字, 比较时不区分大小写
+///
+pub mod names {
+    pub const CONNECTION: &str = "connection";
+    pub const CONTENT_LENGTH: &str = "content-length";
+    pub const CONTENT_TYPE: &str = "content-type";
+    pub const EXPECT: &str = "expect";
+    pub const HOST: &str = "host";
+    pub const SEC_WEBSOCKET_ACCEPT: &str = "sec-websocket-accept";
+    pub const SEC_WEBSOCKET_EXTENSIONS: &str = "sec-websocket-extensions";
+    pub const SEC_WEBSOCKET_KEY: &str = "sec-websocket-key";
+    pub const SEC_WEBSOCKET_PROTOCOL: &str = "sec-websocket-protocol";
+    pub const SEC_WEBSOCKET_VERSION: &str = "sec-websocket-version";
+    pub const TRANSFER_ENCODING: &str = "transfer-encoding";
+    pub const UPGRADE: &str = "upgrade";
+}
+
+///
+/// 常用的 header 值
+///
+pub mod values {
+    pub const CHUNKED: &str = "chunked";
+    pub const CLOSE: &str = "close";
+    pub const CONTINUE: &str = "100-continue";
+    pub const KEEP_ALIVE: &str = "keep-alive";
+    pub const UPGRADE: &str = "upgrade";
+    pub const WEBSOCKET: &str = "websocket";
+}
+
+///
+/// HTTP header, 保留添加的顺序和名字的大小写, 查找时不区分大小写
+///
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct HttpHeaders {
+    entries: Vec<(String, String)>,
+}
+
+impl HttpHeaders {
+    pub fn new() -> HttpHeaders {
+        HttpHeaders { entries: vec![] }
+    }
+
+    pub fn add<N: Into<String>, V: Into<String>>(&mut self, name: N, value: V) -> &mut Self {
+        self.entries.push((name.into(), value.into()));
+        self
+    }
+
+    ///
+    /// 替换同名的所有 header
+    ///
+    pub fn set<N: Into<String>, V: Into<String>>(&mut self, name: N, value: V) -> &mut Self {
+        let name = name.into();
+        self.remove(&name);
+        self.entries.push((name, value.into()));
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.entries
+            .iter()
+            .filter(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    ///
+    /// 同名 header 的值按逗号分隔之后是否包含 value, 不区分大小写, 比如 Connection: keep-alive, Upgrade
+    ///
+    pub fn contains_value(&self, name: &str, value: &str) -> bool {
+        self.get_all(name)
+            .iter()
+            .flat_map(|v| v.split(','))
+            .any(|v| v.trim().eq_ignore_ascii_case(value))
+    }
+
+    pub fn remove(&mut self, name: &str) -> &mut Self {
+        self.entries.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    ///
+    /// 最后一个传输编码是否是 chunked, 只有这时 body 按 chunked 分块, 比如 "gzip, chunked" 是, "chunked, gzip" 不是
+    ///
+    pub fn is_transfer_encoding_chunked(&self) -> bool {
+        self.get_all(names::TRANSFER_ENCODING)
+            .iter()
+            .flat_map(|v| v.split(','))
+            .map(|v| v.trim())
+            .rev()
+            .find(|v| !v.is_empty())
+            .is_some_and(|v| v.eq_ignore_ascii_case(values::CHUNKED))
+    }
+
+    ///
+    /// Content-Length 的值, 没有时返回 None, 不是合法的数字或者多个值不一致时返回 CorruptedFrame 错误
+    ///
+    pub fn content_length(&self) -> Result<Option<u64>, RettyErrorKind> {
+        let mut content_length = None;
+        for value in self
+            .get_all(names::CONTENT_LENGTH)
+            .iter()
+            .flat_map(|v| v.split(','))
+        {
+            let length = value.trim().parse::<u64>().map_err(|_| {
+                RettyErrorKind::codec(
+                    CodecErrorKind::CorruptedFrame,
+                    format!("invalid content-length: {:?}", value),
+                )
+            })?;
+            match content_length {
+                Some(l) if l != length => {
+                    return Err(RettyErrorKind::codec(
+                        CodecErrorKind::CorruptedFrame,
+                        format!("multiple content-length values: {} and {}", l, length),
+                    ))
+                }
+                _ => content_length = Some(length),
+            }
+        }
+        Ok(content_length)
+    }
+
+    pub fn set_content_length(&mut self, length: u64) -> &mut Self {
+        self.set(names::CONTENT_LENGTH, length.to_string())
+    }
+
+    ///
+    /// 按 "name: value\r\n" 写出
+    ///
+    pub(crate) fn encode(&self, out: &mut Vec<u8>) -> Result<(), RettyErrorKind> {
+        for (name, value) in self.iter() {
+            if name.is_empty() || name.bytes().any(|b| b <= b' ' || b == b':' || b >= 0x7F) {
+                return Err(RettyErrorKind::new(
+                    ErrorKind::InvalidInput,
+                    format!("invalid header name: {:?}", name),
+                ));
+            }
+            if value.bytes().any(|b| b == b'\r' || b == b'\n') {
+                return Err(RettyErrorKind::new(
+                    ErrorKind::InvalidInput,
+                    format!("invalid header value for {}: {:?}", name, value),
+                ));
+            }
+            out.extend_from_slice(name.as_bytes());
+            out.extend_from_slice(b": ");
+            out.extend_from_slice(value.as_bytes());
+            out.extend_from_slice(b"\r\n");
+        }
+        Ok(())
+    }
+}
+
+impl Display for HttpHeaders {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (name, value) in self.iter() {
+            writeln!(f, "{}: {}", name, value)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,390 @@
+use std::fmt::{Display, Formatter};
+
+use crate::channel::codec::http::http_headers::{names, values, HttpHeaders};
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum HttpVersion {
+    Http10,
+    Http11,
+}
+
+impl HttpVersion {
+    pub fn parse(s: &str) -> Option<HttpVersion> {
+        match s {
+            "HTTP/1.1" => Some(HttpVersion::Http11),
+            "HTTP/1.0" => Some(HttpVersion::Http10),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HttpVersion::Http10 => "HTTP/1.0",
+            HttpVersion::Http11 => "HTTP/1.1",
+        }
+    }
+
+    ///
+    /// HTTP/1.1 默认长连接, 除非 Connection: close; HTTP/1.0 只有 Connection: keep-alive 时才是长连接
+    ///
+    pub fn is_keep_alive(&self, headers: &HttpHeaders) -> bool {
+        match self {
+            HttpVersion::Http11 => !headers.contains_value(names::CONNECTION, values::CLOSE),
+            HttpVersion::Http10 => headers.contains_value(names::CONNECTION, values::KEEP_ALIVE),
+        }
+    }
+}
+
+impl Display for HttpVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum HttpMethod {
+    Options,
+    Get,
+    Head,
+    Post,
+    Put,
+    Patch,
+    Delete,
+    Trace,
+    Connect,
+    Other(String),
+}
+
+impl HttpMethod {
+    pub fn parse(s: &str) -> HttpMethod {
+        match s {
+            "OPTIONS" => HttpMethod::Options,
+            "GET" => HttpMethod::Get,
+            "HEAD" => HttpMethod::Head,
+            "POST" => HttpMethod::Post,
+            "PUT" => HttpMethod::Put,
+            "PATCH" => HttpMethod::Patch,
+            "DELETE" => HttpMethod::Delete,
+            "TRACE" => HttpMethod::Trace,
+            "CONNECT" => HttpMethod::Connect,
+            other => HttpMethod::Other(other.to_string()),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            HttpMethod::Options => "OPTIONS",
+            HttpMethod::Get => "GET",
+            HttpMethod::Head => "HEAD",
+            HttpMethod::Post => "POST",
+            HttpMethod::Put => "PUT",
+            HttpMethod::Patch => "PATCH",
+            HttpMethod::Delete => "DELETE",
+            HttpMethod::Trace => "TRACE",
+            HttpMethod::Connect => "CONNECT",
+            HttpMethod::Other(method) => method,
+        }
+    }
+}
+
+impl Display for HttpMethod {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct HttpResponseStatus {
+    pub code: u16,
+    pub reason: String,
+}
+
+impl HttpResponseStatus {
+    pub fn new<R: Into<String>>(code: u16, reason: R) -> HttpResponseStatus {
+        HttpResponseStatus {
+            code,
+            reason: reason.into(),
+        }
+    }
+
+    ///
+    /// 使用标准的 reason phrase
+    ///
+    pub fn from_code(code: u16) -> HttpResponseStatus {
+        let reason = match code {
+            100 => "Continue",
+            101 => "Switching Protocols",
+            200 => "OK",
+            201 => "Created",
+            202 => "Accepted",
+            204 => "No Content",
+            206 => "Partial Content",
+            301 => "Moved Permanently",
+            302 => "Found",
+            304 => "Not Modified",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            408 => "Request Timeout",
+            411 => "Length Required",
+            413 => "Request Entity Too Large",
+            414 => "Request-URI Too Long",
+            417 => "Expectation Failed",
+            426 => "Upgrade Required",
+            431 => "Request Header Fields Too Large",
+            500 => "Internal Server Error",
+            501 => "Not Implemented",
+            502 => "Bad Gateway",
+            503 => "Service Unavailable",
+            504 => "Gateway Timeout",
+            505 => "HTTP Version Not Supported",
+            _ => match code / 100 {
+                1 => "Informational",
+                2 => "Success",
+                3 => "Redirection",
+                4 => "Client Error",
+                5 => "Server Error",
+                _ => "Unknown Status",
+            },
+        };
+        HttpResponseStatus::new(code, reason)
+    }
+
+    ///
+    /// 1xx 的响应
+    ///
+    pub fn is_informational(&self) -> bool {
+        self.code / 100 == 1
+    }
+
+    ///
+    /// 1xx / 204 / 304 的响应没有 body
+    ///
+    pub fn is_content_always_empty(&self) -> bool {
+        self.is_informational() || self.code == 204 || self.code == 304
+    }
+}
+
+impl Display for HttpResponseStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.code, self.reason)
+    }
+}
+
+///
+/// 请求行和 header, body 在之后的 HttpContent / LastHttpContent 中
+///
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct HttpRequest {
+    pub method: HttpMethod,
+    pub uri: String,
+    pub version: HttpVersion,
+    pub headers: HttpHeaders,
+}
+
+impl HttpRequest {
+    pub fn new<U: Into<String>>(version: HttpVersion, method: HttpMethod, uri: U) -> HttpRequest {
+        HttpRequest {
+            method,
+            uri: uri.into(),
+            version,
+            headers: HttpHeaders::new(),
+        }
+    }
+
+    pub fn is_keep_alive(&self) -> bool {
+        self.version.is_keep_alive(&self.headers)
+    }
+
+    ///
+    /// HTTP/1.1 的请求带了 Expect: 100-continue
+    ///
+    pub fn is_100_continue_expected(&self) -> bool {
+        self.version == HttpVersion::Http11
+            && self.headers.contains_value(names::EXPECT, values::CONTINUE)
+    }
+}
+
+///
+/// 状态行和 header, body 在之后的 HttpContent / LastHttpContent 中
+///
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct HttpResponse {
+    pub version: HttpVersion,
+    pub status: HttpResponseStatus,
+    pub headers: HttpHeaders,
+}
+
+impl HttpResponse {
+    pub fn new(version: HttpVersion, status: HttpResponseStatus) -> HttpResponse {
+        HttpResponse {
+            version,
+            status,
+            headers: HttpHeaders::new(),
+        }
+    }
+
+    pub fn is_keep_alive(&self) -> bool {
+        self.version.is_keep_alive(&self.headers)
+    }
+}
+
+///
+/// body 的一部分
+///
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct HttpContent {
+    pub content: Vec<u8>,
+}
+
+impl HttpContent {
+    pub fn new(content: Vec<u8>) -> HttpContent {
+        HttpContent { content }
+    }
+}
+
+///
+/// body 的最后一部分, 一个消息结束, chunked 编码时可以带 trailer
+///
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct LastHttpContent {
+    pub content: Vec<u8>,
+    pub trailing_headers: HttpHeaders,
+}
+
+impl LastHttpContent {
+    pub fn new(content: Vec<u8>) -> LastHttpContent {
+        LastHttpContent {
+            content,
+            trailing_headers: HttpHeaders::new(),
+        }
+    }
+
+    pub fn empty() -> LastHttpContent {
+        LastHttpContent::default()
+    }
+}
+
+///
+/// 带完整 body 的响应
+///
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FullHttpResponse {
+    pub version: HttpVersion,
+    pub status: HttpResponseStatus,
+    pub headers: HttpHeaders,
+    pub content: Vec<u8>,
+    pub trailing_headers: HttpHeaders,
+}
+
+impl FullHttpResponse {
+    pub fn new(
+        version: HttpVersion,
+        status: HttpResponseStatus,
+        content: Vec<u8>,
+    ) -> FullHttpResponse {
+        FullHttpResponse {
+            version,
+            status,
+            headers: HttpHeaders::new(),
+            content,
+            trailing_headers: HttpHeaders::new(),
+        }
+    }
+
+    pub fn is_keep_alive(&self) -> bool {
+        self.version.is_keep_alive(&self.headers)
+    }
+
+    ///
+    /// 拆成 HttpResponse 和 LastHttpContent
+    ///
+    pub fn split(self) -> (HttpResponse, LastHttpContent) {
+        (
+            HttpResponse {
+                version: self.version,
+                status: self.status,
+                headers: self.headers,
+            },
+            LastHttpContent {
+                content: self.content,
+                trailing_headers: self.trailing_headers,
+            },
+        )
+    }
+}
+
+///
+/// 带完整 body 的请求
+///
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FullHttpRequest {
+    pub method: HttpMethod,
+    pub uri: String,
+    pub version: HttpVersion,
+    pub headers: HttpHeaders,
+    pub content: Vec<u8>,
+    pub trailing_headers: HttpHeaders,
+}
+
+impl FullHttpRequest {
+    pub fn new<U: Into<String>>(
+        version: HttpVersion,
+        method: HttpMethod,
+        uri: U,
+        content: Vec<u8>,
+    ) -> FullHttpRequest {
+        FullHttpRequest {
+            method,
+            uri: uri.into(),
+            version,
+            headers: HttpHeaders::new(),
+            content,
+            trailing_headers: HttpHeaders::new(),
+        }
+    }
+
+    pub fn is_keep_alive(&self) -> bool {
+        self.version.is_keep_alive(&self.headers)
+    }
+
+    ///
+    /// 拆成 HttpRequest 和 LastHttpContent
+    ///
+    pub fn split(self) -> (HttpRequest, LastHttpContent) {
+        (
+            HttpRequest {
+                method: self.method,
+                uri: self.uri,
+                version: self.version,
+                headers: self.headers,
+            },
+            LastHttpContent {
+                content: self.content,
+                trailing_headers: self.trailing_headers,
+            },
+        )
+    }
+}
+
+///
+/// HTTP 解码器传给下一个handler 的消息, 一个请求(响应) 依次是 Request(Response), 零个或多个 Content, 一个 LastContent
+///
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum HttpObject {
+    Request(HttpRequest),
+    Response(HttpResponse),
+    Content(HttpContent),
+    LastContent(LastHttpContent),
+}
+
+///
+/// HttpServerCodec 写完 101 Switching Protocols 或者 HttpClientCodec 收到 101 之后, 通过 user_event_triggered 传给后面的 handler,
+/// protocol 是 Upgrade 头的值, 之后编解码器不再解码 HTTP, 处理新协议的 handler 把它从 pipeline 移除之后收到剩下的数据
+///
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct HttpUpgradeEvent {
+    pub protocol: String,
+}
@@ -0,0 +1,428 @@
+use std::any::Any;
+use std::io::ErrorKind;
+
+use crate::channel::channel_handler_ctx::ChannelInboundHandlerCtx;
+use crate::channel::codec::http::http_headers::{names, values, HttpHeaders};
+use crate::channel::codec::http::http_message::{
+    FullHttpRequest, FullHttpResponse, HttpObject, HttpRequest, HttpResponse, HttpResponseStatus,
+    HttpVersion, LastHttpContent,
+};
+use crate::channel::handler::ChannelInboundHandler;
+use crate::errors::{CodecErrorKind, RettyErrorKind};
+
+///
+/// 正在聚合的消息
+///
+enum AggregatingMessage {
+    Request(FullHttpRequest),
+    Response(FullHttpResponse),
+}
+
+impl AggregatingMessage {
+    fn content_mut(&mut self) -> &mut Vec<u8> {
+        match self {
+            AggregatingMessage::Request(request) => &mut request.content,
+            AggregatingMessage::Response(response) => &mut response.content,
+        }
+    }
+}
+
+///
+/// 把 HttpObject 聚合成 FullHttpRequest / FullHttpResponse 再传给下一个handler, 其他消息原样往下传
+/// 加在 HttpServerCodec (HttpClientCodec) 后面
+///
+/// 请求带了 Expect: 100-continue 时先回复 100 Continue, body 会超过 max_content_length 时回复 413 并忽略这个请求的 body
+/// 其他的 Expect 回复 417
+/// 请求的 body 超过 max_content_length 时回复 413, 请求不是长连接时写完之后关闭连接
+/// 响应的 body 超过 max_content_length 时触发 TooLongFrame 的 channel_exception 并关闭连接
+///
+pub struct HttpObjectAggregator {
+    max_content_length: usize,
+    current: Option<AggregatingMessage>,
+    // 回复了 413 / 417 之后丢弃这个消息剩下的 body
+    ignoring_content: bool,
+}
+
+impl HttpObjectAggregator {
+    pub fn new(max_content_length: usize) -> HttpObjectAggregator {
+        HttpObjectAggregator {
+            max_content_length,
+            current: None,
+            ignoring_content: false,
+        }
+    }
+
+    pub fn max_content_length(&self) -> usize {
+        self.max_content_length
+    }
+
+    ///
+    /// 收到新的请求(响应) 时上一个还没有聚合完, 丢弃上一个
+    ///
+    fn start_message(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        self.ignoring_content = false;
+        if self.current.take().is_some() {
+            channel_handler_ctx.fire_channel_exception(RettyErrorKind::new(
+                ErrorKind::InvalidData,
+                "start of new message received before the previous message is complete".to_string(),
+            ));
+        }
+    }
+
+    fn is_content_length_invalid(&self, headers: &HttpHeaders) -> bool {
+        match headers.content_length() {
+            Ok(Some(length)) => length > self.max_content_length as u64,
+            _ => false,
+        }
+    }
+
+    fn on_request(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        mut request: HttpRequest,
+    ) {
+        if request.is_100_continue_expected() {
+            if self.is_content_length_invalid(&request.headers) {
+                self.ignoring_content = true;
+                let mut response = FullHttpResponse::new(
+                    request.version,
+                    HttpResponseStatus::from_code(413),
+                    vec![],
+                );
+                response.headers.set_content_length(0);
+                channel_handler_ctx.write_and_flush(&mut response);
+                return;
+            }
+            let mut response =
+                FullHttpResponse::new(request.version, HttpResponseStatus::from_code(100), vec![]);
+            channel_handler_ctx.write_and_flush(&mut response);
+            request.headers.remove(names::EXPECT);
+        } else if request.version == HttpVersion::Http11 && request.headers.contains(names::EXPECT)
+        {
+            self.ignoring_content = true;
+            let mut response =
+                FullHttpResponse::new(request.version, HttpResponseStatus::from_code(417), vec![]);
+            response.headers.set_content_length(0);
+            channel_handler_ctx.write_and_flush(&mut response);
+            return;
+        } else if self.is_content_length_invalid(&request.headers) {
+            self.on_oversized_request(channel_handler_ctx, &request, false);
+            return;
+        }
+
+        self.current = Some(AggregatingMessage::Request(FullHttpRequest {
+            method: request.method,
+            uri: request.uri,
+            version: request.version,
+            headers: request.headers,
+            content: vec![],
+            trailing_headers: HttpHeaders::new(),
+        }));
+    }
+
+    fn on_response(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        response: HttpResponse,
+    ) {
+        if self.is_content_length_invalid(&response.headers) {
+            self.on_oversized_response(channel_handler_ctx);
+            return;
+        }
+        self.current = Some(AggregatingMessage::Response(FullHttpResponse {
+            version: response.version,
+            status: response.status,
+            headers: response.headers,
+            content: vec![],
+            trailing_headers: HttpHeaders::new(),
+        }));
+    }
+
+    ///
+    /// 追加 body, 超过 max_content_length 时返回 false
+    ///
+    fn append_content(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        content: &[u8],
+        last: bool,
+    ) -> bool {
+        let oversized = match self.current.as_mut() {
+            Some(current) => {
+                let buf = current.content_mut();
+                if buf.len() + content.len() > self.max_content_length {
+                    true
+                } else {
+                    buf.extend_from_slice(content);
+                    false
+                }
+            }
+            // 没有对应的请求(响应), 丢弃
+            None => return false,
+        };
+        if oversized {
+            match self.current.take() {
+                Some(AggregatingMessage::Request(request)) => {
+                    let (request, _) = request.split();
+                    self.on_oversized_request(channel_handler_ctx, &request, last);
+                }
+                Some(AggregatingMessage::Response(_)) => {
+                    self.on_oversized_response(channel_handler_ctx)
+                }
+                None => {}
+            }
+            return false;
+        }
+        true
+    }
+
+    fn on_oversized_request(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        request: &HttpRequest,
+        last: bool,
+    ) {
+        self.current = None;
+        self.ignoring_content = !last;
+        let mut response =
+            FullHttpResponse::new(request.version, HttpResponseStatus::from_code(413), vec![]);
+        response.headers.set_content_length(0);
+        if !request.is_100_continue_expected() && !request.is_keep_alive() {
+            response.headers.set(names::CONNECTION, values::CLOSE);
+            channel_handler_ctx
+                .write_and_flush(&mut response)
+                .add_close_listener();
+        } else {
+            channel_handler_ctx
+                .write_and_flush(&mut response)
+                .add_close_on_failure_listener();
+        }
+    }
+
+    fn on_oversized_response(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        self.current = None;
+        self.ignoring_content = true;
+        channel_handler_ctx.fire_channel_exception(RettyErrorKind::codec(
+            CodecErrorKind::TooLongFrame,
+            format!(
+                "response entity too large, content length exceeds {}",
+                self.max_content_length
+            ),
+        ));
+        channel_handler_ctx.close();
+    }
+
+    fn on_last_content(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        last: LastHttpContent,
+    ) {
+        if !self.append_content(channel_handler_ctx, &last.content, true) {
+            return;
+        }
+        match self.current.take() {
+            Some(AggregatingMessage::Request(mut request)) => {
+                request.trailing_headers = last.trailing_headers;
+                finish_headers(&mut request.headers, request.content.len());
+                channel_handler_ctx.fire_channel_read(&mut request);
+            }
+            Some(AggregatingMessage::Response(mut response)) => {
+                response.trailing_headers = last.trailing_headers;
+                finish_headers(&mut response.headers, response.content.len());
+                channel_handler_ctx.fire_channel_read(&mut response);
+            }
+            None => {}
+        }
+    }
+}
+
+///
+/// 聚合之后 body 是完整的, 去掉 chunked 改成 Content-Length
+/// 已经有 Content-Length 时不改, 比如 HEAD 请求的响应
+///
+fn finish_headers(headers: &mut HttpHeaders, content_length: usize) {
+    if headers.is_transfer_encoding_chunked() {
+        headers.remove(names::TRANSFER_ENCODING);
+        headers.set_content_length(content_length as u64);
+    } else if !headers.contains(names::CONTENT_LENGTH) {
+        headers.set_content_length(content_length as u64);
+    }
+}
+
+impl ChannelInboundHandler for HttpObjectAggregator {
+    fn id(&self) -> String {
+        "HttpObjectAggregator".to_string()
+    }
+
+    fn channel_active(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        channel_handler_ctx.fire_channel_active();
+    }
+
+    fn channel_inactive(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        self.current = None;
+        self.ignoring_content = false;
+        channel_handler_ctx.fire_channel_inactive();
+    }
+
+    fn channel_read(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        message: &mut dyn Any,
+    ) {
+        let object = match message.downcast_mut::<HttpObject>() {
+            Some(object) => object,
+            None => {
+                channel_handler_ctx.fire_channel_read(message);
+                return;
+            }
+        };
+        match object {
+            HttpObject::Request(request) => {
+                self.start_message(channel_handler_ctx);
+                self.on_request(channel_handler_ctx, request.clone());
+            }
+            HttpObject::Response(response) => {
+                self.start_message(channel_handler_ctx);
+                self.on_response(channel_handler_ctx, response.clone());
+            }
+            HttpObject::Content(content) => {
+                if !self.ignoring_content {
+                    self.append_content(channel_handler_ctx, &content.content, false);
+                }
+            }
+            HttpObject::LastContent(last) => {
+                if self.ignoring_content {
+                    self.ignoring_content = false;
+                } else {
+                    self.on_last_content(channel_handler_ctx, std::mem::take(last));
+                }
+            }
+        }
+    }
+
+    fn channel_exception(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        error: RettyErrorKind,
+    ) {
+        channel_handler_ctx.fire_channel_exception(error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::codec::http::http_client_codec::HttpClientCodec;
+    use crate::channel::codec::http::http_message::HttpMethod;
+    use crate::channel::codec::http::http_server_codec::HttpServerCodec;
+    use crate::channel::embedded_channel::EmbeddedChannel;
+    use crate::channel::handler_pipe::ChannelHandlerPipe;
+
+    fn server_channel(max_content_length: usize) -> EmbeddedChannel<FullHttpRequest> {
+        let mut handler_pipe = ChannelHandlerPipe::new();
+        handler_pipe.add_last_codec(HttpServerCodec::new());
+        handler_pipe.add_last_inbound(Box::new(HttpObjectAggregator::new(max_content_length)));
+        EmbeddedChannel::new(handler_pipe)
+    }
+
+    #[test]
+    fn aggregates_chunked_request() {
+        let channel = server_channel(1024);
+        channel
+            .write_inbound(b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n");
+        assert!(channel.read_inbound().is_none());
+        channel.write_inbound(b"2\r\nde\r\n0\r\nX-Trailer: 1\r\n\r\n");
+        let request = channel.read_inbound().unwrap();
+        assert_eq!(request.method, HttpMethod::Post);
+        assert_eq!(request.content, b"abcde");
+        assert_eq!(request.headers.content_length(), Ok(Some(5)));
+        assert!(!request.headers.is_transfer_encoding_chunked());
+        assert_eq!(request.trailing_headers.get("X-Trailer"), Some("1"));
+    }
+
+    #[test]
+    fn continue_before_body() {
+        let mut channel = server_channel(1024);
+        channel
+            .write_inbound(b"PUT /a HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n");
+        assert_eq!(
+            channel.read_outbound(),
+            b"HTTP/1.1 100 Continue\r\n\r\n".to_vec()
+        );
+        assert!(channel.read_inbound().is_none());
+        channel.write_inbound(b"hello");
+        let request = channel.read_inbound().unwrap();
+        assert_eq!(request.content, b"hello");
+        assert!(!request.headers.contains(names::EXPECT));
+    }
+
+    #[test]
+    fn expect_continue_with_too_large_content() {
+        let mut channel = server_channel(4);
+        channel
+            .write_inbound(b"PUT /a HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n");
+        let response = channel.read_outbound();
+        assert!(response.starts_with(b"HTTP/1.1 413 Request Entity Too Large\r\n"));
+        assert!(channel.read_inbound().is_none());
+        // 客户端收到 413 之后不会发送 body, 直接发下一个请求
+        channel.write_inbound(b"GET /b HTTP/1.1\r\n\r\n");
+        let request = channel.read_inbound().unwrap();
+        assert_eq!(request.uri, "/b");
+        assert!(channel.take_exceptions().is_empty());
+    }
+
+    #[test]
+    fn too_large_content_length() {
+        let mut channel = server_channel(4);
+        channel.write_inbound(b"POST /a HTTP/1.1\r\nContent-Length: 10\r\n\r\n0123456789");
+        let response = channel.read_outbound();
+        assert!(response.starts_with(b"HTTP/1.1 413 Request Entity Too Large\r\n"));
+        assert!(!String::from_utf8_lossy(&response).contains("close"));
+        assert!(channel.read_inbound().is_none());
+        // 长连接上后面的请求照常处理
+        channel.write_inbound(b"POST /b HTTP/1.1\r\nContent-Length: 2\r\n\r\nok");
+        assert_eq!(channel.read_inbound().unwrap().content, b"ok");
+    }
+
+    #[test]
+    fn too_large_chunked_content_closes_connection() {
+        let mut channel = server_channel(4);
+        channel.write_inbound(
+            b"POST /a HTTP/1.0\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n3\r\ndef\r\n",
+        );
+        let response = String::from_utf8(channel.read_outbound()).unwrap();
+        assert!(response.starts_with("HTTP/1.0 413 Request Entity Too Large\r\n"));
+        assert!(response.contains("connection: close\r\n"));
+        assert!(channel.read_inbound().is_none());
+    }
+
+    #[test]
+    fn unsupported_expectation() {
+        let mut channel = server_channel(1024);
+        channel
+            .write_inbound(b"POST /a HTTP/1.1\r\nExpect: something\r\nContent-Length: 2\r\n\r\n");
+        let response = channel.read_outbound();
+        assert!(response.starts_with(b"HTTP/1.1 417 Expectation Failed\r\n"));
+        channel.write_inbound(b"GET /b HTTP/1.1\r\n\r\n");
+        assert_eq!(channel.read_inbound().unwrap().uri, "/b");
+    }
+
+    #[test]
+    fn too_large_response() {
+        let mut handler_pipe = ChannelHandlerPipe::new();
+        handler_pipe.add_last_codec(HttpClientCodec::new());
+        handler_pipe.add_last_inbound(Box::new(HttpObjectAggregator::new(4)));
+        let channel = EmbeddedChannel::<FullHttpResponse>::new(handler_pipe);
+        channel.write_outbound(&mut FullHttpRequest::new(
+            HttpVersion::Http11,
+            HttpMethod::Get,
+            "/",
+            vec![],
+        ));
+        channel.write_inbound(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello");
+        assert!(channel.read_inbound().is_none());
+        let errors = channel.take_exceptions();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].is_codec_error(CodecErrorKind::TooLongFrame));
+    }
+}
@@ -0,0 +1,792 @@
+use std::collections::VecDeque;
+
+use crate::channel::channel_handler_ctx::ChannelInboundHandlerCtx;
+use crate::channel::codec::byte_to_message_decoder::ByteToMessageDecoder;
+use crate::channel::codec::charset::Charset;
+use crate::channel::codec::http::http_headers::{names, HttpHeaders};
+use crate::channel::codec::http::http_message::{
+    HttpContent, HttpMethod, HttpObject, HttpRequest, HttpResponse, HttpResponseStatus,
+    HttpVersion, LastHttpContent,
+};
+use crate::errors::{CodecErrorKind, RettyErrorKind};
+
+pub const DEFAULT_MAX_INITIAL_LINE_LENGTH: usize = 4096;
+pub const DEFAULT_MAX_HEADER_SIZE: usize = 8192;
+pub const DEFAULT_MAX_CHUNK_SIZE: usize = 8192;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum State {
+    SkipControlChars,
+    ReadInitial,
+    ReadHeader,
+    ReadFixedLengthContent(u64),
+    // 没有 Content-Length 的响应, body 读到连接关闭为止
+    ReadVariableLengthContent,
+    ReadChunkSize,
+    ReadChunkedContent(u64),
+    ReadChunkDelimiter,
+    ReadChunkFooter,
+    // 101 Switching Protocols 之后的数据不是 HTTP, 留给新协议的 handler
+    Upgraded,
+    // 解析出错之后丢弃所有数据
+    BadMessage,
+}
+
+///
+/// HTTP/1.x 请求(响应)解码器, 依次解出 HttpObject::Request(Response), 零个或多个 Content, 一个 LastContent
+///
+/// 支持 Content-Length 和 chunked 编码的 body, 同一个连接上连续发来的多个请求按顺序解码
+/// 请求行(状态行) 超过 max_initial_line_length, header 超过 max_header_size 时触发 TooLongFrame 的 channel_exception,
+/// 格式错误时触发 CorruptedFrame, 之后这个连接上的数据全部丢弃
+/// body 按不超过 max_chunk_size 的大小分成多个 Content
+/// 解码出 101 响应(或者 HttpServerCodec 写出 101) 之后停止解码, 剩下的数据在 ByteToMessageHandler 移除时往下传
+///
+pub struct HttpObjectDecoder {
+    decoding_request: bool,
+    max_initial_line_length: usize,
+    max_header_size: usize,
+    max_chunk_size: usize,
+    state: State,
+    // 已经解析出请求行(状态行), 还在等 header
+    message: Option<HttpObject>,
+    // 解码响应时, 按顺序记录还没有收到响应的请求是否是 HEAD, 由 HttpClientCodec 维护
+    pub(crate) head_requests: Option<VecDeque<bool>>,
+}
+
+impl HttpObjectDecoder {
+    pub fn new_request_decoder() -> HttpObjectDecoder {
+        HttpObjectDecoder::new_with_limits(
+            true,
+            DEFAULT_MAX_INITIAL_LINE_LENGTH,
+            DEFAULT_MAX_HEADER_SIZE,
+            DEFAULT_MAX_CHUNK_SIZE,
+        )
+    }
+
+    pub fn new_response_decoder() -> HttpObjectDecoder {
+        HttpObjectDecoder::new_with_limits(
+            false,
+            DEFAULT_MAX_INITIAL_LINE_LENGTH,
+            DEFAULT_MAX_HEADER_SIZE,
+            DEFAULT_MAX_CHUNK_SIZE,
+        )
+    }
+
+    pub fn new_with_limits(
+        decoding_request: bool,
+        max_initial_line_length: usize,
+        max_header_size: usize,
+        max_chunk_size: usize,
+    ) -> HttpObjectDecoder {
+        HttpObjectDecoder {
+            decoding_request,
+            max_initial_line_length: max_initial_line_length.max(1),
+            max_header_size: max_header_size.max(1),
+            max_chunk_size: max_chunk_size.max(1),
+            state: State::SkipControlChars,
+            message: None,
+            head_requests: None,
+        }
+    }
+
+    ///
+    /// 拒绝了 Expect: 100-continue 的请求之后客户端不会再发送 body, 跳过还没有读到的 body 直接读下一个消息
+    ///
+    pub(crate) fn skip_content(&mut self) {
+        match self.state {
+            State::ReadFixedLengthContent(_)
+            | State::ReadVariableLengthContent
+            | State::ReadChunkSize => self.state = State::SkipControlChars,
+            _ => {}
+        }
+    }
+
+    ///
+    /// 发出了 101 Switching Protocols, 之后不再按 HTTP 解码
+    ///
+    pub(crate) fn upgrade(&mut self) {
+        if self.state != State::BadMessage {
+            self.state = State::Upgraded;
+        }
+    }
+
+    fn too_long(&mut self, message: String) -> RettyErrorKind {
+        self.state = State::BadMessage;
+        RettyErrorKind::codec(CodecErrorKind::TooLongFrame, message)
+    }
+
+    fn corrupted(&mut self, message: String) -> RettyErrorKind {
+        self.state = State::BadMessage;
+        RettyErrorKind::codec(CodecErrorKind::CorruptedFrame, message)
+    }
+
+    fn parse_initial_line(&mut self, line: &str) -> Result<HttpObject, RettyErrorKind> {
+        if self.decoding_request {
+            let parts = line.split_ascii_whitespace().collect::<Vec<&str>>();
+            if parts.len() != 3 {
+                return Err(self.corrupted(format!("invalid request line: {:?}", line)));
+            }
+            let version = match HttpVersion::parse(parts[2]) {
+                Some(version) => version,
+                None => {
+                    return Err(self.corrupted(format!("unsupported HTTP version: {}", parts[2])))
+                }
+            };
+            Ok(HttpObject::Request(HttpRequest::new(
+                version,
+                HttpMethod::parse(parts[0]),
+                parts[1],
+            )))
+        } else {
+            let mut parts = line.trim().splitn(3, ' ');
+            let version = parts.next().and_then(HttpVersion::parse);
+            let code = parts.next().and_then(|code| code.parse::<u16>().ok());
+            match (version, code) {
+                (Some(version), Some(code)) if (100..1000).contains(&code) => {
+                    let reason = parts.next().unwrap_or("").trim();
+                    Ok(HttpObject::Response(HttpResponse::new(
+                        version,
+                        HttpResponseStatus::new(code, reason),
+                    )))
+                }
+                _ => Err(self.corrupted(format!("invalid status line: {:?}", line))),
+            }
+        }
+    }
+
+    ///
+    /// 读到空行为止的所有 header, 数据不够时返回 None
+    ///
+    fn read_headers(
+        &mut self,
+        input: &[u8],
+    ) -> Result<Option<(HttpHeaders, usize)>, RettyErrorKind> {
+        let mut headers = HttpHeaders::new();
+        let mut last: Option<(String, String)> = None;
+        let mut offset = 0;
+        loop {
+            let (line, consumed) = match read_line(&input[offset..]) {
+                Some(line) => line,
+                None => {
+                    if input.len() > self.max_header_size {
+                        return Err(self.too_long(format!(
+                            "HTTP header is larger than {} bytes",
+                            self.max_header_size
+                        )));
+                    }
+                    return Ok(None);
+                }
+            };
+            offset += consumed;
+            if offset > self.max_header_size {
+                return Err(self.too_long(format!(
+                    "HTTP header is larger than {} bytes",
+                    self.max_header_size
+                )));
+            }
+            if line.is_empty() {
+                if let Some((name, value)) = last.take() {
+                    headers.add(name, value);
+                }
+                return Ok(Some((headers, offset)));
+            }
+            let line = latin1(line);
+            if line.starts_with(' ') || line.starts_with('\t') {
+                // 折叠到上一个 header 的值
+                match last.as_mut() {
+                    Some((_, value)) => {
+                        value.push(' ');
+                        value.push_str(line.trim());
+                    }
+                    None => return Err(self.corrupted(format!("invalid header: {:?}", line))),
+                }
+                continue;
+            }
+            if let Some((name, value)) = last.take() {
+                headers.add(name, value);
+            }
+            match line.find(':') {
+                Some(i) if i > 0 && !line[..i].contains(|c: char| c.is_ascii_whitespace()) => {
+                    last = Some((line[..i].to_string(), line[i + 1..].trim().to_string()));
+                }
+                _ => return Err(self.corrupted(format!("invalid header: {:?}", line))),
+            }
+        }
+    }
+
+    ///
+    /// header 读完之后决定怎么读 body
+    ///
+    fn next_state(&mut self, message: &mut HttpObject) -> Result<State, RettyErrorKind> {
+        let (headers, content_always_empty) = match message {
+            HttpObject::Request(request) => (&mut request.headers, false),
+            HttpObject::Response(response) => {
+                // 101 之后不会再有这个请求的响应
+                let head_request =
+                    if response.status.is_informational() && response.status.code != 101 {
+                        false
+                    } else {
+                        self.head_requests
+                            .as_mut()
+                            .and_then(|r| r.pop_front())
+                            .unwrap_or(false)
+                    };
+                if response.status.code == 101 {
+                    return Ok(State::Upgraded);
+                }
+                (
+                    &mut response.headers,
+                    head_request || response.status.is_content_always_empty(),
+                )
+            }
+            _ => unreachable!(),
+        };
+        if content_always_empty {
+            return Ok(State::SkipControlChars);
+        }
+        if headers.is_transfer_encoding_chunked() {
+            // 同时有 chunked 和 Content-Length 时以 chunked 为准
+            headers.remove(names::CONTENT_LENGTH);
+            return Ok(State::ReadChunkSize);
+        }
+        if headers.contains(names::TRANSFER_ENCODING) {
+            // 最后一个传输编码不是 chunked 时请求没法确定 body 的长度, 按 Content-Length 读会被用来夹带请求, RFC 7230 3.3.3
+            if self.decoding_request {
+                let message = format!(
+                    "transfer-encoding does not end with chunked: {:?}",
+                    headers.get_all(names::TRANSFER_ENCODING)
+                );
+                return Err(self.corrupted(message));
+            }
+            // 响应读到连接关闭为止
+            headers.remove(names::CONTENT_LENGTH);
+            return Ok(State::ReadVariableLengthContent);
+        }
+        match headers.content_length() {
+            Ok(Some(0)) => Ok(State::SkipControlChars),
+            Ok(Some(length)) => Ok(State::ReadFixedLengthContent(length)),
+            Ok(None) if self.decoding_request => Ok(State::SkipControlChars),
+            Ok(None) => Ok(State::ReadVariableLengthContent),
+            Err(e) => {
+                self.state = State::BadMessage;
+                Err(e)
+            }
+        }
+    }
+
+    fn read_content(&self, input: &[u8], remaining: u64) -> usize {
+        (input.len() as u64)
+            .min(remaining)
+            .min(self.max_chunk_size as u64) as usize
+    }
+}
+
+///
+/// 一行(不包含行尾) 和包含行尾的长度, 没有读到 '\n' 时返回 None
+///
+fn read_line(input: &[u8]) -> Option<(&[u8], usize)> {
+    let i = input.iter().position(|b| *b == b'\n')?;
+    let line = if i > 0 && input[i - 1] == b'\r' {
+        &input[..i - 1]
+    } else {
+        &input[..i]
+    };
+    Some((line, i + 1))
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| char::from(*b)).collect()
+}
+
+impl ByteToMessageDecoder for HttpObjectDecoder {
+    type Message = HttpObject;
+
+    fn id(&self) -> String {
+        if self.decoding_request {
+            "HttpRequestDecoder".to_string()
+        } else {
+            "HttpResponseDecoder".to_string()
+        }
+    }
+
+    fn decode(
+        &mut self,
+        _channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        input: &[u8],
+        out: &mut Vec<HttpObject>,
+    ) -> Result<usize, RettyErrorKind> {
+        let mut offset = 0;
+        loop {
+            let remaining = &input[offset..];
+            match self.state {
+                State::SkipControlChars => {
+                    let n = remaining
+                        .iter()
+                        .take_while(|b| b.is_ascii_whitespace() || b.is_ascii_control())
+                        .count();
+                    offset += n;
+                    if offset == input.len() {
+                        return Ok(offset);
+                    }
+                    self.state = State::ReadInitial;
+                }
+                State::ReadInitial => {
+                    let (line, consumed) = match read_line(remaining) {
+                        Some(line) => line,
+                        None => {
+                            if remaining.len() > self.max_initial_line_length {
+                                return Err(self.too_long(format!(
+                                    "An HTTP line is larger than {} bytes",
+                                    self.max_initial_line_length
+                                )));
+                            }
+                            return Ok(offset);
+                        }
+                    };
+                    if line.len() > self.max_initial_line_length {
+                        return Err(self.too_long(format!(
+                            "An HTTP line is larger than {} bytes",
+                            self.max_initial_line_length
+                        )));
+                    }
+                    let line = Charset::Latin1.decode(line, false)?;
+                    self.message = Some(self.parse_initial_line(&line)?);
+                    offset += consumed;
+                    self.state = State::ReadHeader;
+                }
+                State::ReadHeader => {
+                    let (headers, consumed) = match self.read_headers(remaining)? {
+                        Some(headers) => headers,
+                        None => return Ok(offset),
+                    };
+                    offset += consumed;
+                    let mut message = self.message.take().unwrap();
+                    match &mut message {
+                        HttpObject::Request(request) => request.headers = headers,
+                        HttpObject::Response(response) => response.headers = headers,
+                        _ => unreachable!(),
+                    }
+                    let next_state = self.next_state(&mut message)?;
+                    out.push(message);
+                    if next_state == State::SkipControlChars || next_state == State::Upgraded {
+                        out.push(HttpObject::LastContent(LastHttpContent::empty()));
+                    }
+                    self.state = next_state;
+                    return Ok(offset);
+                }
+                State::ReadFixedLengthContent(length) => {
+                    let n = self.read_content(remaining, length);
+                    if n == 0 {
+                        return Ok(offset);
+                    }
+                    let content = remaining[..n].to_vec();
+                    if length == n as u64 {
+                        out.push(HttpObject::LastContent(LastHttpContent::new(content)));
+                        self.state = State::SkipControlChars;
+                    } else {
+                        out.push(HttpObject::Content(HttpContent::new(content)));
+                        self.state = State::ReadFixedLengthContent(length - n as u64);
+                    }
+                    return Ok(offset + n);
+                }
+                State::ReadVariableLengthContent => {
+                    let n = self.read_content(remaining, u64::MAX);
+                    if n > 0 {
+                        out.push(HttpObject::Content(HttpContent::new(
+                            remaining[..n].to_vec(),
+                        )));
+                    }
+                    return Ok(offset + n);
+                }
+                State::ReadChunkSize => {
+                    let (line, consumed) = match read_line(remaining) {
+                        Some(line) => line,
+                        None => {
+                            if remaining.len() > self.max_initial_line_length {
+                                return Err(self.too_long(format!(
+                                    "An HTTP line is larger than {} bytes",
+                                    self.max_initial_line_length
+                                )));
+                            }
+                            return Ok(offset);
+                        }
+                    };
+                    let line = latin1(line);
+                    // 忽略 chunk extension
+                    let size = line.split(';').next().unwrap_or("").trim();
+                    let size = match u64::from_str_radix(size, 16) {
+                        Ok(size) => size,
+                        Err(_) => {
+                            return Err(self.corrupted(format!("invalid chunk size: {:?}", line)))
+                        }
+                    };
+                    offset += consumed;
+                    self.state = if size == 0 {
+                        State::ReadChunkFooter
+                    } else {
+                        State::ReadChunkedContent(size)
+                    };
+                }
+                State::ReadChunkedContent(size) => {
+                    let n = self.read_content(remaining, size);
+                    if n == 0 {
+                        return Ok(offset);
+                    }
+                    out.push(HttpObject::Content(HttpContent::new(
+                        remaining[..n].to_vec(),
+                    )));
+                    self.state = if size == n as u64 {
+                        State::ReadChunkDelimiter
+                    } else {
+                        State::ReadChunkedContent(size - n as u64)
+                    };
+                    return Ok(offset + n);
+                }
+                State::ReadChunkDelimiter => match read_line(remaining) {
+                    Some((_, consumed)) => {
+                        offset += consumed;
+                        self.state = State::ReadChunkSize;
+                    }
+                    None => return Ok(offset),
+                },
+                State::ReadChunkFooter => {
+                    let (trailing_headers, consumed) = match self.read_headers(remaining)? {
+                        Some(headers) => headers,
+                        None => return Ok(offset),
+                    };
+                    out.push(HttpObject::LastContent(LastHttpContent {
+                        content: vec![],
+                        trailing_headers,
+                    }));
+                    self.state = State::SkipControlChars;
+                    return Ok(offset + consumed);
+                }
+                State::Upgraded => return Ok(offset),
+                State::BadMessage => return Ok(input.len()),
+            }
+        }
+    }
+
+    fn decode_last(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        input: &[u8],
+        out: &mut Vec<HttpObject>,
+    ) -> Result<usize, RettyErrorKind> {
+        let consumed = self.decode(channel_handler_ctx, input, out)?;
+        match self.state {
+            State::SkipControlChars | State::Upgraded | State::BadMessage => Ok(consumed),
+            // 读到连接关闭的 body 到这里结束
+            State::ReadVariableLengthContent => {
+                out.push(HttpObject::LastContent(LastHttpContent::empty()));
+                self.state = State::SkipControlChars;
+                Ok(consumed)
+            }
+            _ => {
+                self.message = None;
+                Err(self
+                    .corrupted("connection closed before the end of the HTTP message".to_string()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::codec::byte_to_message_decoder::ByteToMessageHandler;
+    use crate::channel::embedded_channel::EmbeddedChannel;
+    use crate::channel::handler_pipe::ChannelHandlerPipe;
+
+    fn decoder_channel(decoder: HttpObjectDecoder) -> EmbeddedChannel<HttpObject> {
+        let mut handler_pipe = ChannelHandlerPipe::new();
+        handler_pipe.add_last_inbound(Box::new(ByteToMessageHandler::new(decoder)));
+        EmbeddedChannel::new(handler_pipe)
+    }
+
+    fn request(object: &HttpObject) -> &HttpRequest {
+        match object {
+            HttpObject::Request(request) => request,
+            other => panic!("expected a request, got {:?}", other),
+        }
+    }
+
+    fn content(content: &[u8]) -> HttpObject {
+        HttpObject::Content(HttpContent::new(content.to_vec()))
+    }
+
+    fn last_content(content: &[u8]) -> HttpObject {
+        HttpObject::LastContent(LastHttpContent::new(content.to_vec()))
+    }
+
+    fn assert_error(channel: &EmbeddedChannel<HttpObject>, codec_kind: CodecErrorKind) {
+        let errors = channel.take_exceptions();
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(errors[0].is_codec_error(codec_kind), "{:?}", errors[0]);
+    }
+
+    #[test]
+    fn request_line_too_long() {
+        let channel = decoder_channel(HttpObjectDecoder::new_with_limits(true, 16, 8192, 8192));
+        channel.write_inbound(b"GET /0123456789abcdef HTTP/1.1\r\n\r\n");
+        assert_error(&channel, CodecErrorKind::TooLongFrame);
+        assert!(channel.read_inbound().is_none());
+        // 出错之后丢弃这个连接上的数据
+        channel.write_inbound(b"GET / HTTP/1.1\r\n\r\n");
+        assert!(channel.read_inbound().is_none());
+        assert!(channel.take_exceptions().is_empty());
+    }
+
+    #[test]
+    fn request_line_too_long_without_line_end() {
+        let channel = decoder_channel(HttpObjectDecoder::new_with_limits(true, 16, 8192, 8192));
+        channel.write_inbound(b"GET /0123456789");
+        assert!(channel.take_exceptions().is_empty());
+        channel.write_inbound(b"abcdef");
+        assert_error(&channel, CodecErrorKind::TooLongFrame);
+    }
+
+    #[test]
+    fn header_too_long() {
+        let channel = decoder_channel(HttpObjectDecoder::new_with_limits(true, 4096, 32, 8192));
+        channel.write_inbound(b"GET / HTTP/1.1\r\nHost: localhost\r\n");
+        assert!(channel.take_exceptions().is_empty());
+        channel.write_inbound(b"X-Long-Header: 0123456789\r\n\r\n");
+        assert_error(&channel, CodecErrorKind::TooLongFrame);
+        assert!(channel.read_inbound().is_none());
+    }
+
+    #[test]
+    fn content_length_body_split_across_reads() {
+        let channel = decoder_channel(HttpObjectDecoder::new_request_decoder());
+        channel.write_inbound(b"POST /upload HTTP/1.1\r\nContent-Le");
+        assert!(channel.read_inbound().is_none());
+        channel.write_inbound(b"ngth: 10\r\n\r\n0123");
+        channel.write_inbound(b"456789");
+        let messages = channel.read_all_inbound();
+        assert_eq!(messages.len(), 3);
+        let request = request(&messages[0]);
+        assert_eq!(request.method, HttpMethod::Post);
+        assert_eq!(request.uri, "/upload");
+        assert_eq!(request.headers.content_length(), Ok(Some(10)));
+        assert_eq!(messages[1], content(b"0123"));
+        assert_eq!(messages[2], last_content(b"456789"));
+    }
+
+    #[test]
+    fn body_larger_than_max_chunk_size() {
+        let channel = decoder_channel(HttpObjectDecoder::new_with_limits(true, 4096, 8192, 4));
+        channel.write_inbound(b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\n0123456789");
+        let messages = channel.read_all_inbound();
+        assert_eq!(
+            messages[1..],
+            [content(b"0123"), content(b"4567"), last_content(b"89")]
+        );
+    }
+
+    #[test]
+    fn chunked_body_with_extensions_and_trailers() {
+        let input: &[u8] = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+            5;name=value\r\nhello\r\n\
+            6 ; ext\r\n world\r\n\
+            0\r\nX-Checksum: abc\r\nX-Folded: a\r\n b\r\n\r\n";
+        let mut trailing_headers = HttpHeaders::new();
+        trailing_headers.add("X-Checksum", "abc");
+        trailing_headers.add("X-Folded", "a b");
+        let last = HttpObject::LastContent(LastHttpContent {
+            content: vec![],
+            trailing_headers,
+        });
+
+        let in_one_read = decoder_channel(HttpObjectDecoder::new_request_decoder());
+        in_one_read.write_inbound(input);
+        let messages = in_one_read.read_all_inbound();
+        assert!(request(&messages[0]).headers.is_transfer_encoding_chunked());
+        assert_eq!(
+            messages[1..],
+            [content(b"hello"), content(b" world"), last.clone()]
+        );
+
+        // 每次只读到一个字节
+        let byte_by_byte = decoder_channel(HttpObjectDecoder::new_request_decoder());
+        for b in input {
+            byte_by_byte.write_inbound(&[*b]);
+        }
+        let messages = byte_by_byte.read_all_inbound();
+        let body = messages[1..messages.len() - 1]
+            .iter()
+            .flat_map(|m| match m {
+                HttpObject::Content(c) => c.content.clone(),
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect::<Vec<u8>>();
+        assert_eq!(body, b"hello world");
+        assert_eq!(messages.last(), Some(&last));
+        assert!(byte_by_byte.take_exceptions().is_empty());
+    }
+
+    #[test]
+    fn invalid_chunk_size() {
+        let channel = decoder_channel(HttpObjectDecoder::new_request_decoder());
+        channel.write_inbound(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n");
+        assert_error(&channel, CodecErrorKind::CorruptedFrame);
+    }
+
+    #[test]
+    fn transfer_encoding_wins_over_content_length() {
+        let channel = decoder_channel(HttpObjectDecoder::new_request_decoder());
+        channel.write_inbound(
+            b"POST / HTTP/1.1\r\nContent-Length: 100\r\nTransfer-Encoding: chunked\r\n\r\n\
+              3\r\nabc\r\n0\r\n\r\n",
+        );
+        let messages = channel.read_all_inbound();
+        assert!(!request(&messages[0])
+            .headers
+            .contains(names::CONTENT_LENGTH));
+        assert_eq!(
+            messages[1..],
+            [
+                content(b"abc"),
+                HttpObject::LastContent(LastHttpContent::empty())
+            ]
+        );
+    }
+
+    #[test]
+    fn request_transfer_encoding_must_end_with_chunked() {
+        let channel = decoder_channel(HttpObjectDecoder::new_request_decoder());
+        channel.write_inbound(
+            b"POST / HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: gzip\r\n\r\nabc",
+        );
+        assert_error(&channel, CodecErrorKind::CorruptedFrame);
+        assert!(channel.read_inbound().is_none());
+
+        let channel = decoder_channel(HttpObjectDecoder::new_request_decoder());
+        channel.write_inbound(
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked, gzip\r\n\r\n3\r\nabc\r\n0\r\n\r\n",
+        );
+        assert_error(&channel, CodecErrorKind::CorruptedFrame);
+        assert!(channel.read_inbound().is_none());
+
+        // 分成多个 header 时也看最后一个
+        let channel = decoder_channel(HttpObjectDecoder::new_request_decoder());
+        channel.write_inbound(
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nTransfer-Encoding: gzip\r\n\r\n",
+        );
+        assert_error(&channel, CodecErrorKind::CorruptedFrame);
+
+        let channel = decoder_channel(HttpObjectDecoder::new_request_decoder());
+        channel.write_inbound(
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n",
+        );
+        assert!(channel.take_exceptions().is_empty());
+        assert_eq!(channel.read_all_inbound()[1], content(b"abc"));
+    }
+
+    #[test]
+    fn response_transfer_encoding_without_chunked_reads_until_close() {
+        let channel = decoder_channel(HttpObjectDecoder::new_response_decoder());
+        channel.write_inbound(
+            b"HTTP/1.1 200 OK\r\nContent-Length: 1\r\nTransfer-Encoding: gzip\r\n\r\nabc",
+        );
+        channel.finish();
+        let messages = channel.read_all_inbound();
+        assert_eq!(
+            messages[1..],
+            [
+                content(b"abc"),
+                HttpObject::LastContent(LastHttpContent::empty())
+            ]
+        );
+        assert!(channel.take_exceptions().is_empty());
+    }
+
+    #[test]
+    fn conflicting_content_length() {
+        let channel = decoder_channel(HttpObjectDecoder::new_request_decoder());
+        channel.write_inbound(
+            b"POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 6\r\n\r\nhello",
+        );
+        assert_error(&channel, CodecErrorKind::CorruptedFrame);
+        assert!(channel.read_inbound().is_none());
+
+        // 重复但是一致的值可以接受
+        let channel = decoder_channel(HttpObjectDecoder::new_request_decoder());
+        channel.write_inbound(b"POST / HTTP/1.1\r\nContent-Length: 5, 5\r\n\r\nhello");
+        assert!(channel.take_exceptions().is_empty());
+        assert_eq!(channel.read_all_inbound()[1], last_content(b"hello"));
+
+        let channel = decoder_channel(HttpObjectDecoder::new_request_decoder());
+        channel.write_inbound(b"POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n");
+        assert_error(&channel, CodecErrorKind::CorruptedFrame);
+    }
+
+    #[test]
+    fn pipelined_requests_in_one_buffer() {
+        let channel = decoder_channel(HttpObjectDecoder::new_request_decoder());
+        channel.write_inbound(
+            b"GET /a HTTP/1.1\r\n\r\n\
+              POST /b HTTP/1.1\r\nContent-Length: 2\r\n\r\nhi\
+              \r\nGET /c HTTP/1.0\r\nConnection: keep-alive\r\n\r\n",
+        );
+        let messages = channel.read_all_inbound();
+        assert_eq!(messages.len(), 6);
+        assert_eq!(request(&messages[0]).uri, "/a");
+        assert_eq!(
+            messages[1],
+            HttpObject::LastContent(LastHttpContent::empty())
+        );
+        assert_eq!(request(&messages[2]).uri, "/b");
+        assert_eq!(messages[3], last_content(b"hi"));
+        let last_request = request(&messages[4]);
+        assert_eq!(last_request.uri, "/c");
+        assert_eq!(last_request.version, HttpVersion::Http10);
+        assert!(last_request.is_keep_alive());
+        assert_eq!(
+            messages[5],
+            HttpObject::LastContent(LastHttpContent::empty())
+        );
+    }
+
+    #[test]
+    fn invalid_request_line() {
+        let channel = decoder_channel(HttpObjectDecoder::new_request_decoder());
+        channel.write_inbound(b"GET /\r\n\r\n");
+        assert_error(&channel, CodecErrorKind::CorruptedFrame);
+
+        let channel = decoder_channel(HttpObjectDecoder::new_request_decoder());
+        channel.write_inbound(b"GET / HTTP/2.0\r\n\r\n");
+        assert_error(&channel, CodecErrorKind::CorruptedFrame);
+    }
+
+    #[test]
+    fn close_delimited_response_body() {
+        let channel = decoder_channel(HttpObjectDecoder::new_response_decoder());
+        channel.write_inbound(b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\nabc");
+        channel.write_inbound(b"def");
+        channel.finish();
+        let messages = channel.read_all_inbound();
+        match &messages[0] {
+            HttpObject::Response(response) => assert_eq!(response.status.code, 200),
+            other => panic!("expected a response, got {:?}", other),
+        }
+        assert_eq!(
+            messages[1..],
+            [
+                content(b"abc"),
+                content(b"def"),
+                HttpObject::LastContent(LastHttpContent::empty())
+            ]
+        );
+        assert!(channel.take_exceptions().is_empty());
+    }
+
+    #[test]
+    fn connection_closed_in_the_middle_of_a_message() {
+        let channel = decoder_channel(HttpObjectDecoder::new_request_decoder());
+        channel.write_inbound(b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\n01234");
+        channel.finish();
+        assert_eq!(channel.read_all_inbound().len(), 2);
+        assert_error(&channel, CodecErrorKind::CorruptedFrame);
+    }
+}
@@ -0,0 +1,140 @@
+use std::io::ErrorKind;
+
+use crate::channel::codec::http::http_headers::HttpHeaders;
+use crate::channel::codec::http::http_message::{HttpRequest, HttpResponse};
+use crate::errors::RettyErrorKind;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum State {
+    Init,
+    ContentNonChunk,
+    ContentChunk,
+    // HEAD 请求的响应, 1xx / 204 / 304 不写 body
+    ContentAlwaysEmpty,
+}
+
+///
+/// HTTP/1.x 请求(响应) 编码, 一个消息依次是请求行(状态行) 和 header, 零个或多个 content, 一个 last content
+/// Transfer-Encoding: chunked 时 content 按 chunk 编码
+///
+pub(crate) struct HttpObjectEncoder {
+    state: State,
+}
+
+impl HttpObjectEncoder {
+    pub(crate) fn new() -> HttpObjectEncoder {
+        HttpObjectEncoder { state: State::Init }
+    }
+
+    pub(crate) fn encode_request_head(
+        &mut self,
+        request: &HttpRequest,
+        out: &mut Vec<u8>,
+    ) -> Result<(), RettyErrorKind> {
+        let initial_line = format!(
+            "{} {} {}\r\n",
+            request.method,
+            if request.uri.is_empty() {
+                "/"
+            } else {
+                &request.uri
+            },
+            request.version
+        );
+        self.encode_head(initial_line, &request.headers, false, out)
+    }
+
+    pub(crate) fn encode_response_head(
+        &mut self,
+        response: &HttpResponse,
+        content_always_empty: bool,
+        out: &mut Vec<u8>,
+    ) -> Result<(), RettyErrorKind> {
+        let initial_line = format!(
+            "{} {} {}\r\n",
+            response.version, response.status.code, response.status.reason
+        );
+        self.encode_head(initial_line, &response.headers, content_always_empty, out)
+    }
+
+    fn encode_head(
+        &mut self,
+        initial_line: String,
+        headers: &HttpHeaders,
+        content_always_empty: bool,
+        out: &mut Vec<u8>,
+    ) -> Result<(), RettyErrorKind> {
+        if self.state != State::Init {
+            return Err(unexpected_message("HTTP message head", self.state));
+        }
+        // uri 和 reason phrase 里不能有换行
+        if initial_line[..initial_line.len() - 2].contains(['\r', '\n']) {
+            return Err(RettyErrorKind::new(
+                ErrorKind::InvalidInput,
+                format!("invalid HTTP initial line: {:?}", initial_line),
+            ));
+        }
+        out.extend_from_slice(initial_line.as_bytes());
+        headers.encode(out)?;
+        out.extend_from_slice(b"\r\n");
+        self.state = if content_always_empty {
+            State::ContentAlwaysEmpty
+        } else if headers.is_transfer_encoding_chunked() {
+            State::ContentChunk
+        } else {
+            State::ContentNonChunk
+        };
+        Ok(())
+    }
+
+    pub(crate) fn encode_content(
+        &mut self,
+        content: &[u8],
+        out: &mut Vec<u8>,
+    ) -> Result<(), RettyErrorKind> {
+        match self.state {
+            State::Init => return Err(unexpected_message("HTTP content", self.state)),
+            State::ContentNonChunk => out.extend_from_slice(content),
+            State::ContentChunk => encode_chunk(content, out),
+            State::ContentAlwaysEmpty => {}
+        }
+        Ok(())
+    }
+
+    pub(crate) fn encode_last_content(
+        &mut self,
+        content: &[u8],
+        trailing_headers: &HttpHeaders,
+        out: &mut Vec<u8>,
+    ) -> Result<(), RettyErrorKind> {
+        match self.state {
+            State::Init => return Err(unexpected_message("HTTP last content", self.state)),
+            State::ContentNonChunk => out.extend_from_slice(content),
+            State::ContentChunk => {
+                encode_chunk(content, out);
+                out.extend_from_slice(b"0\r\n");
+                trailing_headers.encode(out)?;
+                out.extend_from_slice(b"\r\n");
+            }
+            State::ContentAlwaysEmpty => {}
+        }
+        self.state = State::Init;
+        Ok(())
+    }
+}
+
+fn encode_chunk(content: &[u8], out: &mut Vec<u8>) {
+    if content.is_empty() {
+        return;
+    }
+    out.extend_from_slice(format!("{:x}\r\n", content.len()).as_bytes());
+    out.extend_from_slice(content);
+    out.extend_from_slice(b"\r\n");
+}
+
+fn unexpected_message(message: &str, state: State) -> RettyErrorKind {
+    RettyErrorKind::new(
+        ErrorKind::InvalidInput,
+        format!("unexpected {}, encoder state: {:?}", message, state),
+    )
+}
@@ -0,0 +1,682 @@
+use std::any::Any;
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::sync::{Arc, Mutex};
+
+use bytebuf_rs::bytebuf::ByteBuf;
+
+use crate::channel::channel_handler_ctx::{ChannelInboundHandlerCtx, ChannelOutboundHandlerCtx};
+use crate::channel::codec::byte_to_message_decoder::{ByteToMessageDecoder, ByteToMessageHandler};
+use crate::channel::codec::http::http_headers::{names, values, HttpHeaders};
+use crate::channel::codec::http::http_message::{
+    FullHttpResponse, HttpContent, HttpMethod, HttpObject, HttpResponse, HttpResponseStatus,
+    HttpUpgradeEvent, HttpVersion, LastHttpContent,
+};
+use crate::channel::codec::http::http_object_decoder::{
+    HttpObjectDecoder, DEFAULT_MAX_CHUNK_SIZE, DEFAULT_MAX_HEADER_SIZE,
+    DEFAULT_MAX_INITIAL_LINE_LENGTH,
+};
+use crate::channel::codec::http::http_object_encoder::HttpObjectEncoder;
+use crate::channel::handler::{ChannelCodec, ChannelInboundHandler, ChannelOutboundHandler};
+use crate::errors::RettyErrorKind;
+
+///
+/// 还没有响应的请求
+///
+struct PendingRequest {
+    head: bool,
+    keep_alive: bool,
+    // 带了 Expect, 客户端在等 100 Continue 才发送 body
+    expect: bool,
+    version: HttpVersion,
+}
+
+struct HttpServerCodecState {
+    decoder: HttpObjectDecoder,
+    encoder: HttpObjectEncoder,
+    pending_requests: VecDeque<PendingRequest>,
+    // 正在写的 101 Switching Protocols 的 Upgrade 头
+    upgrading: Option<String>,
+    // 101 写完之后用 HttpUpgradeEvent 通知后面的 handler
+    upgrade_protocol: Option<String>,
+}
+
+///
+/// HTTP/1.1 服务端编解码器, 用 ChannelInboundHandlerPipe::add_last_codec 注册
+///
+/// 入站: 把字节解码成 HttpObject, 见 HttpObjectDecoder
+/// 出站: 编码 HttpResponse / HttpContent / LastHttpContent / FullHttpResponse 和 HttpObject, 其他消息原样往下写
+///
+/// 同一个连接上的多个请求(pipelining) 按顺序响应, HEAD 请求的响应不写 body
+/// 对带了 Expect 的请求回复 4xx (比如 HttpObjectAggregator 回复的 413 / 417) 之后不再等待这个请求的 body
+/// 请求不是长连接时响应加上 Connection: close, 写完之后由业务 handler 关闭连接,
+/// 比如 write_and_flush(...).add_close_listener()
+/// 写完 101 Switching Protocols 之后停止解码并触发 HttpUpgradeEvent, 处理新协议的 handler 应该把 HttpServerCodec 从 pipeline 移除,
+/// 101 不一定在 channel_read 里写, 也可以在 channel_read_complete / user_event_triggered 等回调里写, 都在这个回调返回之后触发,
+/// 移除时已经读到但没有解码的数据传给原来的下一个handler, 见 WebSocketServerProtocolHandler
+///
+pub struct HttpServerCodec {
+    state: HttpServerCodecState,
+}
+
+impl Default for HttpServerCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HttpServerCodec {
+    pub fn new() -> HttpServerCodec {
+        HttpServerCodec::new_with_limits(
+            DEFAULT_MAX_INITIAL_LINE_LENGTH,
+            DEFAULT_MAX_HEADER_SIZE,
+            DEFAULT_MAX_CHUNK_SIZE,
+        )
+    }
+
+    pub fn new_with_limits(
+        max_initial_line_length: usize,
+        max_header_size: usize,
+        max_chunk_size: usize,
+    ) -> HttpServerCodec {
+        HttpServerCodec {
+            state: HttpServerCodecState {
+                decoder: HttpObjectDecoder::new_with_limits(
+                    true,
+                    max_initial_line_length,
+                    max_header_size,
+                    max_chunk_size,
+                ),
+                encoder: HttpObjectEncoder::new(),
+                pending_requests: VecDeque::new(),
+                upgrading: None,
+                upgrade_protocol: None,
+            },
+        }
+    }
+}
+
+impl ChannelCodec for HttpServerCodec {
+    fn into_handlers(
+        self,
+    ) -> (
+        Box<dyn ChannelInboundHandler + Send + Sync>,
+        Box<dyn ChannelOutboundHandler + Send + Sync>,
+    ) {
+        let state = Arc::new(Mutex::new(self.state));
+        (
+            Box::new(HttpServerCodecDecoder {
+                handler: ByteToMessageHandler::new(state.clone()),
+                state: state.clone(),
+            }),
+            Box::new(HttpServerCodecEncoder { state }),
+        )
+    }
+}
+
+impl ByteToMessageDecoder for HttpServerCodecState {
+    type Message = HttpObject;
+
+    fn id(&self) -> String {
+        "HttpServerCodec".to_string()
+    }
+
+    fn decode(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        input: &[u8],
+        out: &mut Vec<HttpObject>,
+    ) -> Result<usize, RettyErrorKind> {
+        let start = out.len();
+        let ret = self.decoder.decode(channel_handler_ctx, input, out);
+        self.record_requests(&out[start..]);
+        ret
+    }
+
+    fn decode_last(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        input: &[u8],
+        out: &mut Vec<HttpObject>,
+    ) -> Result<usize, RettyErrorKind> {
+        let start = out.len();
+        let ret = self.decoder.decode_last(channel_handler_ctx, input, out);
+        self.record_requests(&out[start..]);
+        ret
+    }
+}
+
+impl HttpServerCodecState {
+    fn record_requests(&mut self, decoded: &[HttpObject]) {
+        for message in decoded {
+            if let HttpObject::Request(request) = message {
+                self.pending_requests.push_back(PendingRequest {
+                    head: request.method == HttpMethod::Head,
+                    keep_alive: request.is_keep_alive(),
+                    expect: request.version == HttpVersion::Http11
+                        && request.headers.contains(names::EXPECT),
+                    version: request.version,
+                });
+            }
+        }
+    }
+
+    ///
+    /// 按请求的 method 和 keep-alive 调整响应, 返回是否不写 body
+    ///
+    fn prepare_response(
+        &mut self,
+        version: HttpVersion,
+        status: &HttpResponseStatus,
+        headers: &mut HttpHeaders,
+    ) -> bool {
+        // 100 Continue 等中间响应之后还有最终的响应
+        let pending = if !status.is_informational() || status.code == 101 {
+            self.pending_requests.pop_front()
+        } else {
+            None
+        };
+        let mut content_always_empty = status.is_content_always_empty();
+        if let Some(pending) = pending {
+            content_always_empty |= pending.head;
+            // 拒绝了正在读 body 的请求的 Expect, 客户端不会再发送 body
+            if pending.expect && status.code / 100 == 4 && self.pending_requests.is_empty() {
+                self.decoder.skip_content();
+            }
+            if !headers.contains(names::CONNECTION) {
+                if !pending.keep_alive {
+                    headers.set(names::CONNECTION, values::CLOSE);
+                } else if pending.version == HttpVersion::Http10 && version == HttpVersion::Http10 {
+                    headers.set(names::CONNECTION, values::KEEP_ALIVE);
+                }
+            }
+        }
+        content_always_empty
+    }
+
+    fn set_upgrading(&mut self, code: u16, headers: &HttpHeaders) {
+        self.upgrading = if code == 101 {
+            Some(headers.get(names::UPGRADE).unwrap_or_default().to_string())
+        } else {
+            None
+        };
+    }
+
+    fn encode_response(
+        &mut self,
+        response: &mut HttpResponse,
+        out: &mut Vec<u8>,
+    ) -> Result<(), RettyErrorKind> {
+        let content_always_empty =
+            self.prepare_response(response.version, &response.status, &mut response.headers);
+        self.encoder
+            .encode_response_head(response, content_always_empty, out)?;
+        self.set_upgrading(response.status.code, &response.headers);
+        Ok(())
+    }
+
+    fn encode_full_response(
+        &mut self,
+        response: &mut FullHttpResponse,
+        out: &mut Vec<u8>,
+    ) -> Result<(), RettyErrorKind> {
+        let content_always_empty =
+            self.prepare_response(response.version, &response.status, &mut response.headers);
+        let headers = &mut response.headers;
+        if !response.status.is_content_always_empty()
+            && !headers.is_transfer_encoding_chunked()
+            && !headers.contains(names::CONTENT_LENGTH)
+        {
+            headers.set_content_length(response.content.len() as u64);
+        }
+        let head = HttpResponse {
+            version: response.version,
+            status: response.status.clone(),
+            headers: std::mem::take(&mut response.headers),
+        };
+        let ret = self
+            .encoder
+            .encode_response_head(&head, content_always_empty, out);
+        response.headers = head.headers;
+        ret?;
+        self.set_upgrading(response.status.code, &response.headers);
+        self.encode_last_content(&response.content, &response.trailing_headers, out)
+    }
+
+    fn encode_last_content(
+        &mut self,
+        content: &[u8],
+        trailing_headers: &HttpHeaders,
+        out: &mut Vec<u8>,
+    ) -> Result<(), RettyErrorKind> {
+        self.encoder
+            .encode_last_content(content, trailing_headers, out)?;
+        if let Some(protocol) = self.upgrading.take() {
+            self.decoder.upgrade();
+            self.upgrade_protocol = Some(protocol);
+        }
+        Ok(())
+    }
+
+    fn encode(
+        &mut self,
+        message: &mut dyn Any,
+        out: &mut Vec<u8>,
+    ) -> Option<Result<(), RettyErrorKind>> {
+        let ret = if let Some(object) = message.downcast_mut::<HttpObject>() {
+            match object {
+                HttpObject::Response(response) => self.encode_response(response, out),
+                HttpObject::Content(content) => self.encoder.encode_content(&content.content, out),
+                HttpObject::LastContent(last) => {
+                    self.encode_last_content(&last.content, &last.trailing_headers, out)
+                }
+                HttpObject::Request(_) => Err(RettyErrorKind::new(
+                    ErrorKind::InvalidInput,
+                    "HttpServerCodec can not encode a request".to_string(),
+                )),
+            }
+        } else if let Some(response) = message.downcast_mut::<FullHttpResponse>() {
+            self.encode_full_response(response, out)
+        } else if let Some(response) = message.downcast_mut::<HttpResponse>() {
+            self.encode_response(response, out)
+        } else if let Some(content) = message.downcast_ref::<HttpContent>() {
+            self.encoder.encode_content(&content.content, out)
+        } else if let Some(last) = message.downcast_ref::<LastHttpContent>() {
+            self.encode_last_content(&last.content, &last.trailing_headers, out)
+        } else {
+            return None;
+        };
+        Some(ret)
+    }
+}
+
+struct HttpServerCodecDecoder {
+    handler: ByteToMessageHandler<Arc<Mutex<HttpServerCodecState>>>,
+    state: Arc<Mutex<HttpServerCodecState>>,
+}
+
+impl HttpServerCodecDecoder {
+    ///
+    /// 101 写完之后通知后面的 handler
+    /// 后面的 handler 在回调里写 101 时这个 handler 正在往后传事件, 所以在每个往后传的事件返回之后检查
+    ///
+    fn fire_upgrade_event(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        let protocol = self.state.lock().unwrap().upgrade_protocol.take();
+        if let Some(protocol) = protocol {
+            channel_handler_ctx.fire_user_event(Box::new(HttpUpgradeEvent { protocol }));
+        }
+    }
+}
+
+impl ChannelInboundHandler for HttpServerCodecDecoder {
+    fn id(&self) -> String {
+        "HttpServerCodec".to_string()
+    }
+
+    fn channel_active(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        self.handler.channel_active(channel_handler_ctx);
+        self.fire_upgrade_event(channel_handler_ctx);
+    }
+
+    fn channel_inactive(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        self.handler.channel_inactive(channel_handler_ctx);
+    }
+
+    fn channel_read(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        message: &mut dyn Any,
+    ) {
+        self.handler.channel_read(channel_handler_ctx, message);
+        self.fire_upgrade_event(channel_handler_ctx);
+    }
+
+    fn channel_exception(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        error: RettyErrorKind,
+    ) {
+        self.handler.channel_exception(channel_handler_ctx, error);
+        self.fire_upgrade_event(channel_handler_ctx);
+    }
+
+    fn channel_writability_changed(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        self.handler
+            .channel_writability_changed(channel_handler_ctx);
+        self.fire_upgrade_event(channel_handler_ctx);
+    }
+
+    fn channel_read_complete(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        self.handler.channel_read_complete(channel_handler_ctx);
+        self.fire_upgrade_event(channel_handler_ctx);
+    }
+
+    fn user_event_triggered(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        event: Box<dyn Any + Send>,
+    ) {
+        self.handler
+            .user_event_triggered(channel_handler_ctx, event);
+        self.fire_upgrade_event(channel_handler_ctx);
+    }
+
+    fn handler_removed(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        // 升级请求后面已经读到的数据属于新的协议
+        self.handler.handler_removed(channel_handler_ctx);
+    }
+}
+
+struct HttpServerCodecEncoder {
+    state: Arc<Mutex<HttpServerCodecState>>,
+}
+
+impl ChannelOutboundHandler for HttpServerCodecEncoder {
+    fn id(&self) -> String {
+        "HttpServerCodec".to_string()
+    }
+
+    fn channel_write(
+        &mut self,
+        channel_handler_ctx: &mut ChannelOutboundHandlerCtx,
+        message: &mut dyn Any,
+    ) {
+        let mut out = vec![];
+        let ret = self.state.lock().unwrap().encode(message, &mut out);
+        match ret {
+            Some(Ok(())) => channel_handler_ctx.fire_channel_write(&mut ByteBuf::new_from(&out)),
+            Some(Err(e)) => channel_handler_ctx.channel().fail_write(e),
+            None => channel_handler_ctx.fire_channel_write(message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::codec::http::http_message::HttpRequest;
+    use crate::channel::embedded_channel::EmbeddedChannel;
+    use crate::channel::handler_pipe::ChannelHandlerPipe;
+
+    const UPGRADE_REQUEST: &[u8] =
+        b"GET /chat HTTP/1.1\r\nHost: x\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\n";
+
+    ///
+    /// 不在 channel_read 里回复 101 的 handler
+    ///
+    struct DeferredUpgrader {
+        on_read_complete: bool,
+        requested: bool,
+    }
+
+    impl DeferredUpgrader {
+        fn upgrade(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+            if !std::mem::take(&mut self.requested) {
+                return;
+            }
+            let mut response = FullHttpResponse::new(
+                HttpVersion::Http11,
+                HttpResponseStatus::from_code(101),
+                vec![],
+            );
+            response.headers.set(names::UPGRADE, "websocket");
+            response.headers.set(names::CONNECTION, "Upgrade");
+            channel_handler_ctx.write_and_flush(&mut response);
+        }
+    }
+
+    impl ChannelInboundHandler for DeferredUpgrader {
+        fn id(&self) -> String {
+            "DeferredUpgrader".to_string()
+        }
+
+        fn channel_active(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+            channel_handler_ctx.fire_channel_active();
+        }
+
+        fn channel_inactive(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+            channel_handler_ctx.fire_channel_inactive();
+        }
+
+        fn channel_read(
+            &mut self,
+            channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+            message: &mut dyn Any,
+        ) {
+            if let Some(HttpObject::LastContent(_)) = message.downcast_ref::<HttpObject>() {
+                self.requested = true;
+            }
+            channel_handler_ctx.fire_channel_read(message);
+        }
+
+        fn channel_exception(
+            &mut self,
+            channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+            error: RettyErrorKind,
+        ) {
+            channel_handler_ctx.fire_channel_exception(error);
+        }
+
+        fn channel_read_complete(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+            if self.on_read_complete {
+                self.upgrade(channel_handler_ctx);
+            }
+            channel_handler_ctx.fire_channel_read_complete();
+        }
+
+        fn user_event_triggered(
+            &mut self,
+            channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+            event: Box<dyn Any + Send>,
+        ) {
+            if !self.on_read_complete {
+                self.upgrade(channel_handler_ctx);
+            }
+            channel_handler_ctx.fire_user_event(event);
+        }
+    }
+
+    fn upgrade_channel(on_read_complete: bool) -> EmbeddedChannel<HttpObject> {
+        let mut handler_pipe = ChannelHandlerPipe::new();
+        handler_pipe.add_last_codec(HttpServerCodec::new());
+        handler_pipe.add_last_inbound(Box::new(DeferredUpgrader {
+            on_read_complete,
+            requested: false,
+        }));
+        EmbeddedChannel::new(handler_pipe)
+    }
+
+    fn upgrade_events(channel: &EmbeddedChannel<HttpObject>) -> Vec<HttpUpgradeEvent> {
+        channel
+            .take_events()
+            .into_iter()
+            .filter_map(|event| event.downcast_ref::<HttpUpgradeEvent>().cloned())
+            .collect()
+    }
+
+    #[test]
+    fn fires_upgrade_event_for_101_written_in_read_complete() {
+        let mut channel = upgrade_channel(true);
+
+        channel.write_inbound(UPGRADE_REQUEST);
+
+        assert!(channel
+            .read_outbound()
+            .starts_with(b"HTTP/1.1 101 Switching Protocols\r\n"));
+        assert_eq!(
+            upgrade_events(&channel),
+            vec![HttpUpgradeEvent {
+                protocol: "websocket".to_string()
+            }]
+        );
+        assert_eq!(channel.read_all_inbound().len(), 2);
+    }
+
+    #[test]
+    fn fires_upgrade_event_for_101_written_in_user_event() {
+        let mut channel = upgrade_channel(false);
+        channel.write_inbound(UPGRADE_REQUEST);
+        assert!(channel.read_outbound().is_empty());
+
+        channel
+            .pipeline()
+            .head_user_event_triggered(Box::new("authenticated"));
+
+        assert!(channel
+            .read_outbound()
+            .starts_with(b"HTTP/1.1 101 Switching Protocols\r\n"));
+        let events = channel.take_events();
+        assert_eq!(events.len(), 2);
+        assert!(events[0].downcast_ref::<&str>().is_some());
+        assert_eq!(
+            events[1].downcast_ref::<HttpUpgradeEvent>(),
+            Some(&HttpUpgradeEvent {
+                protocol: "websocket".to_string()
+            })
+        );
+    }
+
+    fn server_channel() -> EmbeddedChannel<HttpObject> {
+        let mut handler_pipe = ChannelHandlerPipe::new();
+        handler_pipe.add_last_codec(HttpServerCodec::new());
+        EmbeddedChannel::new(handler_pipe)
+    }
+
+    fn respond(
+        channel: &mut EmbeddedChannel<HttpObject>,
+        version: HttpVersion,
+        code: u16,
+    ) -> Vec<u8> {
+        let mut response = FullHttpResponse::new(
+            version,
+            HttpResponseStatus::from_code(code),
+            b"hello".to_vec(),
+        );
+        assert!(channel.write_outbound(&mut response).is_success());
+        channel.read_outbound()
+    }
+
+    #[test]
+    fn adds_connection_header_by_request() {
+        let mut channel = server_channel();
+        channel.write_inbound(b"GET / HTTP/1.1\r\n\r\n");
+        assert_eq!(
+            respond(&mut channel, HttpVersion::Http11, 200),
+            b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nhello"
+        );
+
+        let mut channel = server_channel();
+        channel.write_inbound(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert_eq!(
+            respond(&mut channel, HttpVersion::Http11, 200),
+            b"HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-length: 5\r\n\r\nhello"
+        );
+
+        let mut channel = server_channel();
+        channel.write_inbound(b"GET / HTTP/1.0\r\n\r\n");
+        assert_eq!(
+            respond(&mut channel, HttpVersion::Http10, 200),
+            b"HTTP/1.0 200 OK\r\nconnection: close\r\ncontent-length: 5\r\n\r\nhello"
+        );
+
+        let mut channel = server_channel();
+        channel.write_inbound(b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n");
+        assert_eq!(
+            respond(&mut channel, HttpVersion::Http10, 200),
+            b"HTTP/1.0 200 OK\r\nconnection: keep-alive\r\ncontent-length: 5\r\n\r\nhello"
+        );
+    }
+
+    #[test]
+    fn does_not_write_body_for_head_204_and_304() {
+        let mut channel = server_channel();
+        channel.write_inbound(b"HEAD / HTTP/1.1\r\n\r\n");
+        assert_eq!(
+            respond(&mut channel, HttpVersion::Http11, 200),
+            b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\n"
+        );
+
+        channel.write_inbound(b"GET / HTTP/1.1\r\n\r\n");
+        assert_eq!(
+            respond(&mut channel, HttpVersion::Http11, 204),
+            b"HTTP/1.1 204 No Content\r\n\r\n"
+        );
+
+        channel.write_inbound(b"GET / HTTP/1.1\r\n\r\n");
+        assert_eq!(
+            respond(&mut channel, HttpVersion::Http11, 304),
+            b"HTTP/1.1 304 Not Modified\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn encodes_chunked_response_with_trailers() {
+        let mut channel = server_channel();
+        channel.write_inbound(b"GET / HTTP/1.1\r\n\r\n");
+
+        let mut response =
+            HttpResponse::new(HttpVersion::Http11, HttpResponseStatus::from_code(200));
+        response
+            .headers
+            .set(names::TRANSFER_ENCODING, values::CHUNKED);
+        channel.write_outbound(&mut response);
+        channel.write_outbound(&mut HttpContent::new(b"abc".to_vec()));
+        // 空的 content 不能写成 0 长度的 chunk, 否则会被当成结束
+        channel.write_outbound(&mut HttpContent::new(vec![]));
+        let mut last = LastHttpContent::new(b"de".to_vec());
+        last.trailing_headers.set("x-checksum", "1");
+        channel.write_outbound(&mut last);
+
+        assert_eq!(
+            channel.read_outbound(),
+            &b"HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n\
+               3\r\nabc\r\n2\r\nde\r\n0\r\nx-checksum: 1\r\n\r\n"[..]
+        );
+    }
+
+    #[test]
+    fn pipelined_responses_follow_request_order() {
+        let mut channel = server_channel();
+        channel
+            .write_inbound(b"HEAD /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert_eq!(channel.read_all_inbound().len(), 4);
+
+        // 第一个响应对应 HEAD, 第二个对应 Connection: close 的 GET
+        assert_eq!(
+            respond(&mut channel, HttpVersion::Http11, 200),
+            b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\n"
+        );
+        assert_eq!(
+            respond(&mut channel, HttpVersion::Http11, 200),
+            b"HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-length: 5\r\n\r\nhello"
+        );
+        // 100 Continue 不对应请求
+        channel.write_inbound(b"GET /c HTTP/1.1\r\n\r\n");
+        let mut response = FullHttpResponse::new(
+            HttpVersion::Http11,
+            HttpResponseStatus::from_code(100),
+            vec![],
+        );
+        channel.write_outbound(&mut response);
+        assert_eq!(channel.read_outbound(), b"HTTP/1.1 100 Continue\r\n\r\n");
+        assert_eq!(
+            respond(&mut channel, HttpVersion::Http11, 200),
+            b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nhello"
+        );
+    }
+
+    #[test]
+    fn rejects_content_without_response_head() {
+        let mut channel = server_channel();
+
+        let future = channel.write_outbound(&mut LastHttpContent::new(b"x".to_vec()));
+        assert_eq!(future.cause().unwrap().kind, ErrorKind::InvalidInput);
+        let future = channel.write_outbound(&mut HttpContent::new(b"x".to_vec()));
+        assert_eq!(future.cause().unwrap().kind, ErrorKind::InvalidInput);
+        let mut request =
+            HttpObject::Request(HttpRequest::new(HttpVersion::Http11, HttpMethod::Get, "/"));
+        let future = channel.write_outbound(&mut request);
+        assert_eq!(future.cause().unwrap().kind, ErrorKind::InvalidInput);
+        assert!(channel.read_outbound().is_empty());
+    }
+}
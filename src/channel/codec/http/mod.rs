@@ -0,0 +1,8 @@
+pub mod http_client_codec;
+pub mod http_headers;
+pub mod http_message;
+pub mod http_object_aggregator;
+pub mod http_object_decoder;
+pub(crate) mod http_object_encoder;
+pub mod http_server_codec;
+pub mod websocket;
@@ -0,0 +1,10 @@
+pub mod per_message_deflate;
+pub mod websocket_client_protocol_handler;
+pub mod websocket_frame;
+pub mod websocket_frame_decoder;
+pub mod websocket_frame_encoder;
+pub(crate) mod websocket_handshake;
+pub(crate) mod websocket_protocol;
+pub mod websocket_server_protocol_handler;
+#[cfg(test)]
+pub(crate) mod websocket_test_frames;
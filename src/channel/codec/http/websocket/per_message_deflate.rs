@@ -0,0 +1,499 @@
+use std::io::ErrorKind;
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+
+use crate::channel::codec::http::websocket::websocket_frame::close_status;
+use crate::errors::RettyErrorKind;
+
+pub(crate) const PERMESSAGE_DEFLATE: &str = "permessage-deflate";
+
+// 压缩的消息第一帧设置 RSV1
+pub(crate) const RSV1: u8 = 0x4;
+
+const SERVER_NO_CONTEXT_TAKEOVER: &str = "server_no_context_takeover";
+const CLIENT_NO_CONTEXT_TAKEOVER: &str = "client_no_context_takeover";
+const SERVER_MAX_WINDOW_BITS: &str = "server_max_window_bits";
+const CLIENT_MAX_WINDOW_BITS: &str = "client_max_window_bits";
+
+// 压缩时 sync flush 的结尾, 发送时去掉, 解压前补上
+const DEFLATE_TAIL: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+// 只支持 32K 的窗口
+const MAX_WINDOW_BITS: u8 = 15;
+
+///
+/// permessage-deflate 扩展(RFC 7692) 的参数, 用 WebSocketServerProtocolHandler::compression 和
+/// WebSocketClientProtocolHandler::compression 启用
+///
+/// server_no_context_takeover / client_no_context_takeover: 要求对应的一端每个消息单独压缩, 不使用之前消息的上下文,
+/// 省内存但是压缩率低一些, 对端提出的要求总是接受
+/// 压缩窗口固定为 15 bits(flate2 默认的 miniz_oxide 后端不能设置窗口大小), 所以不支持 client_max_window_bits 选项:
+/// 服务端不接受要求 server_max_window_bits 小于 15 的协商, 接受客户端提出的 client_max_window_bits 但不在回复里限制客户端的窗口,
+/// 客户端不提出 client_max_window_bits, 服务端不能要求客户端使用更小的窗口
+///
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct PerMessageDeflateOptions {
+    // 0 - 9
+    pub compression_level: u32,
+    pub server_no_context_takeover: bool,
+    pub client_no_context_takeover: bool,
+}
+
+impl Default for PerMessageDeflateOptions {
+    fn default() -> Self {
+        PerMessageDeflateOptions {
+            compression_level: 6,
+            server_no_context_takeover: false,
+            client_no_context_takeover: false,
+        }
+    }
+}
+
+///
+/// 压缩写出的消息
+///
+pub(crate) struct Deflater {
+    compress: Compress,
+    no_context_takeover: bool,
+}
+
+impl Deflater {
+    fn new(compression_level: u32, no_context_takeover: bool) -> Deflater {
+        Deflater {
+            compress: Compress::new(Compression::new(compression_level.min(9)), false),
+            no_context_takeover,
+        }
+    }
+
+    pub(crate) fn deflate(&mut self, payload: &[u8]) -> Result<Vec<u8>, RettyErrorKind> {
+        let mut out = Vec::with_capacity(payload.len() / 2 + 64);
+        let start = self.compress.total_in();
+        loop {
+            let consumed = (self.compress.total_in() - start) as usize;
+            if out.len() == out.capacity() {
+                out.reserve(out.capacity());
+            }
+            self.compress
+                .compress_vec(&payload[consumed..], &mut out, FlushCompress::Sync)
+                .map_err(|e| RettyErrorKind::new(ErrorKind::Other, e.to_string()))?;
+            // 输入都用完并且输出没有写满, sync flush 已经完成
+            if (self.compress.total_in() - start) as usize == payload.len()
+                && out.len() < out.capacity()
+            {
+                break;
+            }
+        }
+        if out.ends_with(&DEFLATE_TAIL) {
+            out.truncate(out.len() - DEFLATE_TAIL.len());
+        }
+        if out.is_empty() {
+            // 空消息压缩之后是一个空的块
+            out.push(0x00);
+        }
+        if self.no_context_takeover {
+            self.compress.reset();
+        }
+        Ok(out)
+    }
+}
+
+///
+/// 解压收到的消息
+///
+pub(crate) struct Inflater {
+    decompress: Decompress,
+    no_context_takeover: bool,
+}
+
+impl Inflater {
+    fn new(no_context_takeover: bool) -> Inflater {
+        Inflater {
+            decompress: Decompress::new(false),
+            no_context_takeover,
+        }
+    }
+
+    ///
+    /// 解压之后超过 max_length 或者数据不对时返回关闭的状态码和原因
+    ///
+    pub(crate) fn inflate(
+        &mut self,
+        payload: &[u8],
+        max_length: usize,
+    ) -> Result<Vec<u8>, (u16, String)> {
+        let mut input = Vec::with_capacity(payload.len() + DEFLATE_TAIL.len());
+        input.extend_from_slice(payload);
+        input.extend_from_slice(&DEFLATE_TAIL);
+
+        let mut out = Vec::with_capacity((payload.len() * 2).min(max_length + 1).max(64));
+        let start = self.decompress.total_in();
+        loop {
+            let consumed = (self.decompress.total_in() - start) as usize;
+            if out.len() == out.capacity() {
+                if out.len() > max_length {
+                    break;
+                }
+                out.reserve(out.len().min(max_length + 1 - out.len()).max(1));
+            }
+            let produced = out.len();
+            let status = self
+                .decompress
+                .decompress_vec(&input[consumed..], &mut out, FlushDecompress::Sync)
+                .map_err(|e| (close_status::INVALID_PAYLOAD_DATA, e.to_string()))?;
+            let done = (self.decompress.total_in() - start) as usize == input.len();
+            if status == Status::StreamEnd || (done && out.len() < out.capacity()) {
+                break;
+            }
+            if status == Status::BufError
+                && consumed == (self.decompress.total_in() - start) as usize
+                && produced == out.len()
+            {
+                return Err((
+                    close_status::INVALID_PAYLOAD_DATA,
+                    "invalid deflate data".to_string(),
+                ));
+            }
+        }
+        if out.len() > max_length {
+            return Err((
+                close_status::MESSAGE_TOO_BIG,
+                format!(
+                    "Max message length of {} has been exceeded after decompression.",
+                    max_length
+                ),
+            ));
+        }
+        if self.no_context_takeover {
+            self.decompress.reset(false);
+        }
+        Ok(out)
+    }
+}
+
+// 扩展名和参数
+type Extension = (String, Vec<(String, Option<String>)>);
+
+///
+/// 解析 Sec-WebSocket-Extensions
+///
+fn parse_extensions(header: &str) -> Vec<Extension> {
+    header
+        .split(',')
+        .filter(|extension| !extension.trim().is_empty())
+        .map(|extension| {
+            let mut parts = extension.split(';').map(|s| s.trim());
+            let name = parts.next().unwrap_or_default().to_ascii_lowercase();
+            let params = parts
+                .filter(|p| !p.is_empty())
+                .map(|p| match p.find('=') {
+                    Some(i) => (
+                        p[..i].trim().to_ascii_lowercase(),
+                        Some(p[i + 1..].trim().trim_matches('"').to_string()),
+                    ),
+                    None => (p.to_ascii_lowercase(), None),
+                })
+                .collect();
+            (name, params)
+        })
+        .collect()
+}
+
+fn window_bits(value: &Option<String>) -> Option<u8> {
+    value
+        .as_ref()
+        .and_then(|v| v.parse::<u8>().ok())
+        .filter(|bits| (8..=MAX_WINDOW_BITS).contains(bits))
+}
+
+///
+/// 服务端: 按顺序选出第一个可以接受的 permessage-deflate 提议, 返回回复的扩展和压缩解压器
+///
+pub(crate) fn server_accept(
+    options: &PerMessageDeflateOptions,
+    extensions: &str,
+) -> Option<(String, Deflater, Inflater)> {
+    'offers: for (name, params) in parse_extensions(extensions) {
+        if name != PERMESSAGE_DEFLATE {
+            continue;
+        }
+        let mut server_no_context_takeover = options.server_no_context_takeover;
+        let mut client_no_context_takeover = options.client_no_context_takeover;
+        let mut seen = vec![];
+        for (param, value) in &params {
+            if seen.contains(param) {
+                continue 'offers;
+            }
+            seen.push(param.clone());
+            match param.as_str() {
+                SERVER_NO_CONTEXT_TAKEOVER if value.is_none() => server_no_context_takeover = true,
+                CLIENT_NO_CONTEXT_TAKEOVER if value.is_none() => client_no_context_takeover = true,
+                // 不能用更小的窗口压缩
+                SERVER_MAX_WINDOW_BITS if window_bits(value) == Some(MAX_WINDOW_BITS) => {}
+                // 客户端用多大的窗口都可以解压
+                CLIENT_MAX_WINDOW_BITS if value.is_none() || window_bits(value).is_some() => {}
+                _ => continue 'offers,
+            }
+        }
+
+        let mut response = PERMESSAGE_DEFLATE.to_string();
+        if server_no_context_takeover {
+            response.push_str("; ");
+            response.push_str(SERVER_NO_CONTEXT_TAKEOVER);
+        }
+        if client_no_context_takeover {
+            response.push_str("; ");
+            response.push_str(CLIENT_NO_CONTEXT_TAKEOVER);
+        }
+        return Some((
+            response,
+            Deflater::new(options.compression_level, server_no_context_takeover),
+            Inflater::new(client_no_context_takeover),
+        ));
+    }
+    None
+}
+
+///
+/// 客户端: 升级请求里的 permessage-deflate 提议
+///
+pub(crate) fn client_offer(options: &PerMessageDeflateOptions) -> String {
+    let mut offer = PERMESSAGE_DEFLATE.to_string();
+    if options.client_no_context_takeover {
+        offer.push_str("; ");
+        offer.push_str(CLIENT_NO_CONTEXT_TAKEOVER);
+    }
+    if options.server_no_context_takeover {
+        offer.push_str("; ");
+        offer.push_str(SERVER_NO_CONTEXT_TAKEOVER);
+    }
+    offer
+}
+
+///
+/// 客户端: 检查服务端回复的扩展, 服务端没有接受 permessage-deflate 时返回 None
+///
+pub(crate) fn client_accept(
+    options: &PerMessageDeflateOptions,
+    extensions: &str,
+) -> Result<Option<(Deflater, Inflater)>, String> {
+    let params = match parse_extensions(extensions)
+        .into_iter()
+        .find(|(name, _)| name == PERMESSAGE_DEFLATE)
+    {
+        Some((_, params)) => params,
+        None => return Ok(None),
+    };
+    let mut server_no_context_takeover = false;
+    let mut client_no_context_takeover = options.client_no_context_takeover;
+    for (param, value) in &params {
+        match param.as_str() {
+            SERVER_NO_CONTEXT_TAKEOVER if value.is_none() => server_no_context_takeover = true,
+            CLIENT_NO_CONTEXT_TAKEOVER if value.is_none() => client_no_context_takeover = true,
+            SERVER_MAX_WINDOW_BITS if window_bits(value).is_some() => {}
+            _ => {
+                return Err(format!(
+                    "invalid {} parameter: {}",
+                    PERMESSAGE_DEFLATE, param
+                ))
+            }
+        }
+    }
+    if options.server_no_context_takeover && !server_no_context_takeover {
+        return Err(format!(
+            "{} rejected by the server",
+            SERVER_NO_CONTEXT_TAKEOVER
+        ));
+    }
+    Ok(Some((
+        Deflater::new(options.compression_level, client_no_context_takeover),
+        Inflater::new(server_no_context_takeover),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 7692 7.2.3.1 和 7.2.3.2
+    const HELLO: &[u8] = &[0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00];
+    const HELLO_WITH_CONTEXT: &[u8] = &[0xf2, 0x00, 0x11, 0x00, 0x00];
+
+    #[test]
+    fn inflates_rfc_7692_hello() {
+        let mut inflater = Inflater::new(false);
+        assert_eq!(inflater.inflate(HELLO, 1024).unwrap(), b"Hello");
+        // 第二个消息引用了第一个消息的上下文
+        assert_eq!(
+            inflater.inflate(HELLO_WITH_CONTEXT, 1024).unwrap(),
+            b"Hello"
+        );
+    }
+
+    #[test]
+    fn deflates_rfc_7692_hello() {
+        let mut deflater = Deflater::new(6, true);
+        assert_eq!(deflater.deflate(b"Hello").unwrap(), HELLO);
+        assert_eq!(deflater.deflate(b"Hello").unwrap(), HELLO);
+    }
+
+    #[test]
+    fn strips_sync_flush_tail() {
+        let mut deflater = Deflater::new(6, false);
+        let mut inflater = Inflater::new(false);
+        let message = b"a message that is long enough to compress, a message that is long enough";
+        let compressed = deflater.deflate(message).unwrap();
+        assert!(!compressed.ends_with(&DEFLATE_TAIL));
+        assert!(compressed.len() < message.len());
+        assert_eq!(inflater.inflate(&compressed, 1024).unwrap(), &message[..]);
+    }
+
+    #[test]
+    fn empty_message() {
+        let mut deflater = Deflater::new(6, false);
+        let mut inflater = Inflater::new(false);
+        assert_eq!(deflater.deflate(b"").unwrap(), vec![0x00]);
+        assert_eq!(inflater.inflate(&[0x00], 1024).unwrap(), b"");
+        // 之后的消息不受影响
+        let compressed = deflater.deflate(b"Hello").unwrap();
+        assert_eq!(inflater.inflate(&compressed, 1024).unwrap(), b"Hello");
+    }
+
+    #[test]
+    fn context_takeover() {
+        let message = b"a repeated message, a repeated message";
+        let mut deflater = Deflater::new(6, false);
+        let mut inflater = Inflater::new(false);
+        let first = deflater.deflate(message).unwrap();
+        let second = deflater.deflate(message).unwrap();
+        // 第二个消息引用第一个消息, 压缩之后更短
+        assert!(second.len() < first.len());
+        assert_eq!(inflater.inflate(&first, 1024).unwrap(), &message[..]);
+        assert_eq!(inflater.inflate(&second, 1024).unwrap(), &message[..]);
+        // 没有第一个消息的上下文不能解压
+        assert_ne!(
+            Inflater::new(false).inflate(&second, 1024).ok().as_deref(),
+            Some(&message[..])
+        );
+    }
+
+    #[test]
+    fn no_context_takeover() {
+        let message = b"a repeated message, a repeated message";
+        let mut deflater = Deflater::new(6, true);
+        let first = deflater.deflate(message).unwrap();
+        let second = deflater.deflate(message).unwrap();
+        assert_eq!(first, second);
+        // 每个消息都可以单独解压
+        assert_eq!(
+            Inflater::new(true).inflate(&second, 1024).unwrap(),
+            &message[..]
+        );
+        let mut inflater = Inflater::new(true);
+        assert_eq!(inflater.inflate(&first, 1024).unwrap(), &message[..]);
+        assert_eq!(inflater.inflate(&second, 1024).unwrap(), &message[..]);
+    }
+
+    #[test]
+    fn large_message_round_trip() {
+        let message = (0..200_000u32)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<u8>>();
+        let mut deflater = Deflater::new(1, false);
+        let compressed = deflater.deflate(&message).unwrap();
+        assert_eq!(
+            Inflater::new(false)
+                .inflate(&compressed, message.len())
+                .unwrap(),
+            message
+        );
+    }
+
+    #[test]
+    fn decompression_bomb() {
+        let mut deflater = Deflater::new(9, false);
+        let compressed = deflater.deflate(&vec![0u8; 1 << 20]).unwrap();
+        assert!(compressed.len() < 2048);
+        let (status, _) = Inflater::new(false)
+            .inflate(&compressed, 65536)
+            .unwrap_err();
+        assert_eq!(status, close_status::MESSAGE_TOO_BIG);
+        assert_eq!(
+            Inflater::new(false)
+                .inflate(&compressed, 1 << 20)
+                .unwrap()
+                .len(),
+            1 << 20
+        );
+    }
+
+    #[test]
+    fn invalid_data() {
+        let (status, _) = Inflater::new(false)
+            .inflate(&[0xff, 0xff, 0xff], 1024)
+            .unwrap_err();
+        assert_eq!(status, close_status::INVALID_PAYLOAD_DATA);
+    }
+
+    #[test]
+    fn server_negotiation() {
+        let options = PerMessageDeflateOptions::default();
+        let accept = |offer: &str| server_accept(&options, offer).map(|(response, _, _)| response);
+        assert_eq!(
+            accept("permessage-deflate"),
+            Some("permessage-deflate".to_string())
+        );
+        assert_eq!(
+            accept("x-webkit-deflate-frame, permessage-deflate; client_no_context_takeover"),
+            Some("permessage-deflate; client_no_context_takeover".to_string())
+        );
+        // 客户端的窗口不受限制, 回复里不带 client_max_window_bits
+        assert_eq!(
+            accept("permessage-deflate; client_max_window_bits"),
+            Some("permessage-deflate".to_string())
+        );
+        assert_eq!(
+            accept("permessage-deflate; client_max_window_bits=10"),
+            Some("permessage-deflate".to_string())
+        );
+        // 只能用 15 bits 的窗口压缩, 选下一个提议
+        assert_eq!(
+            accept("permessage-deflate; server_max_window_bits=10, permessage-deflate; server_no_context_takeover"),
+            Some("permessage-deflate; server_no_context_takeover".to_string())
+        );
+        assert_eq!(
+            accept("permessage-deflate; server_max_window_bits=15"),
+            Some("permessage-deflate".to_string())
+        );
+        assert_eq!(accept("permessage-deflate; unknown"), None);
+        assert_eq!(
+            accept("permessage-deflate; server_no_context_takeover; server_no_context_takeover"),
+            None
+        );
+        assert_eq!(accept("x-webkit-deflate-frame"), None);
+    }
+
+    #[test]
+    fn client_negotiation() {
+        let options = PerMessageDeflateOptions {
+            server_no_context_takeover: true,
+            ..PerMessageDeflateOptions::default()
+        };
+        assert_eq!(
+            client_offer(&options),
+            "permessage-deflate; server_no_context_takeover"
+        );
+        assert!(
+            client_accept(&options, "permessage-deflate; server_no_context_takeover")
+                .unwrap()
+                .is_some()
+        );
+        assert!(client_accept(&options, "permessage-deflate").is_err());
+        // 没有提出 client_max_window_bits, 服务端不能回复
+        assert!(client_accept(
+            &options,
+            "permessage-deflate; server_no_context_takeover; client_max_window_bits=10"
+        )
+        .is_err());
+        assert!(client_accept(&options, "x-other").unwrap().is_none());
+    }
+}
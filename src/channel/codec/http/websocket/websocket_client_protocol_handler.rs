@@ -0,0 +1,691 @@
+use std::any::Any;
+use std::io::ErrorKind;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+
+use crate::channel::channel_handler_ctx::ChannelInboundHandlerCtx;
+use crate::channel::codec::byte_to_message_decoder::ByteToMessageHandler;
+use crate::channel::codec::http::http_headers::{names, values, HttpHeaders};
+use crate::channel::codec::http::http_message::{
+    FullHttpRequest, FullHttpResponse, HttpMethod, HttpVersion,
+};
+use crate::channel::codec::http::websocket::per_message_deflate::{
+    client_accept, client_offer, Deflater, Inflater, PerMessageDeflateOptions,
+};
+use crate::channel::codec::http::websocket::websocket_frame::close_status;
+use crate::channel::codec::http::websocket::websocket_frame_decoder::{
+    WebSocketFrameDecoder, DEFAULT_MAX_FRAME_PAYLOAD_LENGTH,
+};
+use crate::channel::codec::http::websocket::websocket_frame_encoder::WebSocketFrameEncoder;
+use crate::channel::codec::http::websocket::websocket_handshake::{
+    accept_key, split_list, WEBSOCKET_VERSION,
+};
+use crate::channel::codec::http::websocket::websocket_protocol::{
+    switch_to_websocket, WebSocketProtocolDecoder, WebSocketProtocolEncoder,
+};
+use crate::channel::handler::{ChannelCodec, ChannelInboundHandler, ChannelOutboundHandler};
+use crate::errors::{CodecErrorKind, RettyErrorKind};
+
+///
+/// WebSocket 客户端协议处理器, 用 ChannelInboundHandlerPipe::add_last_codec 注册, 加在 HttpClientCodec 和 HttpObjectAggregator 后面
+///
+/// 连接建立之后发送升级请求, 检查服务端的 101 响应(Sec-WebSocket-Accept, 子协议) 之后握手完成,
+/// 握手完成时把自己替换成帧解码器(id 为 WebSocketFrameDecoder), 并移除 HttpClientCodec 和 HttpObjectAggregator, 101 后面已经读到的数据交给帧解码器
+/// 下一个handler 的 channel_active 在握手完成之后才触发, 之后才能写 WebSocketFrame, 紧接着触发 WebSocketClientHandshakeComplete 用户事件
+/// 写出的帧使用随机的掩码, 收到的帧不能带掩码, 分片的消息合并之后再往下传, 收到 Ping 自动回复 Pong
+///
+/// 握手失败时触发 CodecErrorKind::HandshakeFailed 异常并关闭连接
+/// 连接关闭时如果对端的关闭帧不是 1000, 在 channel_inactive 之后触发 CodecErrorKind::WebSocketClosed(状态码) 异常,
+/// 没有收到关闭帧就断开时状态码为 1006
+/// 启用 compression 之后在升级请求里提出 permessage-deflate, 服务端接受时压缩和解压对下一个handler 透明
+///
+pub struct WebSocketClientProtocolHandler {
+    host: String,
+    path: String,
+    subprotocols: Vec<String>,
+    custom_headers: HttpHeaders,
+    allow_extensions: bool,
+    max_frame_payload_length: usize,
+    compression: Option<PerMessageDeflateOptions>,
+}
+
+impl WebSocketClientProtocolHandler {
+    ///
+    /// websocket_url: 比如 ws://127.0.0.1:8080/ws?token=1, Host 和请求的路径从这里取
+    /// 不支持 TLS, wss:// 和其他格式不对的 url 返回 InvalidInput
+    ///
+    pub fn new(websocket_url: &str) -> Result<WebSocketClientProtocolHandler, RettyErrorKind> {
+        WebSocketClientProtocolHandler::new_with_options(
+            websocket_url,
+            None,
+            false,
+            DEFAULT_MAX_FRAME_PAYLOAD_LENGTH,
+        )
+    }
+
+    ///
+    /// subprotocols: 逗号分隔的请求的子协议, 按优先级排列
+    ///
+    pub fn new_with_options(
+        websocket_url: &str,
+        subprotocols: Option<&str>,
+        allow_extensions: bool,
+        max_frame_payload_length: usize,
+    ) -> Result<WebSocketClientProtocolHandler, RettyErrorKind> {
+        let (host, path) = parse_url(websocket_url)?;
+        Ok(WebSocketClientProtocolHandler {
+            host,
+            path,
+            subprotocols: subprotocols.map(split_list).unwrap_or_default(),
+            custom_headers: HttpHeaders::new(),
+            allow_extensions,
+            max_frame_payload_length,
+            compression: None,
+        })
+    }
+
+    ///
+    /// 升级请求里额外的 header, 比如 Origin, Cookie
+    ///
+    pub fn add_header<N: Into<String>, V: Into<String>>(&mut self, name: N, value: V) -> &mut Self {
+        self.custom_headers.add(name, value);
+        self
+    }
+
+    ///
+    /// 请求使用 permessage-deflate 扩展
+    ///
+    pub fn compression(&mut self, options: PerMessageDeflateOptions) -> &mut Self {
+        self.compression = Some(options);
+        self
+    }
+}
+
+///
+/// ws://host[:port][/path], 返回 Host 和请求的路径
+///
+fn parse_url(websocket_url: &str) -> Result<(String, String), RettyErrorKind> {
+    if websocket_url.starts_with("wss://") {
+        return Err(RettyErrorKind::new(
+            ErrorKind::InvalidInput,
+            format!("wss:// is not supported (no TLS): {}", websocket_url),
+        ));
+    }
+    let rest = websocket_url.strip_prefix("ws://").ok_or_else(|| {
+        RettyErrorKind::new(
+            ErrorKind::InvalidInput,
+            format!("invalid WebSocket url: {}", websocket_url),
+        )
+    })?;
+    let (host, path) = match rest.find(['/', '?']) {
+        Some(i) if rest.as_bytes()[i] == b'?' => {
+            (rest[..i].to_string(), format!("/{}", &rest[i..]))
+        }
+        Some(i) => (rest[..i].to_string(), rest[i..].to_string()),
+        None => (rest.to_string(), "/".to_string()),
+    };
+    if host.is_empty() {
+        return Err(RettyErrorKind::new(
+            ErrorKind::InvalidInput,
+            format!("invalid WebSocket url, missing host: {}", websocket_url),
+        ));
+    }
+    Ok((host, path))
+}
+
+///
+/// Sec-WebSocket-Key, 16 个随机字节的 base64, 不用 UUID 是因为 v4 UUID 里有 6 个固定的版本位
+///
+fn handshake_key() -> Result<String, RettyErrorKind> {
+    let mut nonce = [0u8; 16];
+    getrandom::getrandom(&mut nonce).map_err(|e| {
+        RettyErrorKind::new(
+            ErrorKind::Other,
+            format!("failed to generate Sec-WebSocket-Key: {}", e),
+        )
+    })?;
+    Ok(base64::encode(nonce))
+}
+
+impl ChannelCodec for WebSocketClientProtocolHandler {
+    fn into_handlers(
+        self,
+    ) -> (
+        Box<dyn ChannelInboundHandler + Send + Sync>,
+        Box<dyn ChannelOutboundHandler + Send + Sync>,
+    ) {
+        let close_sent = Arc::new(AtomicBool::new(false));
+        let deflater = Arc::new(Mutex::new(None));
+        (
+            Box::new(WebSocketClientProtocolDecoder {
+                host: self.host,
+                path: self.path,
+                subprotocols: self.subprotocols,
+                custom_headers: self.custom_headers,
+                compression: self.compression,
+                expected_accept: String::new(),
+                allow_extensions: self.allow_extensions,
+                max_frame_payload_length: self.max_frame_payload_length,
+                close_sent: close_sent.clone(),
+                deflater: deflater.clone(),
+                state: HandshakeState::Idle,
+            }),
+            Box::new(WebSocketProtocolEncoder::new(
+                WebSocketFrameEncoder::new(true),
+                close_sent,
+                deflater,
+            )),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum HandshakeState {
+    Idle,
+    // 已经发出升级请求, 在等 101
+    Sent,
+    Failed,
+}
+
+///
+/// 客户端握手完成, 检查完服务端的 101 响应之后通过 user_event_triggered 传给后面的 handler
+///
+#[derive(Debug, Clone)]
+pub struct WebSocketClientHandshakeComplete {
+    pub response_headers: HttpHeaders,
+    pub selected_subprotocol: Option<String>,
+}
+
+struct WebSocketClientProtocolDecoder {
+    host: String,
+    path: String,
+    subprotocols: Vec<String>,
+    custom_headers: HttpHeaders,
+    compression: Option<PerMessageDeflateOptions>,
+    // 期望服务端返回的 Sec-WebSocket-Accept
+    expected_accept: String,
+    allow_extensions: bool,
+    max_frame_payload_length: usize,
+    // 和出站部分共享
+    close_sent: Arc<AtomicBool>,
+    // 和出站部分共享, 服务端接受 permessage-deflate 之后设置
+    deflater: Arc<Mutex<Option<Deflater>>>,
+    state: HandshakeState,
+}
+
+impl WebSocketClientProtocolDecoder {
+    fn send_handshake(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        let key = match handshake_key() {
+            Ok(key) => key,
+            Err(e) => return self.handshake_failed(channel_handler_ctx, e.message),
+        };
+        self.expected_accept = accept_key(&key);
+
+        let mut request = FullHttpRequest::new(
+            HttpVersion::Http11,
+            HttpMethod::Get,
+            self.path.clone(),
+            vec![],
+        );
+        request
+            .headers
+            .set(names::HOST, self.host.clone())
+            .set(names::UPGRADE, values::WEBSOCKET)
+            .set(names::CONNECTION, "Upgrade")
+            .set(names::SEC_WEBSOCKET_KEY, key)
+            .set(names::SEC_WEBSOCKET_VERSION, WEBSOCKET_VERSION);
+        if !self.subprotocols.is_empty() {
+            request
+                .headers
+                .set(names::SEC_WEBSOCKET_PROTOCOL, self.subprotocols.join(", "));
+        }
+        if let Some(options) = self.compression.as_ref() {
+            request
+                .headers
+                .set(names::SEC_WEBSOCKET_EXTENSIONS, client_offer(options));
+        }
+        for (name, value) in self.custom_headers.iter() {
+            request.headers.add(name, value);
+        }
+        self.state = HandshakeState::Sent;
+        channel_handler_ctx
+            .write_and_flush(&mut request)
+            .add_close_on_failure_listener();
+    }
+
+    ///
+    /// 检查 101 响应, 返回协商好的 permessage-deflate 压缩和解压器
+    ///
+    fn verify(&self, response: &FullHttpResponse) -> Result<Option<(Deflater, Inflater)>, String> {
+        let headers = &response.headers;
+        if response.status.code != 101 {
+            return Err(format!(
+                "invalid handshake response status: {}",
+                response.status.code
+            ));
+        }
+        if !headers.contains_value(names::UPGRADE, values::WEBSOCKET) {
+            return Err(format!(
+                "invalid handshake response upgrade: {:?}",
+                headers.get(names::UPGRADE)
+            ));
+        }
+        if !headers.contains_value(names::CONNECTION, values::UPGRADE) {
+            return Err(format!(
+                "invalid handshake response connection: {:?}",
+                headers.get(names::CONNECTION)
+            ));
+        }
+        match headers.get(names::SEC_WEBSOCKET_ACCEPT) {
+            Some(accept) if accept.trim() == self.expected_accept => {}
+            accept => return Err(format!("invalid challenge: {:?}", accept)),
+        }
+        if let Some(subprotocol) = headers.get(names::SEC_WEBSOCKET_PROTOCOL) {
+            let subprotocol = subprotocol.trim();
+            if !self.subprotocols.iter().any(|s| s == subprotocol) {
+                return Err(format!("invalid subprotocol: {}", subprotocol));
+            }
+        }
+        match (
+            self.compression.as_ref(),
+            headers.get(names::SEC_WEBSOCKET_EXTENSIONS),
+        ) {
+            (Some(options), Some(extensions)) => client_accept(options, extensions),
+            _ => Ok(None),
+        }
+    }
+
+    fn complete_handshake(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        response: &FullHttpResponse,
+        deflate: Option<(Deflater, Inflater)>,
+    ) {
+        let mut decoder = WebSocketProtocolDecoder::new(
+            WebSocketFrameDecoder::new(false, self.allow_extensions, self.max_frame_payload_length),
+            self.close_sent.clone(),
+        );
+        if let Some((deflater, inflater)) = deflate {
+            decoder.enable_deflate(inflater);
+            *self.deflater.lock().unwrap() = Some(deflater);
+        }
+        let frame_decoder = WebSocketClientFrameDecoder {
+            handler: ByteToMessageHandler::new(decoder),
+        };
+        if let Err(e) = switch_to_websocket(
+            channel_handler_ctx,
+            Box::new(frame_decoder),
+            "HttpClientCodec",
+        ) {
+            return self.handshake_failed(channel_handler_ctx, e.message);
+        }
+        channel_handler_ctx.fire_channel_active();
+        channel_handler_ctx.fire_user_event(Box::new(WebSocketClientHandshakeComplete {
+            response_headers: response.headers.clone(),
+            selected_subprotocol: response
+                .headers
+                .get(names::SEC_WEBSOCKET_PROTOCOL)
+                .map(|s| s.trim().to_string()),
+        }));
+    }
+
+    fn handshake_failed(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        reason: String,
+    ) {
+        self.state = HandshakeState::Failed;
+        channel_handler_ctx.fire_channel_exception(RettyErrorKind::codec(
+            CodecErrorKind::HandshakeFailed,
+            reason,
+        ));
+        channel_handler_ctx.close();
+    }
+}
+
+impl ChannelInboundHandler for WebSocketClientProtocolDecoder {
+    fn id(&self) -> String {
+        "WebSocketClientProtocolHandler".to_string()
+    }
+
+    fn channel_active(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        self.send_handshake(channel_handler_ctx);
+    }
+
+    fn channel_inactive(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        channel_handler_ctx.fire_channel_inactive();
+        if self.state == HandshakeState::Sent {
+            channel_handler_ctx.fire_channel_exception(RettyErrorKind::codec(
+                CodecErrorKind::HandshakeFailed,
+                "channel gone inactive before the handshake completed".to_string(),
+            ));
+        }
+    }
+
+    fn channel_read(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        message: &mut dyn Any,
+    ) {
+        match self.state {
+            HandshakeState::Sent => match message.downcast_ref::<FullHttpResponse>() {
+                Some(response) => match self.verify(response) {
+                    Ok(deflate) => self.complete_handshake(channel_handler_ctx, response, deflate),
+                    Err(reason) => self.handshake_failed(channel_handler_ctx, reason),
+                },
+                None => channel_handler_ctx.fire_channel_read(message),
+            },
+            // 握手失败之后丢弃收到的数据
+            HandshakeState::Failed => {}
+            HandshakeState::Idle => channel_handler_ctx.fire_channel_read(message),
+        }
+    }
+
+    fn channel_exception(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        error: RettyErrorKind,
+    ) {
+        channel_handler_ctx.fire_channel_exception(error);
+    }
+}
+
+///
+/// 握手完成之后替换 WebSocketClientProtocolHandler 的帧解码器, 连接关闭时报告对端的关闭状态码
+///
+struct WebSocketClientFrameDecoder {
+    handler: ByteToMessageHandler<WebSocketProtocolDecoder>,
+}
+
+impl ChannelInboundHandler for WebSocketClientFrameDecoder {
+    fn id(&self) -> String {
+        self.handler.id()
+    }
+
+    fn channel_active(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        self.handler.channel_active(channel_handler_ctx);
+    }
+
+    fn channel_inactive(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        self.handler.channel_inactive(channel_handler_ctx);
+        let (code, reason) = match self.handler.decoder().close_status() {
+            Some((code, _)) if code == close_status::NORMAL_CLOSURE => return,
+            Some((code, reason)) => (code, reason.to_string()),
+            None => return,
+        };
+        let message = if code == close_status::ABNORMAL_CLOSURE {
+            "connection closed without receiving a close frame".to_string()
+        } else {
+            format!("connection closed with status {}: {}", code, reason)
+        };
+        channel_handler_ctx.fire_channel_exception(RettyErrorKind::codec(
+            CodecErrorKind::WebSocketClosed(code),
+            message,
+        ));
+    }
+
+    fn channel_read(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        message: &mut dyn Any,
+    ) {
+        self.handler.channel_read(channel_handler_ctx, message);
+    }
+
+    fn channel_exception(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        error: RettyErrorKind,
+    ) {
+        self.handler.channel_exception(channel_handler_ctx, error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_host_and_path() {
+        assert_eq!(
+            parse_url("ws://127.0.0.1:8080/ws?token=1"),
+            Ok(("127.0.0.1:8080".to_string(), "/ws?token=1".to_string()))
+        );
+        assert_eq!(
+            parse_url("ws://example.com"),
+            Ok(("example.com".to_string(), "/".to_string()))
+        );
+        assert_eq!(
+            parse_url("ws://example.com?a=b"),
+            Ok(("example.com".to_string(), "/?a=b".to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_urls() {
+        for url in &[
+            "wss://example.com/ws",
+            "http://example.com/ws",
+            "ws://",
+            "ws:///ws",
+        ] {
+            let e = WebSocketClientProtocolHandler::new(url).err().unwrap();
+            assert_eq!(e.kind, ErrorKind::InvalidInput, "{}", url);
+        }
+        let e = parse_url("wss://example.com/ws").unwrap_err();
+        assert!(e.message.contains("wss:// is not supported"));
+    }
+
+    use crate::channel::codec::http::http_client_codec::HttpClientCodec;
+    use crate::channel::codec::http::http_object_aggregator::HttpObjectAggregator;
+    use crate::channel::codec::http::websocket::websocket_frame::WebSocketFrame;
+    use crate::channel::codec::http::websocket::websocket_test_frames::{frame, parse_frame};
+    use crate::channel::embedded_channel::EmbeddedChannel;
+    use crate::channel::handler_pipe::ChannelHandlerPipe;
+
+    fn client_channel(subprotocols: Option<&str>) -> EmbeddedChannel<WebSocketFrame> {
+        let mut handler_pipe = ChannelHandlerPipe::new();
+        handler_pipe.add_last_codec(HttpClientCodec::new());
+        handler_pipe.add_last_inbound(Box::new(HttpObjectAggregator::new(1024)));
+        handler_pipe.add_last_codec(
+            WebSocketClientProtocolHandler::new_with_options(
+                "ws://example.com/ws?token=1",
+                subprotocols,
+                false,
+                DEFAULT_MAX_FRAME_PAYLOAD_LENGTH,
+            )
+            .unwrap(),
+        );
+        let channel = EmbeddedChannel::new(handler_pipe);
+        channel.active();
+        channel
+    }
+
+    ///
+    /// 读出升级请求, 返回服务端应该回复的 Sec-WebSocket-Accept
+    ///
+    fn read_handshake(channel: &mut EmbeddedChannel<WebSocketFrame>) -> String {
+        let request = String::from_utf8(channel.read_outbound()).unwrap();
+        assert!(
+            request.starts_with("GET /ws?token=1 HTTP/1.1\r\n"),
+            "{}",
+            request
+        );
+        let key = request
+            .split("\r\n")
+            .find_map(|line| line.strip_prefix("sec-websocket-key: "))
+            .unwrap();
+        assert_eq!(base64::decode(key).unwrap().len(), 16);
+        accept_key(key)
+    }
+
+    fn response(accept: &str, extra_headers: &str) -> Vec<u8> {
+        format!(
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {}\r\n{}\r\n",
+            accept, extra_headers
+        )
+        .into_bytes()
+    }
+
+    fn handshake(channel: &mut EmbeddedChannel<WebSocketFrame>) {
+        let accept = read_handshake(channel);
+        channel.write_inbound(&response(&accept, ""));
+        assert!(channel.take_exceptions().is_empty());
+        channel.take_events();
+    }
+
+    fn assert_handshake_failed(channel: &EmbeddedChannel<WebSocketFrame>) {
+        let errors = channel.take_exceptions();
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(
+            errors[0].is_codec_error(CodecErrorKind::HandshakeFailed),
+            "{:?}",
+            errors[0]
+        );
+        assert!(!channel.is_active());
+        assert!(!channel
+            .take_events()
+            .iter()
+            .any(|e| e.is::<WebSocketClientHandshakeComplete>()));
+    }
+
+    #[test]
+    fn sends_upgrade_request_when_active() {
+        let mut channel = client_channel(Some("chat, superchat"));
+        let request = String::from_utf8(channel.read_outbound()).unwrap();
+        for header in &[
+            "host: example.com\r\n",
+            "upgrade: websocket\r\n",
+            "connection: Upgrade\r\n",
+            "sec-websocket-version: 13\r\n",
+            "sec-websocket-protocol: chat, superchat\r\n",
+        ] {
+            assert!(request.contains(header), "{}", request);
+        }
+
+        // 每次连接使用不同的 key
+        let first = read_handshake(&mut client_channel(None));
+        let second = read_handshake(&mut client_channel(None));
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn completes_handshake_and_replaces_http_handlers() {
+        let mut channel = client_channel(Some("chat, superchat"));
+        let accept = read_handshake(&mut channel);
+        // 第一帧和 101 在同一次读里
+        let mut bytes = response(&accept, "Sec-WebSocket-Protocol: superchat\r\n");
+        bytes.extend_from_slice(&frame(0x81, b"Hello", None));
+        channel.write_inbound(&bytes);
+
+        assert!(channel.take_exceptions().is_empty());
+        let events = channel.take_events();
+        let complete = events
+            .iter()
+            .find_map(|e| e.downcast_ref::<WebSocketClientHandshakeComplete>())
+            .unwrap();
+        assert_eq!(complete.selected_subprotocol.as_deref(), Some("superchat"));
+        assert_eq!(
+            channel.pipeline().names(),
+            vec!["HEAD", "WebSocketFrameDecoder", "EmbeddedChannelCollector"]
+        );
+        assert_eq!(
+            channel.read_inbound(),
+            Some(WebSocketFrame::Text("Hello".to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_wrong_accept() {
+        let mut channel = client_channel(None);
+        read_handshake(&mut channel);
+        channel.write_inbound(&response("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=", ""));
+        assert_handshake_failed(&channel);
+    }
+
+    #[test]
+    fn rejects_non_101_response() {
+        let mut channel = client_channel(None);
+        read_handshake(&mut channel);
+        channel.write_inbound(b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n");
+        assert_handshake_failed(&channel);
+    }
+
+    #[test]
+    fn rejects_unrequested_subprotocol() {
+        let mut channel = client_channel(Some("chat"));
+        let accept = read_handshake(&mut channel);
+        channel.write_inbound(&response(&accept, "Sec-WebSocket-Protocol: superchat\r\n"));
+        assert_handshake_failed(&channel);
+
+        let mut channel = client_channel(None);
+        let accept = read_handshake(&mut channel);
+        channel.write_inbound(&response(&accept, "Sec-WebSocket-Protocol: chat\r\n"));
+        assert_handshake_failed(&channel);
+    }
+
+    #[test]
+    fn masks_outbound_frames() {
+        let mut channel = client_channel(None);
+        handshake(&mut channel);
+
+        let payload = b"Hello, WebSocket".to_vec();
+        channel.write_outbound(&mut WebSocketFrame::Binary(payload.clone()));
+        let bytes = channel.read_outbound();
+        let (b0, masked, unmasked, length) = parse_frame(&bytes);
+        assert_eq!(b0, 0x82);
+        assert!(masked);
+        assert_eq!(unmasked, payload);
+        assert_eq!(length, bytes.len());
+        assert_ne!(&bytes[6..], &payload[..]);
+    }
+
+    #[test]
+    fn reports_close_status_after_inactive() {
+        // 没有收到关闭帧
+        let mut channel = client_channel(None);
+        handshake(&mut channel);
+        channel.finish();
+        let errors = channel.take_exceptions();
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(errors[0].is_codec_error(CodecErrorKind::WebSocketClosed(
+            close_status::ABNORMAL_CLOSURE
+        )));
+
+        // 对端以 1001 关闭
+        let mut channel = client_channel(None);
+        handshake(&mut channel);
+        channel.write_inbound(&frame(0x88, b"\x03\xe9going away", None));
+        assert!(channel.take_exceptions().is_empty());
+        channel.finish();
+        let errors = channel.take_exceptions();
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(errors[0].is_codec_error(CodecErrorKind::WebSocketClosed(
+            close_status::ENDPOINT_UNAVAILABLE
+        )));
+
+        // 正常关闭
+        let mut channel = client_channel(None);
+        handshake(&mut channel);
+        channel.write_inbound(&frame(0x88, b"\x03\xe8", None));
+        channel.finish();
+        assert!(channel.take_exceptions().is_empty());
+    }
+
+    #[test]
+    fn inactive_before_handshake_fails_the_handshake() {
+        let mut channel = client_channel(None);
+        read_handshake(&mut channel);
+        channel.finish();
+        // HttpClientCodec 还会报告没有收到的响应
+        let errors = channel.take_exceptions();
+        assert!(
+            errors
+                .iter()
+                .any(|e| e.is_codec_error(CodecErrorKind::HandshakeFailed)),
+            "{:?}",
+            errors
+        );
+    }
+}
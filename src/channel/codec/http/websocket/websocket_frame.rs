@@ -0,0 +1,183 @@
+use std::fmt::{Display, Formatter};
+
+///
+/// 关闭帧的状态码, 见 RFC 6455 7.4
+///
+pub mod close_status {
+    pub const NORMAL_CLOSURE: u16 = 1000;
+    pub const ENDPOINT_UNAVAILABLE: u16 = 1001;
+    pub const PROTOCOL_ERROR: u16 = 1002;
+    pub const INVALID_MESSAGE_TYPE: u16 = 1003;
+    // 1005 和 1006 不能出现在关闭帧里, 只用来报告关闭的原因
+    pub const NO_STATUS_RECEIVED: u16 = 1005;
+    pub const ABNORMAL_CLOSURE: u16 = 1006;
+    pub const INVALID_PAYLOAD_DATA: u16 = 1007;
+    pub const POLICY_VIOLATION: u16 = 1008;
+    pub const MESSAGE_TOO_BIG: u16 = 1009;
+    pub const MANDATORY_EXTENSION: u16 = 1010;
+    pub const INTERNAL_SERVER_ERROR: u16 = 1011;
+
+    ///
+    /// 可以出现在关闭帧里的状态码
+    ///
+    pub fn is_valid(code: u16) -> bool {
+        (1000..=1003).contains(&code)
+            || (1007..=1014).contains(&code)
+            || (3000..=4999).contains(&code)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum WebSocketOpcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl WebSocketOpcode {
+    pub fn from_u8(opcode: u8) -> Option<WebSocketOpcode> {
+        match opcode {
+            0x0 => Some(WebSocketOpcode::Continuation),
+            0x1 => Some(WebSocketOpcode::Text),
+            0x2 => Some(WebSocketOpcode::Binary),
+            0x8 => Some(WebSocketOpcode::Close),
+            0x9 => Some(WebSocketOpcode::Ping),
+            0xA => Some(WebSocketOpcode::Pong),
+            _ => None,
+        }
+    }
+
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            WebSocketOpcode::Continuation => 0x0,
+            WebSocketOpcode::Text => 0x1,
+            WebSocketOpcode::Binary => 0x2,
+            WebSocketOpcode::Close => 0x8,
+            WebSocketOpcode::Ping => 0x9,
+            WebSocketOpcode::Pong => 0xA,
+        }
+    }
+
+    ///
+    /// Close / Ping / Pong 是控制帧, 不能分片, payload 不能超过 125 字节
+    ///
+    pub fn is_control(&self) -> bool {
+        self.as_u8() & 0x8 != 0
+    }
+}
+
+///
+/// 线路上的一帧, payload 已经去掉掩码
+/// 一个分片的消息是一个 Text / Binary 帧加上零个或多个 Continuation 帧, 最后一帧 fin 为 true
+///
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RawWebSocketFrame {
+    pub fin: bool,
+    // RSV1 RSV2 RSV3, 由扩展使用
+    pub rsv: u8,
+    pub opcode: WebSocketOpcode,
+    pub payload: Vec<u8>,
+}
+
+impl RawWebSocketFrame {
+    pub fn new(fin: bool, rsv: u8, opcode: WebSocketOpcode, payload: Vec<u8>) -> RawWebSocketFrame {
+        RawWebSocketFrame {
+            fin,
+            rsv,
+            opcode,
+            payload,
+        }
+    }
+}
+
+///
+/// 一个完整的 WebSocket 消息, 分片的消息已经合并
+///
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum WebSocketFrame {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close { code: Option<u16>, reason: String },
+}
+
+impl WebSocketFrame {
+    pub fn close<R: Into<String>>(code: u16, reason: R) -> WebSocketFrame {
+        WebSocketFrame::Close {
+            code: Some(code),
+            reason: reason.into(),
+        }
+    }
+
+    pub fn opcode(&self) -> WebSocketOpcode {
+        match self {
+            WebSocketFrame::Text(_) => WebSocketOpcode::Text,
+            WebSocketFrame::Binary(_) => WebSocketOpcode::Binary,
+            WebSocketFrame::Ping(_) => WebSocketOpcode::Ping,
+            WebSocketFrame::Pong(_) => WebSocketOpcode::Pong,
+            WebSocketFrame::Close { .. } => WebSocketOpcode::Close,
+        }
+    }
+
+    ///
+    /// 转成一个不分片的帧
+    ///
+    pub fn into_raw(self) -> RawWebSocketFrame {
+        let opcode = self.opcode();
+        let payload = match self {
+            WebSocketFrame::Text(text) => text.into_bytes(),
+            WebSocketFrame::Binary(data)
+            | WebSocketFrame::Ping(data)
+            | WebSocketFrame::Pong(data) => data,
+            WebSocketFrame::Close { code, reason } => match code {
+                Some(code) => {
+                    let mut payload = code.to_be_bytes().to_vec();
+                    payload.extend_from_slice(reason.as_bytes());
+                    payload
+                }
+                None => vec![],
+            },
+        };
+        RawWebSocketFrame::new(true, 0, opcode, payload)
+    }
+
+    ///
+    /// 解析关闭帧的 payload, 格式不对时返回 None
+    ///
+    pub(crate) fn close_from_payload(payload: &[u8]) -> Option<WebSocketFrame> {
+        match payload.len() {
+            0 => Some(WebSocketFrame::Close {
+                code: None,
+                reason: String::new(),
+            }),
+            1 => None,
+            _ => {
+                let code = u16::from_be_bytes([payload[0], payload[1]]);
+                if !close_status::is_valid(code) {
+                    return None;
+                }
+                let reason = String::from_utf8(payload[2..].to_vec()).ok()?;
+                Some(WebSocketFrame::Close {
+                    code: Some(code),
+                    reason,
+                })
+            }
+        }
+    }
+}
+
+impl Display for WebSocketFrame {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WebSocketFrame::Text(text) => write!(f, "Text({})", text),
+            WebSocketFrame::Binary(data) => write!(f, "Binary({} bytes)", data.len()),
+            WebSocketFrame::Ping(data) => write!(f, "Ping({} bytes)", data.len()),
+            WebSocketFrame::Pong(data) => write!(f, "Pong({} bytes)", data.len()),
+            WebSocketFrame::Close { code, reason } => write!(f, "Close({:?}, {})", code, reason),
+        }
+    }
+}
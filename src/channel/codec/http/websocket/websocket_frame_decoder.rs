@@ -0,0 +1,608 @@
+use crate::channel::channel_handler_ctx::ChannelInboundHandlerCtx;
+use crate::channel::codec::byte_to_message_decoder::ByteToMessageDecoder;
+use crate::channel::codec::http::websocket::websocket_frame::{
+    close_status, RawWebSocketFrame, WebSocketFrame, WebSocketOpcode,
+};
+use crate::errors::{CodecErrorKind, RettyErrorKind};
+
+pub const DEFAULT_MAX_FRAME_PAYLOAD_LENGTH: usize = 65536;
+
+///
+/// WebSocket 帧解码器(RFC 6455), 每次解出一个 RawWebSocketFrame
+///
+/// 服务端要求客户端的帧带掩码, 客户端要求服务端的帧不带掩码
+/// 没有协商扩展时 RSV 必须为 0, 控制帧不能分片也不能超过 125 字节, 分片消息的 Continuation 帧顺序必须正确
+/// 帧的 payload 超过 max_frame_payload_length 时以 1009 关闭连接, 其他协议错误以 1002 关闭连接,
+/// 同时触发 channel_exception, 之后这个连接上的数据全部丢弃
+///
+/// 用 ByteToMessageHandler 包装之后加入 pipeline, 或者使用 WebSocketServerProtocolHandler
+///
+pub struct WebSocketFrameDecoder {
+    expect_masked_frames: bool,
+    // 可以不为 0 的 RSV 位
+    allowed_rsv: u8,
+    max_frame_payload_length: usize,
+    // 正在接收分片消息
+    fragmented: bool,
+    // 出错之后丢弃所有数据
+    corrupted: bool,
+}
+
+impl WebSocketFrameDecoder {
+    pub fn new(
+        expect_masked_frames: bool,
+        allow_extensions: bool,
+        max_frame_payload_length: usize,
+    ) -> WebSocketFrameDecoder {
+        WebSocketFrameDecoder {
+            expect_masked_frames,
+            allowed_rsv: if allow_extensions { 0x07 } else { 0 },
+            max_frame_payload_length,
+            fragmented: false,
+            corrupted: false,
+        }
+    }
+
+    pub fn max_frame_payload_length(&self) -> usize {
+        self.max_frame_payload_length
+    }
+
+    ///
+    /// 协商了使用 RSV 位的扩展, 比如 permessage-deflate 使用 RSV1
+    ///
+    pub(crate) fn allow_rsv(&mut self, rsv: u8) {
+        self.allowed_rsv |= rsv;
+    }
+
+    ///
+    /// 出现过协议错误, 已经触发过 channel_exception
+    ///
+    pub(crate) fn is_corrupted(&self) -> bool {
+        self.corrupted
+    }
+
+    ///
+    /// 发送关闭帧并关闭连接, 返回要触发的异常
+    ///
+    pub(crate) fn protocol_violation(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        status: u16,
+        reason: String,
+    ) -> RettyErrorKind {
+        self.corrupted = true;
+        if channel_handler_ctx.channel().is_active() {
+            channel_handler_ctx
+                .write_and_flush(&mut WebSocketFrame::close(status, reason.clone()))
+                .add_close_listener();
+        }
+        let kind = if status == close_status::MESSAGE_TOO_BIG {
+            CodecErrorKind::TooLongFrame
+        } else {
+            CodecErrorKind::CorruptedFrame
+        };
+        RettyErrorKind::codec(kind, reason)
+    }
+
+    fn check_header(&self, b0: u8, b1: u8) -> Result<WebSocketOpcode, String> {
+        let fin = b0 & 0x80 != 0;
+        let rsv = (b0 >> 4) & 0x07;
+        let masked = b1 & 0x80 != 0;
+        let length = b1 & 0x7F;
+
+        if rsv & !self.allowed_rsv != 0 {
+            return Err(format!("RSV != 0 and no extension negotiated, RSV:{}", rsv));
+        }
+        if masked != self.expect_masked_frames {
+            return Err("received a frame that is not masked as expected".to_string());
+        }
+        let opcode = match WebSocketOpcode::from_u8(b0 & 0x0F) {
+            Some(opcode) => opcode,
+            None => return Err(format!("received unknown opcode {}", b0 & 0x0F)),
+        };
+        if opcode.is_control() {
+            if !fin {
+                return Err("fragmented control frame".to_string());
+            }
+            if length > 125 {
+                return Err("control frame with payload length > 125 octets".to_string());
+            }
+            if opcode == WebSocketOpcode::Close && length == 1 {
+                return Err("received close control frame with payload len 1".to_string());
+            }
+        } else if self.fragmented && opcode != WebSocketOpcode::Continuation {
+            return Err(
+                "received non-continuation data frame while inside fragmented message".to_string(),
+            );
+        } else if !self.fragmented && opcode == WebSocketOpcode::Continuation {
+            return Err("received continuation data frame outside fragmented message".to_string());
+        }
+        Ok(opcode)
+    }
+}
+
+impl ByteToMessageDecoder for WebSocketFrameDecoder {
+    type Message = RawWebSocketFrame;
+
+    fn id(&self) -> String {
+        "WebSocketFrameDecoder".to_string()
+    }
+
+    fn decode(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        input: &[u8],
+        out: &mut Vec<RawWebSocketFrame>,
+    ) -> Result<usize, RettyErrorKind> {
+        if self.corrupted {
+            return Ok(input.len());
+        }
+        if input.len() < 2 {
+            return Ok(0);
+        }
+        let (b0, b1) = (input[0], input[1]);
+        let opcode = match self.check_header(b0, b1) {
+            Ok(opcode) => opcode,
+            Err(reason) => {
+                return Err(self.protocol_violation(
+                    channel_handler_ctx,
+                    close_status::PROTOCOL_ERROR,
+                    reason,
+                ))
+            }
+        };
+
+        let mut offset = 2;
+        let payload_length = match b1 & 0x7F {
+            126 => {
+                if input.len() < offset + 2 {
+                    return Ok(0);
+                }
+                let length = u16::from_be_bytes([input[2], input[3]]) as u64;
+                offset += 2;
+                if length < 126 {
+                    return Err(self.protocol_violation(
+                        channel_handler_ctx,
+                        close_status::PROTOCOL_ERROR,
+                        "invalid data frame length (not using minimal length encoding)".to_string(),
+                    ));
+                }
+                length
+            }
+            127 => {
+                if input.len() < offset + 8 {
+                    return Ok(0);
+                }
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(&input[2..10]);
+                let length = u64::from_be_bytes(bytes);
+                offset += 8;
+                if length >> 63 != 0 {
+                    return Err(self.protocol_violation(
+                        channel_handler_ctx,
+                        close_status::PROTOCOL_ERROR,
+                        "invalid data frame length (negative length)".to_string(),
+                    ));
+                }
+                if length < 65536 {
+                    return Err(self.protocol_violation(
+                        channel_handler_ctx,
+                        close_status::PROTOCOL_ERROR,
+                        "invalid data frame length (not using minimal length encoding)".to_string(),
+                    ));
+                }
+                length
+            }
+            length => length as u64,
+        };
+        if payload_length > self.max_frame_payload_length as u64 {
+            return Err(self.protocol_violation(
+                channel_handler_ctx,
+                close_status::MESSAGE_TOO_BIG,
+                format!(
+                    "Max frame length of {} has been exceeded.",
+                    self.max_frame_payload_length
+                ),
+            ));
+        }
+        let payload_length = payload_length as usize;
+
+        let mask = if b1 & 0x80 != 0 {
+            if input.len() < offset + 4 {
+                return Ok(0);
+            }
+            let mask = [
+                input[offset],
+                input[offset + 1],
+                input[offset + 2],
+                input[offset + 3],
+            ];
+            offset += 4;
+            Some(mask)
+        } else {
+            None
+        };
+        if input.len() < offset + payload_length {
+            return Ok(0);
+        }
+
+        let mut payload = input[offset..offset + payload_length].to_vec();
+        if let Some(mask) = mask {
+            apply_mask(&mut payload, mask);
+        }
+
+        if opcode == WebSocketOpcode::Close
+            && WebSocketFrame::close_from_payload(&payload).is_none()
+        {
+            let code = u16::from_be_bytes([payload[0], payload[1]]);
+            let (status, reason) = if close_status::is_valid(code) {
+                (
+                    close_status::INVALID_PAYLOAD_DATA,
+                    "invalid UTF-8 in close frame reason".to_string(),
+                )
+            } else {
+                (
+                    close_status::PROTOCOL_ERROR,
+                    format!("invalid close frame status code: {}", code),
+                )
+            };
+            return Err(self.protocol_violation(channel_handler_ctx, status, reason));
+        }
+        if !opcode.is_control() {
+            self.fragmented = b0 & 0x80 == 0;
+        }
+        out.push(RawWebSocketFrame::new(
+            b0 & 0x80 != 0,
+            (b0 >> 4) & 0x07,
+            opcode,
+            payload,
+        ));
+        Ok(offset + payload_length)
+    }
+}
+
+///
+/// 掩码和去掉掩码是同一个操作
+///
+pub(crate) fn apply_mask(payload: &mut [u8], mask: [u8; 4]) {
+    for (i, b) in payload.iter_mut().enumerate() {
+        *b ^= mask[i & 3];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::channel::codec::byte_to_message_decoder::ByteToMessageHandler;
+    use crate::channel::codec::http::websocket::websocket_frame_encoder::WebSocketFrameEncoder;
+    use crate::channel::codec::http::websocket::websocket_protocol::WebSocketProtocolEncoder;
+    use crate::channel::codec::http::websocket::websocket_test_frames::{
+        assert_violation, frame, MASK,
+    };
+    use crate::channel::embedded_channel::EmbeddedChannel;
+    use crate::channel::handler_pipe::ChannelHandlerPipe;
+
+    fn decoder_channel(
+        expect_masked_frames: bool,
+        max_frame_payload_length: usize,
+    ) -> EmbeddedChannel<RawWebSocketFrame> {
+        let mut handler_pipe = ChannelHandlerPipe::new();
+        // 协议错误时发出的关闭帧是 WebSocketFrame
+        handler_pipe.add_last_outbound(Box::new(WebSocketProtocolEncoder::new(
+            WebSocketFrameEncoder::new(!expect_masked_frames),
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(Mutex::new(None)),
+        )));
+        handler_pipe.add_last_inbound(Box::new(ByteToMessageHandler::new(
+            WebSocketFrameDecoder::new(expect_masked_frames, false, max_frame_payload_length),
+        )));
+        EmbeddedChannel::new(handler_pipe)
+    }
+
+    fn raw(fin: bool, opcode: WebSocketOpcode, payload: &[u8]) -> RawWebSocketFrame {
+        RawWebSocketFrame::new(fin, 0, opcode, payload.to_vec())
+    }
+
+    #[test]
+    fn server_unmasks_client_frames() {
+        let channel = decoder_channel(true, DEFAULT_MAX_FRAME_PAYLOAD_LENGTH);
+        // RFC 6455 5.7 带掩码的 "Hello"
+        channel.write_inbound(&[
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ]);
+        assert_eq!(
+            channel.read_inbound(),
+            Some(raw(true, WebSocketOpcode::Text, b"Hello"))
+        );
+    }
+
+    #[test]
+    fn server_rejects_unmasked_frame() {
+        let mut channel = decoder_channel(true, DEFAULT_MAX_FRAME_PAYLOAD_LENGTH);
+        channel.write_inbound(&[0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f]);
+        assert_violation(
+            &mut channel,
+            CodecErrorKind::CorruptedFrame,
+            close_status::PROTOCOL_ERROR,
+        );
+    }
+
+    #[test]
+    fn client_rejects_masked_frame() {
+        let mut channel = decoder_channel(false, DEFAULT_MAX_FRAME_PAYLOAD_LENGTH);
+        channel.write_inbound(&[0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f]);
+        assert_eq!(
+            channel.read_inbound(),
+            Some(raw(true, WebSocketOpcode::Text, b"Hello"))
+        );
+        channel.write_inbound(&frame(0x81, b"Hello", Some(MASK)));
+        assert_violation(
+            &mut channel,
+            CodecErrorKind::CorruptedFrame,
+            close_status::PROTOCOL_ERROR,
+        );
+    }
+
+    #[test]
+    fn payload_lengths() {
+        let channel = decoder_channel(true, 70000);
+        for length in &[0, 125, 126, 0xFFFF, 0x10000, 70000] {
+            let payload = vec![0x5a; *length];
+            channel.write_inbound(&frame(0x82, &payload, Some(MASK)));
+            assert_eq!(
+                channel.read_inbound(),
+                Some(raw(true, WebSocketOpcode::Binary, &payload)),
+                "payload length {}",
+                length
+            );
+        }
+    }
+
+    #[test]
+    fn waits_for_the_whole_frame() {
+        let channel = decoder_channel(true, 70000);
+        let payload = vec![0xa5; 0x10000];
+        let bytes = frame(0x82, &payload, Some(MASK));
+        // 在 16 位长度, 64 位长度和掩码中间断开
+        for chunk in bytes.chunks(3).take(6) {
+            channel.write_inbound(chunk);
+            assert!(channel.read_inbound().is_none());
+        }
+        channel.write_inbound(&bytes[18..]);
+        assert_eq!(
+            channel.read_inbound(),
+            Some(raw(true, WebSocketOpcode::Binary, &payload))
+        );
+    }
+
+    #[test]
+    fn rejects_non_minimal_16_bit_length() {
+        let mut channel = decoder_channel(false, DEFAULT_MAX_FRAME_PAYLOAD_LENGTH);
+        let mut bytes = vec![0x82, 126, 0x00, 125];
+        bytes.extend_from_slice(&[0u8; 125]);
+        channel.write_inbound(&bytes);
+        assert_violation(
+            &mut channel,
+            CodecErrorKind::CorruptedFrame,
+            close_status::PROTOCOL_ERROR,
+        );
+    }
+
+    #[test]
+    fn rejects_non_minimal_64_bit_length() {
+        let mut channel = decoder_channel(false, DEFAULT_MAX_FRAME_PAYLOAD_LENGTH);
+        let mut bytes = vec![0x82, 127];
+        bytes.extend_from_slice(&0xFFFFu64.to_be_bytes());
+        channel.write_inbound(&bytes);
+        assert_violation(
+            &mut channel,
+            CodecErrorKind::CorruptedFrame,
+            close_status::PROTOCOL_ERROR,
+        );
+    }
+
+    #[test]
+    fn rejects_64_bit_length_with_most_significant_bit() {
+        let mut channel = decoder_channel(false, DEFAULT_MAX_FRAME_PAYLOAD_LENGTH);
+        let mut bytes = vec![0x82, 127];
+        bytes.extend_from_slice(&(1u64 << 63).to_be_bytes());
+        channel.write_inbound(&bytes);
+        assert_violation(
+            &mut channel,
+            CodecErrorKind::CorruptedFrame,
+            close_status::PROTOCOL_ERROR,
+        );
+    }
+
+    #[test]
+    fn too_long_frame() {
+        let mut channel = decoder_channel(true, 16);
+        // 只收到头部就能判断
+        channel.write_inbound(&[0x82, 0x80 | 17]);
+        assert_violation(
+            &mut channel,
+            CodecErrorKind::TooLongFrame,
+            close_status::MESSAGE_TOO_BIG,
+        );
+    }
+
+    #[test]
+    fn control_frames() {
+        let channel = decoder_channel(true, DEFAULT_MAX_FRAME_PAYLOAD_LENGTH);
+        channel.write_inbound(&frame(0x89, &[1u8; 125], Some(MASK)));
+        channel.write_inbound(&frame(0x8A, b"", Some(MASK)));
+        assert_eq!(
+            channel.read_all_inbound(),
+            vec![
+                raw(true, WebSocketOpcode::Ping, &[1u8; 125]),
+                raw(true, WebSocketOpcode::Pong, b""),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_control_frame_longer_than_125() {
+        let mut channel = decoder_channel(true, DEFAULT_MAX_FRAME_PAYLOAD_LENGTH);
+        channel.write_inbound(&frame(0x89, &[1u8; 126], Some(MASK)));
+        assert_violation(
+            &mut channel,
+            CodecErrorKind::CorruptedFrame,
+            close_status::PROTOCOL_ERROR,
+        );
+    }
+
+    #[test]
+    fn rejects_fragmented_control_frame() {
+        let mut channel = decoder_channel(true, DEFAULT_MAX_FRAME_PAYLOAD_LENGTH);
+        channel.write_inbound(&frame(0x09, b"ping", Some(MASK)));
+        assert_violation(
+            &mut channel,
+            CodecErrorKind::CorruptedFrame,
+            close_status::PROTOCOL_ERROR,
+        );
+    }
+
+    #[test]
+    fn rejects_rsv_without_extension() {
+        let mut channel = decoder_channel(true, DEFAULT_MAX_FRAME_PAYLOAD_LENGTH);
+        channel.write_inbound(&frame(0xC1, b"Hello", Some(MASK)));
+        assert_violation(
+            &mut channel,
+            CodecErrorKind::CorruptedFrame,
+            close_status::PROTOCOL_ERROR,
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_opcode() {
+        let mut channel = decoder_channel(true, DEFAULT_MAX_FRAME_PAYLOAD_LENGTH);
+        channel.write_inbound(&frame(0x83, b"", Some(MASK)));
+        assert_violation(
+            &mut channel,
+            CodecErrorKind::CorruptedFrame,
+            close_status::PROTOCOL_ERROR,
+        );
+    }
+
+    #[test]
+    fn fragmented_message_with_interleaved_control_frame() {
+        let channel = decoder_channel(true, DEFAULT_MAX_FRAME_PAYLOAD_LENGTH);
+        channel.write_inbound(&frame(0x01, b"Hel", Some(MASK)));
+        channel.write_inbound(&frame(0x89, b"", Some(MASK)));
+        channel.write_inbound(&frame(0x00, b"l", Some(MASK)));
+        channel.write_inbound(&frame(0x80, b"o", Some(MASK)));
+        channel.write_inbound(&frame(0x82, b"next", Some(MASK)));
+        assert_eq!(
+            channel.read_all_inbound(),
+            vec![
+                raw(false, WebSocketOpcode::Text, b"Hel"),
+                raw(true, WebSocketOpcode::Ping, b""),
+                raw(false, WebSocketOpcode::Continuation, b"l"),
+                raw(true, WebSocketOpcode::Continuation, b"o"),
+                raw(true, WebSocketOpcode::Binary, b"next"),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_continuation_outside_fragmented_message() {
+        let mut channel = decoder_channel(true, DEFAULT_MAX_FRAME_PAYLOAD_LENGTH);
+        channel.write_inbound(&frame(0x80, b"o", Some(MASK)));
+        assert_violation(
+            &mut channel,
+            CodecErrorKind::CorruptedFrame,
+            close_status::PROTOCOL_ERROR,
+        );
+    }
+
+    #[test]
+    fn rejects_data_frame_inside_fragmented_message() {
+        let mut channel = decoder_channel(true, DEFAULT_MAX_FRAME_PAYLOAD_LENGTH);
+        channel.write_inbound(&frame(0x01, b"Hel", Some(MASK)));
+        assert_eq!(
+            channel.read_inbound(),
+            Some(raw(false, WebSocketOpcode::Text, b"Hel"))
+        );
+        channel.write_inbound(&frame(0x81, b"lo", Some(MASK)));
+        assert_violation(
+            &mut channel,
+            CodecErrorKind::CorruptedFrame,
+            close_status::PROTOCOL_ERROR,
+        );
+    }
+
+    #[test]
+    fn close_frames() {
+        let channel = decoder_channel(true, DEFAULT_MAX_FRAME_PAYLOAD_LENGTH);
+        channel.write_inbound(&frame(0x88, b"", Some(MASK)));
+        channel.write_inbound(&frame(0x88, b"\x03\xe8bye", Some(MASK)));
+        channel.write_inbound(&frame(0x88, b"\x0f\xa0", Some(MASK)));
+        assert_eq!(
+            channel.read_all_inbound(),
+            vec![
+                raw(true, WebSocketOpcode::Close, b""),
+                raw(true, WebSocketOpcode::Close, b"\x03\xe8bye"),
+                raw(true, WebSocketOpcode::Close, b"\x0f\xa0"),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_close_frame_with_one_byte_payload() {
+        let mut channel = decoder_channel(true, DEFAULT_MAX_FRAME_PAYLOAD_LENGTH);
+        channel.write_inbound(&frame(0x88, b"\x03", Some(MASK)));
+        assert_violation(
+            &mut channel,
+            CodecErrorKind::CorruptedFrame,
+            close_status::PROTOCOL_ERROR,
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_close_codes() {
+        for code in &[0u16, 999, 1004, 1005, 1006, 1015, 1016, 2999, 5000] {
+            let mut channel = decoder_channel(true, DEFAULT_MAX_FRAME_PAYLOAD_LENGTH);
+            channel.write_inbound(&frame(0x88, &code.to_be_bytes(), Some(MASK)));
+            assert_violation(
+                &mut channel,
+                CodecErrorKind::CorruptedFrame,
+                close_status::PROTOCOL_ERROR,
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_utf8_close_reason() {
+        let mut channel = decoder_channel(true, DEFAULT_MAX_FRAME_PAYLOAD_LENGTH);
+        channel.write_inbound(&frame(0x88, b"\x03\xe8\xff\xfe", Some(MASK)));
+        assert_violation(
+            &mut channel,
+            CodecErrorKind::CorruptedFrame,
+            close_status::INVALID_PAYLOAD_DATA,
+        );
+    }
+
+    #[test]
+    fn encoder_round_trip() {
+        let channel = decoder_channel(true, 70000);
+        let encoder = WebSocketFrameEncoder::new(true);
+        for length in &[0, 125, 126, 0xFFFF, 0x10000] {
+            let frame = raw(true, WebSocketOpcode::Binary, &vec![0x42; *length]);
+            let mut out = vec![];
+            encoder.encode_frame(&frame, &mut out).unwrap();
+            channel.write_inbound(&out);
+            assert_eq!(channel.read_inbound(), Some(frame));
+        }
+        let mut out = vec![];
+        assert!(encoder
+            .encode_frame(&raw(true, WebSocketOpcode::Ping, &[0; 126]), &mut out)
+            .is_err());
+        assert!(encoder
+            .encode_frame(&raw(false, WebSocketOpcode::Close, b""), &mut out)
+            .is_err());
+        assert!(out.is_empty());
+    }
+}
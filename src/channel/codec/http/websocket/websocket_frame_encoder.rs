@@ -0,0 +1,93 @@
+use std::io::ErrorKind;
+
+use uuid::Uuid;
+
+use crate::channel::channel_handler_ctx::ChannelOutboundHandlerCtx;
+use crate::channel::codec::http::websocket::websocket_frame::RawWebSocketFrame;
+use crate::channel::codec::http::websocket::websocket_frame_decoder::apply_mask;
+use crate::channel::codec::message_to_byte_encoder::MessageToByteEncoder;
+use crate::errors::RettyErrorKind;
+
+///
+/// WebSocket 帧编码器(RFC 6455), 客户端发出的帧必须带随机掩码, 服务端发出的帧不带掩码
+///
+/// 用 MessageToByteHandler 包装之后加入 pipeline, 或者使用 WebSocketServerProtocolHandler
+///
+pub struct WebSocketFrameEncoder {
+    mask_payload: bool,
+}
+
+impl WebSocketFrameEncoder {
+    pub fn new(mask_payload: bool) -> WebSocketFrameEncoder {
+        WebSocketFrameEncoder { mask_payload }
+    }
+
+    pub fn encode_frame(
+        &self,
+        frame: &RawWebSocketFrame,
+        out: &mut Vec<u8>,
+    ) -> Result<(), RettyErrorKind> {
+        let length = frame.payload.len();
+        if frame.opcode.is_control() && (!frame.fin || length > 125) {
+            return Err(RettyErrorKind::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "invalid control frame: fin = {}, payload length = {}",
+                    frame.fin, length
+                ),
+            ));
+        }
+        if frame.rsv > 0x07 {
+            return Err(RettyErrorKind::new(
+                ErrorKind::InvalidInput,
+                format!("invalid RSV: {}", frame.rsv),
+            ));
+        }
+
+        let mut b0 = (frame.rsv << 4) | frame.opcode.as_u8();
+        if frame.fin {
+            b0 |= 0x80;
+        }
+        let mask_bit = if self.mask_payload { 0x80 } else { 0 };
+        out.push(b0);
+        if length <= 125 {
+            out.push(mask_bit | length as u8);
+        } else if length <= 0xFFFF {
+            out.push(mask_bit | 126);
+            out.extend_from_slice(&(length as u16).to_be_bytes());
+        } else {
+            out.push(mask_bit | 127);
+            out.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+
+        if self.mask_payload {
+            let random = Uuid::new_v4();
+            let bytes = random.as_bytes();
+            let mask = [bytes[0], bytes[1], bytes[2], bytes[3]];
+            out.extend_from_slice(&mask);
+            let start = out.len();
+            out.extend_from_slice(&frame.payload);
+            apply_mask(&mut out[start..], mask);
+        } else {
+            out.extend_from_slice(&frame.payload);
+        }
+        Ok(())
+    }
+}
+
+impl MessageToByteEncoder for WebSocketFrameEncoder {
+    type Message = RawWebSocketFrame;
+
+    fn id(&self) -> String {
+        "WebSocketFrameEncoder".to_string()
+    }
+
+    fn encode(
+        &mut self,
+        _channel_handler_ctx: &mut ChannelOutboundHandlerCtx,
+        message: &mut RawWebSocketFrame,
+        out: &mut Vec<u8>,
+    ) -> Result<(), RettyErrorKind> {
+        self.encode_frame(message, out)
+    }
+}
@@ -0,0 +1,67 @@
+use sha1_smol::Sha1;
+
+pub(crate) const WEBSOCKET_VERSION: &str = "13";
+
+// RFC 6455 1.3
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+///
+/// Sec-WebSocket-Accept = base64(sha1(Sec-WebSocket-Key + GUID))
+///
+pub(crate) fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(WEBSOCKET_GUID.as_bytes());
+    base64::encode(sha1.digest().bytes())
+}
+
+///
+/// 逗号分隔的列表
+///
+pub(crate) fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .collect()
+}
+
+///
+/// 按客户端请求的顺序选出第一个服务端支持的子协议, "*" 表示支持所有子协议
+///
+pub(crate) fn select_subprotocol(requested: &str, supported: &[String]) -> Option<String> {
+    split_list(requested).into_iter().find(|requested| {
+        supported
+            .iter()
+            .any(|supported| supported == "*" || supported == requested)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accept_key_rfc_6455() {
+        // RFC 6455 1.3
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn selects_first_requested_subprotocol() {
+        let supported = vec!["chat".to_string(), "superchat".to_string()];
+        assert_eq!(
+            select_subprotocol("soap, superchat ,chat", &supported),
+            Some("superchat".to_string())
+        );
+        assert_eq!(select_subprotocol("soap", &supported), None);
+        assert_eq!(
+            select_subprotocol("soap", &["*".to_string()]),
+            Some("soap".to_string())
+        );
+    }
+}
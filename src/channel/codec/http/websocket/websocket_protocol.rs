@@ -0,0 +1,408 @@
+use std::any::Any;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use bytebuf_rs::bytebuf::ByteBuf;
+
+use crate::channel::channel_handler_ctx::{ChannelInboundHandlerCtx, ChannelOutboundHandlerCtx};
+use crate::channel::codec::byte_to_message_decoder::ByteToMessageDecoder;
+use crate::channel::codec::http::websocket::per_message_deflate::{Deflater, Inflater, RSV1};
+use crate::channel::codec::http::websocket::websocket_frame::{
+    close_status, RawWebSocketFrame, WebSocketFrame, WebSocketOpcode,
+};
+use crate::channel::codec::http::websocket::websocket_frame_decoder::WebSocketFrameDecoder;
+use crate::channel::codec::http::websocket::websocket_frame_encoder::WebSocketFrameEncoder;
+use crate::channel::handler::{ChannelInboundHandler, ChannelOutboundHandler};
+use crate::errors::RettyErrorKind;
+
+///
+/// 握手完成之后修改 pipeline: 把握手的 handler 替换成 decoder, 移除 HttpObjectAggregator 和 http_codec_id 的 HTTP 编解码器,
+/// HTTP 编解码器移除时把已经读到但没有解码的数据交给 decoder
+///
+pub(crate) fn switch_to_websocket(
+    channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+    decoder: Box<dyn ChannelInboundHandler + Send + Sync>,
+    http_codec_id: &str,
+) -> Result<(), RettyErrorKind> {
+    let pipeline = channel_handler_ctx.pipeline();
+    pipeline.replace(&channel_handler_ctx.id(), decoder)?;
+    for id in ["HttpObjectAggregator", http_codec_id] {
+        if pipeline.names().iter().any(|name| name == id) {
+            pipeline.remove(id)?;
+        }
+    }
+    if let Some(outbound_pipeline) = channel_handler_ctx.outbound_pipeline() {
+        if outbound_pipeline
+            .names()
+            .iter()
+            .any(|name| name == http_codec_id)
+        {
+            outbound_pipeline.remove(http_codec_id)?;
+        }
+    }
+    Ok(())
+}
+
+///
+/// 握手完成之后的入站部分, 把帧合并成 WebSocketFrame
+/// 收到 Ping 自动回复 Pong, 收到 Close 时回复 Close(自己还没有发过的话) 然后关闭连接
+/// 协商了 permessage-deflate 时解压第一帧设置了 RSV1 的消息
+///
+pub(crate) struct WebSocketProtocolDecoder {
+    frame_decoder: WebSocketFrameDecoder,
+    // 正在合并的分片消息和它是否压缩过
+    fragments: Option<(WebSocketOpcode, bool, Vec<u8>)>,
+    inflater: Option<Inflater>,
+    close_sent: Arc<AtomicBool>,
+    // 收到的关闭帧的状态码和原因
+    close_received: Option<(u16, String)>,
+}
+
+impl WebSocketProtocolDecoder {
+    pub(crate) fn new(
+        frame_decoder: WebSocketFrameDecoder,
+        close_sent: Arc<AtomicBool>,
+    ) -> WebSocketProtocolDecoder {
+        WebSocketProtocolDecoder {
+            frame_decoder,
+            fragments: None,
+            inflater: None,
+            close_sent,
+            close_received: None,
+        }
+    }
+
+    ///
+    /// 握手时协商了 permessage-deflate
+    ///
+    pub(crate) fn enable_deflate(&mut self, inflater: Inflater) {
+        self.frame_decoder.allow_rsv(RSV1);
+        self.inflater = Some(inflater);
+    }
+
+    ///
+    /// 连接关闭的状态码和原因
+    /// 收到的关闭帧没有状态码时为 1005, 没有收到关闭帧时为 1006, 协议错误已经触发过异常时为 None
+    ///
+    pub(crate) fn close_status(&self) -> Option<(u16, &str)> {
+        match &self.close_received {
+            Some((code, reason)) => Some((*code, reason.as_str())),
+            None if self.frame_decoder.is_corrupted() => None,
+            None => Some((close_status::ABNORMAL_CLOSURE, "")),
+        }
+    }
+
+    fn on_frame(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        frame: RawWebSocketFrame,
+        out: &mut Vec<WebSocketFrame>,
+    ) -> Result<(), RettyErrorKind> {
+        let compressed = frame.rsv & RSV1 != 0;
+        if compressed
+            && self.inflater.is_some()
+            && (frame.opcode.is_control() || frame.opcode == WebSocketOpcode::Continuation)
+        {
+            return Err(self.frame_decoder.protocol_violation(
+                channel_handler_ctx,
+                close_status::PROTOCOL_ERROR,
+                format!("RSV1 set on a {:?} frame", frame.opcode),
+            ));
+        }
+        match frame.opcode {
+            WebSocketOpcode::Ping => {
+                if !self.close_sent.load(Ordering::SeqCst) {
+                    channel_handler_ctx.write_and_flush(&mut WebSocketFrame::Pong(frame.payload));
+                }
+            }
+            WebSocketOpcode::Pong => out.push(WebSocketFrame::Pong(frame.payload)),
+            WebSocketOpcode::Close => {
+                // 帧解码器已经检查过 payload
+                let close = WebSocketFrame::close_from_payload(&frame.payload).unwrap();
+                if let WebSocketFrame::Close { code, reason } = &close {
+                    let code = code.unwrap_or(close_status::NO_STATUS_RECEIVED);
+                    self.close_received = Some((code, reason.clone()));
+                }
+                if self.close_sent.swap(true, Ordering::SeqCst) {
+                    // 对端回复了我们发出的 Close
+                    channel_handler_ctx.close();
+                } else {
+                    let mut reply = match &close {
+                        WebSocketFrame::Close {
+                            code: Some(code), ..
+                        } => WebSocketFrame::close(*code, ""),
+                        _ => WebSocketFrame::Close {
+                            code: None,
+                            reason: String::new(),
+                        },
+                    };
+                    channel_handler_ctx
+                        .write_and_flush(&mut reply)
+                        .add_close_listener();
+                }
+                out.push(close);
+            }
+            WebSocketOpcode::Text | WebSocketOpcode::Binary | WebSocketOpcode::Continuation => {
+                let (opcode, compressed, mut payload) = match self.fragments.take() {
+                    Some((opcode, compressed, mut payload)) => {
+                        payload.extend_from_slice(&frame.payload);
+                        (opcode, compressed, payload)
+                    }
+                    None => (frame.opcode, compressed, frame.payload),
+                };
+                let max_length = self.frame_decoder.max_frame_payload_length();
+                if payload.len() > max_length {
+                    return Err(self.frame_decoder.protocol_violation(
+                        channel_handler_ctx,
+                        close_status::MESSAGE_TOO_BIG,
+                        format!("Max message length of {} has been exceeded.", max_length),
+                    ));
+                }
+                if !frame.fin {
+                    self.fragments = Some((opcode, compressed, payload));
+                    return Ok(());
+                }
+                if compressed {
+                    if let Some(inflater) = self.inflater.as_mut() {
+                        match inflater.inflate(&payload, max_length) {
+                            Ok(inflated) => payload = inflated,
+                            Err((status, reason)) => {
+                                return Err(self.frame_decoder.protocol_violation(
+                                    channel_handler_ctx,
+                                    status,
+                                    reason,
+                                ))
+                            }
+                        }
+                    }
+                }
+                if opcode == WebSocketOpcode::Text {
+                    match String::from_utf8(std::mem::take(&mut payload)) {
+                        Ok(text) => out.push(WebSocketFrame::Text(text)),
+                        Err(_) => {
+                            return Err(self.frame_decoder.protocol_violation(
+                                channel_handler_ctx,
+                                close_status::INVALID_PAYLOAD_DATA,
+                                "bytes are not UTF-8".to_string(),
+                            ))
+                        }
+                    }
+                } else {
+                    out.push(WebSocketFrame::Binary(payload));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ByteToMessageDecoder for WebSocketProtocolDecoder {
+    type Message = WebSocketFrame;
+
+    fn id(&self) -> String {
+        self.frame_decoder.id()
+    }
+
+    fn decode(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        input: &[u8],
+        out: &mut Vec<WebSocketFrame>,
+    ) -> Result<usize, RettyErrorKind> {
+        let mut frames = vec![];
+        let consumed = self
+            .frame_decoder
+            .decode(channel_handler_ctx, input, &mut frames)?;
+        for frame in frames {
+            self.on_frame(channel_handler_ctx, frame, out)?;
+        }
+        Ok(consumed)
+    }
+}
+
+///
+/// 出站部分, 编码 WebSocketFrame 和 RawWebSocketFrame, 其他消息原样往下写
+/// 协商了 permessage-deflate 时压缩 Text / Binary 消息, RawWebSocketFrame 不压缩
+///
+pub(crate) struct WebSocketProtocolEncoder {
+    encoder: WebSocketFrameEncoder,
+    close_sent: Arc<AtomicBool>,
+    // 握手完成之后由入站部分设置
+    deflater: Arc<Mutex<Option<Deflater>>>,
+}
+
+impl WebSocketProtocolEncoder {
+    pub(crate) fn new(
+        encoder: WebSocketFrameEncoder,
+        close_sent: Arc<AtomicBool>,
+        deflater: Arc<Mutex<Option<Deflater>>>,
+    ) -> WebSocketProtocolEncoder {
+        WebSocketProtocolEncoder {
+            encoder,
+            close_sent,
+            deflater,
+        }
+    }
+
+    fn encode(
+        &self,
+        message: &mut dyn Any,
+        out: &mut Vec<u8>,
+    ) -> Option<Result<(), RettyErrorKind>> {
+        let raw = if let Some(frame) = message.downcast_ref::<WebSocketFrame>() {
+            let mut raw = frame.clone().into_raw();
+            if !raw.opcode.is_control() {
+                if let Some(deflater) = self.deflater.lock().unwrap().as_mut() {
+                    match deflater.deflate(&raw.payload) {
+                        Ok(payload) => raw.payload = payload,
+                        Err(e) => return Some(Err(e)),
+                    }
+                    raw.rsv |= RSV1;
+                }
+            }
+            raw
+        } else if let Some(frame) = message.downcast_ref::<RawWebSocketFrame>() {
+            frame.clone()
+        } else {
+            return None;
+        };
+        if raw.opcode == WebSocketOpcode::Close {
+            self.close_sent.store(true, Ordering::SeqCst);
+        }
+        Some(self.encoder.encode_frame(&raw, out))
+    }
+}
+
+impl ChannelOutboundHandler for WebSocketProtocolEncoder {
+    fn id(&self) -> String {
+        "WebSocketFrameEncoder".to_string()
+    }
+
+    fn channel_write(
+        &mut self,
+        channel_handler_ctx: &mut ChannelOutboundHandlerCtx,
+        message: &mut dyn Any,
+    ) {
+        let mut out = vec![];
+        match self.encode(message, &mut out) {
+            Some(Ok(())) => channel_handler_ctx.fire_channel_write(&mut ByteBuf::new_from(&out)),
+            Some(Err(e)) => channel_handler_ctx.channel().fail_write(e),
+            None => channel_handler_ctx.fire_channel_write(message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::codec::byte_to_message_decoder::ByteToMessageHandler;
+    use crate::channel::codec::http::websocket::websocket_frame_decoder::DEFAULT_MAX_FRAME_PAYLOAD_LENGTH;
+    use crate::channel::codec::http::websocket::websocket_test_frames::{
+        assert_violation, frame, MASK,
+    };
+    use crate::channel::embedded_channel::EmbeddedChannel;
+    use crate::channel::handler_pipe::ChannelHandlerPipe;
+    use crate::errors::CodecErrorKind;
+
+    ///
+    /// 服务端: 入站的帧带掩码, 出站的帧不带掩码
+    ///
+    fn server_channel(max_frame_payload_length: usize) -> EmbeddedChannel<WebSocketFrame> {
+        let close_sent = Arc::new(AtomicBool::new(false));
+        let mut handler_pipe = ChannelHandlerPipe::new();
+        handler_pipe.add_last_outbound(Box::new(WebSocketProtocolEncoder::new(
+            WebSocketFrameEncoder::new(false),
+            close_sent.clone(),
+            Arc::new(Mutex::new(None)),
+        )));
+        handler_pipe.add_last_inbound(Box::new(ByteToMessageHandler::new(
+            WebSocketProtocolDecoder::new(
+                WebSocketFrameDecoder::new(true, false, max_frame_payload_length),
+                close_sent,
+            ),
+        )));
+        EmbeddedChannel::new(handler_pipe)
+    }
+
+    #[test]
+    fn reassembles_fragmented_text() {
+        let channel = server_channel(DEFAULT_MAX_FRAME_PAYLOAD_LENGTH);
+        // "é" 的两个字节分在两帧里
+        channel.write_inbound(&frame(0x01, b"caf\xc3", Some(MASK)));
+        channel.write_inbound(&frame(0x8A, b"pong", Some(MASK)));
+        channel.write_inbound(&frame(0x80, b"\xa9", Some(MASK)));
+        assert_eq!(
+            channel.read_all_inbound(),
+            vec![
+                WebSocketFrame::Pong(b"pong".to_vec()),
+                WebSocketFrame::Text("café".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_utf8_text() {
+        let mut channel = server_channel(DEFAULT_MAX_FRAME_PAYLOAD_LENGTH);
+        channel.write_inbound(&frame(
+            0x81,
+            b"\xce\xba\xe1\xbd\xb9\xcf\x83\xce\xbc\xce\xb5\xed\xa0\x80",
+            Some(MASK),
+        ));
+        assert_violation(
+            &mut channel,
+            CodecErrorKind::CorruptedFrame,
+            close_status::INVALID_PAYLOAD_DATA,
+        );
+    }
+
+    #[test]
+    fn rejects_fragmented_message_longer_than_max() {
+        let mut channel = server_channel(8);
+        channel.write_inbound(&frame(0x02, b"12345", Some(MASK)));
+        channel.write_inbound(&frame(0x80, b"6789", Some(MASK)));
+        assert_violation(
+            &mut channel,
+            CodecErrorKind::TooLongFrame,
+            close_status::MESSAGE_TOO_BIG,
+        );
+    }
+
+    #[test]
+    fn answers_ping_with_pong() {
+        let mut channel = server_channel(DEFAULT_MAX_FRAME_PAYLOAD_LENGTH);
+        channel.write_inbound(&frame(0x89, b"hi", Some(MASK)));
+        assert!(channel.read_inbound().is_none());
+        assert_eq!(channel.read_outbound(), b"\x8a\x02hi".to_vec());
+    }
+
+    #[test]
+    fn answers_close_with_close() {
+        let mut channel = server_channel(DEFAULT_MAX_FRAME_PAYLOAD_LENGTH);
+        channel.write_inbound(&frame(0x88, b"\x03\xe9going away", Some(MASK)));
+        assert_eq!(
+            channel.read_inbound(),
+            Some(WebSocketFrame::close(
+                close_status::ENDPOINT_UNAVAILABLE,
+                "going away"
+            ))
+        );
+        assert_eq!(channel.read_outbound(), b"\x88\x02\x03\xe9".to_vec());
+        // 已经发过 Close, 不再回复 Ping
+        channel.write_inbound(&frame(0x89, b"hi", Some(MASK)));
+        assert!(channel.read_outbound().is_empty());
+    }
+
+    #[test]
+    fn close_without_status() {
+        let mut channel = server_channel(DEFAULT_MAX_FRAME_PAYLOAD_LENGTH);
+        channel.write_inbound(&frame(0x88, b"", Some(MASK)));
+        assert_eq!(
+            channel.read_inbound(),
+            Some(WebSocketFrame::Close {
+                code: None,
+                reason: String::new(),
+            })
+        );
+        assert_eq!(channel.read_outbound(), b"\x88\x00".to_vec());
+    }
+}
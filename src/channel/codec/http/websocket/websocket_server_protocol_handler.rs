@@ -0,0 +1,386 @@
+use std::any::Any;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+
+use crate::channel::channel_handler_ctx::ChannelInboundHandlerCtx;
+use crate::channel::codec::byte_to_message_decoder::ByteToMessageHandler;
+use crate::channel::codec::http::http_headers::{names, values, HttpHeaders};
+use crate::channel::codec::http::http_message::{
+    FullHttpRequest, FullHttpResponse, HttpMethod, HttpResponseStatus, HttpVersion,
+};
+use crate::channel::codec::http::websocket::per_message_deflate::{
+    server_accept, Deflater, PerMessageDeflateOptions,
+};
+use crate::channel::codec::http::websocket::websocket_frame_decoder::{
+    WebSocketFrameDecoder, DEFAULT_MAX_FRAME_PAYLOAD_LENGTH,
+};
+use crate::channel::codec::http::websocket::websocket_frame_encoder::WebSocketFrameEncoder;
+use crate::channel::codec::http::websocket::websocket_handshake::{
+    accept_key, select_subprotocol, split_list, WEBSOCKET_VERSION,
+};
+use crate::channel::codec::http::websocket::websocket_protocol::{
+    switch_to_websocket, WebSocketProtocolDecoder, WebSocketProtocolEncoder,
+};
+use crate::channel::handler::{ChannelCodec, ChannelInboundHandler, ChannelOutboundHandler};
+use crate::errors::RettyErrorKind;
+
+///
+/// WebSocket 服务端协议处理器, 用 ChannelInboundHandlerPipe::add_last_codec 注册, 加在 HttpServerCodec 和 HttpObjectAggregator 后面
+///
+/// 收到 websocket_path 上的升级请求时完成握手(Sec-WebSocket-Accept, 子协议协商) 并回复 101, 其他的 HTTP 请求原样往下传
+/// 回复 101 之后把自己替换成帧解码器(id 为 WebSocketFrameDecoder), 并移除 HttpServerCodec 和 HttpObjectAggregator,
+/// 升级请求后面已经读到的数据交给帧解码器, 然后触发 WebSocketServerHandshakeComplete 用户事件
+/// 之后收到的数据解码成 WebSocketFrame 传给下一个handler, 写出的 WebSocketFrame / RawWebSocketFrame 编码成帧
+///
+/// 分片的消息合并之后再往下传, 收到 Ping 自动回复 Pong, 收到 Close 时回复 Close 并关闭连接
+/// 帧和合并之后的消息超过 max_frame_payload_length 时以 1009 关闭连接, 客户端的帧没有掩码等协议错误以 1002 关闭连接
+/// 启用 compression 之后和客户端协商 permessage-deflate, 压缩和解压对下一个handler 透明
+///
+pub struct WebSocketServerProtocolHandler {
+    websocket_path: String,
+    subprotocols: Vec<String>,
+    allow_extensions: bool,
+    max_frame_payload_length: usize,
+    compression: Option<PerMessageDeflateOptions>,
+}
+
+impl WebSocketServerProtocolHandler {
+    pub fn new<P: Into<String>>(websocket_path: P) -> WebSocketServerProtocolHandler {
+        WebSocketServerProtocolHandler::new_with_options(
+            websocket_path,
+            None,
+            false,
+            DEFAULT_MAX_FRAME_PAYLOAD_LENGTH,
+        )
+    }
+
+    ///
+    /// subprotocols: 逗号分隔的支持的子协议, "*" 表示支持所有子协议
+    ///
+    pub fn new_with_options<P: Into<String>>(
+        websocket_path: P,
+        subprotocols: Option<&str>,
+        allow_extensions: bool,
+        max_frame_payload_length: usize,
+    ) -> WebSocketServerProtocolHandler {
+        WebSocketServerProtocolHandler {
+            websocket_path: websocket_path.into(),
+            subprotocols: subprotocols.map(split_list).unwrap_or_default(),
+            allow_extensions,
+            max_frame_payload_length,
+            compression: None,
+        }
+    }
+
+    ///
+    /// 接受客户端提出的 permessage-deflate 扩展
+    ///
+    pub fn compression(&mut self, options: PerMessageDeflateOptions) -> &mut Self {
+        self.compression = Some(options);
+        self
+    }
+}
+
+impl ChannelCodec for WebSocketServerProtocolHandler {
+    fn into_handlers(
+        self,
+    ) -> (
+        Box<dyn ChannelInboundHandler + Send + Sync>,
+        Box<dyn ChannelOutboundHandler + Send + Sync>,
+    ) {
+        let close_sent = Arc::new(AtomicBool::new(false));
+        let deflater = Arc::new(Mutex::new(None));
+        (
+            Box::new(WebSocketServerProtocolDecoder {
+                websocket_path: self.websocket_path,
+                subprotocols: self.subprotocols,
+                allow_extensions: self.allow_extensions,
+                max_frame_payload_length: self.max_frame_payload_length,
+                compression: self.compression,
+                close_sent: close_sent.clone(),
+                deflater: deflater.clone(),
+            }),
+            Box::new(WebSocketProtocolEncoder::new(
+                WebSocketFrameEncoder::new(false),
+                close_sent,
+                deflater,
+            )),
+        )
+    }
+}
+
+///
+/// 服务端握手完成, 回复了 101 之后通过 user_event_triggered 传给后面的 handler, 之后开始收发 WebSocketFrame
+///
+#[derive(Debug, Clone)]
+pub struct WebSocketServerHandshakeComplete {
+    pub request_uri: String,
+    pub request_headers: HttpHeaders,
+    pub selected_subprotocol: Option<String>,
+}
+
+struct WebSocketServerProtocolDecoder {
+    websocket_path: String,
+    subprotocols: Vec<String>,
+    allow_extensions: bool,
+    max_frame_payload_length: usize,
+    compression: Option<PerMessageDeflateOptions>,
+    // 和出站部分共享
+    close_sent: Arc<AtomicBool>,
+    // 和出站部分共享, 协商了 permessage-deflate 之后设置
+    deflater: Arc<Mutex<Option<Deflater>>>,
+}
+
+impl WebSocketServerProtocolDecoder {
+    fn is_websocket_path(&self, uri: &str) -> bool {
+        let path = uri.split('?').next().unwrap_or_default();
+        path == self.websocket_path
+    }
+
+    fn handshake(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        request: &FullHttpRequest,
+    ) {
+        let headers = &request.headers;
+        if request.method != HttpMethod::Get {
+            return reject(channel_handler_ctx, request.version, 403, "");
+        }
+        if !headers.contains_value(names::UPGRADE, values::WEBSOCKET)
+            || !headers.contains_value(names::CONNECTION, values::UPGRADE)
+        {
+            return reject(
+                channel_handler_ctx,
+                request.version,
+                400,
+                "not a WebSocket handshake request: missing upgrade",
+            );
+        }
+        if headers.get(names::SEC_WEBSOCKET_VERSION) != Some(WEBSOCKET_VERSION) {
+            // 告诉客户端支持的版本
+            let mut response =
+                FullHttpResponse::new(request.version, HttpResponseStatus::from_code(426), vec![]);
+            response
+                .headers
+                .set(names::SEC_WEBSOCKET_VERSION, WEBSOCKET_VERSION)
+                .set(names::CONNECTION, values::CLOSE);
+            channel_handler_ctx
+                .write_and_flush(&mut response)
+                .add_close_listener();
+            return;
+        }
+        let key = match headers.get(names::SEC_WEBSOCKET_KEY) {
+            Some(key) => key.trim(),
+            None => {
+                return reject(
+                    channel_handler_ctx,
+                    request.version,
+                    400,
+                    "not a WebSocket request: missing key",
+                )
+            }
+        };
+
+        let mut response = FullHttpResponse::new(
+            HttpVersion::Http11,
+            HttpResponseStatus::from_code(101),
+            vec![],
+        );
+        response
+            .headers
+            .set(names::UPGRADE, values::WEBSOCKET)
+            .set(names::CONNECTION, "Upgrade")
+            .set(names::SEC_WEBSOCKET_ACCEPT, accept_key(key));
+        let mut decoder = WebSocketProtocolDecoder::new(
+            WebSocketFrameDecoder::new(true, self.allow_extensions, self.max_frame_payload_length),
+            self.close_sent.clone(),
+        );
+        let selected_subprotocol = headers
+            .get(names::SEC_WEBSOCKET_PROTOCOL)
+            .and_then(|requested| select_subprotocol(requested, &self.subprotocols));
+        if let Some(subprotocol) = selected_subprotocol.as_ref() {
+            response
+                .headers
+                .set(names::SEC_WEBSOCKET_PROTOCOL, subprotocol.as_str());
+        }
+        if let (Some(options), Some(extensions)) = (
+            self.compression.as_ref(),
+            headers.get(names::SEC_WEBSOCKET_EXTENSIONS),
+        ) {
+            if let Some((extension, deflater, inflater)) = server_accept(options, extensions) {
+                response
+                    .headers
+                    .set(names::SEC_WEBSOCKET_EXTENSIONS, extension);
+                decoder.enable_deflate(inflater);
+                *self.deflater.lock().unwrap() = Some(deflater);
+            }
+        }
+        // 101 由 HttpServerCodec 编码, 写完之后再移除它
+        channel_handler_ctx
+            .write_and_flush(&mut response)
+            .add_close_on_failure_listener();
+        if let Err(e) = switch_to_websocket(
+            channel_handler_ctx,
+            Box::new(ByteToMessageHandler::new(decoder)),
+            "HttpServerCodec",
+        ) {
+            channel_handler_ctx.fire_channel_exception(e);
+            channel_handler_ctx.close();
+            return;
+        }
+        channel_handler_ctx.fire_user_event(Box::new(WebSocketServerHandshakeComplete {
+            request_uri: request.uri.clone(),
+            request_headers: headers.clone(),
+            selected_subprotocol,
+        }));
+    }
+}
+
+///
+/// 握手失败, 回复之后关闭连接
+///
+fn reject(
+    channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+    version: HttpVersion,
+    status: u16,
+    reason: &str,
+) {
+    let mut response = FullHttpResponse::new(
+        version,
+        HttpResponseStatus::from_code(status),
+        reason.as_bytes().to_vec(),
+    );
+    response.headers.set(names::CONNECTION, values::CLOSE);
+    if !reason.is_empty() {
+        response.headers.set(names::CONTENT_TYPE, "text/plain");
+    }
+    channel_handler_ctx
+        .write_and_flush(&mut response)
+        .add_close_listener();
+}
+
+impl ChannelInboundHandler for WebSocketServerProtocolDecoder {
+    fn id(&self) -> String {
+        "WebSocketServerProtocolHandler".to_string()
+    }
+
+    fn channel_active(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        channel_handler_ctx.fire_channel_active();
+    }
+
+    fn channel_inactive(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        channel_handler_ctx.fire_channel_inactive();
+    }
+
+    fn channel_read(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        message: &mut dyn Any,
+    ) {
+        match message.downcast_ref::<FullHttpRequest>() {
+            Some(request) if self.is_websocket_path(&request.uri) => {
+                self.handshake(channel_handler_ctx, request)
+            }
+            _ => channel_handler_ctx.fire_channel_read(message),
+        }
+    }
+
+    fn channel_exception(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        error: RettyErrorKind,
+    ) {
+        channel_handler_ctx.fire_channel_exception(error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::codec::http::http_message::HttpUpgradeEvent;
+    use crate::channel::codec::http::http_object_aggregator::HttpObjectAggregator;
+    use crate::channel::codec::http::http_server_codec::HttpServerCodec;
+    use crate::channel::codec::http::websocket::websocket_frame::WebSocketFrame;
+    use crate::channel::embedded_channel::EmbeddedChannel;
+    use crate::channel::handler_pipe::ChannelHandlerPipe;
+
+    const UPGRADE_REQUEST: &[u8] = b"GET /ws HTTP/1.1\r\nHost: server.example.com\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n";
+
+    // RFC 6455 5.7 带掩码的 "Hello"
+    const MASKED_HELLO: &[u8] = &[
+        0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+    ];
+
+    fn server_channel() -> EmbeddedChannel<WebSocketFrame> {
+        let mut handler_pipe = ChannelHandlerPipe::new();
+        handler_pipe.add_last_codec(HttpServerCodec::new());
+        handler_pipe.add_last_inbound(Box::new(HttpObjectAggregator::new(1024)));
+        handler_pipe.add_last_codec(WebSocketServerProtocolHandler::new("/ws"));
+        EmbeddedChannel::new(handler_pipe)
+    }
+
+    #[test]
+    fn replaces_http_handlers_after_handshake() {
+        let mut channel = server_channel();
+        // 第一帧和升级请求在同一次读里
+        let mut bytes = UPGRADE_REQUEST.to_vec();
+        bytes.extend_from_slice(MASKED_HELLO);
+        channel.write_inbound(&bytes);
+
+        let response = String::from_utf8(channel.read_outbound()).unwrap();
+        assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(response.contains("sec-websocket-accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        assert_eq!(
+            channel.pipeline().names(),
+            vec!["HEAD", "WebSocketFrameDecoder", "EmbeddedChannelCollector"]
+        );
+        assert_eq!(
+            channel.outbound_pipeline().names(),
+            vec!["WebSocketFrameEncoder", "TAIL"]
+        );
+        let events = channel.take_events();
+        assert_eq!(events.len(), 2);
+        assert!(events[0].is::<WebSocketServerHandshakeComplete>());
+        assert_eq!(
+            events[1]
+                .downcast_ref::<HttpUpgradeEvent>()
+                .unwrap()
+                .protocol,
+            "websocket"
+        );
+        assert_eq!(
+            channel.read_inbound(),
+            Some(WebSocketFrame::Text("Hello".to_string()))
+        );
+
+        channel.write_inbound(MASKED_HELLO);
+        assert_eq!(
+            channel.read_inbound(),
+            Some(WebSocketFrame::Text("Hello".to_string()))
+        );
+        channel.write_outbound(&mut WebSocketFrame::Text("Hi".to_string()));
+        assert_eq!(channel.read_outbound(), b"\x81\x02Hi".to_vec());
+        assert!(channel.take_exceptions().is_empty());
+    }
+
+    #[test]
+    fn other_requests_pass_through() {
+        let mut handler_pipe = ChannelHandlerPipe::new();
+        handler_pipe.add_last_codec(HttpServerCodec::new());
+        handler_pipe.add_last_inbound(Box::new(HttpObjectAggregator::new(1024)));
+        handler_pipe.add_last_codec(WebSocketServerProtocolHandler::new("/ws"));
+        let channel = EmbeddedChannel::<FullHttpRequest>::new(handler_pipe);
+        channel.write_inbound(b"GET /other HTTP/1.1\r\n\r\n");
+        assert_eq!(channel.read_inbound().unwrap().uri, "/other");
+        assert_eq!(
+            channel.pipeline().names(),
+            vec![
+                "HEAD",
+                "HttpServerCodec",
+                "HttpObjectAggregator",
+                "WebSocketServerProtocolHandler",
+                "EmbeddedChannelCollector"
+            ]
+        );
+    }
+}
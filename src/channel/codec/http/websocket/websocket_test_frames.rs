@@ -0,0 +1,91 @@
+use std::any::Any;
+use std::fmt::Debug;
+
+use crate::channel::codec::http::websocket::websocket_frame_decoder::apply_mask;
+use crate::channel::embedded_channel::EmbeddedChannel;
+use crate::errors::CodecErrorKind;
+
+///
+/// 测试用的帧, 客户端发出的帧使用这个掩码
+///
+pub(crate) const MASK: [u8; 4] = [0x37, 0xfa, 0x21, 0x3d];
+
+///
+/// 用最短的长度编码组一帧, mask 为 None 时不带掩码
+///
+pub(crate) fn frame(b0: u8, payload: &[u8], mask: Option<[u8; 4]>) -> Vec<u8> {
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    let mut bytes = vec![b0];
+    if payload.len() <= 125 {
+        bytes.push(mask_bit | payload.len() as u8);
+    } else if payload.len() <= 0xFFFF {
+        bytes.push(mask_bit | 126);
+        bytes.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    } else {
+        bytes.push(mask_bit | 127);
+        bytes.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+    }
+    let start = bytes.len();
+    if let Some(mask) = mask {
+        bytes.extend_from_slice(&mask);
+        bytes.extend_from_slice(payload);
+        apply_mask(&mut bytes[start + 4..], mask);
+    } else {
+        bytes.extend_from_slice(payload);
+    }
+    bytes
+}
+
+///
+/// 拆开 bytes 里的一帧, 返回第一个字节、是否带掩码和去掉掩码之后的 payload, 以及这一帧的长度
+///
+pub(crate) fn parse_frame(bytes: &[u8]) -> (u8, bool, Vec<u8>, usize) {
+    let masked = bytes[1] & 0x80 != 0;
+    let (length, mut offset) = match bytes[1] & 0x7F {
+        126 => (u16::from_be_bytes([bytes[2], bytes[3]]) as usize, 4),
+        127 => {
+            let mut length = [0u8; 8];
+            length.copy_from_slice(&bytes[2..10]);
+            (u64::from_be_bytes(length) as usize, 10)
+        }
+        length => (length as usize, 2),
+    };
+    let mut mask = None;
+    if masked {
+        mask = Some([
+            bytes[offset],
+            bytes[offset + 1],
+            bytes[offset + 2],
+            bytes[offset + 3],
+        ]);
+        offset += 4;
+    }
+    let mut payload = bytes[offset..offset + length].to_vec();
+    if let Some(mask) = mask {
+        apply_mask(&mut payload, mask);
+    }
+    (bytes[0], masked, payload, offset + length)
+}
+
+///
+/// 触发了一次异常并且回复了带 status 的关闭帧, 之后的数据全部丢弃
+///
+pub(crate) fn assert_violation<T: Any + Clone + Send + Debug>(
+    channel: &mut EmbeddedChannel<T>,
+    codec_kind: CodecErrorKind,
+    status: u16,
+) {
+    let errors = channel.take_exceptions();
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    assert!(errors[0].is_codec_error(codec_kind), "{:?}", errors[0]);
+    let close = channel.read_outbound();
+    let (b0, _, payload, length) = parse_frame(&close);
+    assert_eq!(b0, 0x88);
+    assert_eq!(&payload[..2], &status.to_be_bytes()[..]);
+    assert_eq!(length, close.len());
+    assert!(channel.read_inbound().is_none());
+    channel.write_inbound(&frame(0x82, b"after", Some(MASK)));
+    channel.write_inbound(&frame(0x82, b"after", None));
+    assert!(channel.read_inbound().is_none());
+    assert!(channel.take_exceptions().is_empty());
+}
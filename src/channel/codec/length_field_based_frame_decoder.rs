@@ -0,0 +1,474 @@
+use bytebuf_rs::bytebuf::ByteBuf;
+
+use crate::channel::channel_handler_ctx::ChannelInboundHandlerCtx;
+use crate::channel::codec::byte_order::{check_length_field_length, ByteOrder};
+use crate::channel::codec::byte_to_message_decoder::ByteToMessageDecoder;
+use crate::errors::{CodecErrorKind, RettyErrorKind};
+
+// Ok((消费的字节数, 去掉 strip 之后的帧范围)), Err((消费的字节数, 错误))
+type DecodeResult = Result<(usize, Option<(usize, usize)>), (usize, RettyErrorKind)>;
+
+///
+/// 根据长度字段拆帧的解码器, 参数含义和 Netty 的 LengthFieldBasedFrameDecoder 相同
+///
+/// 帧长度 = 长度字段的值 + length_adjustment + length_field_offset + length_field_length
+/// 每一帧去掉前面 initial_bytes_to_strip 个字节之后作为一个 ByteBuf 传给下一个handler
+///
+/// 帧长度超过 max_frame_length 时丢弃这一帧并触发 TooLongFrame 的 channel_exception,
+/// fail_fast 为 true 时读到长度字段就触发, 否则等整帧丢弃完再触发
+///
+/// 用 ByteToMessageHandler 包装之后加入 pipeline
+///
+pub struct LengthFieldBasedFrameDecoder {
+    byte_order: ByteOrder,
+    max_frame_length: usize,
+    length_field_offset: usize,
+    length_field_length: usize,
+    length_field_end_offset: usize,
+    length_adjustment: i64,
+    initial_bytes_to_strip: usize,
+    fail_fast: bool,
+    discarding_too_long_frame: bool,
+    too_long_frame_length: u64,
+    bytes_to_discard: u64,
+}
+
+impl LengthFieldBasedFrameDecoder {
+    ///
+    /// 大端, fail_fast
+    ///
+    pub fn new(
+        max_frame_length: usize,
+        length_field_offset: usize,
+        length_field_length: usize,
+        length_adjustment: i64,
+        initial_bytes_to_strip: usize,
+    ) -> Self {
+        LengthFieldBasedFrameDecoder::new_with_byte_order(
+            ByteOrder::BigEndian,
+            max_frame_length,
+            length_field_offset,
+            length_field_length,
+            length_adjustment,
+            initial_bytes_to_strip,
+            true,
+        )
+    }
+
+    ///
+    /// 参数不合法时 panic
+    ///
+    pub fn new_with_byte_order(
+        byte_order: ByteOrder,
+        max_frame_length: usize,
+        length_field_offset: usize,
+        length_field_length: usize,
+        length_adjustment: i64,
+        initial_bytes_to_strip: usize,
+        fail_fast: bool,
+    ) -> Self {
+        if let Err(e) = check_length_field_length(length_field_length) {
+            panic!("{}", e.message);
+        }
+        if max_frame_length == 0 {
+            panic!("max_frame_length must be a positive integer");
+        }
+        if length_field_offset > max_frame_length - length_field_length.min(max_frame_length) {
+            panic!(
+                "max_frame_length ({}) must be equal to or greater than length_field_offset ({}) + length_field_length ({})",
+                max_frame_length, length_field_offset, length_field_length
+            );
+        }
+        LengthFieldBasedFrameDecoder {
+            byte_order,
+            max_frame_length,
+            length_field_offset,
+            length_field_length,
+            length_field_end_offset: length_field_offset + length_field_length,
+            length_adjustment,
+            initial_bytes_to_strip,
+            fail_fast,
+            discarding_too_long_frame: false,
+            too_long_frame_length: 0,
+            bytes_to_discard: 0,
+        }
+    }
+
+    ///
+    /// 修改一帧的最大长度, 从下一次 decode 开始生效, 不能小于长度字段的结尾, 否则 panic
+    ///
+    pub fn set_max_frame_length(&mut self, max_frame_length: usize) {
+        if max_frame_length == 0 || max_frame_length < self.length_field_end_offset {
+            panic!(
+                "max_frame_length ({}) must be equal to or greater than length_field_offset ({}) + length_field_length ({})",
+                max_frame_length, self.length_field_offset, self.length_field_length
+            );
+        }
+        self.max_frame_length = max_frame_length;
+    }
+
+    ///
+    /// 从 input 中拆出一帧, 数据不够一帧时返回 Ok((0, None))
+    ///
+    fn decode_frame(&mut self, input: &[u8]) -> DecodeResult {
+        if self.discarding_too_long_frame {
+            let discard = input.len().min(self.bytes_to_discard as usize);
+            self.bytes_to_discard -= discard as u64;
+            let ret = self.fail_if_necessary(false);
+            return match ret {
+                Some(e) => Err((discard, e)),
+                None => Ok((discard, None)),
+            };
+        }
+
+        if input.len() < self.length_field_end_offset {
+            return Ok((0, None));
+        }
+
+        let length = self
+            .byte_order
+            .read_uint(&input[self.length_field_offset..self.length_field_end_offset]);
+        let frame_length = (length as i128)
+            + (self.length_adjustment as i128)
+            + (self.length_field_end_offset as i128);
+
+        if frame_length < self.length_field_end_offset as i128 {
+            return Err((
+                self.length_field_end_offset,
+                RettyErrorKind::codec(
+                    CodecErrorKind::CorruptedFrame,
+                    format!(
+                        "Adjusted frame length ({}) is less than length_field_end_offset: {}",
+                        frame_length, self.length_field_end_offset
+                    ),
+                ),
+            ));
+        }
+
+        if frame_length > self.max_frame_length as i128 {
+            let frame_length = frame_length.min(u64::MAX as i128) as u64;
+            self.too_long_frame_length = frame_length;
+            let discard = if frame_length <= input.len() as u64 {
+                frame_length as usize
+            } else {
+                self.discarding_too_long_frame = true;
+                self.bytes_to_discard = frame_length - input.len() as u64;
+                input.len()
+            };
+            return match self.fail_if_necessary(true) {
+                Some(e) => Err((discard, e)),
+                None => Ok((discard, None)),
+            };
+        }
+
+        let frame_length = frame_length as usize;
+        if input.len() < frame_length {
+            return Ok((0, None));
+        }
+
+        if self.initial_bytes_to_strip > frame_length {
+            return Err((
+                frame_length,
+                RettyErrorKind::codec(
+                    CodecErrorKind::CorruptedFrame,
+                    format!(
+                        "Adjusted frame length ({}) is less than initial_bytes_to_strip: {}",
+                        frame_length, self.initial_bytes_to_strip
+                    ),
+                ),
+            ));
+        }
+        Ok((
+            frame_length,
+            Some((self.initial_bytes_to_strip, frame_length)),
+        ))
+    }
+
+    fn fail_if_necessary(
+        &mut self,
+        first_detection_of_too_long_frame: bool,
+    ) -> Option<RettyErrorKind> {
+        if self.bytes_to_discard == 0 {
+            // 整帧已经丢弃完
+            let too_long_frame_length = self.too_long_frame_length;
+            self.too_long_frame_length = 0;
+            self.discarding_too_long_frame = false;
+            if !self.fail_fast || first_detection_of_too_long_frame {
+                return Some(self.too_long_frame_error(too_long_frame_length));
+            }
+        } else if self.fail_fast && first_detection_of_too_long_frame {
+            return Some(self.too_long_frame_error(self.too_long_frame_length));
+        }
+        None
+    }
+
+    fn too_long_frame_error(&self, frame_length: u64) -> RettyErrorKind {
+        let message = if frame_length > 0 {
+            format!(
+                "Adjusted frame length exceeds {}: {} - discarded",
+                self.max_frame_length, frame_length
+            )
+        } else {
+            format!(
+                "Adjusted frame length exceeds {} - discarding",
+                self.max_frame_length
+            )
+        };
+        RettyErrorKind::codec(CodecErrorKind::TooLongFrame, message)
+    }
+}
+
+impl ByteToMessageDecoder for LengthFieldBasedFrameDecoder {
+    type Message = ByteBuf;
+
+    fn id(&self) -> String {
+        "LengthFieldBasedFrameDecoder".to_string()
+    }
+
+    fn decode(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        input: &[u8],
+        out: &mut Vec<ByteBuf>,
+    ) -> Result<usize, RettyErrorKind> {
+        match self.decode_frame(input) {
+            Ok((consumed, frame)) => {
+                if let Some((start, end)) = frame {
+                    out.push(ByteBuf::new_from(&input[start..end]));
+                }
+                Ok(consumed)
+            }
+            // 跳过出错的数据继续解码
+            Err((consumed, e)) => {
+                channel_handler_ctx.fire_channel_exception(e);
+                Ok(consumed)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 和 ByteToMessageHandler 一样累积数据反复解码, 返回解出的帧和错误
+    fn decode_chunks(
+        decoder: &mut LengthFieldBasedFrameDecoder,
+        chunks: &[&[u8]],
+    ) -> (Vec<Vec<u8>>, Vec<RettyErrorKind>) {
+        let mut cumulation = vec![];
+        let mut frames = vec![];
+        let mut errors = vec![];
+        for chunk in chunks {
+            cumulation.extend_from_slice(chunk);
+            let mut reader_index = 0;
+            loop {
+                let consumed = match decoder.decode_frame(&cumulation[reader_index..]) {
+                    Ok((consumed, frame)) => {
+                        if let Some((start, end)) = frame {
+                            let input = &cumulation[reader_index..];
+                            frames.push(input[start..end].to_vec());
+                        }
+                        consumed
+                    }
+                    Err((consumed, e)) => {
+                        errors.push(e);
+                        consumed
+                    }
+                };
+                if consumed == 0 {
+                    break;
+                }
+                reader_index += consumed;
+            }
+            cumulation.drain(..reader_index);
+        }
+        (frames, errors)
+    }
+
+    fn decode(decoder: &mut LengthFieldBasedFrameDecoder, input: &[u8]) -> Vec<Vec<u8>> {
+        let (frames, errors) = decode_chunks(decoder, &[input]);
+        assert!(errors.is_empty(), "{:?}", errors);
+        frames
+    }
+
+    #[test]
+    fn length_field_sizes_and_byte_orders() {
+        for byte_order in [ByteOrder::BigEndian, ByteOrder::LittleEndian] {
+            for width in [1, 2, 3, 4, 8] {
+                let mut frame = byte_order.write_uint(5, width).unwrap();
+                frame.extend_from_slice(b"hello");
+                let mut decoder = LengthFieldBasedFrameDecoder::new_with_byte_order(
+                    byte_order, 1024, 0, width, 0, 0, true,
+                );
+                assert_eq!(decode(&mut decoder, &frame), vec![frame.clone()]);
+            }
+        }
+        let mut decoder = LengthFieldBasedFrameDecoder::new_with_byte_order(
+            ByteOrder::LittleEndian,
+            1024,
+            0,
+            2,
+            0,
+            2,
+            true,
+        );
+        assert_eq!(decode(&mut decoder, b"\x03\x00abc"), vec![b"abc".to_vec()]);
+    }
+
+    #[test]
+    fn waits_for_whole_frame() {
+        let mut decoder = LengthFieldBasedFrameDecoder::new(1024, 0, 2, 0, 2);
+        let (frames, errors) =
+            decode_chunks(&mut decoder, &[b"\x00", b"\x05he", b"llo\x00\x01", b"!"]);
+        assert!(errors.is_empty());
+        assert_eq!(frames, vec![b"hello".to_vec(), b"!".to_vec()]);
+    }
+
+    #[test]
+    fn strips_header() {
+        // offset 0, 2 字节长度, 去掉长度字段
+        let mut decoder = LengthFieldBasedFrameDecoder::new(1024, 0, 2, 0, 2);
+        assert_eq!(
+            decode(&mut decoder, b"\x00\x0cHELLO, WORLD"),
+            vec![b"HELLO, WORLD".to_vec()]
+        );
+    }
+
+    #[test]
+    fn length_includes_header() {
+        let mut decoder = LengthFieldBasedFrameDecoder::new(1024, 0, 2, -2, 0);
+        assert_eq!(
+            decode(&mut decoder, b"\x00\x0eHELLO, WORLD"),
+            vec![b"\x00\x0eHELLO, WORLD".to_vec()]
+        );
+    }
+
+    #[test]
+    fn header_before_length_field() {
+        let mut decoder = LengthFieldBasedFrameDecoder::new(1024, 2, 3, 0, 0);
+        assert_eq!(
+            decode(&mut decoder, b"\xca\xfe\x00\x00\x0cHELLO, WORLD"),
+            vec![b"\xca\xfe\x00\x00\x0cHELLO, WORLD".to_vec()]
+        );
+    }
+
+    #[test]
+    fn header_after_length_field() {
+        let mut decoder = LengthFieldBasedFrameDecoder::new(1024, 0, 3, 2, 0);
+        assert_eq!(
+            decode(&mut decoder, b"\x00\x00\x0c\xca\xfeHELLO, WORLD"),
+            vec![b"\x00\x00\x0c\xca\xfeHELLO, WORLD".to_vec()]
+        );
+    }
+
+    #[test]
+    fn adjustment_and_strip() {
+        let mut decoder = LengthFieldBasedFrameDecoder::new(1024, 1, 2, 1, 3);
+        assert_eq!(
+            decode(&mut decoder, b"\xca\x00\x0c\xfeHELLO, WORLD"),
+            vec![b"\xfeHELLO, WORLD".to_vec()]
+        );
+        // 长度字段是整帧的长度
+        let mut decoder = LengthFieldBasedFrameDecoder::new(1024, 1, 2, -3, 3);
+        assert_eq!(
+            decode(&mut decoder, b"\xca\x00\x10\xfeHELLO, WORLD"),
+            vec![b"\xfeHELLO, WORLD".to_vec()]
+        );
+    }
+
+    #[test]
+    fn discard_too_long_frame_fail_fast() {
+        let mut decoder = LengthFieldBasedFrameDecoder::new(8, 0, 1, 0, 1);
+        let (frames, errors) = decode_chunks(&mut decoder, &[b"\x0a1234"]);
+        assert!(frames.is_empty());
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].is_codec_error(CodecErrorKind::TooLongFrame));
+        // 读到长度字段就报错, 丢弃完剩下的部分不再报错
+        let (frames, errors) = decode_chunks(&mut decoder, &[b"567", b"890\x02ok"]);
+        assert!(errors.is_empty());
+        assert_eq!(frames, vec![b"ok".to_vec()]);
+    }
+
+    #[test]
+    fn discard_too_long_frame_without_fail_fast() {
+        let mut decoder = LengthFieldBasedFrameDecoder::new_with_byte_order(
+            ByteOrder::BigEndian,
+            8,
+            0,
+            1,
+            0,
+            1,
+            false,
+        );
+        let (frames, errors) = decode_chunks(&mut decoder, &[b"\x0a1234", b"567"]);
+        assert!(frames.is_empty());
+        assert!(errors.is_empty());
+        // 整帧丢弃完才报错
+        let (frames, errors) = decode_chunks(&mut decoder, &[b"890\x02ok"]);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].is_codec_error(CodecErrorKind::TooLongFrame));
+        assert_eq!(
+            errors[0].message,
+            "Adjusted frame length exceeds 8: 11 - discarded"
+        );
+        assert_eq!(frames, vec![b"ok".to_vec()]);
+    }
+
+    #[test]
+    fn too_long_frame_in_one_read() {
+        for fail_fast in [true, false] {
+            let mut decoder = LengthFieldBasedFrameDecoder::new_with_byte_order(
+                ByteOrder::BigEndian,
+                4,
+                0,
+                1,
+                0,
+                1,
+                fail_fast,
+            );
+            let (frames, errors) = decode_chunks(&mut decoder, &[b"\x05abcde\x01z"]);
+            assert_eq!(errors.len(), 1);
+            assert!(errors[0].is_codec_error(CodecErrorKind::TooLongFrame));
+            assert_eq!(frames, vec![b"z".to_vec()]);
+        }
+    }
+
+    #[test]
+    fn corrupted_frame_length() {
+        // 调整之后的帧长度比长度字段的结尾还小
+        let mut decoder = LengthFieldBasedFrameDecoder::new(1024, 0, 2, -4, 0);
+        let (frames, errors) = decode_chunks(&mut decoder, &[b"\x00\x01"]);
+        assert!(frames.is_empty());
+        assert!(errors[0].is_codec_error(CodecErrorKind::CorruptedFrame));
+
+        // initial_bytes_to_strip 比帧还长
+        let mut decoder = LengthFieldBasedFrameDecoder::new(1024, 0, 1, 0, 4);
+        let (frames, errors) = decode_chunks(&mut decoder, &[b"\x01a\x00"]);
+        assert!(frames.is_empty());
+        assert_eq!(errors.len(), 2);
+        assert!(errors
+            .iter()
+            .all(|e| e.is_codec_error(CodecErrorKind::CorruptedFrame)));
+    }
+
+    #[test]
+    fn set_max_frame_length() {
+        let mut decoder = LengthFieldBasedFrameDecoder::new(4, 0, 1, 0, 1);
+        decoder.set_max_frame_length(16);
+        assert_eq!(decode(&mut decoder, b"\x05abcde"), vec![b"abcde".to_vec()]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn set_max_frame_length_below_length_field() {
+        let mut decoder = LengthFieldBasedFrameDecoder::new(1024, 2, 4, 0, 0);
+        decoder.set_max_frame_length(5);
+    }
+
+    #[test]
+    #[should_panic]
+    fn invalid_length_field_length() {
+        LengthFieldBasedFrameDecoder::new(1024, 0, 5, 0, 0);
+    }
+}
@@ -0,0 +1,195 @@
+use std::any::Any;
+use std::io::ErrorKind;
+
+use bytebuf_rs::bytebuf::ByteBuf;
+
+use crate::channel::channel_handler_ctx::ChannelOutboundHandlerCtx;
+use crate::channel::codec::byte_order::{check_length_field_length, ByteOrder};
+use crate::channel::handler::ChannelOutboundHandler;
+use crate::errors::RettyErrorKind;
+
+///
+/// 在每个写出的 ByteBuf 前面加上长度字段, 和 LengthFieldBasedFrameDecoder 配对使用
+///
+/// 长度字段的值 = 消息长度 + length_adjustment (+ length_field_length, 如果 length_includes_length_field_length)
+/// 长度为负数或者长度字段放不下时这次 write 失败, 消息不再往下写
+///
+pub struct LengthFieldPrepender {
+    byte_order: ByteOrder,
+    length_field_length: usize,
+    length_includes_length_field_length: bool,
+    length_adjustment: i64,
+}
+
+impl LengthFieldPrepender {
+    ///
+    /// 大端, 长度不包含长度字段自己
+    ///
+    pub fn new(length_field_length: usize) -> Self {
+        LengthFieldPrepender::new_with_byte_order(
+            ByteOrder::BigEndian,
+            length_field_length,
+            0,
+            false,
+        )
+    }
+
+    ///
+    /// length_field_length 不是 1 / 2 / 3 / 4 / 8 时 panic
+    ///
+    pub fn new_with_byte_order(
+        byte_order: ByteOrder,
+        length_field_length: usize,
+        length_adjustment: i64,
+        length_includes_length_field_length: bool,
+    ) -> Self {
+        if let Err(e) = check_length_field_length(length_field_length) {
+            panic!("{}", e.message);
+        }
+        LengthFieldPrepender {
+            byte_order,
+            length_field_length,
+            length_includes_length_field_length,
+            length_adjustment,
+        }
+    }
+
+    fn encode(&self, body: &[u8]) -> Result<Vec<u8>, RettyErrorKind> {
+        let mut length = body.len() as i64 + self.length_adjustment;
+        if self.length_includes_length_field_length {
+            length += self.length_field_length as i64;
+        }
+        if length < 0 {
+            return Err(RettyErrorKind::new(
+                ErrorKind::InvalidInput,
+                format!("Adjusted frame length ({}) is less than zero", length),
+            ));
+        }
+        let mut frame = self
+            .byte_order
+            .write_uint(length as u64, self.length_field_length)?;
+        frame.extend_from_slice(body);
+        Ok(frame)
+    }
+}
+
+impl ChannelOutboundHandler for LengthFieldPrepender {
+    fn id(&self) -> String {
+        "LengthFieldPrepender".to_string()
+    }
+
+    fn channel_write(
+        &mut self,
+        channel_handler_ctx: &mut ChannelOutboundHandlerCtx,
+        message: &mut dyn Any,
+    ) {
+        let buf = match message.downcast_ref::<ByteBuf>() {
+            Some(buf) => buf,
+            None => {
+                // 不是 ByteBuf 的消息原样往下写
+                channel_handler_ctx.fire_channel_write(message);
+                return;
+            }
+        };
+        match self.encode(buf.available_bytes()) {
+            Ok(frame) => channel_handler_ctx.fire_channel_write(&mut ByteBuf::new_from(&frame)),
+            Err(e) => channel_handler_ctx.channel().fail_write(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::codec::message_to_message_codec::MessageToMessageEncoderHandler;
+    use crate::channel::codec::string_codec::StringEncoder;
+    use crate::channel::embedded_channel::EmbeddedChannel;
+    use crate::channel::handler_pipe::ChannelHandlerPipe;
+
+    fn prepender_channel(prepender: LengthFieldPrepender) -> EmbeddedChannel<ByteBuf> {
+        let mut handler_pipe = ChannelHandlerPipe::new();
+        // 更靠近 socket, 把经过 prepender 的 String 编码成字节
+        handler_pipe.add_last_outbound(Box::new(MessageToMessageEncoderHandler::new(
+            StringEncoder::new(),
+        )));
+        handler_pipe.add_last_outbound(Box::new(prepender));
+        EmbeddedChannel::new(handler_pipe)
+    }
+
+    #[test]
+    fn length_field_sizes() {
+        let body = b"hello";
+        for (width, field) in [
+            (1, vec![5]),
+            (2, vec![0, 5]),
+            (3, vec![0, 0, 5]),
+            (4, vec![0, 0, 0, 5]),
+            (8, vec![0, 0, 0, 0, 0, 0, 0, 5]),
+        ] {
+            let mut frame = field;
+            frame.extend_from_slice(body);
+            assert_eq!(LengthFieldPrepender::new(width).encode(body), Ok(frame));
+        }
+        let prepender =
+            LengthFieldPrepender::new_with_byte_order(ByteOrder::LittleEndian, 2, 0, false);
+        assert_eq!(prepender.encode(body), Ok(b"\x05\x00hello".to_vec()));
+    }
+
+    #[test]
+    fn length_includes_length_field_and_adjustment() {
+        let prepender = LengthFieldPrepender::new_with_byte_order(ByteOrder::BigEndian, 4, 0, true);
+        assert_eq!(prepender.encode(b"abc"), Ok(b"\0\0\0\x07abc".to_vec()));
+
+        let prepender =
+            LengthFieldPrepender::new_with_byte_order(ByteOrder::BigEndian, 2, -2, false);
+        assert_eq!(prepender.encode(b"abc"), Ok(b"\0\x01abc".to_vec()));
+
+        let prepender = LengthFieldPrepender::new_with_byte_order(ByteOrder::BigEndian, 2, 3, true);
+        assert_eq!(prepender.encode(b"abc"), Ok(b"\0\x08abc".to_vec()));
+    }
+
+    #[test]
+    fn negative_length_is_rejected() {
+        let prepender =
+            LengthFieldPrepender::new_with_byte_order(ByteOrder::BigEndian, 2, -4, false);
+        assert_eq!(
+            prepender.encode(b"abc").unwrap_err().kind,
+            ErrorKind::InvalidInput
+        );
+        // 加上长度字段自己之后不是负数
+        let prepender =
+            LengthFieldPrepender::new_with_byte_order(ByteOrder::BigEndian, 2, -4, true);
+        assert_eq!(prepender.encode(b"abc"), Ok(b"\0\x01abc".to_vec()));
+    }
+
+    #[test]
+    fn length_that_does_not_fit_fails_the_write() {
+        assert!(LengthFieldPrepender::new(1).encode(&[0; 255]).is_ok());
+        assert!(LengthFieldPrepender::new(1).encode(&[0; 256]).is_err());
+        assert!(LengthFieldPrepender::new(2).encode(&[0; 65535]).is_ok());
+        assert!(LengthFieldPrepender::new(2).encode(&[0; 65536]).is_err());
+
+        let mut channel = prepender_channel(LengthFieldPrepender::new(1));
+        let future = channel.write_outbound(&mut ByteBuf::new_from(&[0; 256]));
+        assert!(future.is_done() && !future.is_success());
+        assert!(channel.read_outbound().is_empty());
+
+        assert!(channel
+            .write_outbound(&mut ByteBuf::new_from(b"ok"))
+            .is_success());
+        assert_eq!(channel.read_outbound(), b"\x02ok");
+    }
+
+    #[test]
+    fn passes_other_messages_through() {
+        let mut channel = prepender_channel(LengthFieldPrepender::new(2));
+        assert!(channel.write_outbound(&mut "raw".to_string()).is_success());
+        assert_eq!(channel.read_outbound(), b"raw");
+    }
+
+    #[test]
+    #[should_panic]
+    fn rejects_unsupported_length_field_length() {
+        LengthFieldPrepender::new(5);
+    }
+}
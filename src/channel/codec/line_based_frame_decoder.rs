@@ -0,0 +1,231 @@
+use bytebuf_rs::bytebuf::ByteBuf;
+
+use crate::channel::channel_handler_ctx::ChannelInboundHandlerCtx;
+use crate::channel::codec::byte_to_message_decoder::ByteToMessageDecoder;
+use crate::errors::{CodecErrorKind, RettyErrorKind};
+
+///
+/// 按行拆帧的解码器, 行以 "\n" 或者 "\r\n" 结尾
+///
+/// strip_delimiter 为 true 时传给下一个handler 的 ByteBuf 不包含行尾
+/// 一行超过 max_length 时丢弃这一行并触发 TooLongFrame 的 channel_exception,
+/// fail_fast 为 true 时超过就触发, 否则读到行尾之后再触发
+///
+/// 用 ByteToMessageHandler 包装之后加入 pipeline
+///
+pub struct LineBasedFrameDecoder {
+    max_length: usize,
+    strip_delimiter: bool,
+    fail_fast: bool,
+    discarding: bool,
+    discarded_bytes: usize,
+}
+
+impl LineBasedFrameDecoder {
+    ///
+    /// 去掉行尾, 读到行尾之后才报告超长
+    ///
+    pub fn new(max_length: usize) -> Self {
+        LineBasedFrameDecoder::new_with_options(max_length, true, false)
+    }
+
+    pub fn new_with_options(max_length: usize, strip_delimiter: bool, fail_fast: bool) -> Self {
+        LineBasedFrameDecoder {
+            max_length,
+            strip_delimiter,
+            fail_fast,
+            discarding: false,
+            discarded_bytes: 0,
+        }
+    }
+
+    ///
+    /// 修改一行的最大长度, 从下一次 decode 开始生效
+    ///
+    pub fn set_max_length(&mut self, max_length: usize) {
+        self.max_length = max_length;
+    }
+
+    fn fail(&self, channel_handler_ctx: &mut ChannelInboundHandlerCtx, length: usize) {
+        let message = if length > 0 {
+            format!(
+                "frame length ({}) exceeds the allowed maximum ({})",
+                length, self.max_length
+            )
+        } else {
+            format!("frame length exceeds {} - discarding", self.max_length)
+        };
+        channel_handler_ctx
+            .fire_channel_exception(RettyErrorKind::codec(CodecErrorKind::TooLongFrame, message));
+    }
+}
+
+///
+/// 返回行尾的位置, "\r\n" 返回 '\r' 的位置
+///
+fn find_end_of_line(input: &[u8]) -> Option<usize> {
+    let i = input.iter().position(|b| *b == b'\n')?;
+    if i > 0 && input[i - 1] == b'\r' {
+        Some(i - 1)
+    } else {
+        Some(i)
+    }
+}
+
+impl ByteToMessageDecoder for LineBasedFrameDecoder {
+    type Message = ByteBuf;
+
+    fn id(&self) -> String {
+        "LineBasedFrameDecoder".to_string()
+    }
+
+    fn decode(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        input: &[u8],
+        out: &mut Vec<ByteBuf>,
+    ) -> Result<usize, RettyErrorKind> {
+        let eol = find_end_of_line(input);
+        if !self.discarding {
+            match eol {
+                Some(eol) => {
+                    let delim_length = if input[eol] == b'\r' { 2 } else { 1 };
+                    if eol > self.max_length {
+                        self.fail(channel_handler_ctx, eol);
+                        return Ok(eol + delim_length);
+                    }
+                    let frame = if self.strip_delimiter {
+                        &input[..eol]
+                    } else {
+                        &input[..eol + delim_length]
+                    };
+                    out.push(ByteBuf::new_from(frame));
+                    Ok(eol + delim_length)
+                }
+                None => {
+                    let length = input.len();
+                    if length > self.max_length {
+                        // 丢弃到下一个行尾为止
+                        self.discarded_bytes = length;
+                        self.discarding = true;
+                        if self.fail_fast {
+                            self.fail(channel_handler_ctx, length);
+                        }
+                        return Ok(length);
+                    }
+                    Ok(0)
+                }
+            }
+        } else {
+            match eol {
+                Some(eol) => {
+                    let length = self.discarded_bytes + eol;
+                    let delim_length = if input[eol] == b'\r' { 2 } else { 1 };
+                    self.discarded_bytes = 0;
+                    self.discarding = false;
+                    if !self.fail_fast {
+                        self.fail(channel_handler_ctx, length);
+                    }
+                    Ok(eol + delim_length)
+                }
+                None => {
+                    self.discarded_bytes += input.len();
+                    Ok(input.len())
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::codec::byte_to_message_decoder::ByteToMessageHandler;
+    use crate::channel::embedded_channel::EmbeddedChannel;
+    use crate::channel::handler_pipe::ChannelHandlerPipe;
+
+    fn line_channel(decoder: LineBasedFrameDecoder) -> EmbeddedChannel<ByteBuf> {
+        let mut handler_pipe = ChannelHandlerPipe::new();
+        handler_pipe.add_last_inbound(Box::new(ByteToMessageHandler::new(decoder)));
+        EmbeddedChannel::new(handler_pipe)
+    }
+
+    fn read_lines(channel: &EmbeddedChannel<ByteBuf>) -> Vec<Vec<u8>> {
+        channel
+            .read_all_inbound()
+            .iter()
+            .map(|buf| buf.available_bytes().to_vec())
+            .collect()
+    }
+
+    fn too_long_errors(channel: &EmbeddedChannel<ByteBuf>) -> usize {
+        let errors = channel.take_exceptions();
+        assert!(errors
+            .iter()
+            .all(|e| e.is_codec_error(CodecErrorKind::TooLongFrame)));
+        errors.len()
+    }
+
+    #[test]
+    fn strips_or_keeps_the_delimiter() {
+        let channel = line_channel(LineBasedFrameDecoder::new(1024));
+        channel.write_inbound(b"a\r\nb\n\n");
+        assert_eq!(
+            read_lines(&channel),
+            vec![b"a".to_vec(), b"b".to_vec(), vec![]]
+        );
+
+        let channel = line_channel(LineBasedFrameDecoder::new_with_options(1024, false, false));
+        channel.write_inbound(b"a\r\nb\n");
+        assert_eq!(
+            read_lines(&channel),
+            vec![b"a\r\n".to_vec(), b"b\n".to_vec()]
+        );
+    }
+
+    #[test]
+    fn crlf_split_across_reads() {
+        let channel = line_channel(LineBasedFrameDecoder::new(1024));
+        channel.write_inbound(b"ab\r");
+        assert!(read_lines(&channel).is_empty());
+        channel.write_inbound(b"\ncd\n");
+        assert_eq!(read_lines(&channel), vec![b"ab".to_vec(), b"cd".to_vec()]);
+
+        let channel = line_channel(LineBasedFrameDecoder::new_with_options(1024, false, false));
+        channel.write_inbound(b"ab\r");
+        channel.write_inbound(b"\n");
+        assert_eq!(read_lines(&channel), vec![b"ab\r\n".to_vec()]);
+    }
+
+    #[test]
+    fn too_long_line_with_delimiter_is_skipped() {
+        for fail_fast in [false, true] {
+            let channel = line_channel(LineBasedFrameDecoder::new_with_options(3, true, fail_fast));
+            channel.write_inbound(b"abcdef\r\nok\n");
+            assert_eq!(too_long_errors(&channel), 1);
+            assert_eq!(read_lines(&channel), vec![b"ok".to_vec()]);
+        }
+    }
+
+    #[test]
+    fn too_long_line_is_reported_at_the_delimiter_without_fail_fast() {
+        let channel = line_channel(LineBasedFrameDecoder::new_with_options(3, true, false));
+        channel.write_inbound(b"abcdef");
+        assert_eq!(too_long_errors(&channel), 0);
+        channel.write_inbound(b"ghi");
+        assert_eq!(too_long_errors(&channel), 0);
+        channel.write_inbound(b"j\nok\n");
+        assert_eq!(too_long_errors(&channel), 1);
+        assert_eq!(read_lines(&channel), vec![b"ok".to_vec()]);
+    }
+
+    #[test]
+    fn too_long_line_is_reported_immediately_with_fail_fast() {
+        let channel = line_channel(LineBasedFrameDecoder::new_with_options(3, true, true));
+        channel.write_inbound(b"abcdef");
+        assert_eq!(too_long_errors(&channel), 1);
+        channel.write_inbound(b"ghi\r\nok\n");
+        assert_eq!(too_long_errors(&channel), 0);
+        assert_eq!(read_lines(&channel), vec![b"ok".to_vec()]);
+    }
+}
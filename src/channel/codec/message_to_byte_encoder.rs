@@ -0,0 +1,167 @@
+use std::any::Any;
+use std::sync::{Arc, Mutex};
+
+use bytebuf_rs::bytebuf::ByteBuf;
+
+use crate::channel::channel_handler_ctx::ChannelOutboundHandlerCtx;
+use crate::channel::handler::ChannelOutboundHandler;
+use crate::errors::RettyErrorKind;
+
+///
+/// 把 Message 类型的消息编码成字节, 配合 MessageToByteHandler 使用
+///
+pub trait MessageToByteEncoder {
+    type Message: Any;
+
+    fn id(&self) -> String;
+
+    fn encode(
+        &mut self,
+        channel_handler_ctx: &mut ChannelOutboundHandlerCtx,
+        message: &mut Self::Message,
+        out: &mut Vec<u8>,
+    ) -> Result<(), RettyErrorKind>;
+}
+
+///
+/// codec 的入站和出站部分共用一个编码器
+///
+impl<E: MessageToByteEncoder> MessageToByteEncoder for Arc<Mutex<E>> {
+    type Message = E::Message;
+
+    fn id(&self) -> String {
+        self.lock().unwrap().id()
+    }
+
+    fn encode(
+        &mut self,
+        channel_handler_ctx: &mut ChannelOutboundHandlerCtx,
+        message: &mut Self::Message,
+        out: &mut Vec<u8>,
+    ) -> Result<(), RettyErrorKind> {
+        self.lock()
+            .unwrap()
+            .encode(channel_handler_ctx, message, out)
+    }
+}
+
+///
+/// 把 MessageToByteEncoder 包装成出站 handler
+/// 编码结果作为 ByteBuf 往下写, 其他类型的消息原样往下写, 编码失败时这次 write 的 future 失败
+///
+pub struct MessageToByteHandler<E: MessageToByteEncoder> {
+    encoder: E,
+}
+
+impl<E: MessageToByteEncoder> MessageToByteHandler<E> {
+    pub fn new(encoder: E) -> Self {
+        MessageToByteHandler { encoder }
+    }
+
+    pub fn encoder(&self) -> &E {
+        &self.encoder
+    }
+
+    pub fn encoder_mut(&mut self) -> &mut E {
+        &mut self.encoder
+    }
+}
+
+impl<E: MessageToByteEncoder + 'static> ChannelOutboundHandler for MessageToByteHandler<E> {
+    fn id(&self) -> String {
+        self.encoder.id()
+    }
+
+    fn channel_write(
+        &mut self,
+        channel_handler_ctx: &mut ChannelOutboundHandlerCtx,
+        message: &mut dyn Any,
+    ) {
+        let msg = match message.downcast_mut::<E::Message>() {
+            Some(msg) => msg,
+            None => {
+                channel_handler_ctx.fire_channel_write(message);
+                return;
+            }
+        };
+        let mut out = vec![];
+        match self.encoder.encode(channel_handler_ctx, msg, &mut out) {
+            Ok(()) => channel_handler_ctx.fire_channel_write(&mut ByteBuf::new_from(&out)),
+            Err(e) => channel_handler_ctx.channel().fail_write(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+
+    use super::*;
+    use crate::channel::embedded_channel::EmbeddedChannel;
+    use crate::channel::handler_pipe::ChannelHandlerPipe;
+
+    // 把 String 编码成大写字节, 空字符串编码失败
+    struct UpperEncoder;
+
+    impl MessageToByteEncoder for UpperEncoder {
+        type Message = String;
+
+        fn id(&self) -> String {
+            "UpperEncoder".to_string()
+        }
+
+        fn encode(
+            &mut self,
+            _channel_handler_ctx: &mut ChannelOutboundHandlerCtx,
+            message: &mut String,
+            out: &mut Vec<u8>,
+        ) -> Result<(), RettyErrorKind> {
+            if message.is_empty() {
+                return Err(RettyErrorKind::new(
+                    ErrorKind::InvalidInput,
+                    "empty message".to_string(),
+                ));
+            }
+            out.extend_from_slice(message.to_uppercase().as_bytes());
+            Ok(())
+        }
+    }
+
+    fn encoder_channel() -> EmbeddedChannel<ByteBuf> {
+        let mut handler_pipe = ChannelHandlerPipe::new();
+        handler_pipe.add_last_outbound(Box::new(MessageToByteHandler::new(UpperEncoder)));
+        EmbeddedChannel::new(handler_pipe)
+    }
+
+    #[test]
+    fn encodes_messages_of_its_type() {
+        let mut channel = encoder_channel();
+        assert!(channel
+            .write_outbound(&mut "hello".to_string())
+            .is_success());
+        assert_eq!(channel.read_outbound(), b"HELLO");
+    }
+
+    #[test]
+    fn passes_other_messages_through() {
+        let mut channel = encoder_channel();
+        assert!(channel
+            .write_outbound(&mut ByteBuf::new_from(b"raw"))
+            .is_success());
+        assert_eq!(channel.read_outbound(), b"raw");
+    }
+
+    #[test]
+    fn encode_error_fails_the_write() {
+        let mut channel = encoder_channel();
+        let future = channel.write_outbound(&mut String::new());
+        assert!(future.is_done());
+        assert!(!future.is_success());
+        assert_eq!(future.cause().unwrap().kind, ErrorKind::InvalidInput);
+        assert!(channel.read_outbound().is_empty());
+
+        // 失败不影响之后的写
+        assert!(channel.write_outbound(&mut "ok".to_string()).is_success());
+        assert_eq!(channel.read_outbound(), b"OK");
+    }
+}
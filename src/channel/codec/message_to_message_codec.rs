@@ -0,0 +1,421 @@
+use std::any::Any;
+use std::sync::{Arc, Mutex};
+
+use crate::channel::channel_handler_ctx::{ChannelInboundHandlerCtx, ChannelOutboundHandlerCtx};
+use crate::channel::handler::{ChannelCodec, ChannelInboundHandler, ChannelOutboundHandler};
+use crate::errors::RettyErrorKind;
+
+///
+/// 把 Input 类型的入站消息解码成零个或多个 Output, 配合 MessageToMessageDecoderHandler 使用
+///
+pub trait MessageToMessageDecoder {
+    type Input: Any;
+    type Output: Any;
+
+    fn id(&self) -> String;
+
+    fn decode(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        message: &mut Self::Input,
+        out: &mut Vec<Self::Output>,
+    ) -> Result<(), RettyErrorKind>;
+}
+
+///
+/// 把 Input 类型的出站消息编码成零个或多个 Output, 配合 MessageToMessageEncoderHandler 使用
+///
+pub trait MessageToMessageEncoder {
+    type Input: Any;
+    type Output: Any;
+
+    fn id(&self) -> String;
+
+    fn encode(
+        &mut self,
+        channel_handler_ctx: &mut ChannelOutboundHandlerCtx,
+        message: &mut Self::Input,
+        out: &mut Vec<Self::Output>,
+    ) -> Result<(), RettyErrorKind>;
+}
+
+///
+/// codec 的入站和出站部分共用一个解码器
+/// decode 里触发的异常等锁释放之后再往下传, 下游在 channel_exception 里写数据时编码器要拿同一把锁
+///
+impl<D: MessageToMessageDecoder> MessageToMessageDecoder for Arc<Mutex<D>> {
+    type Input = D::Input;
+    type Output = D::Output;
+
+    fn id(&self) -> String {
+        self.lock().unwrap().id()
+    }
+
+    fn decode(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        message: &mut Self::Input,
+        out: &mut Vec<Self::Output>,
+    ) -> Result<(), RettyErrorKind> {
+        channel_handler_ctx
+            .with_deferred_exceptions(|ctx| self.lock().unwrap().decode(ctx, message, out))
+    }
+}
+
+impl<E: MessageToMessageEncoder> MessageToMessageEncoder for Arc<Mutex<E>> {
+    type Input = E::Input;
+    type Output = E::Output;
+
+    fn id(&self) -> String {
+        self.lock().unwrap().id()
+    }
+
+    fn encode(
+        &mut self,
+        channel_handler_ctx: &mut ChannelOutboundHandlerCtx,
+        message: &mut Self::Input,
+        out: &mut Vec<Self::Output>,
+    ) -> Result<(), RettyErrorKind> {
+        self.lock()
+            .unwrap()
+            .encode(channel_handler_ctx, message, out)
+    }
+}
+
+///
+/// 把 MessageToMessageDecoder 包装成入站 handler, 其他类型的消息原样往下传, 解码失败时触发 channel_exception
+///
+pub struct MessageToMessageDecoderHandler<D: MessageToMessageDecoder> {
+    decoder: D,
+}
+
+impl<D: MessageToMessageDecoder> MessageToMessageDecoderHandler<D> {
+    pub fn new(decoder: D) -> Self {
+        MessageToMessageDecoderHandler { decoder }
+    }
+
+    pub fn decoder(&self) -> &D {
+        &self.decoder
+    }
+
+    pub fn decoder_mut(&mut self) -> &mut D {
+        &mut self.decoder
+    }
+}
+
+impl<D: MessageToMessageDecoder + 'static> ChannelInboundHandler
+    for MessageToMessageDecoderHandler<D>
+{
+    fn id(&self) -> String {
+        self.decoder.id()
+    }
+
+    fn channel_active(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        channel_handler_ctx.fire_channel_active();
+    }
+
+    fn channel_inactive(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        channel_handler_ctx.fire_channel_inactive();
+    }
+
+    fn channel_read(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        message: &mut dyn Any,
+    ) {
+        let msg = match message.downcast_mut::<D::Input>() {
+            Some(msg) => msg,
+            None => {
+                channel_handler_ctx.fire_channel_read(message);
+                return;
+            }
+        };
+        let mut out = vec![];
+        // 先解码完再往下传, 解码出错之前解出的消息也会传下去
+        let ret = self.decoder.decode(channel_handler_ctx, msg, &mut out);
+        for mut decoded in out {
+            channel_handler_ctx.fire_channel_read(&mut decoded);
+        }
+        if let Err(e) = ret {
+            channel_handler_ctx.fire_channel_exception(e);
+        }
+    }
+
+    fn channel_exception(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        error: RettyErrorKind,
+    ) {
+        channel_handler_ctx.fire_channel_exception(error);
+    }
+}
+
+///
+/// 把 MessageToMessageEncoder 包装成出站 handler, 其他类型的消息原样往下写, 编码失败时这次 write 的 future 失败
+///
+pub struct MessageToMessageEncoderHandler<E: MessageToMessageEncoder> {
+    encoder: E,
+}
+
+impl<E: MessageToMessageEncoder> MessageToMessageEncoderHandler<E> {
+    pub fn new(encoder: E) -> Self {
+        MessageToMessageEncoderHandler { encoder }
+    }
+
+    pub fn encoder(&self) -> &E {
+        &self.encoder
+    }
+
+    pub fn encoder_mut(&mut self) -> &mut E {
+        &mut self.encoder
+    }
+}
+
+impl<E: MessageToMessageEncoder + 'static> ChannelOutboundHandler
+    for MessageToMessageEncoderHandler<E>
+{
+    fn id(&self) -> String {
+        self.encoder.id()
+    }
+
+    fn channel_write(
+        &mut self,
+        channel_handler_ctx: &mut ChannelOutboundHandlerCtx,
+        message: &mut dyn Any,
+    ) {
+        let msg = match message.downcast_mut::<E::Input>() {
+            Some(msg) => msg,
+            None => {
+                channel_handler_ctx.fire_channel_write(message);
+                return;
+            }
+        };
+        let mut out = vec![];
+        if let Err(e) = self.encoder.encode(channel_handler_ctx, msg, &mut out) {
+            channel_handler_ctx.channel().fail_write(e);
+            return;
+        }
+        for mut encoded in out {
+            channel_handler_ctx.fire_channel_write(&mut encoded);
+        }
+    }
+}
+
+///
+/// 消息和消息之间的编解码器, 同一个实例处理入站和出站, 可以在两个方向之间共享状态
+/// 用 ChannelInboundHandlerPipe::add_last_codec 注册
+/// decode 里不要往 channel 写数据, 编解码共用一把锁; decode 里触发的异常在锁释放之后才往下传
+///
+pub struct MessageToMessageCodec<C> {
+    codec: Arc<Mutex<C>>,
+}
+
+impl<C> MessageToMessageCodec<C>
+where
+    C: MessageToMessageDecoder + MessageToMessageEncoder + Send + 'static,
+{
+    pub fn new(codec: C) -> Self {
+        MessageToMessageCodec {
+            codec: Arc::new(Mutex::new(codec)),
+        }
+    }
+}
+
+impl<C> ChannelCodec for MessageToMessageCodec<C>
+where
+    C: MessageToMessageDecoder + MessageToMessageEncoder + Send + 'static,
+{
+    fn into_handlers(
+        self,
+    ) -> (
+        Box<dyn ChannelInboundHandler + Send + Sync>,
+        Box<dyn ChannelOutboundHandler + Send + Sync>,
+    ) {
+        (
+            Box::new(MessageToMessageDecoderHandler::new(self.codec.clone())),
+            Box::new(MessageToMessageEncoderHandler::new(self.codec)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use bytebuf_rs::bytebuf::ByteBuf;
+
+    use super::*;
+    use crate::channel::embedded_channel::EmbeddedChannel;
+    use crate::channel::handler_pipe::ChannelHandlerPipe;
+    use crate::errors::CodecErrorKind;
+
+    // 每个数字字符解码成一个 u32, 跳过其他字符并触发 CorruptedFrame; 编码时带上已经解码的个数
+    struct DigitCodec {
+        decoded: usize,
+    }
+
+    impl MessageToMessageDecoder for DigitCodec {
+        type Input = ByteBuf;
+        type Output = u32;
+
+        fn id(&self) -> String {
+            "DigitCodec".to_string()
+        }
+
+        fn decode(
+            &mut self,
+            channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+            message: &mut ByteBuf,
+            out: &mut Vec<u32>,
+        ) -> Result<(), RettyErrorKind> {
+            for b in message.available_bytes() {
+                match (*b as char).to_digit(10) {
+                    Some(digit) => {
+                        self.decoded += 1;
+                        out.push(digit);
+                    }
+                    None if *b == b'!' => {
+                        return Err(RettyErrorKind::new(
+                            ErrorKind::InvalidData,
+                            "stop".to_string(),
+                        ))
+                    }
+                    None => channel_handler_ctx.fire_channel_exception(RettyErrorKind::codec(
+                        CodecErrorKind::CorruptedFrame,
+                        format!("not a digit: {}", b),
+                    )),
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl MessageToMessageEncoder for DigitCodec {
+        type Input = u32;
+        type Output = ByteBuf;
+
+        fn id(&self) -> String {
+            "DigitCodec".to_string()
+        }
+
+        fn encode(
+            &mut self,
+            _channel_handler_ctx: &mut ChannelOutboundHandlerCtx,
+            message: &mut u32,
+            out: &mut Vec<ByteBuf>,
+        ) -> Result<(), RettyErrorKind> {
+            out.push(ByteBuf::new_from(
+                format!("{}/{} ", message, self.decoded).as_bytes(),
+            ));
+            Ok(())
+        }
+    }
+
+    // 收到的数字原样写回, 收到异常时写回 0
+    struct Echo;
+
+    impl ChannelInboundHandler for Echo {
+        fn id(&self) -> String {
+            "Echo".to_string()
+        }
+
+        fn channel_active(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+            channel_handler_ctx.fire_channel_active();
+        }
+
+        fn channel_inactive(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+            channel_handler_ctx.fire_channel_inactive();
+        }
+
+        fn channel_read(
+            &mut self,
+            channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+            message: &mut dyn Any,
+        ) {
+            if let Some(digit) = message.downcast_ref::<u32>() {
+                channel_handler_ctx.write_and_flush(&mut digit.clone());
+            }
+            channel_handler_ctx.fire_channel_read(message);
+        }
+
+        fn channel_exception(
+            &mut self,
+            channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+            error: RettyErrorKind,
+        ) {
+            channel_handler_ctx.write_and_flush(&mut 0u32);
+            channel_handler_ctx.fire_channel_exception(error);
+        }
+    }
+
+    fn digit_channel() -> EmbeddedChannel<u32> {
+        let mut handler_pipe = ChannelHandlerPipe::new();
+        handler_pipe.add_last_codec(MessageToMessageCodec::new(DigitCodec { decoded: 0 }));
+        handler_pipe.add_last_inbound(Box::new(Echo));
+        EmbeddedChannel::new(handler_pipe)
+    }
+
+    #[test]
+    fn decoder_and_encoder_share_state() {
+        let mut channel = digit_channel();
+        channel.write_inbound(b"12");
+
+        assert_eq!(channel.read_all_inbound(), vec![1, 2]);
+        // 先解码完整个消息再往下传
+        assert_eq!(channel.read_outbound(), b"1/2 2/2 ");
+        assert!(channel.take_exceptions().is_empty());
+    }
+
+    #[test]
+    fn decode_error_passes_on_messages_decoded_before_it() {
+        let mut channel = digit_channel();
+        channel.write_inbound(b"3!4");
+
+        assert_eq!(channel.read_all_inbound(), vec![3]);
+        assert_eq!(channel.read_outbound(), b"3/1 0/1 ");
+        let errors = channel.take_exceptions();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "stop");
+    }
+
+    #[test]
+    fn writing_in_channel_exception_fired_by_decode_does_not_deadlock() {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut channel = digit_channel();
+            channel.write_inbound(b"5x6");
+            let errors = channel.take_exceptions();
+            tx.send((
+                channel.read_all_inbound(),
+                channel.read_outbound(),
+                errors.len() == 1 && errors[0].is_codec_error(CodecErrorKind::CorruptedFrame),
+            ))
+            .unwrap();
+        });
+
+        let (inbound, outbound, corrupted) = rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("codec deadlocked");
+        assert_eq!(inbound, vec![5, 6]);
+        // 异常在锁释放之后, 解出的消息之前往下传
+        assert_eq!(outbound, b"0/2 5/2 6/2 ");
+        assert!(corrupted);
+    }
+
+    #[test]
+    fn passes_other_messages_through() {
+        let mut handler_pipe = ChannelHandlerPipe::new();
+        handler_pipe.add_last_codec(MessageToMessageCodec::new(DigitCodec { decoded: 0 }));
+        let mut channel: EmbeddedChannel<String> = EmbeddedChannel::new(handler_pipe);
+
+        channel
+            .pipeline()
+            .head_channel_read(&mut "not bytes".to_string());
+        assert_eq!(channel.read_all_inbound(), vec!["not bytes"]);
+        channel.write_outbound(&mut ByteBuf::new_from(b"raw"));
+        assert_eq!(channel.read_outbound(), b"raw");
+    }
+}
@@ -1 +1,13 @@
+pub mod byte_order;
+pub mod byte_to_message_codec;
+pub mod byte_to_message_decoder;
+pub mod charset;
+pub mod delimiter_based_frame_decoder;
 pub mod first_integer_length_field_decoder;
+pub mod http;
+pub mod length_field_based_frame_decoder;
+pub mod length_field_prepender;
+pub mod line_based_frame_decoder;
+pub mod message_to_byte_encoder;
+pub mod message_to_message_codec;
+pub mod string_codec;
@@ -0,0 +1,191 @@
+use bytebuf_rs::bytebuf::ByteBuf;
+
+use crate::channel::channel_handler_ctx::{ChannelInboundHandlerCtx, ChannelOutboundHandlerCtx};
+use crate::channel::codec::charset::Charset;
+use crate::channel::codec::message_to_message_codec::{
+    MessageToMessageDecoder, MessageToMessageEncoder,
+};
+use crate::errors::RettyErrorKind;
+
+///
+/// 把 ByteBuf 解码成 String, 一般放在拆帧的解码器之后
+/// strict 为 true 时非法的字节触发 MalformedInput 的 channel_exception, 否则替换成 U+FFFD
+///
+/// 用 MessageToMessageDecoderHandler 包装之后加入 pipeline
+///
+pub struct StringDecoder {
+    charset: Charset,
+    strict: bool,
+}
+
+impl Default for StringDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StringDecoder {
+    ///
+    /// UTF-8, strict
+    ///
+    pub fn new() -> Self {
+        StringDecoder::new_with_charset(Charset::Utf8, true)
+    }
+
+    pub fn new_with_charset(charset: Charset, strict: bool) -> Self {
+        StringDecoder { charset, strict }
+    }
+}
+
+impl MessageToMessageDecoder for StringDecoder {
+    type Input = ByteBuf;
+    type Output = String;
+
+    fn id(&self) -> String {
+        "StringDecoder".to_string()
+    }
+
+    fn decode(
+        &mut self,
+        _channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        message: &mut ByteBuf,
+        out: &mut Vec<String>,
+    ) -> Result<(), RettyErrorKind> {
+        out.push(
+            self.charset
+                .decode(message.available_bytes(), self.strict)?,
+        );
+        Ok(())
+    }
+}
+
+///
+/// 把 String 编码成 ByteBuf
+/// strict 为 true 时字符集不能表示的字符让这次 write 失败, 否则替换成 '?'
+///
+/// 用 MessageToMessageEncoderHandler 包装之后加入 pipeline
+///
+pub struct StringEncoder {
+    charset: Charset,
+    strict: bool,
+}
+
+impl Default for StringEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StringEncoder {
+    ///
+    /// UTF-8
+    ///
+    pub fn new() -> Self {
+        StringEncoder::new_with_charset(Charset::Utf8, true)
+    }
+
+    pub fn new_with_charset(charset: Charset, strict: bool) -> Self {
+        StringEncoder { charset, strict }
+    }
+}
+
+impl MessageToMessageEncoder for StringEncoder {
+    type Input = String;
+    type Output = ByteBuf;
+
+    fn id(&self) -> String {
+        "StringEncoder".to_string()
+    }
+
+    fn encode(
+        &mut self,
+        _channel_handler_ctx: &mut ChannelOutboundHandlerCtx,
+        message: &mut String,
+        out: &mut Vec<ByteBuf>,
+    ) -> Result<(), RettyErrorKind> {
+        let bytes = self.charset.encode(message, self.strict)?;
+        out.push(ByteBuf::new_from(&bytes));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::codec::byte_to_message_decoder::ByteToMessageHandler;
+    use crate::channel::codec::line_based_frame_decoder::LineBasedFrameDecoder;
+    use crate::channel::codec::message_to_message_codec::{
+        MessageToMessageDecoderHandler, MessageToMessageEncoderHandler,
+    };
+    use crate::channel::embedded_channel::EmbeddedChannel;
+    use crate::channel::handler_pipe::ChannelHandlerPipe;
+    use crate::errors::CodecErrorKind;
+
+    fn string_channel(charset: Charset, strict: bool, lines: bool) -> EmbeddedChannel<String> {
+        let mut handler_pipe = ChannelHandlerPipe::new();
+        if lines {
+            handler_pipe.add_last_inbound(Box::new(ByteToMessageHandler::new(
+                LineBasedFrameDecoder::new(1024),
+            )));
+        }
+        handler_pipe.add_last_inbound(Box::new(MessageToMessageDecoderHandler::new(
+            StringDecoder::new_with_charset(charset, strict),
+        )));
+        handler_pipe.add_last_outbound(Box::new(MessageToMessageEncoderHandler::new(
+            StringEncoder::new_with_charset(charset, strict),
+        )));
+        EmbeddedChannel::new(handler_pipe)
+    }
+
+    #[test]
+    fn decodes_and_encodes_with_the_charset() {
+        let mut channel = string_channel(Charset::Utf16Le, true, false);
+        channel.write_inbound(&[b'h', 0, 0xE9, 0]);
+        assert_eq!(channel.read_all_inbound(), vec!["h\u{e9}"]);
+
+        assert!(channel.write_outbound(&mut "ok".to_string()).is_success());
+        assert_eq!(channel.read_outbound(), vec![b'o', 0, b'k', 0]);
+    }
+
+    #[test]
+    fn utf8_sequence_split_across_buffers_needs_a_frame_decoder() {
+        // 每个 ByteBuf 单独解码, 被拆开的字符不合法
+        let channel = string_channel(Charset::Utf8, true, false);
+        channel.write_inbound(b"h\xc3");
+        channel.write_inbound(b"\xa9");
+        assert!(channel.read_all_inbound().is_empty());
+        let errors = channel.take_exceptions();
+        assert_eq!(errors.len(), 2);
+        assert!(errors
+            .iter()
+            .all(|e| e.is_codec_error(CodecErrorKind::MalformedInput)));
+
+        let channel = string_channel(Charset::Utf8, false, false);
+        channel.write_inbound(b"h\xc3");
+        channel.write_inbound(b"\xa9");
+        assert_eq!(channel.read_all_inbound(), vec!["h\u{fffd}", "\u{fffd}"]);
+
+        // 先拆帧再解码
+        let channel = string_channel(Charset::Utf8, true, true);
+        channel.write_inbound(b"h\xc3");
+        channel.write_inbound(b"\xa9\n");
+        assert_eq!(channel.read_all_inbound(), vec!["h\u{e9}"]);
+        assert!(channel.take_exceptions().is_empty());
+    }
+
+    #[test]
+    fn unmappable_character_fails_the_write_in_strict_mode() {
+        let mut channel = string_channel(Charset::Latin1, true, false);
+        let future = channel.write_outbound(&mut "\u{20ac}".to_string());
+        assert!(future
+            .cause()
+            .is_some_and(|e| e.is_codec_error(CodecErrorKind::MalformedInput)));
+        assert!(channel.read_outbound().is_empty());
+
+        let mut channel = string_channel(Charset::Latin1, false, false);
+        assert!(channel
+            .write_outbound(&mut "\u{e9}\u{20ac}".to_string())
+            .is_success());
+        assert_eq!(channel.read_outbound(), b"\xe9?");
+    }
+}
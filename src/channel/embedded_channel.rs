@@ -0,0 +1,219 @@
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
+use std::io::Read;
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytebuf_rs::bytebuf::ByteBuf;
+use mio::Token;
+
+use crate::channel::channel_future::ChannelFuture;
+use crate::channel::channel_handler_ctx::ChannelInboundHandlerCtx;
+use crate::channel::channel_handler_ctx_pipe::{
+    ChannelInboundHandlerCtxPipe, ChannelOutboundHandlerCtxPipe,
+};
+use crate::channel::handler::ChannelInboundHandler;
+use crate::channel::handler_pipe::ChannelHandlerPipe;
+use crate::core::bootstrap::Bootstrap;
+use crate::core::eventloop::EventLoop;
+use crate::errors::RettyErrorKind;
+use crate::transport::channel::Channel;
+
+///
+/// 测试用的 channel, 不经过 eventloop 直接驱动 pipeline
+/// 入站数据用 write_inbound 交给 pipeline, 最后一个 handler 收到的 T 类型消息用 read_inbound 取出
+/// 出站数据真正写进一个本地 TCP 连接, 用 read_outbound 从对端读出
+///
+pub(crate) struct EmbeddedChannel<T> {
+    inbound_pipe: ChannelInboundHandlerCtxPipe,
+    outbound_pipe: Arc<Mutex<ChannelOutboundHandlerCtxPipe>>,
+    channel: Arc<Mutex<Channel>>,
+    peer: TcpStream,
+    received: Arc<Mutex<Received<T>>>,
+}
+
+struct Received<T> {
+    messages: VecDeque<T>,
+    exceptions: Vec<RettyErrorKind>,
+    events: Vec<Box<dyn Any + Send>>,
+}
+
+struct Collector<T> {
+    received: Arc<Mutex<Received<T>>>,
+}
+
+impl<T: Any + Clone + Send + 'static> ChannelInboundHandler for Collector<T> {
+    fn id(&self) -> String {
+        "EmbeddedChannelCollector".to_string()
+    }
+
+    fn channel_active(&mut self, _channel_handler_ctx: &mut ChannelInboundHandlerCtx) {}
+
+    fn channel_inactive(&mut self, _channel_handler_ctx: &mut ChannelInboundHandlerCtx) {}
+
+    fn channel_read(
+        &mut self,
+        _channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        message: &mut dyn Any,
+    ) {
+        match message.downcast_ref::<T>() {
+            Some(message) => self
+                .received
+                .lock()
+                .unwrap()
+                .messages
+                .push_back(message.clone()),
+            None => panic!(
+                "unexpected inbound message, expected {}",
+                std::any::type_name::<T>()
+            ),
+        }
+    }
+
+    fn channel_exception(
+        &mut self,
+        _channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        error: RettyErrorKind,
+    ) {
+        self.received.lock().unwrap().exceptions.push(error);
+    }
+
+    fn user_event_triggered(
+        &mut self,
+        _channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        event: Box<dyn Any + Send>,
+    ) {
+        self.received.lock().unwrap().events.push(event);
+    }
+}
+
+impl<T: Any + Clone + Send + 'static> EmbeddedChannel<T> {
+    pub(crate) fn new(mut handler_pipe: ChannelHandlerPipe) -> EmbeddedChannel<T> {
+        let received = Arc::new(Mutex::new(Received {
+            messages: VecDeque::new(),
+            exceptions: vec![],
+            events: vec![],
+        }));
+        handler_pipe.add_last_inbound(Box::new(Collector {
+            received: received.clone(),
+        }));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        peer.set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let stream = mio::net::TcpStream::from_stream(stream).unwrap();
+
+        let event_loop = Arc::new(EventLoop::new(0));
+        let channel = Arc::new(Mutex::new(Channel::create(
+            Token(0),
+            HashMap::new(),
+            event_loop.clone(),
+            stream,
+        )));
+        let (inbound_handlers, outbound_handlers) = handler_pipe.into_handlers();
+        let outbound_pipe = Arc::new(Mutex::new(Bootstrap::create_channel_outbound_ctx_pipe(
+            outbound_handlers,
+            event_loop.clone(),
+            channel.clone(),
+        )));
+        let inbound_pipe = Bootstrap::create_channel_inbound_ctx_pipe(
+            inbound_handlers,
+            event_loop,
+            channel.clone(),
+            outbound_pipe.clone(),
+        );
+        EmbeddedChannel {
+            inbound_pipe,
+            outbound_pipe,
+            channel,
+            peer,
+            received,
+        }
+    }
+
+    pub(crate) fn pipeline(&self) -> ChannelInboundHandlerCtxPipe {
+        self.inbound_pipe.clone()
+    }
+
+    pub(crate) fn outbound_pipeline(&self) -> ChannelOutboundHandlerCtxPipe {
+        self.outbound_pipe.lock().unwrap().clone()
+    }
+
+    pub(crate) fn active(&self) {
+        self.inbound_pipe.head_channel_active();
+    }
+
+    ///
+    /// 像 eventloop 一样把 bytes 作为一次读交给 pipeline, 然后触发 channel_read_complete
+    ///
+    pub(crate) fn write_inbound(&self, bytes: &[u8]) {
+        self.write_inbound_batch(&[bytes]);
+    }
+
+    ///
+    /// 像 eventloop 处理一个读事件一样, 每段数据触发一次 channel_read, 最后触发一次 channel_read_complete,
+    /// 再执行这期间被 FlushConsolidationHandler 推迟的 flush
+    ///
+    pub(crate) fn write_inbound_batch(&self, reads: &[&[u8]]) {
+        self.channel.lock().unwrap().begin_read();
+        for bytes in reads {
+            self.inbound_pipe
+                .head_channel_read(&mut ByteBuf::new_from(bytes));
+        }
+        self.inbound_pipe.head_channel_read_complete();
+        if self.channel.lock().unwrap().end_read() {
+            self.inbound_pipe.flush_outbound();
+        }
+    }
+
+    pub(crate) fn write_outbound(&self, message: &mut dyn Any) -> ChannelFuture {
+        self.outbound_pipe.lock().unwrap().write_and_flush(message)
+    }
+
+    ///
+    /// handler 没有关闭连接
+    ///
+    pub(crate) fn is_active(&self) -> bool {
+        !self.channel.lock().unwrap().is_closed()
+    }
+
+    ///
+    /// 连接断开
+    ///
+    pub(crate) fn finish(&self) {
+        self.inbound_pipe.head_channel_inactive();
+    }
+
+    pub(crate) fn read_inbound(&self) -> Option<T> {
+        self.received.lock().unwrap().messages.pop_front()
+    }
+
+    pub(crate) fn read_all_inbound(&self) -> Vec<T> {
+        self.received.lock().unwrap().messages.drain(..).collect()
+    }
+
+    pub(crate) fn take_exceptions(&self) -> Vec<RettyErrorKind> {
+        std::mem::take(&mut self.received.lock().unwrap().exceptions)
+    }
+
+    pub(crate) fn take_events(&self) -> Vec<Box<dyn Any + Send>> {
+        std::mem::take(&mut self.received.lock().unwrap().events)
+    }
+
+    ///
+    /// 读出目前为止写到 socket 里的所有数据
+    ///
+    pub(crate) fn read_outbound(&mut self) -> Vec<u8> {
+        let mut bytes = vec![];
+        let mut buf = [0u8; 4096];
+        loop {
+            match self.peer.read(&mut buf) {
+                Ok(0) | Err(_) => return bytes,
+                Ok(n) => bytes.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+}
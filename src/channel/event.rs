@@ -0,0 +1,8 @@
+///
+/// 读空闲超过 read_idle_timeout_ms 时通过 user_event_triggered 传给入站 handler,
+/// 一直没有读到数据时每隔 read_idle_timeout_ms 触发一次, 读到数据之后重新计时
+///
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum IdleStateEvent {
+    ReaderIdle,
+}
@@ -0,0 +1,188 @@
+use std::any::Any;
+
+use crate::channel::channel_handler_ctx::ChannelOutboundHandlerCtx;
+use crate::channel::handler::ChannelOutboundHandler;
+
+pub const DEFAULT_EXPLICIT_FLUSH_AFTER_FLUSHES: usize = 256;
+
+///
+/// 合并 flush 的出站 handler
+/// eventloop 分发读到的数据期间(比如客户端 pipeline 发来的多个请求) flush 先不往下传,
+/// 等这一批数据读完之后只 flush 一次, 出站缓冲区里的多个响应用一次 writev 写出
+/// 连续推迟 explicit_flush_after_flushes 次之后强制 flush 一次, 不在读的过程中的 flush 直接往下传
+///
+pub struct FlushConsolidationHandler {
+    explicit_flush_after_flushes: usize,
+    flush_pending_count: usize,
+}
+
+impl Default for FlushConsolidationHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FlushConsolidationHandler {
+    pub fn new() -> Self {
+        FlushConsolidationHandler::new_with_explicit_flush_after_flushes(
+            DEFAULT_EXPLICIT_FLUSH_AFTER_FLUSHES,
+        )
+    }
+
+    pub fn new_with_explicit_flush_after_flushes(explicit_flush_after_flushes: usize) -> Self {
+        FlushConsolidationHandler {
+            explicit_flush_after_flushes: explicit_flush_after_flushes.max(1),
+            flush_pending_count: 0,
+        }
+    }
+}
+
+impl ChannelOutboundHandler for FlushConsolidationHandler {
+    fn id(&self) -> String {
+        "FlushConsolidationHandler".to_string()
+    }
+
+    fn channel_write(
+        &mut self,
+        channel_handler_ctx: &mut ChannelOutboundHandlerCtx,
+        message: &mut dyn Any,
+    ) {
+        channel_handler_ctx.fire_channel_write(message);
+    }
+
+    fn channel_flush(&mut self, channel_handler_ctx: &mut ChannelOutboundHandlerCtx) {
+        if !channel_handler_ctx.channel().is_read_in_progress() {
+            self.flush_pending_count = 0;
+            channel_handler_ctx.fire_channel_flush();
+            return;
+        }
+        self.flush_pending_count += 1;
+        if self.flush_pending_count >= self.explicit_flush_after_flushes {
+            self.flush_pending_count = 0;
+            channel_handler_ctx.channel().cancel_deferred_flush();
+            channel_handler_ctx.fire_channel_flush();
+        } else {
+            channel_handler_ctx.channel().defer_flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use bytebuf_rs::bytebuf::ByteBuf;
+
+    use super::*;
+    use crate::channel::channel_handler_ctx::ChannelInboundHandlerCtx;
+    use crate::channel::embedded_channel::EmbeddedChannel;
+    use crate::channel::handler::ChannelInboundHandler;
+    use crate::channel::handler_pipe::ChannelHandlerPipe;
+    use crate::errors::RettyErrorKind;
+
+    // 在 FlushConsolidationHandler 和 socket 之间, 数一共有几次 flush 往下传
+    struct FlushCounter {
+        flushes: Arc<AtomicUsize>,
+    }
+
+    impl ChannelOutboundHandler for FlushCounter {
+        fn id(&self) -> String {
+            "FlushCounter".to_string()
+        }
+
+        fn channel_write(
+            &mut self,
+            channel_handler_ctx: &mut ChannelOutboundHandlerCtx,
+            message: &mut dyn Any,
+        ) {
+            channel_handler_ctx.fire_channel_write(message);
+        }
+
+        fn channel_flush(&mut self, channel_handler_ctx: &mut ChannelOutboundHandlerCtx) {
+            self.flushes.fetch_add(1, Ordering::SeqCst);
+            channel_handler_ctx.fire_channel_flush();
+        }
+    }
+
+    // 读到的每个字节单独 write_and_flush 一次
+    struct Responder;
+
+    impl ChannelInboundHandler for Responder {
+        fn id(&self) -> String {
+            "Responder".to_string()
+        }
+
+        fn channel_active(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+            channel_handler_ctx.fire_channel_active();
+        }
+
+        fn channel_inactive(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+            channel_handler_ctx.fire_channel_inactive();
+        }
+
+        fn channel_read(
+            &mut self,
+            channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+            message: &mut dyn Any,
+        ) {
+            if let Some(buf) = message.downcast_ref::<ByteBuf>() {
+                for b in buf.available_bytes().to_vec() {
+                    channel_handler_ctx.write_and_flush(&mut ByteBuf::new_from(&[b]));
+                }
+            }
+            channel_handler_ctx.fire_channel_read(message);
+        }
+
+        fn channel_exception(
+            &mut self,
+            channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+            error: RettyErrorKind,
+        ) {
+            channel_handler_ctx.fire_channel_exception(error);
+        }
+    }
+
+    fn consolidating_channel(
+        handler: FlushConsolidationHandler,
+    ) -> (EmbeddedChannel<ByteBuf>, Arc<AtomicUsize>) {
+        let flushes = Arc::new(AtomicUsize::new(0));
+        let mut handler_pipe = ChannelHandlerPipe::new();
+        handler_pipe.add_last_outbound(Box::new(FlushCounter {
+            flushes: flushes.clone(),
+        }));
+        handler_pipe.add_last_outbound(Box::new(handler));
+        handler_pipe.add_last_inbound(Box::new(Responder));
+        (EmbeddedChannel::new(handler_pipe), flushes)
+    }
+
+    #[test]
+    fn flushes_in_a_read_batch_collapse_into_one() {
+        let (mut channel, flushes) = consolidating_channel(FlushConsolidationHandler::new());
+        channel.write_inbound_batch(&[b"abc", b"de"]);
+        assert_eq!(flushes.load(Ordering::SeqCst), 1);
+        assert_eq!(channel.read_outbound(), b"abcde");
+        assert_eq!(channel.read_all_inbound().len(), 2);
+
+        // 不在读的过程中的 flush 直接往下传
+        channel.write_outbound(&mut ByteBuf::new_from(b"f"));
+        assert_eq!(flushes.load(Ordering::SeqCst), 2);
+        assert_eq!(channel.read_outbound(), b"f");
+    }
+
+    #[test]
+    fn flushes_explicitly_after_explicit_flush_after_flushes() {
+        let (mut channel, flushes) = consolidating_channel(
+            FlushConsolidationHandler::new_with_explicit_flush_after_flushes(2),
+        );
+        // 第 2、4 次 flush 强制往下传, 第 5 次推迟到读完
+        channel.write_inbound(b"abcde");
+        assert_eq!(flushes.load(Ordering::SeqCst), 3);
+        assert_eq!(channel.read_outbound(), b"abcde");
+
+        // 强制 flush 之后重新计数, 刚好整除时读完不再 flush
+        channel.write_inbound(b"fg");
+        assert_eq!(flushes.load(Ordering::SeqCst), 4);
+        assert_eq!(channel.read_outbound(), b"fg");
+    }
+}
@@ -0,0 +1 @@
+pub mod flush_consolidation_handler;
@@ -1,10 +1,31 @@
 use bytebuf_rs::bytebuf::ByteBuf;
 use std::any::Any;
+use std::io::ErrorKind;
+use std::sync::Arc;
 
 use crate::channel::channel_handler_ctx::{ChannelInboundHandlerCtx, ChannelOutboundHandlerCtx};
 use crate::errors::RettyErrorKind;
+use crate::transport::datagram::DatagramPacket;
 
-pub trait ChannelInboundHandler {
+///
+/// 把 handler 转成 Any, pipeline 按类型查找 handler 时使用, 所有 'static 的类型自动实现
+///
+pub trait AsAny {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Any> AsAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+pub trait ChannelInboundHandler: AsAny {
     fn id(&self) -> String;
     fn channel_active(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx);
     fn channel_inactive(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx);
@@ -18,15 +39,290 @@ pub trait ChannelInboundHandler {
         channel_handler_ctx: &mut ChannelInboundHandlerCtx,
         error: RettyErrorKind,
     );
+
+    ///
+    /// channel 可写状态变化(出站缓冲区越过高/低水位), 默认传给下一个handler
+    ///
+    fn channel_writability_changed(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        channel_handler_ctx.fire_channel_writability_changed();
+    }
+
+    ///
+    /// eventloop 这一次读完之后调用, 之前的 channel_read 都属于这一批, 可以在这里统一 flush, 默认传给下一个handler
+    ///
+    fn channel_read_complete(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        channel_handler_ctx.fire_channel_read_complete();
+    }
+
+    ///
+    /// 收到不是数据的事件, 比如 IdleStateEvent / HttpUpgradeEvent / WebSocket 握手完成, 用 downcast 区分, 默认传给下一个handler
+    ///
+    fn user_event_triggered(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        event: Box<dyn Any + Send>,
+    ) {
+        channel_handler_ctx.fire_user_event(event);
+    }
+
+    ///
+    /// 加入 pipeline 之后调用, 包括创建连接时的 handler 和运行时加入的 handler
+    ///
+    fn handler_added(&mut self, _channel_handler_ctx: &mut ChannelInboundHandlerCtx) {}
+
+    ///
+    /// 从 pipeline 移除或者被替换之后调用
+    ///
+    fn handler_removed(&mut self, _channel_handler_ctx: &mut ChannelInboundHandlerCtx) {}
 }
 
-pub trait ChannelOutboundHandler {
+pub trait ChannelOutboundHandler: AsAny {
     fn id(&self) -> String;
     fn channel_write(
         &mut self,
         channel_handler_ctx: &mut ChannelOutboundHandlerCtx,
         message: &mut dyn Any,
     );
+
+    ///
+    /// 把之前 write 的数据写进 socket, 默认传给下一个handler
+    ///
+    fn channel_flush(&mut self, channel_handler_ctx: &mut ChannelOutboundHandlerCtx) {
+        channel_handler_ctx.fire_channel_flush();
+    }
+
+    ///
+    /// 加入 pipeline 之后调用
+    ///
+    fn handler_added(&mut self, _channel_handler_ctx: &mut ChannelOutboundHandlerCtx) {}
+
+    ///
+    /// 从 pipeline 移除或者被替换之后调用
+    ///
+    fn handler_removed(&mut self, _channel_handler_ctx: &mut ChannelOutboundHandlerCtx) {}
+}
+
+///
+/// 同时处理入站和出站的编解码器, 通过 ChannelInboundHandlerPipe::add_last_codec 或者 ChannelHandlerPipe::add_last_codec 注册一次,
+/// 入站部分按顺序加入入站 pipeline, 出站部分加入出站 pipeline 最靠近 socket 的位置
+///
+pub trait ChannelCodec {
+    fn into_handlers(
+        self,
+    ) -> (
+        Box<dyn ChannelInboundHandler + Send + Sync>,
+        Box<dyn ChannelOutboundHandler + Send + Sync>,
+    );
+}
+
+///
+/// 同时处理入站和出站的 handler, 同一个实例加入出入站两条 pipeline, 比如在出站时记下请求, 入站时和响应对应起来
+/// 用 ChannelHandlerPipe::add_last_duplex 注册, 没有实现的方法默认传给下一个handler
+///
+/// 出入站的事件可能嵌套调用(比如入站处理时后面的 handler 写数据), 所以方法是 &self,
+/// 状态自己用 Mutex 之类保护, 调用 fire_* 或者写数据时不要持有锁
+///
+pub trait ChannelDuplexHandler {
+    fn id(&self) -> String;
+
+    fn channel_active(&self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        channel_handler_ctx.fire_channel_active();
+    }
+
+    fn channel_inactive(&self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        channel_handler_ctx.fire_channel_inactive();
+    }
+
+    fn channel_read(
+        &self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        message: &mut dyn Any,
+    ) {
+        channel_handler_ctx.fire_channel_read(message);
+    }
+
+    fn channel_exception(
+        &self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        error: RettyErrorKind,
+    ) {
+        channel_handler_ctx.fire_channel_exception(error);
+    }
+
+    fn channel_writability_changed(&self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        channel_handler_ctx.fire_channel_writability_changed();
+    }
+
+    fn channel_read_complete(&self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        channel_handler_ctx.fire_channel_read_complete();
+    }
+
+    fn user_event_triggered(
+        &self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        event: Box<dyn Any + Send>,
+    ) {
+        channel_handler_ctx.fire_user_event(event);
+    }
+
+    fn channel_write(
+        &self,
+        channel_handler_ctx: &mut ChannelOutboundHandlerCtx,
+        message: &mut dyn Any,
+    ) {
+        channel_handler_ctx.fire_channel_write(message);
+    }
+
+    fn channel_flush(&self, channel_handler_ctx: &mut ChannelOutboundHandlerCtx) {
+        channel_handler_ctx.fire_channel_flush();
+    }
+
+    ///
+    /// 入站部分加入入站 pipeline 之后调用, 出站部分另外调用 outbound_handler_added
+    ///
+    fn handler_added(&self, _channel_handler_ctx: &mut ChannelInboundHandlerCtx) {}
+
+    ///
+    /// 入站部分从入站 pipeline 移除或者被替换之后调用
+    ///
+    fn handler_removed(&self, _channel_handler_ctx: &mut ChannelInboundHandlerCtx) {}
+
+    ///
+    /// 出站部分加入出站 pipeline 之后调用
+    ///
+    fn outbound_handler_added(&self, _channel_handler_ctx: &mut ChannelOutboundHandlerCtx) {}
+
+    ///
+    /// 出站部分从出站 pipeline 移除或者被替换之后调用
+    ///
+    fn outbound_handler_removed(&self, _channel_handler_ctx: &mut ChannelOutboundHandlerCtx) {}
+}
+
+///
+/// 双向 handler 在入站 pipeline 里的部分, 可以用 get_typed 找到之后通过 handler() 拿到实例
+///
+pub struct DuplexInboundHandler<H: ChannelDuplexHandler> {
+    handler: Arc<H>,
+}
+
+impl<H: ChannelDuplexHandler> DuplexInboundHandler<H> {
+    pub fn handler(&self) -> &Arc<H> {
+        &self.handler
+    }
+}
+
+impl<H: ChannelDuplexHandler + 'static> ChannelInboundHandler for DuplexInboundHandler<H> {
+    fn id(&self) -> String {
+        self.handler.id()
+    }
+
+    fn channel_active(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        self.handler.channel_active(channel_handler_ctx);
+    }
+
+    fn channel_inactive(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        self.handler.channel_inactive(channel_handler_ctx);
+    }
+
+    fn channel_read(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        message: &mut dyn Any,
+    ) {
+        self.handler.channel_read(channel_handler_ctx, message);
+    }
+
+    fn channel_exception(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        error: RettyErrorKind,
+    ) {
+        self.handler.channel_exception(channel_handler_ctx, error);
+    }
+
+    fn channel_writability_changed(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        self.handler
+            .channel_writability_changed(channel_handler_ctx);
+    }
+
+    fn channel_read_complete(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        self.handler.channel_read_complete(channel_handler_ctx);
+    }
+
+    fn user_event_triggered(
+        &mut self,
+        channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+        event: Box<dyn Any + Send>,
+    ) {
+        self.handler
+            .user_event_triggered(channel_handler_ctx, event);
+    }
+
+    fn handler_added(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        self.handler.handler_added(channel_handler_ctx);
+    }
+
+    fn handler_removed(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+        self.handler.handler_removed(channel_handler_ctx);
+    }
+}
+
+///
+/// 双向 handler 在出站 pipeline 里的部分
+///
+pub struct DuplexOutboundHandler<H: ChannelDuplexHandler> {
+    handler: Arc<H>,
+}
+
+impl<H: ChannelDuplexHandler> DuplexOutboundHandler<H> {
+    pub fn handler(&self) -> &Arc<H> {
+        &self.handler
+    }
+}
+
+impl<H: ChannelDuplexHandler + 'static> ChannelOutboundHandler for DuplexOutboundHandler<H> {
+    fn id(&self) -> String {
+        self.handler.id()
+    }
+
+    fn channel_write(
+        &mut self,
+        channel_handler_ctx: &mut ChannelOutboundHandlerCtx,
+        message: &mut dyn Any,
+    ) {
+        self.handler.channel_write(channel_handler_ctx, message);
+    }
+
+    fn channel_flush(&mut self, channel_handler_ctx: &mut ChannelOutboundHandlerCtx) {
+        self.handler.channel_flush(channel_handler_ctx);
+    }
+
+    fn handler_added(&mut self, channel_handler_ctx: &mut ChannelOutboundHandlerCtx) {
+        self.handler.outbound_handler_added(channel_handler_ctx);
+    }
+
+    fn handler_removed(&mut self, channel_handler_ctx: &mut ChannelOutboundHandlerCtx) {
+        self.handler.outbound_handler_removed(channel_handler_ctx);
+    }
+}
+
+///
+/// 共享的双向 handler 也可以当作 codec 注册
+///
+impl<H: ChannelDuplexHandler + Send + Sync + 'static> ChannelCodec for Arc<H> {
+    fn into_handlers(
+        self,
+    ) -> (
+        Box<dyn ChannelInboundHandler + Send + Sync>,
+        Box<dyn ChannelOutboundHandler + Send + Sync>,
+    ) {
+        (
+            Box::new(DuplexInboundHandler {
+                handler: self.clone(),
+            }),
+            Box::new(DuplexOutboundHandler { handler: self }),
+        )
+    }
 }
 
 pub(crate) struct HeadHandler {}
@@ -85,16 +381,23 @@ impl ChannelOutboundHandler for TailHandler {
         channel_handler_ctx: &mut ChannelOutboundHandlerCtx,
         message: &mut dyn Any,
     ) {
-        let bytes = message.downcast_ref::<ByteBuf>();
-        match bytes {
-            Some(buf) => {
-                channel_handler_ctx.channel().write_bytebuf(buf);
-            }
-            None => {
-                println!("TailHandler message is not bytebuf");
-            }
+        // 写失败由 channel 记录下来, write 返回的 future 以这个错误失败
+        if let Some(buf) = message.downcast_ref::<ByteBuf>() {
+            let _ = channel_handler_ctx.channel().write_bytebuf(buf);
+        } else if let Some(packet) = message.downcast_ref::<DatagramPacket>() {
+            let _ = channel_handler_ctx.channel().send_datagram(packet);
+        } else {
+            channel_handler_ctx.channel().fail_write(RettyErrorKind::new(
+                ErrorKind::InvalidInput,
+                "TailHandler message is not ByteBuf or DatagramPacket".to_string(),
+            ));
         }
     }
+
+    fn channel_flush(&mut self, channel_handler_ctx: &mut ChannelOutboundHandlerCtx) {
+        // 写 socket 失败时出站缓冲区里等待的 future 都以这个错误失败
+        let _ = channel_handler_ctx.channel().flush();
+    }
 }
 
 impl TailHandler {
@@ -1,7 +1,13 @@
-use crate::channel::handler::{ChannelInboundHandler, ChannelOutboundHandler};
+use std::sync::Arc;
+
+use crate::channel::handler::{
+    ChannelCodec, ChannelDuplexHandler, ChannelInboundHandler, ChannelOutboundHandler,
+};
 
 pub struct ChannelInboundHandlerPipe {
     pub handlers: Vec<Box<dyn ChannelInboundHandler + Send + Sync>>,
+    // add_last_codec 注册的 codec 的出站部分, 创建 pipeline 时加入出站 pipeline
+    codec_outbound_handlers: Vec<Box<dyn ChannelOutboundHandler + Send + Sync>>,
 }
 
 impl Default for ChannelInboundHandlerPipe {
@@ -14,6 +20,7 @@ impl ChannelInboundHandlerPipe {
     pub fn new() -> ChannelInboundHandlerPipe {
         ChannelInboundHandlerPipe {
             handlers: Vec::new(),
+            codec_outbound_handlers: Vec::new(),
         }
     }
     pub fn add_last(&mut self, handler: Box<dyn ChannelInboundHandler + Send + Sync>) {
@@ -23,6 +30,21 @@ impl ChannelInboundHandlerPipe {
     pub fn add_first(&mut self, handler: Box<dyn ChannelInboundHandler + Send + Sync>) {
         self.handlers.insert(0, handler);
     }
+
+    ///
+    /// 注册一个编解码器, 同一个实例同时处理这个连接的入站和出站消息
+    ///
+    pub fn add_last_codec<C: ChannelCodec>(&mut self, codec: C) {
+        let (inbound, outbound) = codec.into_handlers();
+        self.handlers.push(inbound);
+        self.codec_outbound_handlers.push(outbound);
+    }
+
+    pub(crate) fn take_codec_outbound_handlers(
+        &mut self,
+    ) -> Vec<Box<dyn ChannelOutboundHandler + Send + Sync>> {
+        std::mem::take(&mut self.codec_outbound_handlers)
+    }
 }
 
 pub struct ChannelOutboundHandlerPipe {
@@ -49,3 +71,187 @@ impl ChannelOutboundHandlerPipe {
         self.handlers.insert(0, handler);
     }
 }
+
+type InboundHandlers = Vec<Box<dyn ChannelInboundHandler + Send + Sync>>;
+type OutboundHandlers = Vec<Box<dyn ChannelOutboundHandler + Send + Sync>>;
+
+pub(crate) type ChannelHandlerPipeFn = Arc<dyn Fn() -> ChannelHandlerPipe + Send + Sync + 'static>;
+
+///
+/// 统一的 pipeline, 出入站 handler 按从 socket 到业务的顺序注册在一起, 用 Bootstrap::initialize_handler_pipeline 注册
+/// 入站事件按注册的顺序经过入站 handler, 出站消息按相反的顺序经过出站 handler
+///
+pub struct ChannelHandlerPipe {
+    inbound_handlers: InboundHandlers,
+    // 注册的顺序, 创建 pipeline 时反序
+    outbound_handlers: OutboundHandlers,
+}
+
+impl Default for ChannelHandlerPipe {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChannelHandlerPipe {
+    pub fn new() -> ChannelHandlerPipe {
+        ChannelHandlerPipe {
+            inbound_handlers: Vec::new(),
+            outbound_handlers: Vec::new(),
+        }
+    }
+
+    pub fn add_last_inbound(&mut self, handler: Box<dyn ChannelInboundHandler + Send + Sync>) {
+        self.inbound_handlers.push(handler);
+    }
+
+    pub fn add_last_outbound(&mut self, handler: Box<dyn ChannelOutboundHandler + Send + Sync>) {
+        self.outbound_handlers.push(handler);
+    }
+
+    pub fn add_last_codec<C: ChannelCodec>(&mut self, codec: C) {
+        let (inbound, outbound) = codec.into_handlers();
+        self.inbound_handlers.push(inbound);
+        self.outbound_handlers.push(outbound);
+    }
+
+    ///
+    /// 注册一个双向 handler, 同一个实例处理这个连接的入站和出站消息
+    ///
+    pub fn add_last_duplex<H>(&mut self, handler: H)
+    where
+        H: ChannelDuplexHandler + Send + Sync + 'static,
+    {
+        self.add_last_codec(Arc::new(handler));
+    }
+
+    ///
+    /// 分开注册的出入站 pipeline, codec 的出站部分比出站 handler 更靠近 socket
+    ///
+    pub(crate) fn from_pipes(
+        mut inbound: ChannelInboundHandlerPipe,
+        outbound: ChannelOutboundHandlerPipe,
+    ) -> ChannelHandlerPipe {
+        let mut outbound_handlers = inbound.take_codec_outbound_handlers();
+        outbound_handlers.extend(outbound.handlers);
+        ChannelHandlerPipe {
+            inbound_handlers: inbound.handlers,
+            outbound_handlers,
+        }
+    }
+
+    ///
+    /// 入站 handler 和按出站顺序排好的出站 handler
+    ///
+    pub(crate) fn into_handlers(self) -> (InboundHandlers, OutboundHandlers) {
+        let mut outbound_handlers = self.outbound_handlers;
+        outbound_handlers.reverse();
+        (self.inbound_handlers, outbound_handlers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::Any;
+    use std::sync::Mutex;
+
+    use bytebuf_rs::bytebuf::ByteBuf;
+
+    use super::*;
+    use crate::channel::channel_handler_ctx::{
+        ChannelInboundHandlerCtx, ChannelOutboundHandlerCtx,
+    };
+    use crate::channel::embedded_channel::EmbeddedChannel;
+    use crate::channel::handler::{DuplexInboundHandler, DuplexOutboundHandler};
+
+    // 两个方向的事件记在同一个 log 里
+    struct Tracker {
+        log: Mutex<Vec<&'static str>>,
+    }
+
+    impl Tracker {
+        fn record(&self, event: &'static str) {
+            self.log.lock().unwrap().push(event);
+        }
+    }
+
+    impl ChannelDuplexHandler for Tracker {
+        fn id(&self) -> String {
+            "Tracker".to_string()
+        }
+
+        fn channel_read(
+            &self,
+            channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+            message: &mut dyn Any,
+        ) {
+            self.record("read");
+            channel_handler_ctx.fire_channel_read(message);
+        }
+
+        fn channel_write(
+            &self,
+            channel_handler_ctx: &mut ChannelOutboundHandlerCtx,
+            message: &mut dyn Any,
+        ) {
+            self.record("write");
+            channel_handler_ctx.fire_channel_write(message);
+        }
+
+        fn handler_added(&self, _channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+            self.record("inbound added");
+        }
+
+        fn handler_removed(&self, _channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+            self.record("inbound removed");
+        }
+
+        fn outbound_handler_added(&self, _channel_handler_ctx: &mut ChannelOutboundHandlerCtx) {
+            self.record("outbound added");
+        }
+
+        fn outbound_handler_removed(&self, _channel_handler_ctx: &mut ChannelOutboundHandlerCtx) {
+            self.record("outbound removed");
+        }
+    }
+
+    #[test]
+    fn add_last_duplex_shares_one_instance_between_both_directions() {
+        let mut handler_pipe = ChannelHandlerPipe::new();
+        handler_pipe.add_last_duplex(Tracker {
+            log: Mutex::new(vec![]),
+        });
+        let mut channel: EmbeddedChannel<ByteBuf> = EmbeddedChannel::new(handler_pipe);
+
+        let inbound = channel
+            .pipeline()
+            .get_typed(|h: &mut DuplexInboundHandler<Tracker>| h.handler().clone())
+            .unwrap();
+        let outbound = channel
+            .outbound_pipeline()
+            .get_typed(|h: &mut DuplexOutboundHandler<Tracker>| h.handler().clone())
+            .unwrap();
+        assert!(Arc::ptr_eq(&inbound, &outbound));
+
+        channel.write_inbound(b"in");
+        assert_eq!(channel.read_all_inbound().len(), 1);
+        assert!(channel
+            .write_outbound(&mut ByteBuf::new_from(b"out"))
+            .is_success());
+        assert_eq!(channel.read_outbound(), b"out");
+
+        channel.pipeline().remove("Tracker").unwrap();
+        channel.outbound_pipeline().remove("Tracker").unwrap();
+        assert_eq!(
+            *inbound.log.lock().unwrap(),
+            vec![
+                "outbound added",
+                "inbound added",
+                "read",
+                "write",
+                "inbound removed",
+                "outbound removed"
+            ]
+        );
+    }
+}
@@ -1,5 +1,10 @@
 pub mod handler;
 pub mod channel_handler_ctx;
 pub mod channel_handler_ctx_pipe;
+pub mod channel_future;
 pub mod handler_pipe;
 pub mod codec;
+pub mod flush;
+pub mod event;
+#[cfg(test)]
+pub(crate) mod embedded_channel;
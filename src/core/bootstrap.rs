@@ -1,7 +1,6 @@
 use std::collections::HashMap;
-use std::io::ErrorKind;
 use std::net::SocketAddr;
-use std::ops::Sub;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
@@ -9,22 +8,31 @@ use std::time::Duration;
 
 use chrono::Local;
 use crossbeam::channel::{bounded, select};
-use mio::net::TcpListener;
-use mio::{Events, Poll, PollOpt, Ready, Token};
+use mio::net::{TcpListener, UdpSocket};
+use mio::{Events, Poll, Token};
+use mio_uds::UnixListener;
 
-use crate::channel::channel_handler_ctx::{ChannelInboundHandlerCtx, ChannelOutboundHandlerCtx};
 use crate::channel::channel_handler_ctx_pipe::{
     ChannelInboundHandlerCtxPipe, ChannelOutboundHandlerCtxPipe,
 };
-use crate::channel::handler::{HeadHandler, TailHandler};
-use crate::channel::handler_pipe::{ChannelInboundHandlerPipe, ChannelOutboundHandlerPipe};
+use crate::channel::event::IdleStateEvent;
+use crate::channel::handler::{
+    ChannelInboundHandler, ChannelOutboundHandler, HeadHandler, TailHandler,
+};
+use crate::channel::handler_pipe::{
+    ChannelHandlerPipe, ChannelHandlerPipeFn, ChannelInboundHandlerPipe, ChannelOutboundHandlerPipe,
+};
 use crate::core::eventloop::{EventLoop, EventLoopGroup};
-use crate::errors::RettyErrorKind;
 use crate::transport::channel::{Channel, ChannelOptions};
+use crate::transport::recv_buf_allocator::RecvByteBufAllocator;
+use crate::transport::stream::ChannelListener;
+use crate::transport::unix::remove_stale_socket;
 
 struct Sessions {
     channel: Arc<Mutex<Channel>>,
     in_pipe: Arc<ChannelInboundHandlerCtxPipe>,
+    // 上一次触发 ReaderIdle 的时间, 之后重新计时
+    last_idle_event_ms: u64,
 }
 
 impl Sessions {
@@ -32,19 +40,32 @@ impl Sessions {
         Sessions {
             channel: ch,
             in_pipe: pipe,
+            last_idle_event_ms: 0,
         }
     }
 }
 
+///
+/// 服务端传输层类型
+///
+enum Transport {
+    Tcp,
+    Udp,
+    Unix,
+}
+
 pub struct Bootstrap {
+    transport: Transport,
     host: String,
     port: u16,
+    path: Option<PathBuf>,
     boss_group: EventLoopGroup,
     worker_group: Option<Arc<EventLoopGroup>>,
     channel_inbound_handler_pipe_fn:
         Option<Arc<dyn Fn() -> ChannelInboundHandlerPipe + Send + Sync + 'static>>,
     channel_outbound_handler_pipe_fn:
         Option<Arc<dyn Fn() -> ChannelOutboundHandlerPipe + Send + Sync + 'static>>,
+    channel_handler_pipe_fn: Option<ChannelHandlerPipeFn>,
     opts: HashMap<String, ChannelOptions>,
     stopped: Arc<AtomicBool>,
     channel_container: Arc<Mutex<HashMap<Token, Arc<Mutex<Sessions>>>>>,
@@ -53,18 +74,30 @@ pub struct Bootstrap {
 impl Bootstrap {
     pub fn new_server_bootstrap() -> Bootstrap {
         Bootstrap {
+            transport: Transport::Tcp,
             host: "0.0.0.0".to_owned(),
             port: 1511,
+            path: None,
             boss_group: EventLoopGroup::new(2),
             worker_group: None,
             channel_inbound_handler_pipe_fn: None,
             channel_outbound_handler_pipe_fn: None,
+            channel_handler_pipe_fn: None,
             opts: HashMap::new(),
             stopped: Arc::new(AtomicBool::new(false)),
             channel_container: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
+    ///
+    /// UDP 服务端, 绑定的 socket 作为一个 channel, 入站收到 DatagramPacket, 出站写 DatagramPacket
+    ///
+    pub fn new_datagram_bootstrap() -> Bootstrap {
+        let mut bootstrap = Bootstrap::new_server_bootstrap();
+        bootstrap.transport = Transport::Udp;
+        bootstrap
+    }
+
     pub fn initialize_inbound_handler_pipeline<F>(&mut self, pipe_fn: F) -> &mut Self
     where
         F: Fn() -> ChannelInboundHandlerPipe + Send + Sync + 'static,
@@ -81,6 +114,17 @@ impl Bootstrap {
         self
     }
 
+    ///
+    /// 用一个 ChannelHandlerPipe 同时注册出入站 handler, 设置之后不再使用分开注册的出入站 pipeline
+    ///
+    pub fn initialize_handler_pipeline<F>(&mut self, pipe_fn: F) -> &mut Self
+    where
+        F: Fn() -> ChannelHandlerPipe + Send + Sync + 'static,
+    {
+        self.channel_handler_pipe_fn = Some(Arc::new(pipe_fn));
+        self
+    }
+
     // 设置 worker_group
     pub fn worker_group(&mut self, n: usize) -> &mut Self {
         self.worker_group = Some(Arc::new(EventLoopGroup::new(n)));
@@ -126,6 +170,32 @@ impl Bootstrap {
         self
     }
 
+    /// 接收缓冲区分配策略, 默认是 adaptive(64, 2048, 65536), 每个读事件最多读 16 次 / 1MB
+    pub fn opt_recv_buf_allocator(&mut self, allocator: RecvByteBufAllocator) -> &mut Self {
+        self.opts.extend(allocator.opts());
+        self
+    }
+
+    /// 出站缓冲区高低水位, 超过 high 时 channel 不可写, 降到 low 以下恢复可写
+    pub fn opt_write_buffer_water_mark(&mut self, low: usize, high: usize) -> &mut Self {
+        self.opts.insert(
+            "write_buffer_low_water_mark".to_owned(),
+            ChannelOptions::NUMBER(low),
+        );
+        self.opts.insert(
+            "write_buffer_high_water_mark".to_owned(),
+            ChannelOptions::NUMBER(high),
+        );
+        self
+    }
+
+    /// set udp broadcast
+    pub fn opt_broadcast(&mut self, broadcast: bool) -> &mut Self {
+        self.opts
+            .insert("broadcast".to_owned(), ChannelOptions::BOOL(broadcast));
+        self
+    }
+
     pub fn opt_read_idle_timeout_ms(&mut self, ms: usize) -> &mut Self {
         self.opts.insert(
             "read_idle_timeout_ms".to_owned(),
@@ -141,9 +211,19 @@ impl Bootstrap {
         self
     }
 
+    /// bind unix domain socket path, 启动时会删除残留的 socket 文件
+    pub fn bind_path<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.transport = Transport::Unix;
+        self.path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
     ///
     pub fn terminate(&mut self) {
         self.stopped.store(true, Ordering::Relaxed);
+        if let Some(path) = &self.path {
+            let _ = remove_stale_socket(path);
+        }
         if let Some(ref group) = &self.worker_group {
             group.event_loop_group().iter().for_each(|g| {
                 g.shutdown();
@@ -152,6 +232,9 @@ impl Bootstrap {
     }
 
     pub fn start(&mut self) {
+        if let Transport::Udp = self.transport {
+            return self.start_datagram();
+        }
         let boss_group = &mut self.boss_group;
         let boss_eventloop = boss_group.next().unwrap();
         let idle_task_event_loop = boss_group.next().unwrap();
@@ -159,43 +242,56 @@ impl Bootstrap {
             None => panic!("work_group error"),
             Some(g) => Arc::clone(g),
         };
-        let ip_addr = self.host.parse().unwrap();
-        let sock_addr = Arc::new(SocketAddr::new(ip_addr, self.port));
+        let host = self.host.clone();
+        let port = self.port;
+        let path = self.path.clone();
 
         let opts = self.opts.clone();
         let stopped = Arc::clone(&self.stopped);
 
-        let channel_inbound_handler_pipe_fn =
-            &self.channel_inbound_handler_pipe_fn.as_ref().unwrap();
-        let channel_inbound_handler_pipe_fn = Arc::clone(channel_inbound_handler_pipe_fn);
-        let channel_outbound_handler_pipe_fn =
-            &self.channel_outbound_handler_pipe_fn.as_ref().unwrap();
-        let channel_outbound_handler_pipe_fn = Arc::clone(channel_outbound_handler_pipe_fn);
+        let channel_handler_pipe_fn = Bootstrap::channel_handler_pipe_fn(
+            &self.channel_handler_pipe_fn,
+            &self.channel_inbound_handler_pipe_fn,
+            &self.channel_outbound_handler_pipe_fn,
+        );
 
         let channel_container = Arc::clone(&self.channel_container);
         idle_task_event_loop.excutor.spawn(move || {
             let (s, r) = bounded::<Token>(1024);
+            let fire_reader_idle = |key: Token| {
+                let sess_opt = channel_container.lock().unwrap().get(&key).cloned();
+                if let Some(session) = sess_opt {
+                    let pipe = {
+                        let mut session = session.lock().unwrap();
+                        session.last_idle_event_ms = Local::now().timestamp_millis() as u64;
+                        session.in_pipe.clone()
+                    };
+                    pipe.head_user_event_triggered(Box::new(IdleStateEvent::ReaderIdle));
+                }
+            };
             loop {
-                for (k, sess) in channel_container.lock().unwrap().iter() {
-                    let sess = sess.lock().unwrap();
-                    let channel = sess.channel.lock().unwrap();
-                    if (Local::now().timestamp_millis() as u64).sub(channel.last_read_time_ms()) > channel.read_idle_timeout_ms() {
-                        let _ = s.send(*k);
+                {
+                    let mut channel_container = channel_container.lock().unwrap();
+                    // 已经关闭的连接不再检测
+                    channel_container.retain(|_, sess| {
+                        !sess.lock().unwrap().channel.lock().unwrap().is_closed()
+                    });
+                    let now = Local::now().timestamp_millis() as u64;
+                    for (k, sess) in channel_container.iter() {
+                        let sess = sess.lock().unwrap();
+                        let channel = sess.channel.lock().unwrap();
+                        // 一直没有读到数据时每隔 read_idle_timeout_ms 触发一次
+                        let idle_since = channel.last_read_time_ms().max(sess.last_idle_event_ms);
+                        if now.saturating_sub(idle_since) > channel.read_idle_timeout_ms() {
+                            let _ = s.send(*k);
+                        }
                     }
                 }
                 select! {
                     recv(r)->key=>{
-                        let mut channel_container = channel_container.lock().unwrap();
-                        let sess_opt   =  channel_container.remove( &key.unwrap());
-                        match sess_opt{
-                            Some(session) =>{
-                                     let  session = session.lock().unwrap();
-                                      let pipe =   &session.in_pipe;
-                                     let read_timeout_err = RettyErrorKind::new(ErrorKind::TimedOut ,  "ReadIdleTimeout".to_string());
-                                      pipe.head_channel_exception(read_timeout_err);
-                            },
-                            None =>{}
-                        }
+                        // 这一轮检测到的都处理掉, 下一轮不会重复触发
+                        fire_reader_idle(key.unwrap());
+                        r.try_iter().for_each(fire_reader_idle);
                     },
                     default(Duration::from_millis(5000)) =>{}
                 }
@@ -208,26 +304,14 @@ impl Bootstrap {
             let mut events = Events::with_capacity(1024);
             let mut ch_id: usize = 1;
 
-            let listener = match TcpListener::bind(&sock_addr) {
-                Ok(s) => {
-                    println!("[High performance I/O framework written by Rust inspired by Netty]");
-                    println!(
-                        "[Retty server is listening : {:?} : {:?}]",
-                        sock_addr.ip(),
-                        sock_addr.port()
-                    );
-                    s
-                }
-                Err(e) => {
-                    println!("error : {:?}", e);
-                    panic!("server is not started:{:?}", e)
-                }
+            let listener = match &path {
+                Some(path) => Bootstrap::bind_unix_listener(path),
+                None => Bootstrap::bind_tcp_listener(&host, port),
             };
 
             let sel = Poll::new().unwrap();
             // 将监听器绑定在selector上 , 打上Token(0)的标记，注册read事件, 也就是只监听Tcplistener的事件，后面是监听TcpStream的事件
-            sel.register(&listener, Token(0), Ready::readable(), PollOpt::edge())
-                .unwrap();
+            listener.register(&sel, Token(0)).unwrap();
             // 循环event_loop,启动reactor线程
             work_group.event_loop_group().iter().for_each(|e| e.run());
             //当服务器没有停的时候
@@ -244,31 +328,25 @@ impl Bootstrap {
                 }
                 // 循环事件，监听accept
                 for _e in events.iter() {
-                    let (sock, _) = match listener.accept() {
-                        Ok((s, a)) => (s, a),
+                    let sock = match listener.accept() {
+                        Ok(s) => s,
                         Err(_) => {
                             continue;
                         }
                     };
 
-                    let channel = Channel::create(
+                    let channel = Channel::create_from_stream(
                         Token(ch_id),
                         opts.clone(),
                         event_loop.clone(),
-                        sock.try_clone().unwrap(),
+                        sock,
                     );
 
                     let channel = Arc::new(Mutex::new(channel));
-                    let outbound_ctx_pipe = Bootstrap::create_channel_outbound_ctx_pipe(
-                        channel_outbound_handler_pipe_fn.clone(),
-                        event_loop.clone(),
-                        channel.clone(),
-                    );
-                    let inbound_ctx_pipe = Bootstrap::create_channel_inbound_ctx_pipe(
-                        channel_inbound_handler_pipe_fn.clone(),
+                    let (inbound_ctx_pipe, _) = Bootstrap::create_channel_ctx_pipes(
+                        channel_handler_pipe_fn.clone(),
                         event_loop.clone(),
                         channel.clone(),
-                        Arc::new(Mutex::new(outbound_ctx_pipe)),
                     );
                     event_loop
                         .clone()
@@ -287,6 +365,85 @@ impl Bootstrap {
         });
     }
 
+    fn bind_tcp_listener(host: &str, port: u16) -> ChannelListener {
+        let ip_addr = host.parse().unwrap();
+        let sock_addr = SocketAddr::new(ip_addr, port);
+        match TcpListener::bind(&sock_addr) {
+            Ok(s) => {
+                println!("[High performance I/O framework written by Rust inspired by Netty]");
+                println!(
+                    "[Retty server is listening : {:?} : {:?}]",
+                    sock_addr.ip(),
+                    sock_addr.port()
+                );
+                ChannelListener::Tcp(s)
+            }
+            Err(e) => {
+                println!("error : {:?}", e);
+                panic!("server is not started:{:?}", e)
+            }
+        }
+    }
+
+    fn bind_unix_listener(path: &Path) -> ChannelListener {
+        let bind_ret = remove_stale_socket(path).and_then(|_| UnixListener::bind(path));
+        match bind_ret {
+            Ok(s) => {
+                println!("[High performance I/O framework written by Rust inspired by Netty]");
+                println!("[Retty server is listening : {}]", path.display());
+                ChannelListener::Unix(s)
+            }
+            Err(e) => {
+                println!("error : {:?}", e);
+                panic!("server is not started:{:?}", e)
+            }
+        }
+    }
+
+    ///
+    /// 启动 UDP 服务, 只需要一个 eventloop 处理绑定的 socket
+    ///
+    fn start_datagram(&mut self) {
+        let work_group = match &self.worker_group {
+            None => panic!("work_group error"),
+            Some(g) => Arc::clone(g),
+        };
+        let ip_addr = self.host.parse().unwrap();
+        let sock_addr = SocketAddr::new(ip_addr, self.port);
+        let socket = match UdpSocket::bind(&sock_addr) {
+            Ok(s) => {
+                println!("[High performance I/O framework written by Rust inspired by Netty]");
+                println!(
+                    "[Retty datagram server is listening : {:?} : {:?}]",
+                    sock_addr.ip(),
+                    sock_addr.port()
+                );
+                s
+            }
+            Err(e) => {
+                println!("error : {:?}", e);
+                panic!("server is not started:{:?}", e)
+            }
+        };
+
+        let ch_id: usize = 1;
+        let event_loop = work_group.event_loop_group()[0].clone();
+        event_loop.run();
+        let channel =
+            Channel::create_datagram(Token(ch_id), self.opts.clone(), event_loop.clone(), socket);
+        let channel = Arc::new(Mutex::new(channel));
+        let (inbound_ctx_pipe, _) = Bootstrap::create_channel_ctx_pipes(
+            Bootstrap::channel_handler_pipe_fn(
+                &self.channel_handler_pipe_fn,
+                &self.channel_inbound_handler_pipe_fn,
+                &self.channel_outbound_handler_pipe_fn,
+            ),
+            event_loop.clone(),
+            channel.clone(),
+        );
+        event_loop.attach(ch_id, channel, inbound_ctx_pipe);
+    }
+
     #[inline]
     fn incr_id(cur_id: usize) -> usize {
         if cur_id == usize::MAX {
@@ -296,151 +453,99 @@ impl Bootstrap {
         }
     }
 
+    ///
+    /// 优先使用统一注册的 pipeline, 否则把分开注册的出入站 pipeline 合起来, 没有注册的一边为空
+    ///
+    pub(crate) fn channel_handler_pipe_fn(
+        channel_handler_pipe_fn: &Option<ChannelHandlerPipeFn>,
+        in_channel_handler_pipe_fn: &Option<
+            Arc<dyn Fn() -> ChannelInboundHandlerPipe + Send + Sync + 'static>,
+        >,
+        out_channel_handler_pipe_fn: &Option<
+            Arc<dyn Fn() -> ChannelOutboundHandlerPipe + Send + Sync + 'static>,
+        >,
+    ) -> ChannelHandlerPipeFn {
+        if let Some(pipe_fn) = channel_handler_pipe_fn {
+            return pipe_fn.clone();
+        }
+        let in_pipe_fn = in_channel_handler_pipe_fn.clone();
+        let out_pipe_fn = out_channel_handler_pipe_fn.clone();
+        Arc::new(move || {
+            ChannelHandlerPipe::from_pipes(
+                in_pipe_fn
+                    .as_ref()
+                    .map_or_else(ChannelInboundHandlerPipe::new, |f| f()),
+                out_pipe_fn
+                    .as_ref()
+                    .map_or_else(ChannelOutboundHandlerPipe::new, |f| f()),
+            )
+        })
+    }
+
+    ///
+    /// 创建一个连接的出入站 pipeline, 先创建出站 pipeline, 入站 handler 通过它写数据
+    ///
+    pub(crate) fn create_channel_ctx_pipes(
+        channel_handler_pipe_fn: ChannelHandlerPipeFn,
+        event_loop: Arc<EventLoop>,
+        channel: Arc<Mutex<Channel>>,
+    ) -> (
+        ChannelInboundHandlerCtxPipe,
+        Arc<Mutex<ChannelOutboundHandlerCtxPipe>>,
+    ) {
+        // 创建ChannelHandlerPipe , 每一个连接创建自己的一套pipeline
+        let (inbound_handlers, outbound_handlers) = (channel_handler_pipe_fn)().into_handlers();
+        let outbound_ctx_pipe = Arc::new(Mutex::new(Bootstrap::create_channel_outbound_ctx_pipe(
+            outbound_handlers,
+            event_loop.clone(),
+            channel.clone(),
+        )));
+        let inbound_ctx_pipe = Bootstrap::create_channel_inbound_ctx_pipe(
+            inbound_handlers,
+            event_loop,
+            channel,
+            outbound_ctx_pipe.clone(),
+        );
+        (inbound_ctx_pipe, outbound_ctx_pipe)
+    }
+
     ///
     /// 创建入站处理pipeline
     ///
-    fn create_channel_inbound_ctx_pipe(
-        in_channel_handler_pipe_fn: Arc<dyn Fn() -> ChannelInboundHandlerPipe + Send + Sync>,
+    pub(crate) fn create_channel_inbound_ctx_pipe(
+        handlers: Vec<Box<dyn ChannelInboundHandler + Send + Sync>>,
         event_loop: Arc<EventLoop>,
         channel: Arc<Mutex<Channel>>,
         out_pipe: Arc<Mutex<ChannelOutboundHandlerCtxPipe>>,
     ) -> ChannelInboundHandlerCtxPipe {
-        // 创建ChannelHandlerPipe , 每一个连接创建自己的一套pipeline
-        let mut channel_handler_pipe: ChannelInboundHandlerPipe = (in_channel_handler_pipe_fn)();
-        //添加头handler
-        channel_handler_pipe.add_first(Box::new(HeadHandler::new()));
         // 创建ChannelHandlerCtxPipe
-        let mut channel_handler_context_pipe = ChannelInboundHandlerCtxPipe::new();
-        for _i in 0..channel_handler_pipe.handlers.len() {
-            let handler = channel_handler_pipe.handlers.remove(0);
-            let id = handler.id().clone();
-            let handler_arc = Arc::new(Mutex::new(handler));
-            let ctx = Arc::new(Mutex::new(ChannelInboundHandlerCtx::new(
-                id,
-                event_loop.clone(),
-                channel.clone(),
-                handler_arc.clone(),
-                Some(out_pipe.clone()),
-            )));
-            channel_handler_context_pipe.add_last(ctx, handler_arc.clone());
-        }
-
-        let mut enumerate = channel_handler_context_pipe
-            .channel_handler_ctx_pipe
-            .iter()
-            .enumerate();
-        let ctx_pipe_len = channel_handler_context_pipe.channel_handler_ctx_pipe.len();
-
-        let head_ctx = channel_handler_context_pipe
-            .channel_handler_ctx_pipe
-            .get(0)
-            .unwrap()
-            .clone();
-        let head_handler = head_ctx.lock().unwrap().handler.clone();
-
-        for _i in 0..ctx_pipe_len {
-            let (_j, ctx) = enumerate.next().unwrap();
-
-            let mut curr = ctx.lock().unwrap();
-            curr.channel_handler_ctx_pipe = Some(channel_handler_context_pipe.clone());
-            if _j == 0 {
-                curr.head_handler = None;
-                curr.head_ctx = None;
-            } else {
-                curr.head_handler = Some(head_handler.clone());
-                curr.head_ctx = Some(head_ctx.clone());
-            }
-            let next_context = channel_handler_context_pipe
-                .channel_handler_ctx_pipe
-                .get(_j + 1);
-            match next_context {
-                Some(next_ctx) => {
-                    let next_ctx_clone = next_ctx.clone();
-                    let next_ctx_guard = next_ctx.lock().unwrap();
-                    curr.next_ctx = Some(next_ctx_clone);
-                    curr.next_handler = Some(next_ctx_guard.handler.clone());
-                }
-                None => {
-                    curr.next_ctx = None;
-                    curr.next_handler = None;
-                }
-            }
+        let channel_handler_context_pipe =
+            ChannelInboundHandlerCtxPipe::new(event_loop, channel, out_pipe);
+        //添加头handler
+        channel_handler_context_pipe.add_last(Box::new(HeadHandler::new()));
+        for handler in handlers {
+            channel_handler_context_pipe.add_last(handler);
         }
-        channel_handler_context_pipe.clone()
+        channel_handler_context_pipe
     }
 
     ///
-    /// 创建出站处理器pipeline
+    /// 创建出站处理器pipeline, handlers 已经按出站的顺序排好
     ///
-    fn create_channel_outbound_ctx_pipe(
-        out_channel_handler_pipe_fn: Arc<dyn Fn() -> ChannelOutboundHandlerPipe + Send + Sync>,
+    pub(crate) fn create_channel_outbound_ctx_pipe(
+        handlers: Vec<Box<dyn ChannelOutboundHandler + Send + Sync>>,
         event_loop: Arc<EventLoop>,
         channel: Arc<Mutex<Channel>>,
     ) -> ChannelOutboundHandlerCtxPipe {
-        // 创建ChannelHandlerPipe , 每一个连接创建自己的一套pipeline
-        let mut channel_handler_pipe: ChannelOutboundHandlerPipe = (out_channel_handler_pipe_fn)();
         // 创建ChannelHandlerCtxPipe
-        let mut channel_handler_context_pipe = ChannelOutboundHandlerCtxPipe::new();
-        //将handler pipeline 反序
-        channel_handler_pipe.handlers.reverse();
+        let channel_handler_context_pipe = ChannelOutboundHandlerCtxPipe::new(event_loop, channel);
+        for handler in handlers {
+            channel_handler_context_pipe.add_last(handler);
+        }
         //
         // 添加TailHandler，追加到最后面
         //
-        channel_handler_pipe.add_last(Box::new(TailHandler::new()));
-
-        for _i in 0..channel_handler_pipe.handlers.len() {
-            let handler = channel_handler_pipe.handlers.remove(0);
-            let id = handler.id().clone();
-            let handler_arc = Arc::new(Mutex::new(handler));
-            let ctx = Arc::new(Mutex::new(ChannelOutboundHandlerCtx::new(
-                id,
-                event_loop.clone(),
-                channel.clone(),
-                handler_arc.clone(),
-            )));
-            channel_handler_context_pipe.add_last(ctx, handler_arc.clone());
-        }
-
-        let mut enumerate = channel_handler_context_pipe
-            .channel_handler_ctx_pipe
-            .iter()
-            .enumerate();
-        let ctx_pipe_len = channel_handler_context_pipe.channel_handler_ctx_pipe.len();
-
-        let head_ctx = channel_handler_context_pipe
-            .channel_handler_ctx_pipe
-            .get(0)
-            .unwrap()
-            .clone();
-        let head_handler = head_ctx.lock().unwrap().handler.clone();
-
-        for _i in 0..ctx_pipe_len {
-            let (_j, ctx) = enumerate.next().unwrap();
-            let mut curr = ctx.lock().unwrap();
-            curr.channel_handler_ctx_pipe = Some(channel_handler_context_pipe.clone());
-            if _j == 0 {
-                curr.head_handler = None;
-                curr.head_ctx = None;
-            } else {
-                curr.head_handler = Some(head_handler.clone());
-                curr.head_ctx = Some(head_ctx.clone());
-            }
-            let next_context = channel_handler_context_pipe
-                .channel_handler_ctx_pipe
-                .get(_j + 1);
-            match next_context {
-                Some(next_ctx) => {
-                    let next_ctx_clone = next_ctx.clone();
-                    let next_ctx_guard = next_ctx.lock().unwrap();
-                    curr.next_ctx = Some(next_ctx_clone);
-                    curr.next_handler = Some(next_ctx_guard.handler.clone());
-                }
-                None => {
-                    curr.next_ctx = None;
-                    curr.next_handler = None;
-                }
-            }
-        }
-        channel_handler_context_pipe.clone()
+        channel_handler_context_pipe.add_last(Box::new(TailHandler::new()));
+        channel_handler_context_pipe
     }
 }
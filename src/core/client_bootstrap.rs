@@ -0,0 +1,313 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::io::{ErrorKind, Result};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use mio::net::TcpStream;
+use mio::Token;
+use mio_uds::UnixStream;
+
+use crate::channel::channel_future::ChannelFuture;
+use crate::channel::channel_handler_ctx_pipe::ChannelOutboundHandlerCtxPipe;
+use crate::channel::handler_pipe::{
+    ChannelHandlerPipe, ChannelHandlerPipeFn, ChannelInboundHandlerPipe, ChannelOutboundHandlerPipe,
+};
+use crate::core::bootstrap::Bootstrap;
+use crate::core::eventloop::{EventLoop, EventLoopGroup};
+use crate::errors::RettyErrorKind;
+use crate::transport::channel::{close_channel, Channel, ChannelOptions};
+use crate::transport::recv_buf_allocator::RecvByteBufAllocator;
+use crate::transport::unix::PeerCredentials;
+
+///
+/// 客户端启动器, 连接远端地址, 每个连接创建自己的一套出入站 pipeline
+///
+pub struct ClientBootstrap {
+    worker_group: Option<Arc<EventLoopGroup>>,
+    channel_inbound_handler_pipe_fn:
+        Option<Arc<dyn Fn() -> ChannelInboundHandlerPipe + Send + Sync + 'static>>,
+    channel_outbound_handler_pipe_fn:
+        Option<Arc<dyn Fn() -> ChannelOutboundHandlerPipe + Send + Sync + 'static>>,
+    channel_handler_pipe_fn: Option<ChannelHandlerPipeFn>,
+    opts: HashMap<String, ChannelOptions>,
+    connect_timeout_ms: u64,
+    started: Arc<AtomicBool>,
+    ch_id: usize,
+}
+
+impl ClientBootstrap {
+    pub fn new_client_bootstrap() -> ClientBootstrap {
+        ClientBootstrap {
+            worker_group: None,
+            channel_inbound_handler_pipe_fn: None,
+            channel_outbound_handler_pipe_fn: None,
+            channel_handler_pipe_fn: None,
+            opts: HashMap::new(),
+            connect_timeout_ms: 30000,
+            started: Arc::new(AtomicBool::new(false)),
+            ch_id: 1,
+        }
+    }
+
+    pub fn initialize_inbound_handler_pipeline<F>(&mut self, pipe_fn: F) -> &mut Self
+    where
+        F: Fn() -> ChannelInboundHandlerPipe + Send + Sync + 'static,
+    {
+        self.channel_inbound_handler_pipe_fn = Some(Arc::new(Box::new(pipe_fn)));
+        self
+    }
+
+    pub fn initialize_outbound_handler_pipeline<F>(&mut self, pipe_fn: F) -> &mut Self
+    where
+        F: Fn() -> ChannelOutboundHandlerPipe + Send + Sync + 'static,
+    {
+        self.channel_outbound_handler_pipe_fn = Some(Arc::new(Box::new(pipe_fn)));
+        self
+    }
+
+    ///
+    /// 用一个 ChannelHandlerPipe 同时注册出入站 handler, 设置之后不再使用分开注册的出入站 pipeline
+    ///
+    pub fn initialize_handler_pipeline<F>(&mut self, pipe_fn: F) -> &mut Self
+    where
+        F: Fn() -> ChannelHandlerPipe + Send + Sync + 'static,
+    {
+        self.channel_handler_pipe_fn = Some(Arc::new(pipe_fn));
+        self
+    }
+
+    // 设置 worker_group
+    pub fn worker_group(&mut self, n: usize) -> &mut Self {
+        self.worker_group = Some(Arc::new(EventLoopGroup::new(n)));
+        self
+    }
+
+    /// set connect timeout in ms
+    pub fn opt_connect_timeout_ms(&mut self, ms: usize) -> &mut Self {
+        self.connect_timeout_ms = ms as u64;
+        self
+    }
+
+    /// set ttl in ms
+    pub fn opt_ttl_ms(&mut self, ttl: usize) -> &mut Self {
+        self.opts
+            .insert("ttl".to_owned(), ChannelOptions::NUMBER(ttl));
+        self
+    }
+
+    /// set linger in ms
+    pub fn opt_linger_ms(&mut self, linger: usize) -> &mut Self {
+        self.opts
+            .insert("linger".to_owned(), ChannelOptions::NUMBER(linger));
+        self
+    }
+
+    /// set tcp nodelay
+    pub fn opt_nodelay(&mut self, nodelay: bool) -> &mut Self {
+        self.opts
+            .insert("nodelay".to_owned(), ChannelOptions::BOOL(nodelay));
+        self
+    }
+
+    pub fn opt_keep_alive_ms(&mut self, keep_alive: usize) -> &mut Self {
+        self.opts
+            .insert("keep_alive".to_owned(), ChannelOptions::NUMBER(keep_alive));
+        self
+    }
+
+    pub fn opt_recv_buf_size(&mut self, buf_size: usize) -> &mut Self {
+        self.opts
+            .insert("recv_buf_size".to_owned(), ChannelOptions::NUMBER(buf_size));
+        self
+    }
+
+    pub fn opt_send_buf_size(&mut self, buf_size: usize) -> &mut Self {
+        self.opts
+            .insert("send_buf_size".to_owned(), ChannelOptions::NUMBER(buf_size));
+        self
+    }
+
+    /// 接收缓冲区分配策略, 默认是 adaptive(64, 2048, 65536), 每个读事件最多读 16 次 / 1MB
+    pub fn opt_recv_buf_allocator(&mut self, allocator: RecvByteBufAllocator) -> &mut Self {
+        self.opts.extend(allocator.opts());
+        self
+    }
+
+    /// 出站缓冲区高低水位, 超过 high 时 channel 不可写, 降到 low 以下恢复可写
+    pub fn opt_write_buffer_water_mark(&mut self, low: usize, high: usize) -> &mut Self {
+        self.opts.insert(
+            "write_buffer_low_water_mark".to_owned(),
+            ChannelOptions::NUMBER(low),
+        );
+        self.opts.insert(
+            "write_buffer_high_water_mark".to_owned(),
+            ChannelOptions::NUMBER(high),
+        );
+        self
+    }
+
+    ///
+    /// 发起非阻塞连接, 连接成功触发 channel_active, 连接失败或超时触发 channel_exception
+    /// 地址解析失败或者立即失败的连接直接返回错误
+    ///
+    pub fn connect(
+        &mut self,
+        host: &str,
+        port: u16,
+    ) -> std::result::Result<ClientChannel, RettyErrorKind> {
+        let sock_addr = ClientBootstrap::resolve(host, port)?;
+        let stream = TcpStream::connect(&sock_addr)?;
+        Ok(self.attach(|id, opts, event_loop| Channel::create(id, opts, event_loop, stream)))
+    }
+
+    ///
+    /// 连接 unix domain socket
+    ///
+    pub fn connect_unix<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> std::result::Result<ClientChannel, RettyErrorKind> {
+        let stream = UnixStream::connect(path)?;
+        Ok(self.attach(|id, opts, event_loop| Channel::create_unix(id, opts, event_loop, stream)))
+    }
+
+    fn attach<F>(&mut self, create_channel: F) -> ClientChannel
+    where
+        F: FnOnce(Token, HashMap<String, ChannelOptions>, Arc<EventLoop>) -> Channel,
+    {
+        let work_group = match &self.worker_group {
+            None => panic!("work_group error"),
+            Some(g) => Arc::clone(g),
+        };
+        if !self.started.swap(true, Ordering::Relaxed) {
+            work_group.event_loop_group().iter().for_each(|e| e.run());
+        }
+
+        let ch_id = self.ch_id;
+        self.ch_id = if ch_id == usize::MAX { 1 } else { ch_id + 1 };
+        let event_loop =
+            work_group.event_loop_group()[ch_id % work_group.event_loop_group().len()].clone();
+
+        let channel = create_channel(Token(ch_id), self.opts.clone(), event_loop.clone());
+        let channel = Arc::new(Mutex::new(channel));
+        let connect_future = ChannelFuture::new(Arc::downgrade(&channel));
+        channel
+            .lock()
+            .unwrap()
+            .set_connect_promise(connect_future.clone());
+
+        let channel_handler_pipe_fn = Bootstrap::channel_handler_pipe_fn(
+            &self.channel_handler_pipe_fn,
+            &self.channel_inbound_handler_pipe_fn,
+            &self.channel_outbound_handler_pipe_fn,
+        );
+        let (inbound_ctx_pipe, outbound_ctx_pipe) = Bootstrap::create_channel_ctx_pipes(
+            channel_handler_pipe_fn,
+            event_loop.clone(),
+            channel.clone(),
+        );
+        event_loop.attach_connecting(
+            ch_id,
+            channel.clone(),
+            inbound_ctx_pipe,
+            self.connect_timeout_ms,
+        );
+        ClientChannel {
+            channel,
+            outbound_ctx_pipe,
+            connect_future,
+        }
+    }
+
+    pub fn terminate(&mut self) {
+        if let Some(ref group) = &self.worker_group {
+            group.event_loop_group().iter().for_each(|g| {
+                g.shutdown();
+            });
+        }
+    }
+
+    fn resolve(host: &str, port: u16) -> Result<SocketAddr> {
+        match (host, port).to_socket_addrs()?.next() {
+            Some(addr) => Ok(addr),
+            None => Err(std::io::Error::new(
+                ErrorKind::AddrNotAvailable,
+                format!("could not resolve {}:{}", host, port),
+            )),
+        }
+    }
+}
+
+///
+/// 客户端连接的句柄, 可以在 eventloop 之外通过出站 pipeline 写数据
+///
+#[derive(Clone)]
+pub struct ClientChannel {
+    channel: Arc<Mutex<Channel>>,
+    outbound_ctx_pipe: Arc<Mutex<ChannelOutboundHandlerCtxPipe>>,
+    connect_future: ChannelFuture,
+}
+
+impl ClientChannel {
+    pub fn id(&self) -> String {
+        let channel = self.channel.lock().unwrap();
+        format!("{}", channel.id().0)
+    }
+
+    ///
+    /// 连接建立(成功), 连接失败或超时(失败) 时完成
+    ///
+    pub fn connect_future(&self) -> ChannelFuture {
+        self.connect_future.clone()
+    }
+
+    ///
+    /// 连接建立之前写入的数据会先放在出站缓冲区, 连接成功后再写出
+    ///
+    pub fn write_and_flush(&self, message: &mut dyn Any) -> ChannelFuture {
+        let pipe = self.outbound_ctx_pipe.lock().unwrap();
+        pipe.write_and_flush(message)
+    }
+
+    pub fn write(&self, message: &mut dyn Any) -> ChannelFuture {
+        let pipe = self.outbound_ctx_pipe.lock().unwrap();
+        pipe.write(message)
+    }
+
+    pub fn flush(&self) {
+        let pipe = self.outbound_ctx_pipe.lock().unwrap();
+        pipe.flush();
+    }
+
+    pub fn remote_addr(&self) -> Result<SocketAddr> {
+        let channel = self.channel.lock().unwrap();
+        channel.remote_addr()
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        let channel = self.channel.lock().unwrap();
+        channel.local_addr()
+    }
+
+    pub fn peer_credentials(&self) -> Result<PeerCredentials> {
+        let channel = self.channel.lock().unwrap();
+        channel.peer_credentials()
+    }
+
+    pub fn is_active(&self) -> bool {
+        let channel = self.channel.lock().unwrap();
+        !channel.is_closed() && !channel.is_connecting()
+    }
+
+    pub fn is_writable(&self) -> bool {
+        let channel = self.channel.lock().unwrap();
+        channel.is_writable()
+    }
+
+    pub fn close(&self) -> ChannelFuture {
+        close_channel(&self.channel)
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::io::ErrorKind;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
@@ -6,12 +7,18 @@ use std::time::Duration;
 
 use bytebuf_rs::bytebuf::ByteBuf;
 use chashmap::CHashMap;
+use chrono::Local;
+use mio::unix::UnixReady;
 use mio::{Events, Poll, Token};
 use rayon_core::ThreadPool;
 
+use crate::buffer::pooled_allocator::PooledByteBufAllocator;
 use crate::channel::channel_handler_ctx_pipe::ChannelInboundHandlerCtxPipe;
 use crate::errors::RettyErrorKind;
-use crate::transport::channel::Channel;
+use crate::transport::channel::{notify_promises, Channel};
+use crate::transport::datagram::DatagramPacket;
+
+const MAX_DATAGRAM_SIZE: usize = 65535;
 
 pub struct EventLoop {
     pub(crate) excutor: Arc<ThreadPool>,
@@ -19,6 +26,14 @@ pub struct EventLoop {
     pub(crate) channel_map: Arc<CHashMap<Token, Arc<Mutex<Channel>>>>,
     pub(crate) channel_inbound_handler_ctx_pipe_map:
         Arc<CHashMap<Token, ChannelInboundHandlerCtxPipe>>,
+    ///
+    /// 客户端正在连接中的 channel 及其连接超时的时间点(ms)
+    ///
+    pub(crate) pending_connects: Arc<Mutex<HashMap<Token, u64>>>,
+    ///
+    /// 这个 eventloop 上所有 channel 共用的池化内存分配器
+    ///
+    pub(crate) allocator: PooledByteBufAllocator,
     pub(crate) stopped: Arc<AtomicBool>,
 }
 
@@ -35,9 +50,15 @@ impl EventLoop {
             selector: Arc::new(Poll::new().unwrap()),
             channel_map: Arc::new(CHashMap::new()),
             channel_inbound_handler_ctx_pipe_map: Arc::new(CHashMap::new()),
+            pending_connects: Arc::new(Mutex::new(HashMap::new())),
+            allocator: PooledByteBufAllocator::default(),
             stopped: Arc::new(AtomicBool::new(false)),
         }
     }
+    pub fn allocator(&self) -> PooledByteBufAllocator {
+        self.allocator.clone()
+    }
+
     pub fn shutdown(&self) {
         self.stopped.store(true, Ordering::Relaxed);
     }
@@ -50,76 +71,401 @@ impl EventLoop {
     ) {
         let channel = ch.clone();
         let channel_2 = ch;
-        // 一个channel注册一个selector
-        {
-            let channel = channel.lock().unwrap();
-            channel.register(&self.selector);
-        }
         {
             ctx_inbound_ctx_pipe.head_channel_active();
         }
+        // 先放入 map 再注册, 否则注册之后马上到达的读事件会因为找不到 channel 而丢失
         self.channel_inbound_handler_ctx_pipe_map
             .insert_new(Token(id), ctx_inbound_ctx_pipe);
         self.channel_map.insert_new(Token(id), channel_2);
+        // 一个channel注册一个selector
+        {
+            let mut channel = channel.lock().unwrap();
+            channel.register(&self.selector);
+        }
+    }
+
+    ///
+    /// 客户端连接: 先放入 map 再注册到 selector, 连接建立之后才触发 channel_active
+    ///
+    pub(crate) fn attach_connecting(
+        &self,
+        id: usize,
+        ch: Arc<Mutex<Channel>>,
+        ctx_inbound_ctx_pipe: ChannelInboundHandlerCtxPipe,
+        connect_timeout_ms: u64,
+    ) {
+        self.channel_inbound_handler_ctx_pipe_map
+            .insert_new(Token(id), ctx_inbound_ctx_pipe);
+        self.channel_map.insert_new(Token(id), ch.clone());
+        self.pending_connects.lock().unwrap().insert(
+            Token(id),
+            Local::now().timestamp_millis() as u64 + connect_timeout_ms,
+        );
+        let mut channel = ch.lock().unwrap();
+        channel.register_connect(&self.selector);
+    }
+
+    ///
+    /// 处理客户端连接的就绪事件
+    ///
+    fn finish_connect(
+        selector: &Poll,
+        token: Token,
+        ch: Arc<Mutex<Channel>>,
+        channel_map: &CHashMap<Token, Arc<Mutex<Channel>>>,
+        channel_inbound_ctx_pipe_map: &CHashMap<Token, ChannelInboundHandlerCtxPipe>,
+        pending_connects: &Mutex<HashMap<Token, u64>>,
+    ) {
+        let ret = {
+            let mut ch = ch.lock().unwrap();
+            match ch.finish_connect(selector) {
+                Ok(connected) => Ok(connected),
+                Err(e) => {
+                    let e: RettyErrorKind = e.into();
+                    ch.abort_connect(selector, &e);
+                    Err(e)
+                }
+            }
+        };
+        notify_promises(&ch);
+        let ctx_pipe = match channel_inbound_ctx_pipe_map.get(&token) {
+            Some(pipe) => (*pipe).clone(),
+            None => return,
+        };
+        match ret {
+            Ok(false) => {}
+            Ok(true) => {
+                pending_connects.lock().unwrap().remove(&token);
+                ctx_pipe.head_channel_active();
+            }
+            Err(e) => {
+                pending_connects.lock().unwrap().remove(&token);
+                channel_map.remove(&token);
+                channel_inbound_ctx_pipe_map.remove(&token);
+                ctx_pipe.head_channel_exception(e);
+            }
+        }
+    }
+
+    ///
+    /// 连接超时的 channel 触发 channel_exception
+    ///
+    fn expire_connects(
+        selector: &Poll,
+        channel_map: &CHashMap<Token, Arc<Mutex<Channel>>>,
+        channel_inbound_ctx_pipe_map: &CHashMap<Token, ChannelInboundHandlerCtxPipe>,
+        pending_connects: &Mutex<HashMap<Token, u64>>,
+    ) {
+        let now = Local::now().timestamp_millis() as u64;
+        let expired: Vec<Token> = {
+            let mut pending = pending_connects.lock().unwrap();
+            let expired = pending
+                .iter()
+                .filter(|(_, deadline)| **deadline <= now)
+                .map(|(token, _)| *token)
+                .collect::<Vec<Token>>();
+            expired.iter().for_each(|token| {
+                pending.remove(token);
+            });
+            expired
+        };
+        for token in expired {
+            let err = RettyErrorKind::new(ErrorKind::TimedOut, "ConnectTimeout".to_string());
+            if let Some(ch) = channel_map.remove(&token) {
+                ch.lock().unwrap().abort_connect(selector, &err);
+                notify_promises(&ch);
+            }
+            if let Some(ctx_pipe) = channel_inbound_ctx_pipe_map.remove(&token) {
+                ctx_pipe.head_channel_exception(err);
+            }
+        }
+    }
+
+    ///
+    /// UDP channel 循环读数据报直到 WouldBlock, 每个数据报触发一次 channel_read, 最后触发一次 channel_read_complete
+    /// 和 TCP 一样受 max_messages_per_read / max_bytes_per_read 限制, 返回 true 表示 socket 里可能还有数据, 下一轮接着读
+    ///
+    fn read_datagrams(
+        token: Token,
+        ch: &Arc<Mutex<Channel>>,
+        channel_inbound_ctx_pipe_map: &CHashMap<Token, ChannelInboundHandlerCtxPipe>,
+        read_buf: &mut Vec<u8>,
+    ) -> bool {
+        let mut packets = Vec::<DatagramPacket>::new();
+        let mut read_pending = false;
+        let err = {
+            let mut ch = ch.lock().unwrap();
+            let local_addr = ch.local_addr().ok();
+            if read_buf.len() < MAX_DATAGRAM_SIZE {
+                read_buf.resize(MAX_DATAGRAM_SIZE, 0);
+            }
+            ch.recv_buf_allocator_handle().reset();
+            loop {
+                match ch.recv_from(&mut read_buf[..MAX_DATAGRAM_SIZE]) {
+                    Ok((n, sender)) => {
+                        let content = ByteBuf::new_from(&read_buf[..n]);
+                        packets.push(DatagramPacket::received(content, sender, local_addr));
+                        let handle = ch.recv_buf_allocator_handle();
+                        handle.last_datagram_read(n);
+                        if !handle.continue_reading() {
+                            // 到了上限, socket 里可能还有数据
+                            read_pending = true;
+                            break None;
+                        }
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break None,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(e) => break Some(e),
+                }
+            }
+        };
+        let ctx_pipe = match channel_inbound_ctx_pipe_map.get(&token) {
+            Some(pipe) => (*pipe).clone(),
+            None => return false,
+        };
+        let read_any = !packets.is_empty();
+        for mut packet in packets {
+            ctx_pipe.head_channel_read(&mut packet);
+        }
+        if read_any {
+            ctx_pipe.head_channel_read_complete();
+        }
+        if let Some(err) = err {
+            ctx_pipe.head_channel_exception(err.into());
+        }
+        read_pending
+    }
+
+    ///
+    /// 按接收缓冲区分配器给出的大小循环读到 WouldBlock, 每读一次触发一次 channel_read, 这一批读完之后触发 channel_read_complete
+    /// 读的次数或字节数到了上限就让出 eventloop, 返回 true 表示 socket 里可能还有数据, 下一轮接着读
+    ///
+    fn read_channel(
+        token: Token,
+        ch: &Arc<Mutex<Channel>>,
+        channel_map: &CHashMap<Token, Arc<Mutex<Channel>>>,
+        channel_inbound_ctx_pipe_map: &CHashMap<Token, ChannelInboundHandlerCtxPipe>,
+        read_buf: &mut Vec<u8>,
+    ) -> bool {
+        let ctx_pipe = match channel_inbound_ctx_pipe_map.get(&token) {
+            Some(pipe) => (*pipe).clone(),
+            None => return false,
+        };
+        ch.lock().unwrap().begin_read();
+        let mut read_pending = false;
+        let mut read_any = false;
+        let (closed, err) = loop {
+            let (ret, attempted) = {
+                let mut ch = ch.lock().unwrap();
+                let attempted = ch.recv_buf_allocator_handle().guess();
+                if read_buf.len() < attempted {
+                    read_buf.resize(attempted, 0);
+                }
+                let ret = ch.read(&mut read_buf[..attempted]);
+                if let Ok(0) = ret {
+                    ch.close();
+                }
+                if ch.is_closed() {
+                    channel_map.remove(&token);
+                    break (true, None);
+                }
+                (ret, attempted)
+            };
+            match ret {
+                Ok(n) => {
+                    let continue_reading = {
+                        let mut ch = ch.lock().unwrap();
+                        let handle = ch.recv_buf_allocator_handle();
+                        handle.last_bytes_read(attempted, n);
+                        handle.continue_reading()
+                    };
+                    // ByteBuf 只能从 slice 复制构造, 读缓冲区本身是复用的
+                    let mut bytebuf = ByteBuf::new_from(&read_buf[..n]);
+                    ctx_pipe.head_channel_read(&mut bytebuf);
+                    read_any = true;
+                    if !continue_reading {
+                        // 到了上限, socket 里可能还有数据
+                        read_pending = true;
+                        break (false, None);
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break (false, None),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => break (false, Some(e)),
+            }
+        };
+        if read_any {
+            // 在 end_read 之前, 这里的 flush 也和这一批的 flush 合并
+            ctx_pipe.head_channel_read_complete();
+        }
+        let flush_deferred = ch.lock().unwrap().end_read();
+        if flush_deferred && !closed {
+            ctx_pipe.flush_outbound();
+        }
+        notify_promises(ch);
+        if closed {
+            ctx_pipe.head_channel_inactive();
+        } else if let Some(err) = err {
+            let error: RettyErrorKind = err.into();
+            ctx_pipe.head_channel_exception(error);
+        }
+        read_pending
+    }
+
+    ///
+    /// 可写事件: 继续写出站缓冲区中剩余的数据
+    ///
+    fn flush_channel(
+        token: Token,
+        ch: &Arc<Mutex<Channel>>,
+        channel_inbound_ctx_pipe_map: &CHashMap<Token, ChannelInboundHandlerCtxPipe>,
+    ) {
+        let ret = ch.lock().unwrap().flush_outbound();
+        notify_promises(ch);
+        if let Err(e) = ret {
+            if let Some(pipe) = channel_inbound_ctx_pipe_map.get(&token) {
+                let ctx_pipe = (*pipe).clone();
+                drop(pipe);
+                ctx_pipe.head_channel_exception(e.into());
+            }
+        }
+    }
+
+    fn notify_writability_changed(
+        token: Token,
+        ch: &Arc<Mutex<Channel>>,
+        channel_inbound_ctx_pipe_map: &CHashMap<Token, ChannelInboundHandlerCtxPipe>,
+    ) {
+        let changed = ch.lock().unwrap().take_writability_changed();
+        if changed {
+            if let Some(pipe) = channel_inbound_ctx_pipe_map.get(&token) {
+                let ctx_pipe = (*pipe).clone();
+                drop(pipe);
+                ctx_pipe.head_channel_writability_changed();
+            }
+        }
     }
 
     pub(crate) fn run(&self) {
         let selector = Arc::clone(&self.selector);
         let channel_map = Arc::clone(&self.channel_map);
         let channel_inbound_ctx_pipe_map = Arc::clone(&self.channel_inbound_handler_ctx_pipe_map);
+        let pending_connects = Arc::clone(&self.pending_connects);
         let stopped = Arc::clone(&self.stopped);
 
         self.excutor.spawn(move || {
             let mut events = Events::with_capacity(1024);
+            // 同一个 eventloop 上的 channel 共用的读缓冲区
+            let mut read_buf: Vec<u8> = Vec::new();
+            // 因为读到上限而让出的 channel, 下一轮不等事件继续读
+            let mut read_pending: Vec<Token> = Vec::new();
             while !stopped.load(Ordering::Relaxed) {
-                selector
-                    .poll(&mut events, Some(Duration::from_millis(200)))
-                    .unwrap();
+                let timeout = if read_pending.is_empty() {
+                    Duration::from_millis(200)
+                } else {
+                    Duration::from_millis(0)
+                };
+                selector.poll(&mut events, Some(timeout)).unwrap();
+                let mut read_retry = std::mem::take(&mut read_pending);
 
                 for e in events.iter() {
-                    let channel = match channel_map.remove(&e.token()) {
-                        Some(ch) => {
-                            let mut buf: Vec<u8> = Vec::with_capacity(65535);
-                            let ch_clone = ch.clone();
-                            let mut ch = ch.lock().unwrap();
-                            let ch_ret = match ch.read(&mut buf) {
-                                Ok(0) => {
-                                    ch.close();
-                                    None
-                                }
-                                Ok(_) => None,
-                                Err(e) if e.kind() == ErrorKind::WouldBlock => None,
-                                Err(e) => Some(e),
-                            };
-                            if !ch.is_closed() {
-                                channel_map.insert_new(e.token(), ch_clone);
-                            }
-                            Some((ch.clone(), buf.clone(), ch_ret))
-                        }
-                        None => None,
+                    let token = e.token();
+                    let ch = match channel_map.get(&token) {
+                        Some(ch) => (*ch).clone(),
+                        None => continue,
+                    };
+                    let (is_connecting, is_datagram) = {
+                        let ch = ch.lock().unwrap();
+                        (ch.is_connecting(), ch.is_datagram())
                     };
-                    if let Some((ch, buf, err)) = channel {
-                        if ch.is_closed() {
-                            {
-                                let ctx_pipe =
-                                    channel_inbound_ctx_pipe_map.get_mut(&e.token()).unwrap();
-                                ctx_pipe.head_channel_inactive();
-                            }
+                    if is_connecting {
+                        EventLoop::finish_connect(
+                            &selector,
+                            token,
+                            ch,
+                            &channel_map,
+                            &channel_inbound_ctx_pipe_map,
+                            &pending_connects,
+                        );
+                        continue;
+                    }
+                    if is_datagram {
+                        read_retry.retain(|t| *t != token);
+                        if EventLoop::read_datagrams(
+                            token,
+                            &ch,
+                            &channel_inbound_ctx_pipe_map,
+                            &mut read_buf,
+                        ) {
+                            read_pending.push(token);
+                        }
+                        continue;
+                    }
+                    let readiness = e.readiness();
+                    if readiness.is_writable() {
+                        EventLoop::flush_channel(token, &ch, &channel_inbound_ctx_pipe_map);
+                    }
+                    let unix_readiness = UnixReady::from(readiness);
+                    if readiness.is_readable()
+                        || unix_readiness.is_hup()
+                        || unix_readiness.is_error()
+                    {
+                        read_retry.retain(|t| *t != token);
+                        if EventLoop::read_channel(
+                            token,
+                            &ch,
+                            &channel_map,
+                            &channel_inbound_ctx_pipe_map,
+                            &mut read_buf,
+                        ) {
+                            read_pending.push(token);
                         }
-                        if !ch.is_closed() {
-                            if let Some(err) = err {
-                                let ctx_pipe =
-                                    channel_inbound_ctx_pipe_map.get_mut(&e.token()).unwrap();
-                                let error: RettyErrorKind = err.into();
-                                ctx_pipe.head_channel_exception(error);
-                            } else {
-                                let mut bytebuf = ByteBuf::new_from(&buf[..]);
-                                let ctx_pipe =
-                                    channel_inbound_ctx_pipe_map.get_mut(&e.token()).unwrap();
-                                ctx_pipe.head_channel_read(&mut bytebuf);
-                            }
+                    }
+                    EventLoop::notify_writability_changed(
+                        token,
+                        &ch,
+                        &channel_inbound_ctx_pipe_map,
+                    );
+                }
+                for token in read_retry {
+                    let ch = match channel_map.get(&token) {
+                        Some(ch) => (*ch).clone(),
+                        None => continue,
+                    };
+                    if ch.lock().unwrap().is_datagram() {
+                        if EventLoop::read_datagrams(
+                            token,
+                            &ch,
+                            &channel_inbound_ctx_pipe_map,
+                            &mut read_buf,
+                        ) {
+                            read_pending.push(token);
                         }
+                        continue;
+                    }
+                    if EventLoop::read_channel(
+                        token,
+                        &ch,
+                        &channel_map,
+                        &channel_inbound_ctx_pipe_map,
+                        &mut read_buf,
+                    ) {
+                        read_pending.push(token);
                     }
+                    EventLoop::notify_writability_changed(
+                        token,
+                        &ch,
+                        &channel_inbound_ctx_pipe_map,
+                    );
+                }
+                if !pending_connects.lock().unwrap().is_empty() {
+                    EventLoop::expire_connects(
+                        &selector,
+                        &channel_map,
+                        &channel_inbound_ctx_pipe_map,
+                        &pending_connects,
+                    );
                 }
             }
         });
@@ -179,3 +525,214 @@ impl EventLoopGroup {
         &self.group
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::any::Any;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    use super::*;
+    use crate::channel::channel_handler_ctx::{
+        ChannelInboundHandlerCtx, ChannelOutboundHandlerCtx,
+    };
+    use crate::channel::flush::flush_consolidation_handler::FlushConsolidationHandler;
+    use crate::channel::handler::{ChannelInboundHandler, ChannelOutboundHandler};
+    use crate::channel::handler_pipe::ChannelHandlerPipe;
+    use crate::core::bootstrap::Bootstrap;
+    use crate::transport::recv_buf_allocator::RecvByteBufAllocator;
+
+    type Log = Arc<Mutex<Vec<String>>>;
+
+    // 记录入站事件, 读到的数据原样写回, 读完之后再写一个 "!"
+    struct Echo {
+        log: Log,
+    }
+
+    impl ChannelInboundHandler for Echo {
+        fn id(&self) -> String {
+            "Echo".to_string()
+        }
+
+        fn channel_active(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+            channel_handler_ctx.fire_channel_active();
+        }
+
+        fn channel_inactive(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+            channel_handler_ctx.fire_channel_inactive();
+        }
+
+        fn channel_read(
+            &mut self,
+            channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+            message: &mut dyn Any,
+        ) {
+            let buf = message.downcast_ref::<ByteBuf>().unwrap();
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("read {}", buf.readable_bytes()));
+            channel_handler_ctx.write_and_flush(&mut ByteBuf::new_from(buf.available_bytes()));
+        }
+
+        fn channel_exception(
+            &mut self,
+            channel_handler_ctx: &mut ChannelInboundHandlerCtx,
+            error: RettyErrorKind,
+        ) {
+            channel_handler_ctx.fire_channel_exception(error);
+        }
+
+        fn channel_read_complete(&mut self, channel_handler_ctx: &mut ChannelInboundHandlerCtx) {
+            self.log.lock().unwrap().push("read complete".to_string());
+            channel_handler_ctx.write_and_flush(&mut ByteBuf::new_from(b"!"));
+        }
+    }
+
+    // 最靠近 socket, 记录真正往下传的 flush
+    struct FlushLog {
+        log: Log,
+    }
+
+    impl ChannelOutboundHandler for FlushLog {
+        fn id(&self) -> String {
+            "FlushLog".to_string()
+        }
+
+        fn channel_write(
+            &mut self,
+            channel_handler_ctx: &mut ChannelOutboundHandlerCtx,
+            message: &mut dyn Any,
+        ) {
+            channel_handler_ctx.fire_channel_write(message);
+        }
+
+        fn channel_flush(&mut self, channel_handler_ctx: &mut ChannelOutboundHandlerCtx) {
+            self.log.lock().unwrap().push("flush".to_string());
+            channel_handler_ctx.fire_channel_flush();
+        }
+    }
+
+    struct Connection {
+        channel: Arc<Mutex<Channel>>,
+        channel_map: CHashMap<Token, Arc<Mutex<Channel>>>,
+        pipe_map: CHashMap<Token, ChannelInboundHandlerCtxPipe>,
+        peer: TcpStream,
+        log: Log,
+    }
+
+    impl Connection {
+        // 每次最多读 4 个字节, 方便一批读多次
+        fn new(max_messages_per_read: usize) -> Connection {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            peer.set_read_timeout(Some(Duration::from_millis(100)))
+                .unwrap();
+            let (stream, _) = listener.accept().unwrap();
+            let stream = mio::net::TcpStream::from_stream(stream).unwrap();
+
+            let mut allocator = RecvByteBufAllocator::new_fixed(4);
+            allocator.max_messages_per_read(max_messages_per_read);
+            let event_loop = Arc::new(EventLoop::new(0));
+            let channel = Arc::new(Mutex::new(Channel::create(
+                Token(1),
+                allocator.opts().into_iter().collect(),
+                event_loop.clone(),
+                stream,
+            )));
+            let log: Log = Arc::new(Mutex::new(vec![]));
+            let pipe_log = log.clone();
+            let (inbound_pipe, _) = Bootstrap::create_channel_ctx_pipes(
+                Arc::new(move || {
+                    let mut handler_pipe = ChannelHandlerPipe::new();
+                    handler_pipe.add_last_outbound(Box::new(FlushLog {
+                        log: pipe_log.clone(),
+                    }));
+                    handler_pipe.add_last_outbound(Box::new(FlushConsolidationHandler::new()));
+                    handler_pipe.add_last_inbound(Box::new(Echo {
+                        log: pipe_log.clone(),
+                    }));
+                    handler_pipe
+                }),
+                event_loop,
+                channel.clone(),
+            );
+            let channel_map = CHashMap::new();
+            channel_map.insert(Token(1), channel.clone());
+            let pipe_map = CHashMap::new();
+            pipe_map.insert(Token(1), inbound_pipe);
+            Connection {
+                channel,
+                channel_map,
+                pipe_map,
+                peer,
+                log,
+            }
+        }
+
+        // 对端写入 bytes, 然后像 eventloop 收到一个可读事件一样读一批
+        fn read_batch(&mut self, bytes: &[u8]) -> bool {
+            self.peer.write_all(bytes).unwrap();
+            thread::sleep(Duration::from_millis(50));
+            let mut read_buf = vec![];
+            EventLoop::read_channel(
+                Token(1),
+                &self.channel,
+                &self.channel_map,
+                &self.pipe_map,
+                &mut read_buf,
+            )
+        }
+
+        fn take_log(&self) -> Vec<String> {
+            std::mem::take(&mut *self.log.lock().unwrap())
+        }
+
+        fn read_peer(&mut self) -> Vec<u8> {
+            let mut bytes = vec![];
+            let mut buf = [0u8; 1024];
+            while let Ok(n) = self.peer.read(&mut buf) {
+                if n == 0 {
+                    break;
+                }
+                bytes.extend_from_slice(&buf[..n]);
+            }
+            bytes
+        }
+    }
+
+    #[test]
+    fn read_complete_fires_once_per_batch_after_all_reads_and_before_the_flush() {
+        let mut connection = Connection::new(16);
+        assert!(!connection.read_batch(b"0123456789"));
+        assert_eq!(
+            connection.take_log(),
+            vec!["read 4", "read 4", "read 2", "read complete", "flush"]
+        );
+        assert_eq!(connection.read_peer(), b"0123456789!");
+
+        assert!(!connection.read_batch(b"ab"));
+        assert_eq!(
+            connection.take_log(),
+            vec!["read 2", "read complete", "flush"]
+        );
+        assert_eq!(connection.read_peer(), b"ab!");
+    }
+
+    #[test]
+    fn batch_cut_short_by_max_messages_per_read_still_completes_once() {
+        let mut connection = Connection::new(2);
+        // socket 里还有数据, 下一轮接着读
+        assert!(connection.read_batch(b"0123456789"));
+        assert_eq!(
+            connection.take_log(),
+            vec!["read 4", "read 4", "read complete", "flush"]
+        );
+        assert!(!connection.read_batch(b""));
+        assert_eq!(
+            connection.take_log(),
+            vec!["read 2", "read complete", "flush"]
+        );
+        assert_eq!(connection.read_peer(), b"01234567!89!");
+    }
+}
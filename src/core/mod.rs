@@ -1,2 +1,3 @@
 pub mod bootstrap;
+pub mod client_bootstrap;
 pub mod eventloop;
@@ -6,11 +6,32 @@ use std::io::{Error as ioError, ErrorKind};
 pub struct RettyErrorKind {
     pub kind: ErrorKind,
     pub message: String,
+    codec_kind: Option<CodecErrorKind>,
+}
+
+///
+/// 编解码错误
+///
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum CodecErrorKind {
+    /// 帧长度超过了解码器允许的最大长度
+    TooLongFrame,
+    /// 帧格式错误, 比如长度字段的值不合法
+    CorruptedFrame,
+    /// 字节不符合字符集的编码, 或者字符集不能表示某个字符
+    MalformedInput,
+    /// 协议握手失败, 比如 WebSocket 服务端的响应不对
+    HandshakeFailed,
+    /// WebSocket 连接没有以 1000 正常关闭, 值为对端关闭帧的状态码, 没有完成关闭握手时为 1006
+    WebSocketClosed(u16),
 }
 
 impl Display for RettyErrorKind {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?} , {}", self.kind, self.message)
+        match self.codec_kind {
+            Some(codec_kind) => write!(f, "{:?} , {}", codec_kind, self.message),
+            None => write!(f, "{:?} , {}", self.kind, self.message),
+        }
     }
 }
 
@@ -21,12 +42,39 @@ impl From<ioError> for RettyErrorKind {
         RettyErrorKind {
             kind: e.kind(),
             message: e.to_string(),
+            codec_kind: None,
         }
     }
 }
 
 impl RettyErrorKind {
     pub fn new(kind: ErrorKind, message: String) -> Self {
-        RettyErrorKind { kind, message }
+        RettyErrorKind {
+            kind,
+            message,
+            codec_kind: None,
+        }
+    }
+
+    ///
+    /// 编解码错误, kind 为 InvalidData
+    ///
+    pub fn codec(codec_kind: CodecErrorKind, message: String) -> Self {
+        RettyErrorKind {
+            kind: ErrorKind::InvalidData,
+            message,
+            codec_kind: Some(codec_kind),
+        }
+    }
+
+    ///
+    /// 编解码器产生的错误的具体类型, 其他错误为 None
+    ///
+    pub fn codec_kind(&self) -> Option<CodecErrorKind> {
+        self.codec_kind
+    }
+
+    pub fn is_codec_error(&self, codec_kind: CodecErrorKind) -> bool {
+        self.codec_kind == Some(codec_kind)
     }
 }
@@ -1,6 +1,7 @@
 #![warn(rust_2018_idioms)]
 #![allow(dead_code)]
 
+pub mod buffer;
 pub mod channel;
 pub mod core;
 pub mod errors;
@@ -1,6 +1,6 @@
 use std::any::Any;
 use std::collections::HashMap;
-use std::io::{Read, Result, Write};
+use std::io::{ErrorKind, Read, Result, Write};
 use std::net::Shutdown;
 use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
@@ -9,10 +9,21 @@ use std::time::Duration;
 use bytebuf_rs::bytebuf::ByteBuf;
 use chashmap::CHashMap;
 use crossbeam::channel::{bounded, Receiver, Sender};
-use mio::net::TcpStream;
-use mio::{Poll, PollOpt, Ready, Token};
+use mio::net::{TcpStream, UdpSocket};
+use mio::{Poll, Ready, Token};
+use mio_uds::UnixStream;
 
+use crate::channel::channel_future::ChannelFuture;
 use crate::core::eventloop::EventLoop;
+use crate::errors::RettyErrorKind;
+use crate::transport::datagram::DatagramPacket;
+use crate::transport::outbound_buffer::{
+    ChannelOutboundBuffer, DEFAULT_WRITE_BUFFER_HIGH_WATER_MARK,
+    DEFAULT_WRITE_BUFFER_LOW_WATER_MARK,
+};
+use crate::transport::recv_buf_allocator::{RecvByteBufAllocator, RecvByteBufAllocatorHandle};
+use crate::transport::stream::ChannelStream;
+use crate::transport::unix::PeerCredentials;
 
 #[derive(Clone)]
 pub enum ChannelOptions {
@@ -22,13 +33,28 @@ pub enum ChannelOptions {
 
 pub struct Channel {
     id: Token,
-    stream: TcpStream,
+    stream: ChannelStream,
     closed: bool,
     eventloop: Arc<EventLoop>,
     attribute: CHashMap<String, Arc<Mutex<Box<dyn Any + Send + Sync>>>>,
     inner_ch: (Sender<bool>, Receiver<bool>),
     last_read_time_ms: u64,
     read_idle_timeout_ms: u64,
+    connecting: bool,
+    outbound_buffer: ChannelOutboundBuffer,
+    // 是否已经注册到 selector, 以及是否注册了可写事件
+    registered: bool,
+    writable_interest: bool,
+    // 经过 TailHandler 写入的消息数, 以及最近一次写失败的原因, 用来完成 write_and_flush 的 future
+    written_messages: u64,
+    write_error: Option<RettyErrorKind>,
+    connect_promise: Option<ChannelFuture>,
+    completed_promises: Vec<(ChannelFuture, std::result::Result<(), RettyErrorKind>)>,
+    // eventloop 正在分发读到的数据, 以及分发期间被推迟到读完之后的 flush
+    read_in_progress: bool,
+    flush_deferred: bool,
+    recv_buf_allocator: RecvByteBufAllocator,
+    recv_buf_allocator_handle: RecvByteBufAllocatorHandle,
 }
 
 impl Clone for Channel {
@@ -42,6 +68,21 @@ impl Clone for Channel {
             inner_ch: self.inner_ch.clone(),
             last_read_time_ms: self.last_read_time_ms,
             read_idle_timeout_ms: self.read_idle_timeout_ms,
+            connecting: self.connecting,
+            outbound_buffer: ChannelOutboundBuffer::new(
+                DEFAULT_WRITE_BUFFER_HIGH_WATER_MARK,
+                DEFAULT_WRITE_BUFFER_LOW_WATER_MARK,
+            ),
+            registered: self.registered,
+            writable_interest: self.writable_interest,
+            written_messages: self.written_messages,
+            write_error: None,
+            connect_promise: None,
+            completed_promises: vec![],
+            read_in_progress: false,
+            flush_deferred: false,
+            recv_buf_allocator: self.recv_buf_allocator.clone(),
+            recv_buf_allocator_handle: self.recv_buf_allocator.new_handle(),
         }
     }
 
@@ -110,18 +151,123 @@ impl Channel {
                 _ => {}
             }
         }
+        Channel::new(
+            id,
+            ChannelStream::Tcp(tcp_stream),
+            eventloop,
+            &opts,
+            read_idle_timeout_ms,
+        )
+    }
+
+    ///
+    /// 创建 UDP channel, 一个绑定的 socket 对应一个 channel
+    ///
+    pub fn create_datagram(
+        id: Token,
+        opts: HashMap<String, ChannelOptions>,
+        eventloop: Arc<EventLoop>,
+        socket: UdpSocket,
+    ) -> Channel {
+        for (k, ref v) in opts.iter() {
+            match k.as_ref() {
+                "ttl" => match v {
+                    ChannelOptions::NUMBER(ttl) => {
+                        socket.set_ttl(*ttl as u32).unwrap();
+                    }
+                    ChannelOptions::BOOL(_) => {}
+                },
+                "broadcast" => match v {
+                    ChannelOptions::NUMBER(_) => {}
+                    ChannelOptions::BOOL(b) => {
+                        socket.set_broadcast(*b).unwrap();
+                    }
+                },
+                _ => {}
+            }
+        }
+        Channel::new(id, ChannelStream::Udp(socket), eventloop, &opts, 50000u64)
+    }
+
+    ///
+    /// 创建 unix domain socket channel
+    ///
+    pub fn create_unix(
+        id: Token,
+        opts: HashMap<String, ChannelOptions>,
+        eventloop: Arc<EventLoop>,
+        stream: UnixStream,
+    ) -> Channel {
+        let mut read_idle_timeout_ms = 50000u64; // 50 secs
+        if let Some(ChannelOptions::NUMBER(read_idle_timeout)) = opts.get("read_idle_timeout_ms") {
+            read_idle_timeout_ms = *read_idle_timeout as u64;
+        }
+        Channel::new(
+            id,
+            ChannelStream::Unix(stream),
+            eventloop,
+            &opts,
+            read_idle_timeout_ms,
+        )
+    }
+
+    fn new(
+        id: Token,
+        stream: ChannelStream,
+        eventloop: Arc<EventLoop>,
+        opts: &HashMap<String, ChannelOptions>,
+        read_idle_timeout_ms: u64,
+    ) -> Channel {
+        let mut high_water_mark = DEFAULT_WRITE_BUFFER_HIGH_WATER_MARK;
+        let mut low_water_mark = DEFAULT_WRITE_BUFFER_LOW_WATER_MARK;
+        if let Some(ChannelOptions::NUMBER(n)) = opts.get("write_buffer_high_water_mark") {
+            high_water_mark = *n;
+        }
+        if let Some(ChannelOptions::NUMBER(n)) = opts.get("write_buffer_low_water_mark") {
+            low_water_mark = *n;
+        }
+        let recv_buf_allocator = RecvByteBufAllocator::from_opts(opts);
         Channel {
             id,
-            stream: tcp_stream,
+            stream,
             closed: false,
             eventloop,
             attribute: CHashMap::new(),
             inner_ch: bounded(1024),
             last_read_time_ms: 0,
             read_idle_timeout_ms,
+            connecting: false,
+            outbound_buffer: ChannelOutboundBuffer::new(high_water_mark, low_water_mark),
+            registered: false,
+            writable_interest: false,
+            written_messages: 0,
+            write_error: None,
+            connect_promise: None,
+            completed_promises: vec![],
+            read_in_progress: false,
+            flush_deferred: false,
+            recv_buf_allocator_handle: recv_buf_allocator.new_handle(),
+            recv_buf_allocator,
         }
     }
 
+    pub(crate) fn create_from_stream(
+        id: Token,
+        opts: HashMap<String, ChannelOptions>,
+        eventloop: Arc<EventLoop>,
+        stream: ChannelStream,
+    ) -> Channel {
+        match stream {
+            ChannelStream::Tcp(s) => Channel::create(id, opts, eventloop, s),
+            ChannelStream::Udp(s) => Channel::create_datagram(id, opts, eventloop, s),
+            ChannelStream::Unix(s) => Channel::create_unix(id, opts, eventloop, s),
+        }
+    }
+
+    pub(crate) fn id(&self) -> Token {
+        self.id
+    }
+
     pub(crate) fn remote_addr(&self) -> Result<SocketAddr> {
         self.stream.peer_addr()
     }
@@ -130,9 +276,211 @@ impl Channel {
         self.stream.local_addr()
     }
 
-    pub(crate) fn write_bytebuf(&mut self, buf: &ByteBuf) {
-        let _ = self.stream.write(buf.available_bytes());
-        self.stream.flush().unwrap();
+    pub(crate) fn peer_credentials(&self) -> Result<PeerCredentials> {
+        self.stream.peer_credentials()
+    }
+
+    ///
+    /// 数据只放入出站缓冲区, flush 之后才写出, 写不完的部分等 selector 通知可写后继续写
+    ///
+    pub(crate) fn write_bytebuf(&mut self, buf: &ByteBuf) -> Result<()> {
+        let ret = self.enqueue_bytebuf(buf);
+        self.record_write(&ret);
+        ret
+    }
+
+    fn enqueue_bytebuf(&mut self, buf: &ByteBuf) -> Result<()> {
+        if self.closed {
+            return Err(std::io::Error::new(
+                ErrorKind::NotConnected,
+                "channel is closed",
+            ));
+        }
+        let bytes = self
+            .eventloop
+            .allocator
+            .allocate_from(buf.available_bytes());
+        self.outbound_buffer.add(bytes);
+        Ok(())
+    }
+
+    ///
+    /// 把出站缓冲区中已有的数据标记为 flush 并尽量写出
+    ///
+    pub(crate) fn flush(&mut self) -> Result<()> {
+        self.outbound_buffer.add_flush();
+        if self.connecting || self.closed {
+            // 连接建立之后再写
+            return Ok(());
+        }
+        self.flush_outbound()
+    }
+
+    ///
+    /// 把出站缓冲区中 flush 过的数据写入 socket, 没写完则注册可写事件, 写完则取消
+    ///
+    pub(crate) fn flush_outbound(&mut self) -> Result<()> {
+        let mut ret = self.outbound_buffer.write_to(&mut self.stream);
+        if ret.is_ok() {
+            ret = self.stream.flush();
+        }
+        if let Err(e) = &ret {
+            self.outbound_buffer
+                .clear(&RettyErrorKind::new(e.kind(), e.to_string()));
+        }
+        let interest_ret = self.update_write_interest();
+        ret.and(interest_ret)
+    }
+
+    fn update_write_interest(&mut self) -> Result<()> {
+        let want_writable = self.outbound_buffer.has_flushed();
+        if !self.registered
+            || self.connecting
+            || self.closed
+            || want_writable == self.writable_interest
+        {
+            return Ok(());
+        }
+        let interest = if want_writable {
+            Ready::readable() | Ready::writable()
+        } else {
+            Ready::readable()
+        };
+        self.stream
+            .reregister(&self.eventloop.selector, self.id, interest)?;
+        self.writable_interest = want_writable;
+        Ok(())
+    }
+
+    pub(crate) fn is_writable(&self) -> bool {
+        self.outbound_buffer.is_writable()
+    }
+
+    pub(crate) fn pending_outbound_bytes(&self) -> usize {
+        self.outbound_buffer.pending_bytes()
+    }
+
+    pub(crate) fn take_writability_changed(&mut self) -> bool {
+        self.outbound_buffer.take_writability_changed()
+    }
+
+    fn record_write<T>(&mut self, ret: &Result<T>) {
+        self.written_messages += 1;
+        if let Err(e) = ret {
+            self.write_error = Some(RettyErrorKind::new(e.kind(), e.to_string()));
+        }
+    }
+
+    ///
+    /// 出站 handler 编码失败时记录错误, 这次 write 的 future 以这个错误失败
+    ///
+    pub(crate) fn fail_write(&mut self, error: RettyErrorKind) {
+        self.write_error = Some(error);
+    }
+
+    ///
+    /// 开始一次 write_and_flush, 返回目前写入的消息数
+    ///
+    pub(crate) fn begin_write(&mut self) -> u64 {
+        self.write_error = None;
+        self.written_messages
+    }
+
+    ///
+    /// write_and_flush 经过出站 pipeline 之后调用:
+    /// 写失败或者没有消息到达 TailHandler 时 future 失败, 数据全部写出时成功, 否则等出站缓冲区写完
+    ///
+    pub(crate) fn finish_write(&mut self, written_messages: u64, promise: ChannelFuture) {
+        if let Some(e) = self.write_error.take() {
+            self.completed_promises.push((promise, Err(e)));
+        } else if self.written_messages == written_messages {
+            let e = RettyErrorKind::new(
+                ErrorKind::InvalidInput,
+                "no message reached the channel".to_string(),
+            );
+            self.completed_promises.push((promise, Err(e)));
+        } else {
+            self.outbound_buffer.add_promise(promise);
+        }
+    }
+
+    ///
+    /// eventloop 开始处理一个读事件
+    ///
+    pub(crate) fn begin_read(&mut self) {
+        self.read_in_progress = true;
+        self.recv_buf_allocator_handle.reset();
+    }
+
+    ///
+    /// 读事件处理完, 返回是否有被推迟的 flush
+    ///
+    pub(crate) fn end_read(&mut self) -> bool {
+        self.read_in_progress = false;
+        self.recv_buf_allocator_handle.read_complete();
+        self.take_deferred_flush()
+    }
+
+    pub(crate) fn recv_buf_allocator_handle(&mut self) -> &mut RecvByteBufAllocatorHandle {
+        &mut self.recv_buf_allocator_handle
+    }
+
+    pub(crate) fn is_read_in_progress(&self) -> bool {
+        self.read_in_progress
+    }
+
+    ///
+    /// 推迟到这一批数据读完之后再 flush, 见 FlushConsolidationHandler
+    ///
+    pub(crate) fn defer_flush(&mut self) {
+        self.flush_deferred = true;
+    }
+
+    pub(crate) fn take_deferred_flush(&mut self) -> bool {
+        let deferred = self.flush_deferred;
+        self.flush_deferred = false;
+        deferred
+    }
+
+    pub(crate) fn set_connect_promise(&mut self, promise: ChannelFuture) {
+        self.connect_promise = Some(promise);
+    }
+
+    ///
+    /// 取出已经完成的 future, 需要在释放 channel 锁之后再通知, 见 notify_promises
+    ///
+    pub(crate) fn take_completed_promises(
+        &mut self,
+    ) -> Vec<(ChannelFuture, std::result::Result<(), RettyErrorKind>)> {
+        let mut completed = std::mem::take(&mut self.completed_promises);
+        completed.append(&mut self.outbound_buffer.take_completed());
+        completed
+    }
+
+    pub(crate) fn send_datagram(&mut self, packet: &DatagramPacket) -> Result<usize> {
+        let ret = self.send_datagram_packet(packet);
+        self.record_write(&ret);
+        ret
+    }
+
+    fn send_datagram_packet(&mut self, packet: &DatagramPacket) -> Result<usize> {
+        match packet.recipient() {
+            Some(target) => self
+                .stream
+                .send_to(packet.content().available_bytes(), &target),
+            None => Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "DatagramPacket has no recipient",
+            )),
+        }
+    }
+
+    pub(crate) fn recv_from(&mut self, buf: &mut [u8]) -> Result<(usize, SocketAddr)> {
+        self.stream.recv_from(buf)
+    }
+
+    pub(crate) fn is_datagram(&self) -> bool {
+        self.stream.is_datagram()
     }
 
     pub(crate) fn set_last_read_time(&mut self, ms: u64) {
@@ -147,18 +495,85 @@ impl Channel {
         self.read_idle_timeout_ms
     }
 
-    pub fn register(&self, poll: &Poll) {
-        poll.register(&self.stream, self.id, Ready::readable(), PollOpt::edge())
+    pub fn register(&mut self, poll: &Poll) {
+        // channel_active 中写入但还没写完的数据需要可写事件
+        self.writable_interest = self.outbound_buffer.has_flushed();
+        let interest = if self.writable_interest {
+            Ready::readable() | Ready::writable()
+        } else {
+            Ready::readable()
+        };
+        self.stream.register(poll, self.id, interest).unwrap();
+        self.registered = true;
+    }
+
+    ///
+    /// 客户端发起连接时注册读写事件, 连接完成时 selector 会通知可写
+    ///
+    pub(crate) fn register_connect(&mut self, poll: &Poll) {
+        self.connecting = true;
+        self.stream
+            .register(poll, self.id, Ready::readable() | Ready::writable())
             .unwrap();
+        self.registered = true;
+    }
+
+    ///
+    /// 检查非阻塞连接的结果, Ok(true) 表示连接已建立, Ok(false) 表示仍在连接中
+    ///
+    pub(crate) fn finish_connect(&mut self, poll: &Poll) -> Result<bool> {
+        if let Some(e) = self.stream.take_error()? {
+            return Err(e);
+        }
+        if !self.stream.check_connected()? {
+            return Ok(false);
+        }
+        self.connecting = false;
+        if let Some(promise) = self.connect_promise.take() {
+            self.completed_promises.push((promise, Ok(())));
+        }
+        self.writable_interest = self.outbound_buffer.has_flushed();
+        let interest = if self.writable_interest {
+            Ready::readable() | Ready::writable()
+        } else {
+            Ready::readable()
+        };
+        self.stream.reregister(poll, self.id, interest)?;
+        Ok(true)
     }
 
-    pub fn read(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
-        self.stream.read_to_end(buf)
+    ///
+    /// 连接失败或超时, 从 selector 上注销并标记为关闭
+    ///
+    pub(crate) fn abort_connect(&mut self, poll: &Poll, cause: &RettyErrorKind) {
+        let _ = self.stream.deregister(poll);
+        self.connecting = false;
+        self.closed = true;
+        if let Some(promise) = self.connect_promise.take() {
+            self.completed_promises.push((promise, Err(cause.clone())));
+        }
+        self.outbound_buffer.clear(cause);
+    }
+
+    pub(crate) fn is_connecting(&self) -> bool {
+        self.connecting
+    }
+
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.stream.read(buf)
     }
 
+    ///
+    /// 关闭之后出站缓冲区中没写完的数据被丢弃, 对应的 future 失败
+    ///
     pub fn close(&mut self) {
-        self.stream.shutdown(Shutdown::Both).unwrap();
+        let _ = self.stream.shutdown(Shutdown::Both);
         self.closed = true;
+        let cause = RettyErrorKind::new(ErrorKind::NotConnected, "channel is closed".to_string());
+        if let Some(promise) = self.connect_promise.take() {
+            self.completed_promises.push((promise, Err(cause.clone())));
+        }
+        self.outbound_buffer.clear(&cause);
     }
 
     pub fn is_closed(&self) -> bool {
@@ -166,6 +581,27 @@ impl Channel {
     }
 }
 
+///
+/// 在 channel 锁之外完成 future, listener 里可以再操作 channel(比如写完之后关闭)
+///
+pub(crate) fn notify_promises(channel: &Arc<Mutex<Channel>>) {
+    let completed = channel.lock().unwrap().take_completed_promises();
+    for (promise, result) in completed {
+        promise.complete(result);
+    }
+}
+
+///
+/// 关闭 channel 并返回已经完成的 future
+///
+pub(crate) fn close_channel(channel: &Arc<Mutex<Channel>>) -> ChannelFuture {
+    channel.lock().unwrap().close();
+    notify_promises(channel);
+    let future = ChannelFuture::new(Arc::downgrade(channel));
+    future.complete(Ok(()));
+    future
+}
+
 ///
 /// 暴露channel 用
 ///
@@ -203,14 +639,34 @@ impl InboundChannelCtx {
         channel.local_addr()
     }
 
+    ///
+    /// unix domain socket 对端的 uid / gid / pid
+    ///
+    pub fn peer_credentials(&self) -> Result<PeerCredentials> {
+        let channel = self.channel.lock().unwrap();
+        channel.peer_credentials()
+    }
+
     pub fn is_active(&self) -> bool {
         let channel = self.channel.lock().unwrap();
         !channel.is_closed()
     }
 
+    ///
+    /// 出站缓冲区超过高水位时返回 false, 生产者应该暂停写入直到 channel_writability_changed
+    ///
+    pub fn is_writable(&self) -> bool {
+        let channel = self.channel.lock().unwrap();
+        channel.is_writable()
+    }
+
+    pub fn pending_outbound_bytes(&self) -> usize {
+        let channel = self.channel.lock().unwrap();
+        channel.pending_outbound_bytes()
+    }
+
     pub fn close(&mut self) {
-        let mut channel = self.channel.lock().unwrap();
-        channel.close()
+        close_channel(&self.channel);
     }
 
     pub(crate) fn set_last_read_time(&mut self, ms: u64) {
@@ -243,9 +699,48 @@ impl OutboundChannelCtx {
         format!("{}", channel.id.0)
     }
 
-    pub(crate) fn write_bytebuf(&mut self, buf: &ByteBuf) {
+    pub(crate) fn write_bytebuf(&mut self, buf: &ByteBuf) -> Result<()> {
+        let mut channel = self.channel.lock().unwrap();
+        channel.write_bytebuf(buf)
+    }
+
+    pub(crate) fn flush(&mut self) -> Result<()> {
+        let mut channel = self.channel.lock().unwrap();
+        channel.flush()
+    }
+
+    pub(crate) fn send_datagram(&mut self, packet: &DatagramPacket) -> Result<usize> {
+        let mut channel = self.channel.lock().unwrap();
+        channel.send_datagram(packet)
+    }
+
+    ///
+    /// 出站 handler 处理消息失败时调用, 消息不再往下写, write 返回的 future 以 error 失败
+    ///
+    pub fn fail_write(&mut self, error: RettyErrorKind) {
+        let mut channel = self.channel.lock().unwrap();
+        channel.fail_write(error)
+    }
+
+    ///
+    /// eventloop 是否正在分发读到的数据, 这期间的 flush 可以合并到读完之后
+    ///
+    pub fn is_read_in_progress(&self) -> bool {
+        let channel = self.channel.lock().unwrap();
+        channel.is_read_in_progress()
+    }
+
+    pub(crate) fn defer_flush(&mut self) {
         let mut channel = self.channel.lock().unwrap();
-        channel.write_bytebuf(buf);
+        channel.defer_flush()
+    }
+
+    ///
+    /// 马上 flush 时取消之前推迟的 flush, 读完之后不用再 flush 一次
+    ///
+    pub(crate) fn cancel_deferred_flush(&mut self) {
+        let mut channel = self.channel.lock().unwrap();
+        channel.take_deferred_flush();
     }
 
     pub fn remote_addr(&self) -> Result<SocketAddr> {
@@ -262,4 +757,17 @@ impl OutboundChannelCtx {
         let channel = self.channel.lock().unwrap();
         !channel.is_closed()
     }
+
+    ///
+    /// 出站缓冲区超过高水位时返回 false, 生产者应该暂停写入直到 channel_writability_changed
+    ///
+    pub fn is_writable(&self) -> bool {
+        let channel = self.channel.lock().unwrap();
+        channel.is_writable()
+    }
+
+    pub fn pending_outbound_bytes(&self) -> usize {
+        let channel = self.channel.lock().unwrap();
+        channel.pending_outbound_bytes()
+    }
 }
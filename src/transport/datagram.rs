@@ -0,0 +1,57 @@
+use std::net::SocketAddr;
+
+use bytebuf_rs::bytebuf::ByteBuf;
+
+///
+/// UDP 数据报, 入站时携带发送方地址, 出站时携带目标地址
+///
+pub struct DatagramPacket {
+    content: ByteBuf,
+    sender: Option<SocketAddr>,
+    recipient: Option<SocketAddr>,
+}
+
+impl DatagramPacket {
+    ///
+    /// 创建一个发往 recipient 的数据报
+    ///
+    pub fn new(content: ByteBuf, recipient: SocketAddr) -> DatagramPacket {
+        DatagramPacket {
+            content,
+            sender: None,
+            recipient: Some(recipient),
+        }
+    }
+
+    pub(crate) fn received(
+        content: ByteBuf,
+        sender: SocketAddr,
+        recipient: Option<SocketAddr>,
+    ) -> DatagramPacket {
+        DatagramPacket {
+            content,
+            sender: Some(sender),
+            recipient,
+        }
+    }
+
+    pub fn sender(&self) -> Option<SocketAddr> {
+        self.sender
+    }
+
+    pub fn recipient(&self) -> Option<SocketAddr> {
+        self.recipient
+    }
+
+    pub fn content(&self) -> &ByteBuf {
+        &self.content
+    }
+
+    pub fn content_mut(&mut self) -> &mut ByteBuf {
+        &mut self.content
+    }
+
+    pub fn into_content(self) -> ByteBuf {
+        self.content
+    }
+}
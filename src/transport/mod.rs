@@ -1 +1,6 @@
 pub mod channel;
+pub mod datagram;
+pub(crate) mod outbound_buffer;
+pub mod recv_buf_allocator;
+pub(crate) mod stream;
+pub mod unix;
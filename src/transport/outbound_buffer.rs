@@ -0,0 +1,396 @@
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind, IoSlice, Result, Write};
+
+use crate::buffer::pooled_allocator::PooledBuf;
+use crate::channel::channel_future::ChannelFuture;
+use crate::errors::RettyErrorKind;
+
+pub(crate) const DEFAULT_WRITE_BUFFER_HIGH_WATER_MARK: usize = 64 * 1024;
+pub(crate) const DEFAULT_WRITE_BUFFER_LOW_WATER_MARK: usize = 32 * 1024;
+// 一次 writev 最多合并的数据段数
+const MAX_WRITE_SLICES: usize = 1024;
+
+///
+/// channel 出站缓冲区, 保存还没有写进 socket 的数据
+/// 待写字节数超过高水位时 channel 变为不可写, 低于低水位时恢复可写
+/// write 只是放进缓冲区, flush 之后前 flushed 段数据才会写进 socket
+///
+pub(crate) struct ChannelOutboundBuffer {
+    entries: VecDeque<OutboundEntry>,
+    // entries 前面已经 flush 的段数
+    flushed: usize,
+    // entries 第一个元素已经写出去的字节数
+    front_offset: usize,
+    pending_bytes: usize,
+    high_water_mark: usize,
+    low_water_mark: usize,
+    writable: bool,
+    writability_changed: bool,
+    // 已经写完或者失败, 等待在 channel 锁之外通知的 future
+    completed: Vec<(ChannelFuture, std::result::Result<(), RettyErrorKind>)>,
+}
+
+struct OutboundEntry {
+    bytes: PooledBuf,
+    // 这段数据写完之后完成的 future
+    promises: Vec<ChannelFuture>,
+}
+
+impl ChannelOutboundBuffer {
+    pub(crate) fn new(high_water_mark: usize, low_water_mark: usize) -> ChannelOutboundBuffer {
+        ChannelOutboundBuffer {
+            entries: VecDeque::new(),
+            flushed: 0,
+            front_offset: 0,
+            pending_bytes: 0,
+            high_water_mark,
+            low_water_mark: low_water_mark.min(high_water_mark),
+            writable: true,
+            writability_changed: false,
+            completed: vec![],
+        }
+    }
+
+    pub(crate) fn add(&mut self, bytes: PooledBuf) {
+        if bytes.is_empty() {
+            return;
+        }
+        self.pending_bytes += bytes.len();
+        self.entries.push_back(OutboundEntry {
+            bytes,
+            promises: vec![],
+        });
+        self.update_writability();
+    }
+
+    ///
+    /// future 挂在最后一段数据上, 缓冲区为空说明之前的数据都已经写完
+    ///
+    pub(crate) fn add_promise(&mut self, promise: ChannelFuture) {
+        match self.entries.back_mut() {
+            Some(entry) => entry.promises.push(promise),
+            None => self.completed.push((promise, Ok(()))),
+        }
+    }
+
+    pub(crate) fn take_completed(
+        &mut self,
+    ) -> Vec<(ChannelFuture, std::result::Result<(), RettyErrorKind>)> {
+        std::mem::take(&mut self.completed)
+    }
+
+    ///
+    /// 把目前缓冲区里所有的数据标记为可以写出
+    ///
+    pub(crate) fn add_flush(&mut self) {
+        self.flushed = self.entries.len();
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    ///
+    /// 是否还有 flush 过但没写完的数据
+    ///
+    pub(crate) fn has_flushed(&self) -> bool {
+        self.flushed > 0
+    }
+
+    pub(crate) fn pending_bytes(&self) -> usize {
+        self.pending_bytes
+    }
+
+    pub(crate) fn is_writable(&self) -> bool {
+        self.writable
+    }
+
+    ///
+    /// 读取并清除可写状态变化的标记
+    ///
+    pub(crate) fn take_writability_changed(&mut self) -> bool {
+        let changed = self.writability_changed;
+        self.writability_changed = false;
+        changed
+    }
+
+    ///
+    /// 把 flush 过的数据用 writev 尽可能多的写入, 遇到 WouldBlock 时保留剩余的数据等待下一次可写事件
+    ///
+    pub(crate) fn write_to<W: Write>(&mut self, writer: &mut W) -> Result<()> {
+        let ret = loop {
+            if self.flushed == 0 {
+                break Ok(());
+            }
+            let front_offset = self.front_offset;
+            let slices = self
+                .entries
+                .iter()
+                .take(self.flushed.min(MAX_WRITE_SLICES))
+                .enumerate()
+                .map(|(i, entry)| {
+                    if i == 0 {
+                        IoSlice::new(&entry.bytes[front_offset..])
+                    } else {
+                        IoSlice::new(&entry.bytes)
+                    }
+                })
+                .collect::<Vec<IoSlice<'_>>>();
+            match writer.write_vectored(&slices) {
+                Ok(0) => {
+                    break Err(Error::new(
+                        ErrorKind::WriteZero,
+                        "failed to write whole buffer",
+                    ))
+                }
+                Ok(n) => self.advance(n),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break Ok(()),
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => break Err(e),
+            }
+        };
+        self.update_writability();
+        ret
+    }
+
+    ///
+    /// 写失败或者 channel 关闭时丢弃所有待写数据, 对应的 future 以 cause 失败
+    ///
+    pub(crate) fn clear(&mut self, cause: &RettyErrorKind) {
+        self.flushed = 0;
+        for entry in self.entries.drain(..) {
+            for promise in entry.promises {
+                self.completed.push((promise, Err(cause.clone())));
+            }
+        }
+        self.front_offset = 0;
+        self.pending_bytes = 0;
+        self.update_writability();
+    }
+
+    fn advance(&mut self, mut n: usize) {
+        self.pending_bytes -= n;
+        while n > 0 {
+            let remaining = match self.entries.front() {
+                Some(front) => front.bytes.len() - self.front_offset,
+                None => break,
+            };
+            if n < remaining {
+                self.front_offset += n;
+                break;
+            }
+            n -= remaining;
+            if let Some(entry) = self.entries.pop_front() {
+                for promise in entry.promises {
+                    self.completed.push((promise, Ok(())));
+                }
+            }
+            self.flushed -= 1;
+            self.front_offset = 0;
+        }
+    }
+
+    fn update_writability(&mut self) {
+        if self.writable && self.pending_bytes > self.high_water_mark {
+            self.writable = false;
+            self.writability_changed = true;
+        } else if !self.writable && self.pending_bytes <= self.low_water_mark {
+            self.writable = true;
+            self.writability_changed = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::sync::Weak;
+
+    use super::*;
+    use crate::buffer::pooled_allocator::PooledByteBufAllocator;
+
+    // 每次 write_vectored 最多写 limits 里的字节数, None 返回 WouldBlock, 用完之后不限制
+    struct LimitedWriter {
+        written: Vec<u8>,
+        limits: VecDeque<Option<usize>>,
+        slices_per_call: Vec<usize>,
+    }
+
+    impl LimitedWriter {
+        fn new(limits: &[Option<usize>]) -> LimitedWriter {
+            LimitedWriter {
+                written: vec![],
+                limits: limits.iter().cloned().collect(),
+                slices_per_call: vec![],
+            }
+        }
+    }
+
+    impl Write for LimitedWriter {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.write_vectored(&[IoSlice::new(buf)])
+        }
+
+        fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<usize> {
+            let limit = match self.limits.pop_front() {
+                Some(None) => return Err(Error::from(ErrorKind::WouldBlock)),
+                Some(Some(limit)) => limit,
+                None => usize::MAX,
+            };
+            self.slices_per_call.push(bufs.len());
+            let mut n = 0;
+            for buf in bufs {
+                let take = buf.len().min(limit - n);
+                self.written.extend_from_slice(&buf[..take]);
+                n += take;
+            }
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn add(buffer: &mut ChannelOutboundBuffer, bytes: &[u8]) -> ChannelFuture {
+        buffer.add(PooledByteBufAllocator::default().allocate_from(bytes));
+        let promise = ChannelFuture::new(Weak::new());
+        buffer.add_promise(promise.clone());
+        promise
+    }
+
+    fn complete(buffer: &mut ChannelOutboundBuffer) {
+        for (promise, result) in buffer.take_completed() {
+            promise.complete(result);
+        }
+    }
+
+    #[test]
+    fn partial_writes_resume_from_the_written_offset() {
+        let mut buffer = ChannelOutboundBuffer::new(1024, 512);
+        let hello = add(&mut buffer, b"hello");
+        let world = add(&mut buffer, b"world");
+        buffer.add_flush();
+
+        let mut writer = LimitedWriter::new(&[Some(3), None]);
+        buffer.write_to(&mut writer).unwrap();
+        complete(&mut buffer);
+        assert_eq!(writer.written, b"hel");
+        assert_eq!(buffer.pending_bytes(), 7);
+        assert!(!hello.is_done());
+
+        writer.limits.push_back(Some(4));
+        writer.limits.push_back(None);
+        buffer.write_to(&mut writer).unwrap();
+        complete(&mut buffer);
+        assert_eq!(writer.written, b"hellowo");
+        assert!(hello.is_success());
+        assert!(!world.is_done());
+
+        buffer.write_to(&mut writer).unwrap();
+        complete(&mut buffer);
+        assert_eq!(writer.written, b"helloworld");
+        assert!(world.is_success());
+        assert!(buffer.is_empty());
+        assert!(!buffer.has_flushed());
+    }
+
+    #[test]
+    fn writes_only_flushed_entries() {
+        let mut buffer = ChannelOutboundBuffer::new(1024, 512);
+        add(&mut buffer, b"a");
+        buffer.add_flush();
+        add(&mut buffer, b"b");
+
+        let mut writer = LimitedWriter::new(&[]);
+        buffer.write_to(&mut writer).unwrap();
+        assert_eq!(writer.written, b"a");
+        assert!(!buffer.has_flushed());
+        assert_eq!(buffer.pending_bytes(), 1);
+
+        buffer.add_flush();
+        buffer.write_to(&mut writer).unwrap();
+        assert_eq!(writer.written, b"ab");
+    }
+
+    #[test]
+    fn writev_takes_at_most_max_write_slices() {
+        let mut buffer = ChannelOutboundBuffer::new(usize::MAX, usize::MAX);
+        for _ in 0..MAX_WRITE_SLICES + 10 {
+            add(&mut buffer, b"x");
+        }
+        buffer.add_flush();
+
+        let mut writer = LimitedWriter::new(&[]);
+        buffer.write_to(&mut writer).unwrap();
+        assert_eq!(writer.slices_per_call, vec![MAX_WRITE_SLICES, 10]);
+        assert_eq!(writer.written.len(), MAX_WRITE_SLICES + 10);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn writability_follows_high_and_low_water_marks() {
+        let mut buffer = ChannelOutboundBuffer::new(10, 5);
+        add(&mut buffer, b"12345678");
+        assert!(buffer.is_writable());
+        assert!(!buffer.take_writability_changed());
+
+        add(&mut buffer, b"9012");
+        assert!(!buffer.is_writable());
+        assert!(buffer.take_writability_changed());
+        assert!(!buffer.take_writability_changed());
+        buffer.add_flush();
+
+        // 高于低水位时保持不可写
+        let mut writer = LimitedWriter::new(&[Some(6), None]);
+        buffer.write_to(&mut writer).unwrap();
+        assert_eq!(buffer.pending_bytes(), 6);
+        assert!(!buffer.is_writable());
+        assert!(!buffer.take_writability_changed());
+
+        writer.limits.push_back(Some(1));
+        writer.limits.push_back(None);
+        buffer.write_to(&mut writer).unwrap();
+        assert_eq!(buffer.pending_bytes(), 5);
+        assert!(buffer.is_writable());
+        assert!(buffer.take_writability_changed());
+    }
+
+    #[test]
+    fn clear_fails_pending_promises() {
+        let mut buffer = ChannelOutboundBuffer::new(10, 5);
+        let first = add(&mut buffer, b"first");
+        buffer.add_flush();
+        let second = add(&mut buffer, b"second");
+        assert!(!buffer.is_writable());
+        buffer.take_writability_changed();
+
+        let cause = RettyErrorKind::new(ErrorKind::BrokenPipe, "closed".to_string());
+        buffer.clear(&cause);
+        complete(&mut buffer);
+        assert_eq!(first.cause(), Some(cause.clone()));
+        assert_eq!(second.cause(), Some(cause));
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.pending_bytes(), 0);
+        assert!(buffer.is_writable());
+        assert!(buffer.take_writability_changed());
+
+        // 缓冲区为空时 promise 马上成功
+        let empty = ChannelFuture::new(Weak::new());
+        buffer.add_promise(empty.clone());
+        complete(&mut buffer);
+        assert!(empty.is_success());
+    }
+
+    #[test]
+    fn zero_length_write_is_an_error() {
+        let mut buffer = ChannelOutboundBuffer::new(1024, 512);
+        add(&mut buffer, b"data");
+        buffer.add_flush();
+
+        let mut writer = LimitedWriter::new(&[Some(0)]);
+        let e = buffer.write_to(&mut writer).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::WriteZero);
+        assert_eq!(buffer.pending_bytes(), 4);
+    }
+}
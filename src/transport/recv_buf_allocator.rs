@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+
+use crate::transport::channel::ChannelOptions;
+
+pub const DEFAULT_MINIMUM_RECV_BUF_SIZE: usize = 64;
+pub const DEFAULT_INITIAL_RECV_BUF_SIZE: usize = 2048;
+pub const DEFAULT_MAXIMUM_RECV_BUF_SIZE: usize = 65536;
+pub const DEFAULT_MAX_MESSAGES_PER_READ: usize = 16;
+pub const DEFAULT_MAX_BYTES_PER_READ: usize = 1024 * 1024;
+
+// 读满之后放大的步数, 读不满时缩小的步数
+const INDEX_INCREMENT: usize = 4;
+const INDEX_DECREMENT: usize = 1;
+
+///
+/// 接收缓冲区大小表: 16 ~ 496 每 16 字节一档, 之后每档翻倍
+///
+fn size_table() -> Vec<usize> {
+    let mut table = (1..32).map(|i| i * 16).collect::<Vec<usize>>();
+    let mut size = 512usize;
+    while size <= 1 << 30 {
+        table.push(size);
+        size <<= 1;
+    }
+    table
+}
+
+///
+/// 第一个不小于 size 的档位
+///
+fn size_table_index(table: &[usize], size: usize) -> usize {
+    match table.binary_search(&size) {
+        Ok(i) => i,
+        Err(i) => i.min(table.len() - 1),
+    }
+}
+
+///
+/// 接收缓冲区分配策略
+/// fixed: 每次都按同一个大小读; adaptive: 根据上一次读到的字节数在 minimum ~ maximum 之间调整下一次读的大小
+/// 一个读事件最多读 max_messages_per_read 次、max_bytes_per_read 字节, 剩下的数据留到下一轮, 避免一个 channel 占住 eventloop
+///
+#[derive(Debug, Clone)]
+pub struct RecvByteBufAllocator {
+    minimum: usize,
+    initial: usize,
+    maximum: usize,
+    max_messages_per_read: usize,
+    max_bytes_per_read: usize,
+}
+
+impl Default for RecvByteBufAllocator {
+    fn default() -> Self {
+        RecvByteBufAllocator::new_adaptive(
+            DEFAULT_MINIMUM_RECV_BUF_SIZE,
+            DEFAULT_INITIAL_RECV_BUF_SIZE,
+            DEFAULT_MAXIMUM_RECV_BUF_SIZE,
+        )
+    }
+}
+
+impl RecvByteBufAllocator {
+    pub fn new_fixed(size: usize) -> RecvByteBufAllocator {
+        RecvByteBufAllocator::new_adaptive(size, size, size)
+    }
+
+    pub fn new_adaptive(minimum: usize, initial: usize, maximum: usize) -> RecvByteBufAllocator {
+        let minimum = minimum.max(1);
+        let maximum = maximum.max(minimum);
+        RecvByteBufAllocator {
+            minimum,
+            initial: initial.max(minimum).min(maximum),
+            maximum,
+            max_messages_per_read: DEFAULT_MAX_MESSAGES_PER_READ,
+            max_bytes_per_read: DEFAULT_MAX_BYTES_PER_READ,
+        }
+    }
+
+    pub fn max_messages_per_read(&mut self, n: usize) -> &mut Self {
+        self.max_messages_per_read = n.max(1);
+        self
+    }
+
+    pub fn max_bytes_per_read(&mut self, n: usize) -> &mut Self {
+        self.max_bytes_per_read = n.max(1);
+        self
+    }
+
+    pub fn is_fixed(&self) -> bool {
+        self.minimum == self.maximum
+    }
+
+    ///
+    /// 从 channel 参数中读取, 见 Bootstrap::opt_recv_buf_allocator
+    ///
+    pub(crate) fn from_opts(opts: &HashMap<String, ChannelOptions>) -> RecvByteBufAllocator {
+        let number = |key: &str, default: usize| match opts.get(key) {
+            Some(ChannelOptions::NUMBER(n)) => *n,
+            _ => default,
+        };
+        let mut allocator = RecvByteBufAllocator::new_adaptive(
+            number("recv_buf_allocator_minimum", DEFAULT_MINIMUM_RECV_BUF_SIZE),
+            number("recv_buf_allocator_initial", DEFAULT_INITIAL_RECV_BUF_SIZE),
+            number("recv_buf_allocator_maximum", DEFAULT_MAXIMUM_RECV_BUF_SIZE),
+        );
+        allocator
+            .max_messages_per_read(number(
+                "max_messages_per_read",
+                DEFAULT_MAX_MESSAGES_PER_READ,
+            ))
+            .max_bytes_per_read(number("max_bytes_per_read", DEFAULT_MAX_BYTES_PER_READ));
+        allocator
+    }
+
+    pub(crate) fn opts(&self) -> Vec<(String, ChannelOptions)> {
+        vec![
+            ("recv_buf_allocator_minimum", self.minimum),
+            ("recv_buf_allocator_initial", self.initial),
+            ("recv_buf_allocator_maximum", self.maximum),
+            ("max_messages_per_read", self.max_messages_per_read),
+            ("max_bytes_per_read", self.max_bytes_per_read),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_owned(), ChannelOptions::NUMBER(v)))
+        .collect()
+    }
+
+    pub(crate) fn new_handle(&self) -> RecvByteBufAllocatorHandle {
+        RecvByteBufAllocatorHandle::new(self)
+    }
+}
+
+///
+/// 每个 channel 一个, 记录下一次读的大小和这一轮已经读了多少
+///
+pub(crate) struct RecvByteBufAllocatorHandle {
+    table: Vec<usize>,
+    min_index: usize,
+    max_index: usize,
+    index: usize,
+    next_receive_buf_size: usize,
+    decrease_now: bool,
+    max_messages_per_read: usize,
+    max_bytes_per_read: usize,
+    messages_read: usize,
+    total_bytes_read: usize,
+}
+
+impl RecvByteBufAllocatorHandle {
+    fn new(allocator: &RecvByteBufAllocator) -> RecvByteBufAllocatorHandle {
+        let (table, min_index, max_index, index) = if allocator.is_fixed() {
+            (vec![allocator.initial], 0, 0, 0)
+        } else {
+            let table = size_table();
+            let min_index = size_table_index(&table, allocator.minimum);
+            let mut max_index = size_table_index(&table, allocator.maximum);
+            if table[max_index] > allocator.maximum && max_index > min_index {
+                max_index -= 1;
+            }
+            let index = size_table_index(&table, allocator.initial).min(max_index);
+            (table, min_index, max_index, index)
+        };
+        RecvByteBufAllocatorHandle {
+            next_receive_buf_size: table[index],
+            table,
+            min_index,
+            max_index,
+            index,
+            decrease_now: false,
+            max_messages_per_read: allocator.max_messages_per_read,
+            max_bytes_per_read: allocator.max_bytes_per_read,
+            messages_read: 0,
+            total_bytes_read: 0,
+        }
+    }
+
+    ///
+    /// 一个读事件开始时调用
+    ///
+    pub(crate) fn reset(&mut self) {
+        self.messages_read = 0;
+        self.total_bytes_read = 0;
+    }
+
+    ///
+    /// 下一次读的大小, 不超过这一轮剩下可读的字节数
+    ///
+    pub(crate) fn guess(&self) -> usize {
+        let remaining = self
+            .max_bytes_per_read
+            .saturating_sub(self.total_bytes_read);
+        self.next_receive_buf_size.min(remaining).max(1)
+    }
+
+    ///
+    /// 读满了说明 socket 里可能还有数据, 马上放大
+    ///
+    pub(crate) fn last_bytes_read(&mut self, attempted: usize, n: usize) {
+        self.messages_read += 1;
+        self.total_bytes_read += n;
+        if n == attempted {
+            self.record(n);
+        }
+    }
+
+    ///
+    /// UDP 读到一个数据报, 只计数, 不调整下一次读的大小
+    ///
+    pub(crate) fn last_datagram_read(&mut self, n: usize) {
+        self.messages_read += 1;
+        self.total_bytes_read += n;
+    }
+
+    ///
+    /// 没有超过次数和字节数的限制时继续读
+    /// selector 是边缘触发的, 没读到 WouldBlock 之前不会再通知, 所以读不满也要继续读
+    ///
+    pub(crate) fn continue_reading(&self) -> bool {
+        self.messages_read < self.max_messages_per_read
+            && self.total_bytes_read < self.max_bytes_per_read
+    }
+
+    ///
+    /// 一个读事件结束时按这一轮读到的总字节数调整
+    ///
+    pub(crate) fn read_complete(&mut self) {
+        let total_bytes_read = self.total_bytes_read;
+        self.record(total_bytes_read);
+    }
+
+    fn record(&mut self, actual: usize) {
+        let lower = self.table[self
+            .index
+            .saturating_sub(INDEX_DECREMENT)
+            .max(self.min_index)];
+        // 先判断放大: 在最小档时 lower 就是当前大小, 读满也要放大
+        if actual >= self.next_receive_buf_size {
+            self.index = (self.index + INDEX_INCREMENT).min(self.max_index);
+            self.next_receive_buf_size = self.table[self.index];
+            self.decrease_now = false;
+        } else if actual <= lower {
+            if self.decrease_now {
+                self.index = self
+                    .index
+                    .saturating_sub(INDEX_DECREMENT)
+                    .max(self.min_index);
+                self.next_receive_buf_size = self.table[self.index];
+                self.decrease_now = false;
+            } else {
+                self.decrease_now = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size_table_steps() {
+        let table = size_table();
+        assert_eq!(&table[..3], &[16, 32, 48]);
+        assert_eq!(table[30], 496);
+        assert_eq!(&table[31..34], &[512, 1024, 2048]);
+        assert_eq!(*table.last().unwrap(), 1 << 30);
+
+        for (size, expected) in [
+            (0, 16),
+            (16, 16),
+            (17, 32),
+            (496, 496),
+            (497, 512),
+            (2048, 2048),
+            (2049, 4096),
+            (usize::MAX, 1 << 30),
+        ] {
+            assert_eq!(table[size_table_index(&table, size)], expected, "{}", size);
+        }
+    }
+
+    // 每一轮读到的字节数和之后 guess 的大小
+    fn assert_read_complete_steps(
+        handle: &mut RecvByteBufAllocatorHandle,
+        steps: &[(usize, usize)],
+    ) {
+        for (i, (bytes_read, next)) in steps.iter().enumerate() {
+            handle.reset();
+            handle.last_bytes_read(handle.guess(), *bytes_read);
+            handle.read_complete();
+            assert_eq!(handle.guess(), *next, "step {}", i);
+        }
+    }
+
+    #[test]
+    fn grows_four_steps_after_a_full_read() {
+        let mut handle = RecvByteBufAllocator::default().new_handle();
+        assert_eq!(handle.guess(), 2048);
+
+        handle.reset();
+        handle.last_bytes_read(2048, 2048);
+        assert_eq!(handle.guess(), 32768);
+        handle.last_bytes_read(32768, 32768);
+        // 不超过 maximum
+        assert_eq!(handle.guess(), 65536);
+    }
+
+    #[test]
+    fn shrinks_one_step_after_two_small_reads_in_a_row() {
+        let mut handle = RecvByteBufAllocator::new_adaptive(64, 2048, 65536).new_handle();
+        assert_read_complete_steps(
+            &mut handle,
+            &[
+                // 不超过下一档(1024)算小
+                (1024, 2048),
+                (100, 1024),
+                // 介于两档之间不调整
+                (600, 1024),
+                (10, 1024),
+                (10, 512),
+                // 16 字节一档的部分每次缩小 16
+                (10, 512),
+                (10, 496),
+            ],
+        );
+    }
+
+    #[test]
+    fn grows_when_a_read_event_reads_more_than_the_guess() {
+        let mut handle = RecvByteBufAllocator::default().new_handle();
+        handle.reset();
+        handle.last_bytes_read(2048, 1500);
+        handle.last_bytes_read(2048, 1500);
+        assert_eq!(handle.guess(), 2048);
+        handle.read_complete();
+        assert_eq!(handle.guess(), 32768);
+    }
+
+    #[test]
+    fn stays_within_minimum_and_maximum() {
+        let mut handle = RecvByteBufAllocator::new_adaptive(64, 128, 60000).new_handle();
+        assert_eq!(handle.guess(), 128);
+        assert_read_complete_steps(&mut handle, &[(0, 128), (0, 112), (0, 112), (0, 96)]);
+        for _ in 0..20 {
+            handle.reset();
+            handle.read_complete();
+        }
+        assert_eq!(handle.guess(), 64);
+
+        // 在最小档读满也会放大, maximum 不在表里时取不超过它的那一档
+        for _ in 0..10 {
+            let guess = handle.guess();
+            handle.reset();
+            handle.last_bytes_read(guess, guess);
+        }
+        assert_eq!(handle.guess(), 32768);
+    }
+
+    #[test]
+    fn fixed_allocator_never_changes() {
+        let allocator = RecvByteBufAllocator::new_fixed(1000);
+        assert!(allocator.is_fixed());
+        let mut handle = allocator.new_handle();
+        assert_read_complete_steps(&mut handle, &[(1000, 1000), (0, 1000), (0, 1000)]);
+    }
+
+    #[test]
+    fn one_read_event_is_limited_by_messages_and_bytes() {
+        let mut allocator = RecvByteBufAllocator::new_fixed(1000);
+        allocator.max_messages_per_read(3).max_bytes_per_read(2500);
+        let mut handle = allocator.new_handle();
+
+        handle.reset();
+        handle.last_bytes_read(1000, 1000);
+        handle.last_bytes_read(1000, 1000);
+        assert!(handle.continue_reading());
+        // 最后一次只读这一轮剩下的字节数
+        assert_eq!(handle.guess(), 500);
+        handle.last_bytes_read(500, 500);
+        assert!(!handle.continue_reading());
+
+        handle.reset();
+        for _ in 0..3 {
+            handle.last_bytes_read(1000, 1);
+        }
+        assert!(!handle.continue_reading());
+    }
+}
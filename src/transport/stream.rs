@@ -0,0 +1,226 @@
+use std::io::{Error, ErrorKind, IoSlice, Read, Result, Write};
+use std::net::{Shutdown, SocketAddr};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use mio::net::{TcpListener, TcpStream, UdpSocket};
+use mio::{Evented, Poll, PollOpt, Ready, Token};
+use mio_uds::{UnixListener, UnixStream};
+
+use crate::transport::unix::{peer_credentials, PeerCredentials};
+
+///
+/// channel 底层的 socket, 统一注册到 eventloop 的 selector 上
+///
+pub(crate) enum ChannelStream {
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+    Unix(UnixStream),
+}
+
+impl ChannelStream {
+    pub(crate) fn try_clone(&self) -> Result<ChannelStream> {
+        match self {
+            ChannelStream::Tcp(s) => Ok(ChannelStream::Tcp(s.try_clone()?)),
+            ChannelStream::Udp(s) => Ok(ChannelStream::Udp(s.try_clone()?)),
+            ChannelStream::Unix(s) => Ok(ChannelStream::Unix(s.try_clone()?)),
+        }
+    }
+
+    fn evented(&self) -> &dyn Evented {
+        match self {
+            ChannelStream::Tcp(s) => s,
+            ChannelStream::Udp(s) => s,
+            ChannelStream::Unix(s) => s,
+        }
+    }
+
+    pub(crate) fn register(&self, poll: &Poll, token: Token, interest: Ready) -> Result<()> {
+        poll.register(self.evented(), token, interest, PollOpt::edge())
+    }
+
+    pub(crate) fn reregister(&self, poll: &Poll, token: Token, interest: Ready) -> Result<()> {
+        poll.reregister(self.evented(), token, interest, PollOpt::edge())
+    }
+
+    pub(crate) fn deregister(&self, poll: &Poll) -> Result<()> {
+        poll.deregister(self.evented())
+    }
+
+    pub(crate) fn is_datagram(&self) -> bool {
+        matches!(self, ChannelStream::Udp(_))
+    }
+
+    pub(crate) fn peer_addr(&self) -> Result<SocketAddr> {
+        match self {
+            ChannelStream::Tcp(s) => s.peer_addr(),
+            ChannelStream::Udp(_) => Err(Error::new(
+                ErrorKind::NotConnected,
+                "datagram channel has no remote address",
+            )),
+            ChannelStream::Unix(_) => Err(Error::new(
+                ErrorKind::Unsupported,
+                "unix domain socket has no ip address",
+            )),
+        }
+    }
+
+    ///
+    /// 非阻塞连接是否已经建立
+    ///
+    pub(crate) fn check_connected(&self) -> Result<bool> {
+        let ret = match self {
+            ChannelStream::Tcp(s) => s.peer_addr().map(|_| ()),
+            ChannelStream::Unix(s) => s.peer_addr().map(|_| ()),
+            ChannelStream::Udp(_) => Ok(()),
+        };
+        match ret {
+            Ok(_) => Ok(true),
+            Err(ref e) if e.kind() == ErrorKind::NotConnected => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub(crate) fn peer_credentials(&self) -> Result<PeerCredentials> {
+        match self {
+            ChannelStream::Unix(s) => peer_credentials(s.as_raw_fd()),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "peer credentials are only available on unix domain sockets",
+            )),
+        }
+    }
+
+    pub(crate) fn local_addr(&self) -> Result<SocketAddr> {
+        match self {
+            ChannelStream::Tcp(s) => s.local_addr(),
+            ChannelStream::Udp(s) => s.local_addr(),
+            ChannelStream::Unix(_) => Err(Error::new(
+                ErrorKind::Unsupported,
+                "unix domain socket has no ip address",
+            )),
+        }
+    }
+
+    pub(crate) fn take_error(&self) -> Result<Option<Error>> {
+        match self {
+            ChannelStream::Tcp(s) => s.take_error(),
+            ChannelStream::Udp(s) => s.take_error(),
+            ChannelStream::Unix(s) => s.take_error(),
+        }
+    }
+
+    pub(crate) fn shutdown(&self, how: Shutdown) -> Result<()> {
+        match self {
+            ChannelStream::Tcp(s) => s.shutdown(how),
+            ChannelStream::Udp(_) => Ok(()),
+            ChannelStream::Unix(s) => s.shutdown(how),
+        }
+    }
+
+    pub(crate) fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr)> {
+        match self {
+            ChannelStream::Udp(s) => s.recv_from(buf),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "recv_from on a stream channel",
+            )),
+        }
+    }
+
+    pub(crate) fn send_to(&self, buf: &[u8], target: &SocketAddr) -> Result<usize> {
+        match self {
+            ChannelStream::Udp(s) => s.send_to(buf, target),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "send_to on a stream channel",
+            )),
+        }
+    }
+}
+
+impl Read for ChannelStream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self {
+            ChannelStream::Tcp(s) => s.read(buf),
+            ChannelStream::Udp(s) => s.recv(buf),
+            ChannelStream::Unix(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for ChannelStream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        match self {
+            ChannelStream::Tcp(s) => s.write(buf),
+            ChannelStream::Udp(s) => s.send(buf),
+            ChannelStream::Unix(s) => s.write(buf),
+        }
+    }
+
+    ///
+    /// 一次系统调用写多段数据
+    ///
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<usize> {
+        match self {
+            ChannelStream::Tcp(s) => writev(s.as_raw_fd(), bufs),
+            ChannelStream::Udp(s) => {
+                let buf = bufs
+                    .iter()
+                    .find(|b| !b.is_empty())
+                    .map_or(&[][..], |b| &**b);
+                s.send(buf)
+            }
+            ChannelStream::Unix(s) => writev(s.as_raw_fd(), bufs),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            ChannelStream::Tcp(s) => s.flush(),
+            ChannelStream::Udp(_) => Ok(()),
+            ChannelStream::Unix(s) => s.flush(),
+        }
+    }
+}
+
+fn writev(fd: RawFd, bufs: &[IoSlice<'_>]) -> Result<usize> {
+    // IoSlice 在 unix 上和 iovec 的内存布局相同
+    let ret = unsafe {
+        libc::writev(
+            fd,
+            bufs.as_ptr() as *const libc::iovec,
+            bufs.len().min(libc::c_int::MAX as usize) as libc::c_int,
+        )
+    };
+    if ret < 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(ret as usize)
+}
+
+///
+/// 服务端监听的 socket
+///
+pub(crate) enum ChannelListener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl ChannelListener {
+    pub(crate) fn register(&self, poll: &Poll, token: Token) -> Result<()> {
+        match self {
+            ChannelListener::Tcp(l) => poll.register(l, token, Ready::readable(), PollOpt::edge()),
+            ChannelListener::Unix(l) => poll.register(l, token, Ready::readable(), PollOpt::edge()),
+        }
+    }
+
+    pub(crate) fn accept(&self) -> Result<ChannelStream> {
+        match self {
+            ChannelListener::Tcp(l) => l.accept().map(|(s, _)| ChannelStream::Tcp(s)),
+            ChannelListener::Unix(l) => match l.accept()? {
+                Some((s, _)) => Ok(ChannelStream::Unix(s)),
+                None => Err(Error::from(ErrorKind::WouldBlock)),
+            },
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+
+///
+/// unix domain socket 对端进程的身份, pid 在部分平台上拿不到
+///
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct PeerCredentials {
+    pub uid: u32,
+    pub gid: u32,
+    pub pid: Option<i32>,
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn peer_credentials(fd: RawFd) -> Result<PeerCredentials> {
+    let mut ucred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut ucred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(PeerCredentials {
+        uid: ucred.uid,
+        gid: ucred.gid,
+        pid: Some(ucred.pid),
+    })
+}
+
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+))]
+pub(crate) fn peer_credentials(fd: RawFd) -> Result<PeerCredentials> {
+    let mut uid: libc::uid_t = 0;
+    let mut gid: libc::gid_t = 0;
+    let ret = unsafe { libc::getpeereid(fd, &mut uid, &mut gid) };
+    if ret != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(PeerCredentials {
+        uid,
+        gid,
+        pid: None,
+    })
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+)))]
+pub(crate) fn peer_credentials(_fd: RawFd) -> Result<PeerCredentials> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        "peer credentials are not supported on this platform",
+    ))
+}
+
+///
+/// 删除上次没有清理掉的 socket 文件, 如果路径上是普通文件则报错
+///
+pub(crate) fn remove_stale_socket(path: &Path) -> Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) => {
+            if meta.file_type().is_socket() {
+                fs::remove_file(path)
+            } else {
+                Err(Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a socket", path.display()),
+                ))
+            }
+        }
+        Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}